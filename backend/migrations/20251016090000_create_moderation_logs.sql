@@ -0,0 +1,16 @@
+-- 管理者・モデレーターによる操作を記録するモデレーションログテーブル
+CREATE TABLE moderation_logs (
+    id SERIAL PRIMARY KEY,
+    -- 操作を実行したユーザー
+    actor_user_id INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    -- 操作の種類 (例: 'transfer_board_ownership')
+    action VARCHAR(64) NOT NULL,
+    -- 操作対象の板 (板に紐づく操作の場合)
+    board_id INTEGER REFERENCES boards(id) ON DELETE SET NULL,
+    -- 操作内容の詳細 (変更前後の値など)
+    details JSONB NOT NULL DEFAULT '{}'::jsonb,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_moderation_logs_board_id ON moderation_logs (board_id);
+CREATE INDEX idx_moderation_logs_created_at ON moderation_logs (created_at);
@@ -0,0 +1,22 @@
+-- proxycheckのレスポンスから抽出した国コード (ISO 3166-1 alpha-2) を保存するカラムを追加
+ALTER TABLE level_up_attempts
+ADD COLUMN country_code VARCHAR(8);
+
+-- 既存の試行記録についても、保存済みのproxycheck_jsonから国コードを埋める。
+-- v2形式ではIP詳細の直下に "isocode"、v3形式では "location" オブジェクト内に "country_code" がある。
+UPDATE level_up_attempts AS a
+SET country_code = sub.country_code
+FROM (
+    SELECT
+        la.id,
+        (
+            SELECT UPPER(COALESCE(d.value->>'isocode', d.value->'location'->>'country_code'))
+            FROM jsonb_each(la.proxycheck_json) AS d
+            WHERE jsonb_typeof(d.value) = 'object'
+              AND COALESCE(d.value->>'isocode', d.value->'location'->>'country_code') IS NOT NULL
+            LIMIT 1
+        ) AS country_code
+    FROM level_up_attempts la
+    WHERE la.proxycheck_json IS NOT NULL AND jsonb_typeof(la.proxycheck_json) = 'object'
+) AS sub
+WHERE a.id = sub.id AND sub.country_code IS NOT NULL;
@@ -0,0 +1,7 @@
+-- レス数上限に達したスレッドのアーカイブ予定日時を記録するカラムを追加
+-- プロセスの再起動で遅延アーカイブタスクが失われても、バッチジョブがこの値を元にアーカイブを確定できるようにする。
+ALTER TABLE posts
+ADD COLUMN archive_scheduled_at TIMESTAMPTZ;
+
+CREATE INDEX idx_posts_archive_scheduled_at ON posts (archive_scheduled_at)
+WHERE archive_scheduled_at IS NOT NULL AND archived_at IS NULL;
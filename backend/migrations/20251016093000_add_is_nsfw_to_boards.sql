@@ -0,0 +1,4 @@
+-- 板をNSFW (年齢制限あり) として扱うかどうかのフラグを追加
+-- NSFWの板は、閲覧者が明示的に同意しない限り板一覧に表示されない。
+ALTER TABLE boards
+ADD COLUMN is_nsfw BOOLEAN NOT NULL DEFAULT false;
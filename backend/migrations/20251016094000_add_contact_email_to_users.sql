@@ -0,0 +1,5 @@
+-- 連絡用のメールアドレスを保存するカラムを追加
+-- `email` カラムには実際にはアカウントIDが保存されており、ハッシュ生成にも使われているため、
+-- 実在のメールアドレスはこのカラムに分けて保存する。(将来のアカウント復旧機能のための下準備)
+ALTER TABLE users
+ADD COLUMN contact_email VARCHAR(254);
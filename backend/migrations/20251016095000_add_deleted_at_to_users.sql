@@ -0,0 +1,5 @@
+-- ユーザー自身によるアカウント削除 (論理削除) 用のカラムを追加
+-- 削除済みのユーザーはログインおよびセッション認証ができなくなる。
+-- 書き込み内容は削除せずに残す (スレッドの流れを保つため)。必要に応じて投稿者名のみ匿名化する。
+ALTER TABLE users
+ADD COLUMN deleted_at TIMESTAMPTZ;
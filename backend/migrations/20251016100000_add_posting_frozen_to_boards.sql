@@ -0,0 +1,4 @@
+-- 板への書き込みを一時的に停止 (凍結) するためのフラグを追加
+-- アーカイブとは異なり、板は通常通り閲覧できるが、新規スレッド作成とレスの書き込みのみを拒否する。
+ALTER TABLE boards
+ADD COLUMN posting_frozen BOOLEAN NOT NULL DEFAULT false;
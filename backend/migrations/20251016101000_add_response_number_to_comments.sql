@@ -0,0 +1,14 @@
+-- レス番号をスレッドごとに保存し、取得時に毎回 ROW_NUMBER で計算しなくて済むようにする
+-- スレッド本文が1のため、レスは2から始まる
+ALTER TABLE comments ADD COLUMN response_number INTEGER;
+
+-- 既存のレスにレス番号を付与する
+WITH numbered AS (
+    SELECT id, (1 + ROW_NUMBER() OVER (PARTITION BY post_id ORDER BY created_at ASC, id ASC))::INTEGER AS n
+    FROM comments
+)
+UPDATE comments c SET response_number = numbered.n
+FROM numbered
+WHERE c.id = numbered.id;
+
+CREATE INDEX idx_comments_post_id_response_number ON comments (post_id, response_number);
@@ -0,0 +1,6 @@
+-- 同一内容の連続投稿 (コピペ荒らし) を安価に検出するため、正規化した本文のハッシュを保存する
+ALTER TABLE posts ADD COLUMN body_hash VARCHAR(64);
+ALTER TABLE comments ADD COLUMN body_hash VARCHAR(64);
+
+CREATE INDEX idx_posts_body_hash_created_at ON posts (body_hash, created_at);
+CREATE INDEX idx_comments_body_hash_created_at ON comments (body_hash, created_at);
@@ -0,0 +1,15 @@
+-- 板の設定変更 (名前・デフォルト名・スレッド数上限など) の履歴
+CREATE TABLE board_settings_history (
+    id SERIAL PRIMARY KEY,
+    board_id INTEGER NOT NULL REFERENCES boards(id) ON DELETE CASCADE,
+    -- 変更を実行したユーザー
+    changed_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    -- 変更された設定項目 (例: 'default_name', 'max_posts')
+    setting VARCHAR(64) NOT NULL,
+    -- 変更前後の値
+    old_value JSONB,
+    new_value JSONB,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_board_settings_history_board_id_created_at ON board_settings_history (board_id, created_at DESC);
@@ -0,0 +1,14 @@
+-- 板ごとの連番のスレッド番号 (人が参照しやすい安定した番号)
+ALTER TABLE posts ADD COLUMN board_sequence INTEGER;
+
+-- 既存のスレッドに作成日時順で番号を付与する
+WITH numbered AS (
+    SELECT id, ROW_NUMBER() OVER (PARTITION BY board_id ORDER BY created_at ASC, id ASC)::INTEGER AS seq
+    FROM posts
+    WHERE board_id IS NOT NULL
+)
+UPDATE posts p SET board_sequence = numbered.seq
+FROM numbered
+WHERE p.id = numbered.id;
+
+CREATE INDEX idx_posts_board_id_board_sequence ON posts (board_id, board_sequence);
@@ -0,0 +1,11 @@
+-- BAN理由の定型文。管理者が管理し、管理者と板作成者がBAN作成時に利用できる
+CREATE TABLE ban_reason_templates (
+    id SERIAL PRIMARY KEY,
+    -- 一覧表示用の短い名前
+    name VARCHAR(64) NOT NULL,
+    -- BAN理由として使われる本文 (bans.reason と同じ上限)
+    reason VARCHAR(255) NOT NULL,
+    created_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
@@ -0,0 +1,14 @@
+-- メンバー限定の板: 参加したユーザーのみが新規スレッド作成・レスの書き込みを行える
+ALTER TABLE boards
+    ADD COLUMN members_only BOOLEAN NOT NULL DEFAULT FALSE,
+    -- 板に参加するために必要なユーザーレベル
+    ADD COLUMN member_min_level INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE board_members (
+    board_id INTEGER NOT NULL REFERENCES boards(id) ON DELETE CASCADE,
+    user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    joined_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (board_id, user_id)
+);
+
+CREATE INDEX idx_board_members_user_id ON board_members (user_id);
@@ -0,0 +1,12 @@
+-- 板ごとのスレッド番号カウンター (最後に払い出した board_sequence)
+-- MAX()+1 による採番は同時作成時に競合するため、板の行をロックして採番する
+ALTER TABLE boards ADD COLUMN next_seq INTEGER NOT NULL DEFAULT 0;
+
+UPDATE boards b SET next_seq = COALESCE(
+    (SELECT MAX(p.board_sequence) FROM posts p WHERE p.board_id = b.id),
+    0
+);
+
+-- 採番の一意性をDBレベルでも保証する
+DROP INDEX IF EXISTS idx_posts_board_id_board_sequence;
+CREATE UNIQUE INDEX idx_posts_board_id_board_sequence ON posts (board_id, board_sequence);
@@ -0,0 +1,2 @@
+-- 板ごとに、書き込み時のCaptcha (Turnstile) 認証を必須にするかどうか
+ALTER TABLE boards ADD COLUMN require_captcha BOOLEAN NOT NULL DEFAULT FALSE;
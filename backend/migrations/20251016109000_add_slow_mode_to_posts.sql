@@ -0,0 +1,2 @@
+-- スレッドごとの低速モード: レスとレスの間に空けなければならない最小秒数 (0 は無効)
+ALTER TABLE posts ADD COLUMN slow_mode_seconds INTEGER NOT NULL DEFAULT 0;
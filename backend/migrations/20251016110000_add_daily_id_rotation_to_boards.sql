@@ -0,0 +1,2 @@
+-- 板ごとのID: 有効な場合、表示IDに板IDを含めて生成し、同じユーザーでも板ごとに異なるIDになる (日替わりは従来どおり)
+ALTER TABLE boards ADD COLUMN daily_id_rotation BOOLEAN NOT NULL DEFAULT FALSE;
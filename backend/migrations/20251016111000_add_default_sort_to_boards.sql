@@ -0,0 +1,2 @@
+-- 板ごとのスレッド一覧のデフォルトソート順 (ソート指定がない場合に使用)
+ALTER TABLE boards ADD COLUMN default_sort TEXT NOT NULL DEFAULT 'momentum_desc';
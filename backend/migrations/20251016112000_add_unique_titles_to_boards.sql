@@ -0,0 +1,2 @@
+-- 有効な場合、同じ板の現行スレッドと同じタイトルのスレッドを作成できないようにする
+ALTER TABLE boards ADD COLUMN unique_titles BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,16 @@
+-- レス番号がスレッド内で重複しないようにする
+-- 同時書き込みで重複したレス番号が保存されている可能性があるため、先に投稿順で振り直す
+WITH numbered AS (
+    SELECT id, (1 + ROW_NUMBER() OVER (PARTITION BY post_id ORDER BY created_at ASC, id ASC))::INTEGER AS n
+    FROM comments
+)
+UPDATE comments c SET response_number = numbered.n
+FROM numbered
+WHERE c.id = numbered.id AND c.response_number IS DISTINCT FROM numbered.n;
+
+-- レス番号の振り直し (recompute_response_numbers) は1回のUPDATEで番号を入れ替えるため、
+-- 一意制約の検査はトランザクションの終了時まで遅延させる
+DROP INDEX IF EXISTS idx_comments_post_id_response_number;
+ALTER TABLE comments
+    ADD CONSTRAINT uq_comments_post_id_response_number UNIQUE (post_id, response_number)
+    DEFERRABLE INITIALLY DEFERRED;
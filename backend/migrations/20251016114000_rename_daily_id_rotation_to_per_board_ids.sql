@@ -0,0 +1,2 @@
+-- 板ごとのIDの設定は日替わりの有無を切り替えるものではないため、実際の動作に合わせて列名を変更する
+ALTER TABLE boards RENAME COLUMN daily_id_rotation TO per_board_ids;
@@ -0,0 +1,11 @@
+-- レスの編集履歴: 編集のたびに、編集前の本文を1行として保存する
+CREATE TABLE comment_edits (
+    id SERIAL PRIMARY KEY,
+    comment_id INTEGER NOT NULL REFERENCES comments(id) ON DELETE CASCADE,
+    -- 編集前の本文 (サニタイズ済みの形式)
+    body TEXT NOT NULL,
+    edited_by INTEGER REFERENCES users(id) ON DELETE SET NULL,
+    edited_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_comment_edits_comment_id ON comment_edits (comment_id, edited_at);
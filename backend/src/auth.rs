@@ -1,6 +1,6 @@
 use actix_web::{
     cookie::{time::OffsetDateTime, Cookie, SameSite},
-    get, post, web, HttpResponse, Responder,
+    delete, get, post, web, HttpResponse, Responder,
 };
 use chrono::{Duration, Utc};
 use hex;
@@ -9,6 +9,7 @@ use reqwest;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use sqlx::PgPool;
+use validator::Validate;
 
 use crate::errors::ServiceError;
 use crate::middleware::{AuthenticatedUser, Role};
@@ -134,6 +135,10 @@ pub async fn preflight_check(
                 .unwrap_or_else(|| "Verification failed.".to_string()),
         ));
     }
+    // 登録の試行は常に記録されるため、IDが存在する
+    let attempt_id = attempt_id.ok_or_else(|| {
+        ServiceError::InternalServerError("Verification attempt was not recorded.".to_string())
+    })?;
 
     // --- 8. Generate JWT with attempt_id ---
     let expiration = Utc::now()
@@ -553,9 +558,13 @@ pub async fn login_with_account_id(
     })?;
 
     // --- 2. アカウントIDでユーザーを検索 ---
+    // 匿名投稿用の共有ユーザーにはログインさせない
+    if payload.account_id == crate::ANONYMOUS_USER_ACCOUNT_ID {
+        return Err(ServiceError::BadRequest("アカウントIDが見つかりません。".to_string()));
+    }
     // 要件通り、emailカラムをアカウントIDとして検索する
     let user = sqlx::query!(
-        "SELECT id FROM users WHERE email = $1",
+        "SELECT id FROM users WHERE email = $1 AND deleted_at IS NULL",
         payload.account_id
     )
         .fetch_optional(pool.get_ref())
@@ -585,10 +594,8 @@ pub async fn login_with_account_id(
             .await?;
     }
 
-    // このユーザーIDに紐づく他のセッションもすべて削除 (1ユーザー1セッションを強制)
-    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
-        .execute(&mut *tx)
-        .await?;
+    // シングルセッションモードの場合、このユーザーIDに紐づく他のセッションをすべて削除
+    revoke_sessions_for_new_login(&mut tx, user_id, is_single_session_mode()).await?;
 
     // 新しいセッショントークンを生成して保存
     let session_token: String = rand::thread_rng()
@@ -636,12 +643,53 @@ pub async fn login_with_account_id(
     Ok(HttpResponse::Ok().cookie(cookie).json(response_body))
 }
 
+/// シングルセッションモードが有効かどうかを返す。
+///
+/// 環境変数 `SINGLE_SESSION_MODE` が `true` の場合、新しいセッションを作成するたびに
+/// 同じユーザーの既存セッションをすべて無効化する (1ユーザー1セッション)。
+/// デフォルトは `false` で、ブラウザと専ブラなど複数の端末で同時にログインしたままにできる。
+/// ログイン (`login_with_account_id`) と連携トークンによる認証 (`authenticate_poster`) の両方がこの設定に従う。
+pub fn is_single_session_mode() -> bool {
+    parse_single_session_mode(std::env::var("SINGLE_SESSION_MODE").ok().as_deref())
+}
+
+/// `SINGLE_SESSION_MODE` の値を解釈します。`true` 以外 (未設定を含む) は無効として扱います。
+fn parse_single_session_mode(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
+/// 新しいセッションを作成する前に、シングルセッションモードであれば同じユーザーの既存セッションをすべて無効化する。
+/// シングルセッションモードでない場合は何もせず、他の端末のセッションを維持する。
+pub async fn revoke_sessions_for_new_login(
+    conn: &mut sqlx::PgConnection,
+    user_id: i32,
+    single_session_mode: bool,
+) -> Result<(), sqlx::Error> {
+    if single_session_mode {
+        revoke_all_sessions(conn, user_id).await?;
+    }
+    Ok(())
+}
+
+/// 指定したユーザーのセッションをすべて削除する。
+pub async fn revoke_all_sessions(
+    conn: &mut sqlx::PgConnection,
+    user_id: i32,
+) -> Result<(), sqlx::Error> {
+    sqlx::query!("DELETE FROM sessions WHERE user_id = $1", user_id)
+        .execute(conn)
+        .await?;
+    Ok(())
+}
+
 // This struct defines the shape of the JSON response for the /me endpoint.
 // It should match the `User` type defined in the frontend's `app.d.ts`.
 #[derive(Serialize)]
 struct UserResponse {
     user_id: i32,
     email: Option<String>,
+    // アカウントIDとは別に登録された連絡用メールアドレス
+    contact_email: Option<String>,
     role: String,
     level: i32,
     is_rate_limit_exempt: bool,
@@ -656,7 +704,7 @@ pub async fn get_me(
 
     // ユーザーの完全な情報を取得
     let user_details = sqlx::query!(
-        r#"SELECT email, is_rate_limit_exempt FROM users WHERE id = $1"#,
+        r#"SELECT email, contact_email, is_rate_limit_exempt FROM users WHERE id = $1"#,
         authenticated_user.user_id
     )
     .fetch_optional(pool.get_ref())
@@ -667,12 +715,111 @@ pub async fn get_me(
         user_id: authenticated_user.user_id,
         // user_details.email にはアカウントIDが入っているが、フィールド名はemailのまま返す
         email: Some(user_details.email),
+        contact_email: user_details.contact_email,
         role: authenticated_user.role.to_string(),
         level: authenticated_user.level,
         is_rate_limit_exempt: user_details.is_rate_limit_exempt,
     }))
 }
 
+/// [認証必須] 連絡用のメールアドレスを設定します。
+/// アカウントID (`email` カラム) とは独立しており、ハッシュ生成には使用しません。
+#[post("/me/contact-email")]
+pub async fn set_contact_email(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    payload: web::Json<models::SetContactEmailRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    payload.validate()?;
+    // nullが指定された場合は登録済みのアドレスを削除する
+    let contact_email = payload.contact_email.as_deref();
+
+    let updated = sqlx::query_scalar!(
+        "UPDATE users SET contact_email = $1, updated_at = NOW() WHERE id = $2 RETURNING contact_email",
+        contact_email,
+        user.user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("User not found".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "contact_email": updated })))
+}
+
+/// アカウント削除時に、匿名化した書き込みの投稿者名として使用する文字列
+const DELETED_USER_AUTHOR_NAME: &str = "削除済みユーザー";
+
+/// [認証必須] 自身のアカウントを削除します。
+///
+/// ユーザーは論理削除され、全てのセッションと専ブラ連携トークンが無効化されるため、以後ログインできなくなります。
+/// 書き込み (スレッド・レス・板) はスレッドの流れを保つために削除せず残します。
+/// `anonymize_content=true` の場合は、書き込みの投稿者名を削除済みユーザーを示す名前に置き換えます。
+/// 削除したアカウントは、板の所有者の移譲先 (`transfer_board_ownership`) にも指定できなくなります。
+#[delete("/me")]
+pub async fn delete_my_account(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<models::DeleteAccountQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    // 管理者が誤って自身を削除し、管理者不在になることを防ぐ
+    if matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Forbidden(
+            "管理者アカウントは削除できません。".to_string(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+        "UPDATE users SET deleted_at = NOW(), contact_email = NULL, updated_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+        user.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::NotFound("User not found".to_string()));
+    }
+
+    revoke_all_sessions(&mut tx, user.user_id).await?;
+    sqlx::query!(
+        "DELETE FROM device_linking_tokens WHERE user_id = $1",
+        user.user_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    if query.anonymize_content.unwrap_or(false) {
+        sqlx::query!(
+            "UPDATE posts SET author_name = $1 WHERE user_id = $2",
+            DELETED_USER_AUTHOR_NAME,
+            user.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query!(
+            "UPDATE comments SET author_name = $1 WHERE user_id = $2",
+            DELETED_USER_AUTHOR_NAME,
+            user.user_id
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    log::info!(
+        "[Auth] User {} deleted their account (anonymize_content: {})",
+        user.user_id,
+        query.anonymize_content.unwrap_or(false)
+    );
+
+    // ブラウザ側のセッションCookieも削除する
+    let mut removal_cookie = Cookie::build("session_token", "").path("/").finish();
+    removal_cookie.make_removal();
+
+    Ok(HttpResponse::NoContent().cookie(removal_cookie).finish())
+}
+
 /// [管理者用] 自身のレート制限免除設定を切り替えます。
 #[post("/me/toggle-rate-limit-exemption")]
 pub async fn toggle_rate_limit_exemption(
@@ -702,36 +849,147 @@ pub async fn toggle_rate_limit_exemption(
     Ok(HttpResponse::Ok().json(updated_user))
 }
 
-/// [認証必須] 専ブラ連携用のトークンを再発行します。
-#[post("/me/regenerate-linking-token")]
-pub async fn regenerate_linking_token(
+/// [管理者用] 指定したユーザーのレート制限免除設定を変更します。
+/// 信頼済みのbotやパワーユーザーなど、管理者以外にも免除を付与するために使用します。
+#[post("/{id}/rate-limit-exemption")]
+pub async fn set_user_rate_limit_exemption(
     pool: web::Data<PgPool>,
     user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+    payload: web::Json<models::SetRateLimitExemptionRequest>,
 ) -> Result<HttpResponse, ServiceError> {
-    const COOLDOWN_SECONDS: i64 = 60;
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Forbidden(
+            "Only admins can change this setting.".to_string(),
+        ));
+    }
+    let target_user_id = path.into_inner();
 
-    // トランザクションを開始し、チェックと更新をアトミックに行う
-    let mut tx = pool.begin().await?;
+    let updated_user = sqlx::query_as!(
+        models::User,
+        r#"
+        UPDATE users SET is_rate_limit_exempt = $1, updated_at = NOW()
+        WHERE id = $2
+        RETURNING id, email, role as "role: _", created_at, level, last_level_up_at, last_level_up_ip, level_up_failure_count, last_level_up_attempt_at, banned_from_level_up, is_rate_limit_exempt, last_linking_token_generated_at
+        "#,
+        payload.is_rate_limit_exempt,
+        target_user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたユーザーが見つかりません。".to_string()))?;
 
-    // ユーザーの最終発行日時を取得
-    let last_generated_at: Option<chrono::DateTime<Utc>> = sqlx::query_scalar!(
-        "SELECT last_linking_token_generated_at FROM users WHERE id = $1",
+    log::info!(
+        "[Admin] Rate limit exemption for user {} set to {} by admin {}",
+        target_user_id,
+        payload.is_rate_limit_exempt,
         user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(updated_user))
+}
+
+#[derive(Serialize)]
+pub struct SessionSummary {
+    pub id: i32,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct UserSessionsResponse {
+    pub user_id: i32,
+    pub active_count: usize,
+    pub sessions: Vec<SessionSummary>,
+}
+
+/// [管理者用] 指定したユーザーの有効なセッション (連携済みの端末) の一覧を返します。
+/// セッショントークンそのものは返しません。
+#[get("/{id}/sessions")]
+pub async fn get_user_sessions(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Forbidden(
+            "管理者権限が必要です。".to_string(),
+        ));
+    }
+    let target_user_id = path.into_inner();
+
+    let user_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM users WHERE id = $1) as "exists!""#,
+        target_user_id
     )
-    .fetch_one(&mut *tx)
+    .fetch_one(pool.get_ref())
     .await?;
+    if !user_exists {
+        return Err(ServiceError::NotFound(
+            "指定されたユーザーが見つかりません。".to_string(),
+        ));
+    }
 
-    // クールダウン期間中かチェック
-    if let Some(last_time) = last_generated_at {
-        let elapsed = Utc::now().signed_duration_since(last_time).num_seconds();
-        if elapsed < COOLDOWN_SECONDS {
-            let remaining = COOLDOWN_SECONDS - elapsed;
-            return Err(ServiceError::TooManyRequests(format!(
-                "トークンを再発行するには、あと {} 秒待つ必要があります。",
-                remaining
-            )));
-        }
+    let sessions = sqlx::query_as!(
+        SessionSummary,
+        r#"
+        SELECT id, created_at, expires_at
+        FROM sessions
+        WHERE user_id = $1 AND expires_at > NOW()
+        ORDER BY created_at DESC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(UserSessionsResponse {
+        user_id: target_user_id,
+        active_count: sessions.len(),
+        sessions,
+    }))
+}
+
+/// [管理者用] 指定したユーザーのセッションをすべて無効化します (全端末で再連携が必要になります)。
+#[delete("/{id}/sessions")]
+pub async fn revoke_user_sessions(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Forbidden(
+            "管理者権限が必要です。".to_string(),
+        ));
     }
+    let target_user_id = path.into_inner();
+
+    let revoked = sqlx::query!("DELETE FROM sessions WHERE user_id = $1", target_user_id)
+        .execute(pool.get_ref())
+        .await?
+        .rows_affected();
+
+    log::info!(
+        "[Admin] Revoked {} sessions of user {} by admin {}",
+        revoked,
+        target_user_id,
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "user_id": target_user_id,
+        "revoked_count": revoked,
+    })))
+}
+
+/// [認証必須] 専ブラ連携用のトークンを再発行します。
+#[post("/me/regenerate-linking-token")]
+pub async fn regenerate_linking_token(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, ServiceError> {
+    // トランザクションを開始し、チェックと更新をアトミックに行う
+    let mut tx = pool.begin().await?;
 
     // 最終発行日時を更新
     sqlx::query!(
@@ -741,7 +999,7 @@ pub async fn regenerate_linking_token(
     .execute(&mut *tx)
     .await?;
 
-    // 新しいトークンを生成してDBに保存
+    // 新しいトークンを生成してDBに保存 (有効なトークン数の上限もここでチェックする)
     let linking_token = generate_and_save_linking_token(&mut tx, user.user_id).await?;
 
     // トランザクションをコミット
@@ -755,12 +1013,97 @@ pub async fn regenerate_linking_token(
     })))
 }
 
+/// ユーザーが同時に保持できる有効な連携トークンの上限数。
+/// 環境変数 `MAX_ACTIVE_LINKING_TOKENS` で変更可能 (デフォルト: 5)。
+fn max_active_linking_tokens() -> i64 {
+    std::env::var("MAX_ACTIVE_LINKING_TOKENS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(5)
+}
+
+#[derive(Serialize)]
+pub struct LinkingTokenSummary {
+    pub id: i32,
+    pub created_at: chrono::DateTime<Utc>,
+    pub expires_at: chrono::DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct LinkingTokensResponse {
+    pub count: usize,
+    pub max_active: i64,
+    pub tokens: Vec<LinkingTokenSummary>,
+}
+
+/// [認証必須] 現在有効な (未使用かつ有効期限内の) 連携トークンの一覧を返します。
+/// トークンの値そのものはハッシュのみ保存しているため返しません。
+#[get("/linking-tokens")]
+pub async fn get_linking_tokens(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, ServiceError> {
+    let tokens = sqlx::query_as!(
+        LinkingTokenSummary,
+        r#"
+        SELECT id, created_at, expires_at
+        FROM device_linking_tokens
+        WHERE user_id = $1 AND used_at IS NULL AND expires_at > NOW()
+        ORDER BY expires_at ASC
+        "#,
+        user.user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(LinkingTokensResponse {
+        count: tokens.len(),
+        max_active: max_active_linking_tokens(),
+        tokens,
+    }))
+}
+
 /// 連携トークンを生成し、ハッシュ化してDBに保存するヘルパー関数。
 /// 生のトークンを返す。
+///
+/// 発行経路 (登録・ログイン・再発行) によらず、有効なトークン数の上限 (`max_active_linking_tokens`) をここでチェックする。
+/// 同一ユーザーの同時発行を直列化するためユーザー行をロックするので、トランザクション内で呼び出すこと。
 async fn generate_and_save_linking_token(
     conn: &mut sqlx::PgConnection,
     user_id: i32,
 ) -> Result<String, ServiceError> {
+    let max_active_tokens = max_active_linking_tokens();
+
+    // 同一ユーザーの同時発行を直列化するため、ユーザー行をロックする
+    sqlx::query!("SELECT id FROM users WHERE id = $1 FOR UPDATE", user_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    // 複数端末の連携用に、未使用かつ有効期限内のトークンは同時に複数保持できる。
+    // ただし無制限な発行を防ぐため、有効なトークン数に上限を設ける。
+    let active = sqlx::query!(
+        r#"
+        SELECT COUNT(*) as "count!", MIN(expires_at) as earliest_expires_at
+        FROM device_linking_tokens
+        WHERE user_id = $1 AND used_at IS NULL AND expires_at > NOW()
+        "#,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if active.count >= max_active_tokens {
+        let remaining = active
+            .earliest_expires_at
+            .map(|t| t.signed_duration_since(Utc::now()).num_seconds().max(1))
+            .unwrap_or(1);
+        return Err(ServiceError::TooManyRequests(format!(
+            "有効な連携トークンが上限 ({} 件) に達しています。あと {} 秒待つか、発行済みのトークンを使用してください。",
+            max_active_tokens, remaining
+        )));
+    }
+
     // 1. 安全な一度きりの専ブラ連携トークンを生成する
     let linking_token: String = rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -786,3 +1129,70 @@ async fn generate_and_save_linking_token(
 
     Ok(linking_token)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+
+    #[test]
+    fn single_session_mode_is_opt_in() {
+        assert!(!parse_single_session_mode(None));
+        assert!(!parse_single_session_mode(Some("")));
+        assert!(!parse_single_session_mode(Some("false")));
+        assert!(!parse_single_session_mode(Some("1")));
+        assert!(parse_single_session_mode(Some("true")));
+        assert!(parse_single_session_mode(Some(" TRUE ")));
+    }
+
+    async fn count_sessions(conn: &mut sqlx::PgConnection, user_id: i32) -> i64 {
+        sqlx::query_scalar("SELECT COUNT(*) FROM sessions WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_one(conn)
+            .await
+            .unwrap()
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    #[tokio::test]
+    #[ignore]
+    async fn new_login_keeps_or_revokes_other_sessions_by_mode() {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = sqlx::PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let user_id: i32 = sqlx::query_scalar("INSERT INTO users (email) VALUES ($1) RETURNING id")
+            .bind(format!(
+                "session-mode-test-{}@example.invalid",
+                Utc::now().timestamp_micros()
+            ))
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        for token in ["browser-session", "client-session"] {
+            sqlx::query(
+                "INSERT INTO sessions (user_id, session_token, expires_at) VALUES ($1, $2, NOW() + INTERVAL '1 day')",
+            )
+            .bind(user_id)
+            .bind(format!("{}-{}", token, user_id))
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+
+        // 複数端末モード (デフォルト): 他の端末のセッションは残る
+        revoke_sessions_for_new_login(&mut tx, user_id, false)
+            .await
+            .unwrap();
+        assert_eq!(count_sessions(&mut tx, user_id).await, 2);
+
+        // シングルセッションモード: 既存のセッションはすべて無効化される
+        revoke_sessions_for_new_login(&mut tx, user_id, true)
+            .await
+            .unwrap();
+        assert_eq!(count_sessions(&mut tx, user_id).await, 0);
+
+        tx.rollback().await.unwrap();
+    }
+}
@@ -1,8 +1,10 @@
 use crate::encryption;
 use crate::errors::ServiceError;
 use crate::middleware::{AuthenticatedUser, Role};
-use crate::models::{self, Ban, BanDetails, BanScope, BanType, Board, CreateBanRequest};
-use actix_web::{delete, get, post, web, HttpResponse};
+use crate::models::{
+    self, Ban, BanDetails, BanReasonTemplate, BanScope, BanType, Board, CreateBanRequest,
+};
+use actix_web::{delete, get, patch, post, web, HttpResponse};
 use serde::Serialize;
 use sqlx::PgPool;
 use validator::Validate;
@@ -22,6 +24,9 @@ pub struct PaginatedBansResponse {
     // フロントエンドの他のAPIと合わせるため、JSONでは "items" というキーで出力
     bans: Vec<BanDetails>,
     total_count: i64,
+    page: i64,
+    limit: i64,
+    total_pages: i64,
 }
 
 /// BAN削除時の権限チェッククエリの結果を保持する一時的な構造体
@@ -137,6 +142,23 @@ pub async fn create_ban(
             ));
         };
 
+    // 板IDが直接指定されている場合、対象スレッドがその板に属しているか確認する。
+    // 不整合なBANを記録すると check_if_banned で一致せず、意図どおりに機能しないため。
+    if let (Some(requested_board_id), Some(post_id)) = (ban_data.board_id, target_post_id) {
+        let post_board_id: Option<i32> =
+            sqlx::query_scalar!("SELECT board_id FROM posts WHERE id = $1", post_id)
+                .fetch_optional(pool.get_ref())
+                .await?
+                .ok_or_else(|| {
+                    ServiceError::NotFound("指定されたスレッドが見つかりません。".to_string())
+                })?;
+        if post_board_id != Some(requested_board_id) {
+            return Err(ServiceError::BadRequest(
+                "指定されたスレッドは、指定された板に属していません。".to_string(),
+            ));
+        }
+    }
+
     // 2. 権限チェック
     let is_admin = matches!(user.role, Role::Admin);
 
@@ -166,15 +188,7 @@ pub async fn create_ban(
                 )
             })?;
             if !is_admin {
-                let is_board_owner: bool = sqlx::query_scalar!(
-                    "SELECT EXISTS(SELECT 1 FROM boards WHERE id = $1 AND created_by = $2)",
-                    board_id,
-                    user.user_id
-                )
-                .fetch_one(pool.get_ref())
-                .await?
-                .unwrap_or(false);
-                if !is_board_owner {
+                if !is_board_owner(pool.get_ref(), board_id, user.user_id).await? {
                     return Err(ServiceError::Forbidden(
                         "この板を管理する権限がありません。".to_string(),
                     ));
@@ -215,7 +229,15 @@ pub async fn create_ban(
                         archived_at as "archived_at: _",
                         moderation_type as "moderation_type: _",
                         last_activity_at,
-                        auto_archive_enabled
+                        auto_archive_enabled,
+                        is_nsfw,
+                        posting_frozen,
+                        members_only,
+                        member_min_level,
+                        require_captcha,
+                        per_board_ids,
+                        default_sort,
+                        unique_titles
                     FROM boards WHERE id = $1
                     "#,
                     board_id
@@ -226,24 +248,19 @@ pub async fn create_ban(
                     ServiceError::NotFound("対象の板が見つかりません。".to_string())
                 })?;
 
-                if board.created_by == Some(user.user_id) {
-                    // 板の所有者なのでOK
-                } else if board.moderation_type == models::BoardModerationType::Beta {
-                    // βタイプの板の場合、スレ主かチェック
-                    let is_thread_creator: bool = sqlx::query_scalar!(
-                        "SELECT EXISTS(SELECT 1 FROM posts WHERE id = $1 AND user_id = $2)",
-                        post_id,
-                        user.user_id
-                    )
-                    .fetch_one(pool.get_ref())
-                    .await?
-                    .unwrap_or(false);
-                    if !is_thread_creator {
-                        return Err(ServiceError::Forbidden(
-                            "このスレッドを管理する権限がありません。".to_string(),
-                        ));
-                    }
-                } else {
+                // 板の所有者か、またはβタイプの板のスレ主であればスレッド内BANが可能
+                let thread_creator_id: Option<i32> =
+                    sqlx::query_scalar!("SELECT user_id FROM posts WHERE id = $1", post_id)
+                        .fetch_optional(pool.get_ref())
+                        .await?
+                        .flatten();
+                let permissions = crate::compute_thread_permissions(
+                    Some(&*user),
+                    board.created_by,
+                    thread_creator_id,
+                    board.moderation_type,
+                );
+                if !permissions.can_ban {
                     return Err(ServiceError::Forbidden(
                         "このスレッドを管理する権限がありません。".to_string(),
                     ));
@@ -298,6 +315,10 @@ pub async fn create_ban(
         _ => None,
     };
 
+    // 定型文が指定されている場合は、定型文と追記を組み合わせてBAN理由とする
+    let reason = resolve_ban_reason(pool.get_ref(), ban_data.template_id, ban_data.reason.as_deref())
+        .await?;
+
     let new_ban = sqlx::query_as!(
         Ban,
         r#"
@@ -310,7 +331,7 @@ pub async fn create_ban(
         hash_to_ban,
         board_id_for_db,
         post_id_for_db,
-        ban_data.reason,
+        reason,
         user.user_id,
         source_post_id,
         source_comment_id,
@@ -324,12 +345,27 @@ pub async fn create_ban(
     Ok(HttpResponse::Created().json(new_ban))
 }
 
+/// 検索条件のハッシュ値を正規化する。前後の空白を除去し、空文字列は未指定として扱う
+fn normalize_hash_filter(value: Option<&str>) -> Option<&str> {
+    value.map(str::trim).filter(|s| !s.is_empty())
+}
+
+/// BANスコープを、データベースの scope カラムに格納されている値に変換する
+fn ban_scope_filter_value(scope: BanScope) -> &'static str {
+    match scope {
+        BanScope::Global => "Global",
+        BanScope::Board => "Board",
+        BanScope::Thread => "Thread",
+    }
+}
+
 // 管理者専用: 全てのBAN情報を取得する
+// hash_value / ban_type / scope を指定すると、条件に一致するBANのみに絞り込む
 #[get("/bans")]
 pub async fn get_admin_bans(
     pool: web::Data<PgPool>,
     user: Option<web::ReqData<AuthenticatedUser>>,
-    query: web::Query<models::PaginationParams>,
+    query: web::Query<models::AdminBanSearchParams>,
 ) -> Result<HttpResponse, ServiceError> {
     // ユーザーが認証されているか手動でチェックし、されていなければUnauthorizedエラーを返す
     let authenticated_user = user.ok_or(ServiceError::Unauthorized)?;
@@ -341,14 +377,33 @@ pub async fn get_admin_bans(
         ));
     }
 
+    // 検索条件。未指定 (NULL) の条件は無視される
+    let hash_value = normalize_hash_filter(query.hash_value.as_deref());
+    let scope = query.scope.map(ban_scope_filter_value);
+
     // BANの総件数を取得
-    let total_count: i64 = sqlx::query_scalar!("SELECT count(*) FROM bans")
-        .fetch_one(pool.get_ref())
-        .await?
-        .unwrap_or(0);
+    let total_count: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT count(*) FROM bans b
+        WHERE ($1::text IS NULL OR b.hash_value = $1)
+          AND ($2::ban_type IS NULL OR b.ban_type = $2)
+          AND (
+            $3::text IS NULL
+            OR ($3 = 'Global' AND b.board_id IS NULL AND b.post_id IS NULL)
+            OR ($3 = 'Board' AND b.board_id IS NOT NULL AND b.post_id IS NULL)
+            OR ($3 = 'Thread' AND b.post_id IS NOT NULL)
+          )
+        "#,
+        hash_value,
+        query.ban_type as _,
+        scope
+    )
+    .fetch_one(pool.get_ref())
+    .await?
+    .unwrap_or(0);
 
     // ページネーションのためのオフセットを計算
-    let offset = (query.page - 1) * query.limit;
+    let offset = models::page_offset(query.page, query.limit);
 
     // N+1問題を解決するため、1回のクエリでBAN情報と関連情報をJOINして取得
     let ban_rows = sqlx::query_as!(
@@ -376,11 +431,22 @@ pub async fn get_admin_bans(
         LEFT JOIN boards bo ON b.board_id = bo.id
         LEFT JOIN posts p ON b.post_id = p.id
         LEFT JOIN users u ON b.created_by = u.id
+        WHERE ($3::text IS NULL OR b.hash_value = $3)
+          AND ($4::ban_type IS NULL OR b.ban_type = $4)
+          AND (
+            $5::text IS NULL
+            OR ($5 = 'Global' AND b.board_id IS NULL AND b.post_id IS NULL)
+            OR ($5 = 'Board' AND b.board_id IS NOT NULL AND b.post_id IS NULL)
+            OR ($5 = 'Thread' AND b.post_id IS NOT NULL)
+          )
         ORDER BY b.created_at DESC
         LIMIT $1 OFFSET $2
         "#,
         query.limit,
-        offset
+        offset,
+        hash_value,
+        query.ban_type as _,
+        scope
     )
     .fetch_all(pool.get_ref())
     .await?;
@@ -388,54 +454,380 @@ pub async fn get_admin_bans(
     // 取得したデータをBanDetailsに変換し、スコープを判定し、個人情報を復号
     let bans: Vec<BanDetails> = ban_rows
         .into_iter()
-        .map(|row| {
-            let (scope, scope_display_name) = if row.post_id.is_some() {
-                ("Thread".to_string(), "スレッド内".to_string())
-            } else if row.board_id.is_some() {
-                ("Board".to_string(), "板内".to_string())
-            } else {
-                ("Global".to_string(), "グローバル".to_string())
-            };
+        .map(|row| admin_ban_row_to_details(row, true))
+        .collect();
 
-            BanDetails {
-                id: row.id,
-                ban_type: row.ban_type,
-                hash_value: row.hash_value,
-                board_id: row.board_id,
-                post_id: row.post_id,
-                board_name: row.board_name,
-                post_title: row.post_title,
-                reason: row.reason,
-                created_by: row.created_by,
-                created_by_email: row.created_by_email,
-                scope,
-                scope_display_name,
-                created_at: row.created_at,
-                expires_at: row.expires_at,
-                source_post_id: row.source_post_id,
-                source_comment_id: row.source_comment_id,
-                source_email: row
-                    .encrypted_source_email
-                    .as_deref()
-                    .and_then(|e| encryption::decrypt(e).ok()),
-                source_ip_address: row
-                    .encrypted_source_ip
-                    .as_deref()
-                    .and_then(|e| encryption::decrypt(e).ok()),
-                source_device_info: row
-                    .encrypted_source_device_info
-                    .as_deref()
-                    .and_then(|e| encryption::decrypt(e).ok()),
-                source_user_id: None, // This field is not populated by AdminBanRow
-            }
-        })
+    let response = PaginatedBansResponse {
+        bans,
+        total_count,
+        page: query.page.max(1),
+        limit: query.limit,
+        total_pages: models::total_pages(total_count, query.limit),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+/// 指定したユーザーが板の作成者 (所有者) かどうかを判定する。
+async fn is_board_owner(pool: &PgPool, board_id: i32, user_id: i32) -> Result<bool, ServiceError> {
+    let is_owner: bool = sqlx::query_scalar!(
+        "SELECT EXISTS(SELECT 1 FROM boards WHERE id = $1 AND created_by = $2)",
+        board_id,
+        user_id
+    )
+    .fetch_one(pool)
+    .await?
+    .unwrap_or(false);
+    Ok(is_owner)
+}
+
+/// 板に紐づくBAN (板BANとスレッドBAN) の一覧を取得する。
+/// 板の作成者と管理者のみ利用可能で、管理者以外には個人情報を除いて返す。グローバルBANは含まない。
+#[get("/{id}/bans")]
+pub async fn get_board_bans(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+    query: web::Query<models::PaginationParams>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.into_inner();
+    let is_admin = matches!(user.role, Role::Admin);
+
+    // create_ban の板BANと同じ権限チェック
+    if !is_admin && !is_board_owner(pool.get_ref(), board_id, user.user_id).await? {
+        return Err(ServiceError::Forbidden(
+            "この板を管理する権限がありません。".to_string(),
+        ));
+    }
+
+    let total_count: i64 = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM bans WHERE board_id = $1",
+        board_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?
+    .unwrap_or(0);
+
+    let offset = models::page_offset(query.page, query.limit);
+
+    let ban_rows = sqlx::query_as!(
+        AdminBanRow,
+        r#"
+        SELECT
+            b.id,
+            b.ban_type as "ban_type: BanType",
+            b.hash_value,
+            b.board_id,
+            b.post_id,
+            bo.name as "board_name?",
+            p.title as "post_title?",
+            b.reason,
+            b.created_by,
+            u.email as "created_by_email?",
+            b.created_at,
+            b.expires_at,
+            b.source_post_id,
+            b.source_comment_id,
+            b.encrypted_source_email,
+            b.encrypted_source_ip,
+            b.encrypted_source_device_info
+        FROM bans b
+        LEFT JOIN boards bo ON b.board_id = bo.id
+        LEFT JOIN posts p ON b.post_id = p.id
+        LEFT JOIN users u ON b.created_by = u.id
+        WHERE b.board_id = $1
+        ORDER BY b.created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        board_id,
+        query.limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let bans: Vec<BanDetails> = ban_rows
+        .into_iter()
+        .map(|row| admin_ban_row_to_details(row, is_admin))
         .collect();
 
-    let response = PaginatedBansResponse { bans, total_count };
+    let response = PaginatedBansResponse {
+        bans,
+        total_count,
+        page: query.page.max(1),
+        limit: query.limit,
+        total_pages: models::total_pages(total_count, query.limit),
+    };
 
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// [管理者用] 指定した投稿 (スレッド本文またはレス) の投稿者に、現在どのBANが適用されるかを返す。
+/// 判定条件は `check_if_banned` と同一で、BANを作成する前に既存のBANの適用範囲を確認するために使用する。
+#[get("/bans/simulate")]
+pub async fn simulate_bans(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<models::BanSourceQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Forbidden(
+            "管理者権限が必要です。".to_string(),
+        ));
+    }
+
+    let target = match (query.post_id, query.comment_id) {
+        (Some(post_id), None) => sqlx::query_as!(
+            TargetHashes,
+            "SELECT id as post_id, board_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash FROM posts WHERE id = $1",
+            post_id,
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| ServiceError::NotFound("指定された投稿が見つかりません。".to_string()))?,
+        (None, Some(comment_id)) => sqlx::query_as!(
+            TargetHashes,
+            r#"
+            SELECT p.board_id, c.post_id, c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash
+            FROM comments c
+            INNER JOIN posts p ON c.post_id = p.id
+            WHERE c.id = $1
+            "#,
+            comment_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| ServiceError::NotFound("指定されたコメントが見つかりません。".to_string()))?,
+        _ => {
+            return Err(ServiceError::BadRequest(
+                "post_id または comment_id のどちらか一方を指定してください。".to_string(),
+            ))
+        }
+    };
+
+    // check_if_banned と同じ条件で、一致するBANを全て取得する
+    let mut conn = pool.acquire().await?;
+    let ban_rows = find_applicable_bans(&mut conn, &target).await?;
+
+    let bans: Vec<BanDetails> = ban_rows
+        .into_iter()
+        .map(|row| admin_ban_row_to_details(row, true))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(bans))
+}
+
+/// 投稿者のハッシュに一致し、その投稿に適用されるBANを全て取得する。
+/// `check_if_banned` と同じ条件で、期限切れの一時BANは含めない。
+async fn find_applicable_bans(
+    conn: &mut sqlx::PgConnection,
+    target: &TargetHashes,
+) -> Result<Vec<AdminBanRow>, ServiceError> {
+    let ban_rows = sqlx::query_as!(
+        AdminBanRow,
+        r#"
+        SELECT
+            b.id,
+            b.ban_type as "ban_type: BanType",
+            b.hash_value,
+            b.board_id,
+            b.post_id,
+            bo.name as "board_name?",
+            p.title as "post_title?",
+            b.reason,
+            b.created_by,
+            u.email as "created_by_email?",
+            b.created_at,
+            b.expires_at,
+            b.source_post_id,
+            b.source_comment_id,
+            b.encrypted_source_email,
+            b.encrypted_source_ip,
+            b.encrypted_source_device_info
+        FROM bans b
+        LEFT JOIN boards bo ON b.board_id = bo.id
+        LEFT JOIN posts p ON b.post_id = p.id
+        LEFT JOIN users u ON b.created_by = u.id
+        WHERE
+            (
+                (b.ban_type = 'user' AND b.hash_value = $3) OR
+                (b.ban_type = 'ip' AND b.hash_value = $4) OR
+                (b.ban_type = 'device' AND b.hash_value = $5)
+            )
+            AND (
+                (b.board_id IS NULL AND b.post_id IS NULL)
+                OR (b.board_id = $1 AND b.post_id IS NULL)
+                OR (b.post_id = $2)
+            )
+            AND (b.expires_at IS NULL OR b.expires_at > NOW())
+        ORDER BY b.created_at DESC
+        "#,
+        target.board_id,
+        target.post_id,
+        target.permanent_user_hash,
+        target.permanent_ip_hash,
+        target.permanent_device_hash
+    )
+    .fetch_all(conn)
+    .await?;
+    Ok(ban_rows)
+}
+
+/// AdminBanRowをBanDetailsに変換する。
+/// `include_pii` が true の場合のみ、暗号化された発生源の個人情報と作成者のアカウントIDを含める。
+fn admin_ban_row_to_details(row: AdminBanRow, include_pii: bool) -> BanDetails {
+    let (scope, scope_display_name) = if row.post_id.is_some() {
+        ("Thread".to_string(), "スレッド内".to_string())
+    } else if row.board_id.is_some() {
+        ("Board".to_string(), "板内".to_string())
+    } else {
+        ("Global".to_string(), "グローバル".to_string())
+    };
+    let decrypt = |data: Option<Vec<u8>>| {
+        data.filter(|_| include_pii)
+            .and_then(|e| encryption::decrypt(&e).ok())
+    };
+
+    BanDetails {
+        id: row.id,
+        ban_type: row.ban_type,
+        hash_value: row.hash_value,
+        board_id: row.board_id,
+        post_id: row.post_id,
+        board_name: row.board_name,
+        post_title: row.post_title,
+        reason: row.reason,
+        created_by: row.created_by,
+        created_by_email: row.created_by_email.filter(|_| include_pii),
+        scope,
+        scope_display_name,
+        created_at: row.created_at,
+        expires_at: row.expires_at,
+        source_post_id: row.source_post_id,
+        source_comment_id: row.source_comment_id,
+        source_email: decrypt(row.encrypted_source_email),
+        source_ip_address: decrypt(row.encrypted_source_ip),
+        source_device_info: decrypt(row.encrypted_source_device_info),
+        source_user_id: None, // This field is not populated by AdminBanRow
+    }
+}
+
+/// 発生源（スレッド本文またはレス）に紐づくBANを取得する。
+/// 管理者以外には、その板のBANのうち自分が作成したものか、このスレッドを対象とするものだけを個人情報を除いて返す。
+#[get("/by-source")]
+pub async fn get_bans_by_source(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<models::BanSourceQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    // 発生源からスレッドと板、モデレーション権限の判定に必要な情報を取得
+    let source = match (query.post_id, query.comment_id) {
+        (Some(post_id), None) => sqlx::query!(
+            r#"
+            SELECT p.id as "thread_id", p.user_id as "thread_creator_id", b.id as "board_id",
+                   b.created_by as "board_creator_id",
+                   b.moderation_type as "moderation_type: models::BoardModerationType"
+            FROM posts p
+            JOIN boards b ON p.board_id = b.id
+            WHERE p.id = $1
+            "#,
+            post_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .map(|r| (r.thread_id, r.thread_creator_id, r.board_id, r.board_creator_id, r.moderation_type)),
+        (None, Some(comment_id)) => sqlx::query!(
+            r#"
+            SELECT p.id as "thread_id", p.user_id as "thread_creator_id", b.id as "board_id",
+                   b.created_by as "board_creator_id",
+                   b.moderation_type as "moderation_type: models::BoardModerationType"
+            FROM comments c
+            JOIN posts p ON c.post_id = p.id
+            JOIN boards b ON p.board_id = b.id
+            WHERE c.id = $1
+            "#,
+            comment_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .map(|r| (r.thread_id, r.thread_creator_id, r.board_id, r.board_creator_id, r.moderation_type)),
+        _ => {
+            return Err(ServiceError::BadRequest(
+                "post_id または comment_id のどちらか一方を指定してください。".to_string(),
+            ))
+        }
+    };
+    let (thread_id, thread_creator_id, board_id, board_creator_id, moderation_type) = source
+        .ok_or_else(|| ServiceError::NotFound("指定された投稿が見つかりません。".to_string()))?;
+
+    let is_admin = matches!(user.role, Role::Admin);
+    let can_moderate = crate::compute_thread_permissions(
+        Some(&*user),
+        board_creator_id,
+        thread_creator_id,
+        moderation_type,
+    )
+    .can_moderate;
+    if !can_moderate {
+        return Err(ServiceError::Forbidden(
+            "このスレッドを管理する権限がありません。".to_string(),
+        ));
+    }
+
+    // 管理者以外はグローバルBANや他板のBANを閲覧できない。
+    // さらに、自分が作成したBANか、このスレッドを対象とするBANのみに限定する
+    // (板内の他のモデレーターが作成した板BANなどは見せない)
+    let restrict_board_id = if is_admin { None } else { Some(board_id) };
+    let restrict_creator_id = if is_admin { None } else { Some(user.user_id) };
+
+    let ban_rows = sqlx::query_as!(
+        AdminBanRow,
+        r#"
+        SELECT
+            b.id,
+            b.ban_type as "ban_type: BanType",
+            b.hash_value,
+            b.board_id,
+            b.post_id,
+            bo.name as "board_name?",
+            p.title as "post_title?",
+            b.reason,
+            b.created_by,
+            u.email as "created_by_email?",
+            b.created_at,
+            b.expires_at,
+            b.source_post_id,
+            b.source_comment_id,
+            b.encrypted_source_email,
+            b.encrypted_source_ip,
+            b.encrypted_source_device_info
+        FROM bans b
+        LEFT JOIN boards bo ON b.board_id = bo.id
+        LEFT JOIN posts p ON b.post_id = p.id
+        LEFT JOIN users u ON b.created_by = u.id
+        WHERE (($1::int IS NOT NULL AND b.source_post_id = $1)
+            OR ($2::int IS NOT NULL AND b.source_comment_id = $2))
+          AND ($3::int IS NULL OR b.board_id = $3)
+          AND ($4::int IS NULL OR b.created_by = $4 OR b.post_id = $5)
+        ORDER BY b.created_at DESC
+        "#,
+        query.post_id,
+        query.comment_id,
+        restrict_board_id,
+        restrict_creator_id,
+        thread_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let bans: Vec<BanDetails> = ban_rows
+        .into_iter()
+        .map(|row| admin_ban_row_to_details(row, is_admin))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(bans))
+}
+
 // get_bans のための、個人情報を含まない一時的な構造体
 #[derive(sqlx::FromRow)]
 struct MyBanRow {
@@ -486,7 +878,7 @@ pub async fn get_bans(
     .unwrap_or(0);
 
     // ページネーションのためのオフセットを計算
-    let offset = (query.page - 1) * query.limit;
+    let offset = models::page_offset(query.page, query.limit);
 
     // このエンドポイントは、ログインしているユーザーが作成したBANのみを返す。
     let ban_rows = sqlx::query_as!(
@@ -570,7 +962,13 @@ pub async fn get_bans(
         authenticated_user.user_id
     );
 
-    let response = PaginatedBansResponse { bans, total_count };
+    let response = PaginatedBansResponse {
+        bans,
+        total_count,
+        page: query.page.max(1),
+        limit: query.limit,
+        total_pages: models::total_pages(total_count, query.limit),
+    };
 
     Ok(HttpResponse::Ok().json(response))
 }
@@ -626,6 +1024,218 @@ pub async fn delete_ban(
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// BAN理由の最大文字数 (bans.reason の列の長さ)
+const MAX_BAN_REASON_LENGTH: usize = 255;
+
+/// BAN作成時の理由を決定する。
+/// 定型文IDが指定されている場合は定型文の理由を使い、自由記述があれば末尾に追記する。
+/// 組み合わせた結果が上限を超える場合は BadRequest を返す。
+async fn resolve_ban_reason(
+    pool: &PgPool,
+    template_id: Option<i32>,
+    free_text: Option<&str>,
+) -> Result<Option<String>, ServiceError> {
+    let free_text = free_text.map(str::trim).filter(|s| !s.is_empty());
+    let Some(template_id) = template_id else {
+        return Ok(free_text.map(str::to_owned));
+    };
+
+    let template_reason: String = sqlx::query_scalar!(
+        "SELECT reason FROM ban_reason_templates WHERE id = $1",
+        template_id
+    )
+    .fetch_optional(pool)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたBAN理由の定型文が見つかりません。".to_string()))?;
+
+    let reason = match free_text {
+        Some(text) => format!("{} {}", template_reason, text),
+        None => template_reason,
+    };
+    if reason.chars().count() > MAX_BAN_REASON_LENGTH {
+        return Err(ServiceError::BadRequest(format!(
+            "BAN理由は定型文と追記を合わせて{}文字以内にしてください。",
+            MAX_BAN_REASON_LENGTH
+        )));
+    }
+    Ok(Some(reason))
+}
+
+/// [認証必須] BAN理由の定型文一覧を取得します。
+/// 定型文は管理者が管理し、管理者と板作成者がBAN作成時に利用します。
+#[get("/reason-templates")]
+pub async fn get_ban_reason_templates(
+    pool: web::Data<PgPool>,
+    _user: web::ReqData<AuthenticatedUser>,
+) -> Result<HttpResponse, ServiceError> {
+    let templates = sqlx::query_as!(
+        BanReasonTemplate,
+        "SELECT id, name, reason, created_by, created_at, updated_at FROM ban_reason_templates ORDER BY id ASC"
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(templates))
+}
+
+/// [管理者用] BAN理由の定型文を作成します。
+#[post("/ban-reason-templates")]
+pub async fn create_ban_reason_template(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    payload: web::Json<models::CreateBanReasonTemplateRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    payload.validate()?;
+
+    let template = sqlx::query_as!(
+        BanReasonTemplate,
+        r#"
+        INSERT INTO ban_reason_templates (name, reason, created_by) VALUES ($1, $2, $3)
+        RETURNING id, name, reason, created_by, created_at, updated_at
+        "#,
+        payload.name.trim(),
+        payload.reason.trim(),
+        user.user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Created().json(template))
+}
+
+/// [管理者用] BAN理由の定型文を更新します。
+/// 既存のBANの理由は作成時にコピーされているため、更新しても変わりません。
+#[patch("/ban-reason-templates/{id}")]
+pub async fn update_ban_reason_template(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+    payload: web::Json<models::UpdateBanReasonTemplateRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    payload.validate()?;
+
+    let template = sqlx::query_as!(
+        BanReasonTemplate,
+        r#"
+        UPDATE ban_reason_templates
+        SET name = COALESCE($1, name), reason = COALESCE($2, reason), updated_at = NOW()
+        WHERE id = $3
+        RETURNING id, name, reason, created_by, created_at, updated_at
+        "#,
+        payload.name.as_deref().map(str::trim),
+        payload.reason.as_deref().map(str::trim),
+        path.into_inner()
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたBAN理由の定型文が見つかりません。".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(template))
+}
+
+/// [管理者用] BAN理由の定型文を削除します。
+#[delete("/ban-reason-templates/{id}")]
+pub async fn delete_ban_reason_template(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let result = sqlx::query!(
+        "DELETE FROM ban_reason_templates WHERE id = $1",
+        path.into_inner()
+    )
+    .execute(pool.get_ref())
+    .await?;
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::NotFound(
+            "指定されたBAN理由の定型文が見つかりません。".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// [管理者用] 同じ発生源 (スレッド本文・レス、またはその投稿者) から作成されたBANをまとめて解除します。
+/// 誤ったBANを一括で取り消すために使用し、解除した件数を返します。
+#[delete("/bans/by-source")]
+pub async fn delete_bans_by_source(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<models::BulkUnbanBySourceQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let specified = [
+        query.source_post_id.is_some(),
+        query.source_comment_id.is_some(),
+        query.user_id.is_some(),
+    ]
+    .into_iter()
+    .filter(|s| *s)
+    .count();
+    if specified != 1 {
+        return Err(ServiceError::BadRequest(
+            "source_post_id、source_comment_id、user_id のいずれか1つを指定してください。"
+                .to_string(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    let deleted_ids: Vec<i32> = sqlx::query_scalar!(
+        r#"
+        DELETE FROM bans
+        WHERE source_post_id = $1
+           OR source_comment_id = $2
+           OR source_post_id IN (SELECT id FROM posts WHERE user_id = $3)
+           OR source_comment_id IN (SELECT id FROM comments WHERE user_id = $3)
+        RETURNING id
+        "#,
+        query.source_post_id,
+        query.source_comment_id,
+        query.user_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO moderation_logs (actor_user_id, action, details) VALUES ($1, 'bulk_unban_by_source', $2)",
+        user.user_id,
+        serde_json::json!({
+            "source_post_id": query.source_post_id,
+            "source_comment_id": query.source_comment_id,
+            "user_id": query.user_id,
+            "deleted_ban_ids": deleted_ids,
+        })
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "[Admin] Bulk-deleted {} bans by source by admin {}",
+        deleted_ids.len(),
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "deleted_count": deleted_ids.len(),
+    })))
+}
+
 /// Checks if a user is banned from posting on a specific board.
 ///
 /// This function checks for both board-specific and global bans based on the
@@ -667,6 +1277,8 @@ pub async fn check_if_banned(
                     OR (board_id = $1 AND post_id IS NULL)    -- Board Ban
                     OR (post_id = $2)                       -- Thread Ban
                 )
+                -- Ignore temporary bans that have expired
+                AND (expires_at IS NULL OR expires_at > NOW())
         )
         "#,
         board_id,
@@ -680,8 +1292,87 @@ pub async fn check_if_banned(
     .unwrap_or(false);
 
     if is_banned {
-        Err(ServiceError::Forbidden("".to_string()))
+        Err(ServiceError::posting_restricted())
     } else {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Connection, PgConnection};
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    #[tokio::test]
+    #[ignore]
+    async fn applicable_bans_exclude_expired_bans() {
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let admin_id: i32 =
+            sqlx::query_scalar("INSERT INTO users (email) VALUES ($1) RETURNING id")
+                .bind(format!(
+                    "simulate-bans-test-{}@example.invalid",
+                    chrono::Utc::now().timestamp_micros()
+                ))
+                .fetch_one(&mut *tx)
+                .await
+                .unwrap();
+        let suffix = chrono::Utc::now().timestamp_micros();
+        let user_hash = format!("simulate-user-{}", suffix);
+        let ip_hash = format!("simulate-ip-{}", suffix);
+        // ユーザーBANは有効、IP BANは期限切れ
+        let active_id: i32 = sqlx::query_scalar(
+            "INSERT INTO bans (ban_type, hash_value, created_by, expires_at) VALUES ('user', $1, $2, NOW() + INTERVAL '1 day') RETURNING id",
+        )
+        .bind(&user_hash)
+        .bind(admin_id)
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        sqlx::query(
+            "INSERT INTO bans (ban_type, hash_value, created_by, expires_at) VALUES ('ip', $1, $2, NOW() - INTERVAL '1 day')",
+        )
+        .bind(&ip_hash)
+        .bind(admin_id)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        let target = TargetHashes {
+            board_id: None,
+            post_id: None,
+            permanent_user_hash: Some(user_hash),
+            permanent_ip_hash: Some(ip_hash),
+            permanent_device_hash: None,
+        };
+        let bans = find_applicable_bans(&mut tx, &target).await.unwrap();
+        let ids: Vec<i32> = bans.iter().map(|b| b.id).collect();
+        assert_eq!(ids, vec![active_id]);
+
+        tx.rollback().await.unwrap();
+    }
+
+    #[test]
+    fn normalize_hash_filter_trims_whitespace() {
+        assert_eq!(normalize_hash_filter(Some("  abc123  ")), Some("abc123"));
+        assert_eq!(normalize_hash_filter(Some("abc123")), Some("abc123"));
+    }
+
+    #[test]
+    fn normalize_hash_filter_treats_blank_as_unspecified() {
+        assert_eq!(normalize_hash_filter(None), None);
+        assert_eq!(normalize_hash_filter(Some("")), None);
+        assert_eq!(normalize_hash_filter(Some("   ")), None);
+    }
+
+    #[test]
+    fn ban_scope_filter_value_matches_stored_values() {
+        assert_eq!(ban_scope_filter_value(BanScope::Global), "Global");
+        assert_eq!(ban_scope_filter_value(BanScope::Board), "Board");
+        assert_eq!(ban_scope_filter_value(BanScope::Thread), "Thread");
+    }
+}
@@ -5,6 +5,7 @@ use derive_more::Display;
 use log;
 use serde_json;
 use sqlx::Error as SqlxError;
+use std::env;
 use validator::ValidationErrors;
 
 #[derive(Debug, Display)]
@@ -30,9 +31,49 @@ pub enum ServiceError {
 
     #[display(fmt = "Input validation failed")]
     ValidationFailed(ValidationErrors),
+
+    // 過去ログ化されたスレッドへの書き込み。専ブラが「過去ログ」として扱えるよう、
+    // エラーコードと `X-Thread-State: archived` ヘッダーを付けて返す
+    #[display(fmt = "BadRequest: {}", _0)]
+    ThreadArchived(String),
     // 他のエラーケース
 }
 
+/// 過去ログ化されたスレッドへの書き込みに返すステータスコード。
+/// 環境変数 `ARCHIVED_THREAD_RESPONSE_STATUS` で4xxの任意のコード (例: 410) に変更できる (デフォルト: 400)。
+fn archived_thread_status() -> StatusCode {
+    archived_thread_status_from(|key| env::var(key).ok())
+}
+
+/// `archived_thread_status` の本体。環境変数の取得方法を差し替えられます。
+/// 数値でない値や4xx以外のコードが設定されている場合はデフォルト (400) を使います。
+fn archived_thread_status_from(lookup: impl Fn(&str) -> Option<String>) -> StatusCode {
+    lookup("ARCHIVED_THREAD_RESPONSE_STATUS")
+        .and_then(|v| v.parse::<u16>().ok())
+        .and_then(|code| StatusCode::from_u16(code).ok())
+        .filter(|status| status.is_client_error())
+        .unwrap_or(StatusCode::BAD_REQUEST)
+}
+
+/// 書き込みを拒否する際に、理由 (BANの有無や対象など) を明かさずに返す汎用メッセージのデフォルト値
+const DEFAULT_POSTING_RESTRICTED_MESSAGE: &str = "書き込みが制限されています。";
+
+impl ServiceError {
+    /// BANや予約文字の使用などで書き込みを拒否する際の `Forbidden` エラーを作成します。
+    /// メッセージは環境変数 `POSTING_RESTRICTED_MESSAGE` で変更できますが、拒否の具体的な理由は含めません。
+    pub fn posting_restricted() -> Self {
+        ServiceError::Forbidden(posting_restricted_message_from(|key| env::var(key).ok()))
+    }
+}
+
+/// `posting_restricted` のメッセージを、環境変数の取得方法を差し替えられる形で決定します。
+/// 未設定または空白のみの場合はデフォルトのメッセージを使います。
+fn posting_restricted_message_from(lookup: impl Fn(&str) -> Option<String>) -> String {
+    lookup("POSTING_RESTRICTED_MESSAGE")
+        .filter(|m| !m.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_POSTING_RESTRICTED_MESSAGE.to_string())
+}
+
 impl ResponseError for ServiceError {
     fn status_code(&self) -> StatusCode {
         match *self {
@@ -43,6 +84,7 @@ impl ResponseError for ServiceError {
             ServiceError::Forbidden(_) => StatusCode::FORBIDDEN,
             ServiceError::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
             ServiceError::ValidationFailed(_) => StatusCode::BAD_REQUEST,
+            ServiceError::ThreadArchived(_) => archived_thread_status(),
         }
     }
 
@@ -76,6 +118,12 @@ impl ResponseError for ServiceError {
                     "details": details
                 }))
             }
+            ServiceError::ThreadArchived(_) => HttpResponse::build(status)
+                .insert_header(("X-Thread-State", "archived"))
+                .json(serde_json::json!({
+                    "error": self.to_string(),
+                    "code": "thread_archived",
+                })),
             _ => HttpResponse::build(status).json(serde_json::json!({
                 "error": self.to_string()
             })),
@@ -137,3 +185,53 @@ impl From<serde_json::Error> for ServiceError {
         ))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn posting_restricted_message_uses_default_when_unset_or_blank() {
+        assert_eq!(
+            posting_restricted_message_from(|_| None),
+            DEFAULT_POSTING_RESTRICTED_MESSAGE
+        );
+        assert_eq!(
+            posting_restricted_message_from(|_| Some("  ".to_string())),
+            DEFAULT_POSTING_RESTRICTED_MESSAGE
+        );
+        assert_eq!(
+            posting_restricted_message_from(|_| Some("規制中です。".to_string())),
+            "規制中です。"
+        );
+    }
+
+    #[test]
+    fn posting_restricted_is_forbidden() {
+        let error = ServiceError::posting_restricted();
+        assert!(matches!(error, ServiceError::Forbidden(_)));
+        assert_eq!(error.status_code(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn archived_thread_status_accepts_only_client_errors() {
+        let status = |value: Option<&str>| archived_thread_status_from(|_| value.map(String::from));
+        assert_eq!(status(None), StatusCode::BAD_REQUEST);
+        assert_eq!(status(Some("410")), StatusCode::GONE);
+        assert_eq!(status(Some("404")), StatusCode::NOT_FOUND);
+        assert_eq!(status(Some("500")), StatusCode::BAD_REQUEST);
+        assert_eq!(status(Some("200")), StatusCode::BAD_REQUEST);
+        assert_eq!(status(Some("gone")), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn thread_archived_response_has_code_and_header() {
+        let error = ServiceError::ThreadArchived("過去ログです。".to_string());
+        let response = error.error_response();
+        assert_eq!(
+            response.headers().get("X-Thread-State").unwrap(),
+            "archived"
+        );
+        assert!(response.status().is_client_error());
+    }
+}
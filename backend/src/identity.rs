@@ -11,6 +11,54 @@ use base62;
 // HMAC-SHA256の型エイリアスを定義
 type HmacSha256 = Hmac<Sha256>;
 
+// --- 表示IDのフォーマット ---
+// 表示IDは `{ユーザー部}-{IP部}-{デバイス部}` の形式で、各部分はbase62文字 (0-9, A-Z, a-z) で構成されます。
+// 各部分の長さは環境変数で変更できますが、過去に生成されたIDは異なる長さ (旧フォーマットは4文字) で
+// 保存されているため、ID検索は前方一致で新旧両方の長さに対応しています (user_history.rs を参照)。
+
+/// ユーザー部の既定の長さ
+const DEFAULT_USER_PART_LENGTH: usize = 8;
+/// IP部の既定の長さ
+const DEFAULT_IP_PART_LENGTH: usize = 4;
+/// デバイス部の既定の長さ
+const DEFAULT_DEVICE_PART_LENGTH: usize = 4;
+/// 旧フォーマットで使われていた各部分の長さ。これより短いIDは存在しない。
+pub const LEGACY_DISPLAY_ID_PART_LENGTH: usize = 4;
+/// 各部分の最大の長さ。保存先の `display_id_user` などのカラムが VARCHAR(8) のため、これより長くはできない。
+const MAX_DISPLAY_ID_PART_LENGTH: usize = 8;
+/// u128をbase62エンコードした際の最大文字数
+const BASE62_U128_WIDTH: usize = 22;
+
+/// 表示IDの各部分の長さ設定
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayIdLengths {
+    pub user_part: usize,
+    pub ip_part: usize,
+    pub device_part: usize,
+}
+
+/// 環境変数から表示IDの各部分の長さを読み込みます。
+/// 未設定または不正な値の場合は既定値を使用し、生成可能な範囲に丸めます。
+pub fn display_id_lengths() -> DisplayIdLengths {
+    display_id_lengths_from(|key| env::var(key).ok())
+}
+
+/// `display_id_lengths` の本体。値の取得元を差し替えられるように分けています。
+fn display_id_lengths_from(lookup: impl Fn(&str) -> Option<String>) -> DisplayIdLengths {
+    let read_length = |key: &str, default: usize| -> usize {
+        lookup(key)
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(default)
+            .clamp(LEGACY_DISPLAY_ID_PART_LENGTH, MAX_DISPLAY_ID_PART_LENGTH)
+    };
+
+    DisplayIdLengths {
+        user_part: read_length("DISPLAY_ID_USER_PART_LENGTH", DEFAULT_USER_PART_LENGTH),
+        ip_part: read_length("DISPLAY_ID_IP_PART_LENGTH", DEFAULT_IP_PART_LENGTH),
+        device_part: read_length("DISPLAY_ID_DEVICE_PART_LENGTH", DEFAULT_DEVICE_PART_LENGTH),
+    }
+}
+
 /// 生成された各種ハッシュを保持するための構造体
 #[derive(Debug)]
 pub struct IdentityHashes {
@@ -23,6 +71,14 @@ pub struct IdentityHashes {
     pub permanent_device_hash: String,
 }
 
+/// 切り詰め済みのIPアドレスから、BANに使われる永続IPハッシュを生成します。
+/// `generate_identity_hashes` が返す `permanent_ip_hash` と同じ値になります。
+pub fn permanent_ip_hash(ip_address: &str) -> String {
+    let permanent_salt =
+        env::var("PERMANENT_HASH_SALT").expect("PERMANENT_HASH_SALT must be set in .env file");
+    create_hmac_hash(permanent_salt.as_bytes(), ip_address)
+}
+
 /// HMAC-SHA256ハッシュを生成するヘルパー関数
 fn create_hmac_hash(key: &[u8], data: &str) -> String {
     let mut mac = HmacSha256::new_from_slice(key).expect("HMAC can take key of any size");
@@ -41,11 +97,17 @@ fn create_base62_id_part(key: &[u8], data: &str, length: usize) -> String {
     bytes.copy_from_slice(&hash_result[..16]);
     let num = u128::from_be_bytes(bytes);
 
-    // u128数値をbase62文字列にエンコード
-    let encoded = base62::encode(num);
+    // u128数値をbase62文字列にエンコードし、指定された文字数を切り出して返す
+    encode_base62_fixed_width(num)
+        .chars()
+        .take(length)
+        .collect()
+}
 
-    // 指定された文字数を切り出して返す
-    encoded.chars().take(length).collect()
+/// u128数値を、常に同じ文字数になるよう先頭を '0' で埋めてbase62エンコードします。
+/// `base62::encode` は値が小さいと短い文字列を返すため、そのまま切り出すとIDの長さが揃わなくなります。
+fn encode_base62_fixed_width(num: u128) -> String {
+    format!("{:0>width$}", base62::encode(num), width = BASE62_U128_WIDTH)
 }
 
 /// ユーザー情報、IP、デバイス情報から日替わりIDと永続ハッシュを生成します。
@@ -53,6 +115,18 @@ pub fn generate_identity_hashes(
     user_identifier: &str, // ユーザーを永続的に識別する情報 (例: email)
     ip_address: &str,
     device_info: &str, // User-Agent やブラウザフィンガープリント
+) -> IdentityHashes {
+    generate_identity_hashes_for_board(user_identifier, ip_address, device_info, None)
+}
+
+/// `generate_identity_hashes` と同様ですが、`board_id` を指定すると日替わりIDに板IDを含め、
+/// 同じユーザーでも板ごとに異なる表示IDを生成します (板の `per_board_ids` が有効な場合)。
+/// BANに使われる永続ハッシュは板によらず同じです。
+pub fn generate_identity_hashes_for_board(
+    user_identifier: &str,
+    ip_address: &str,
+    device_info: &str,
+    board_id: Option<i32>,
 ) -> IdentityHashes {
     // --- 1. 永続ハッシュの生成 (HMACを使用) ---
     // BANに使われる、時間で変化しないハッシュ。専用のソルト（ペッパー）を使用します。
@@ -76,14 +150,24 @@ pub fn generate_identity_hashes(
         .format("%Y-%m-%d")
         .to_string();
 
+    // 板ごとのIDの場合は、日付に板IDを加えたものを日替わりの要素として使います
+    let today = match board_id {
+        Some(board_id) => format!("{}-board{}", today, board_id),
+        None => today,
+    };
+
     // HMACのメッセージ部分を作成します (キーとしてソルトを使うため、データにソルトを含める必要はありません)
     let daily_user_data = format!("{}-{}", user_identifier, &today);
     let daily_ip_data = format!("{}-{}", ip_address, &today);
     let daily_device_data = format!("{}-{}", device_info, &today);
 
-    let display_id_user_part = create_base62_id_part(daily_salt_bytes, &daily_user_data, 8);
-    let display_id_ip_part = create_base62_id_part(daily_salt_bytes, &daily_ip_data, 4);
-    let display_id_device_part = create_base62_id_part(daily_salt_bytes, &daily_device_data, 4);
+    let lengths = display_id_lengths();
+    let display_id_user_part =
+        create_base62_id_part(daily_salt_bytes, &daily_user_data, lengths.user_part);
+    let display_id_ip_part =
+        create_base62_id_part(daily_salt_bytes, &daily_ip_data, lengths.ip_part);
+    let display_id_device_part =
+        create_base62_id_part(daily_salt_bytes, &daily_device_data, lengths.device_part);
 
     let display_user_id = format!(
         "{}-{}-{}",
@@ -100,3 +184,96 @@ pub fn generate_identity_hashes(
         permanent_device_hash,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn display_id_lengths_use_defaults_when_unset_or_invalid() {
+        let defaults = DisplayIdLengths {
+            user_part: DEFAULT_USER_PART_LENGTH,
+            ip_part: DEFAULT_IP_PART_LENGTH,
+            device_part: DEFAULT_DEVICE_PART_LENGTH,
+        };
+        assert_eq!(display_id_lengths_from(|_| None), defaults);
+        assert_eq!(
+            display_id_lengths_from(|_| Some("abc".to_string())),
+            defaults
+        );
+    }
+
+    #[test]
+    fn display_id_lengths_are_clamped_to_generatable_range() {
+        let lengths = display_id_lengths_from(|key| match key {
+            "DISPLAY_ID_USER_PART_LENGTH" => Some("100".to_string()),
+            "DISPLAY_ID_IP_PART_LENGTH" => Some("1".to_string()),
+            "DISPLAY_ID_DEVICE_PART_LENGTH" => Some("6".to_string()),
+            _ => None,
+        });
+        assert_eq!(lengths.user_part, MAX_DISPLAY_ID_PART_LENGTH);
+        assert_eq!(lengths.ip_part, LEGACY_DISPLAY_ID_PART_LENGTH);
+        assert_eq!(lengths.device_part, 6);
+    }
+
+    #[test]
+    fn base62_encoding_is_padded_to_fixed_width() {
+        assert_eq!(encode_base62_fixed_width(0), "0".repeat(BASE62_U128_WIDTH));
+        assert_eq!(encode_base62_fixed_width(61).len(), BASE62_U128_WIDTH);
+        assert!(encode_base62_fixed_width(61).ends_with('z'));
+        assert_eq!(
+            encode_base62_fixed_width(u128::MAX).len(),
+            BASE62_U128_WIDTH
+        );
+    }
+
+    #[test]
+    fn generated_id_parts_have_configured_length() {
+        for length in LEGACY_DISPLAY_ID_PART_LENGTH..=MAX_DISPLAY_ID_PART_LENGTH {
+            for i in 0..500 {
+                let part = create_base62_id_part(b"salt", &format!("user{}-2025-01-01", i), length);
+                assert_eq!(part.chars().count(), length);
+                assert!(part.chars().all(|c| c.is_ascii_alphanumeric()));
+            }
+        }
+    }
+
+    fn set_test_salts() {
+        if std::env::var("PERMANENT_HASH_SALT").is_err() {
+            std::env::set_var("PERMANENT_HASH_SALT", "test-permanent-salt");
+        }
+        if std::env::var("USER_ID_SALT").is_err() {
+            std::env::set_var("USER_ID_SALT", "test-user-id-salt");
+        }
+    }
+
+    #[test]
+    fn per_board_ids_differ_between_boards_but_keep_permanent_hashes() {
+        set_test_salts();
+        let board_1 = generate_identity_hashes_for_board("account", "192.0.2.1", "device", Some(1));
+        let board_2 = generate_identity_hashes_for_board("account", "192.0.2.1", "device", Some(2));
+        let same_board =
+            generate_identity_hashes_for_board("account", "192.0.2.1", "device", Some(1));
+
+        assert_ne!(board_1.display_user_id, board_2.display_user_id);
+        assert_eq!(board_1.display_user_id, same_board.display_user_id);
+        assert_eq!(board_1.permanent_user_hash, board_2.permanent_user_hash);
+        assert_eq!(board_1.permanent_ip_hash, board_2.permanent_ip_hash);
+        assert_eq!(board_1.permanent_device_hash, board_2.permanent_device_hash);
+    }
+
+    #[test]
+    fn shared_ids_do_not_depend_on_board() {
+        set_test_salts();
+        let shared = generate_identity_hashes("account", "192.0.2.1", "device");
+        let per_board =
+            generate_identity_hashes_for_board("account", "192.0.2.1", "device", Some(1));
+        assert_ne!(shared.display_user_id, per_board.display_user_id);
+        assert_eq!(shared.permanent_user_hash, per_board.permanent_user_hash);
+        assert_eq!(
+            shared.display_user_id,
+            generate_identity_hashes_for_board("account", "192.0.2.1", "device", None)
+                .display_user_id
+        );
+    }
+}
@@ -2,13 +2,13 @@ use actix_web::{get, post, web, HttpRequest, HttpResponse};
 use chrono::{Duration, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, QueryBuilder};
 
 use crate::{
     bans, get_ip_address,
     errors::ServiceError,
     identity,
-    middleware::AuthenticatedUser,
+    middleware::{AuthenticatedUser, Role},
     models,
     users,
     verification::{self, VerificationInput, VerificationType},
@@ -123,6 +123,196 @@ pub async fn get_status(
     Ok(HttpResponse::Ok().json(status))
 }
 
+/// ログイン中のユーザー自身の認証・レベルアップ履歴を取得する。
+/// VPN等による失敗の原因をユーザー自身が確認できるよう、成否と失敗理由のみを返す。
+#[get("/me/verification-history")]
+pub async fn get_my_verification_history(
+    pool: web::Data<PgPool>,
+    user: Option<web::ReqData<AuthenticatedUser>>,
+    query: web::Query<models::PaginationParams>,
+) -> Result<HttpResponse, ServiceError> {
+    let user = user.ok_or(ServiceError::Unauthorized)?;
+
+    let page = query.page.max(1);
+    let limit = query.limit.clamp(1, 100);
+    let offset = models::page_offset(page, limit);
+
+    let total_count: i64 = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM level_up_attempts WHERE user_id = $1",
+        user.user_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?
+    .unwrap_or(0);
+
+    let items = sqlx::query_as!(
+        models::MyVerificationHistoryItem,
+        r#"
+        SELECT id, attempt_type, is_success, created_at, rejection_reason
+        FROM level_up_attempts
+        WHERE user_id = $1
+        ORDER BY created_at DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        user.user_id,
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(models::PagedResponse::new(
+        items,
+        total_count,
+        page,
+        limit,
+    )))
+}
+
+/// 失敗した認証試行の検索条件をクエリに追加します。
+fn push_failed_verification_filters(
+    builder: &mut QueryBuilder<Postgres>,
+    query: &models::FailedVerificationSearchQuery,
+) {
+    builder.push(" WHERE is_success = false");
+    if let Some(attempt_type) = query.attempt_type.as_deref().filter(|s| !s.is_empty()) {
+        builder.push(" AND attempt_type = ").push_bind(attempt_type.to_string());
+    }
+    if let Some(reason) = query.reason.as_deref().map(str::trim).filter(|s| !s.is_empty()) {
+        builder
+            .push(" AND rejection_reason ILIKE ")
+            .push_bind(format!("%{}%", reason));
+    }
+    if let Some(from) = query.from {
+        builder.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = query.to {
+        builder.push(" AND created_at < ").push_bind(to);
+    }
+}
+
+/// [管理者用] 保存済みの認証試行のIPとフィンガープリントを使い、フィンガープリントとproxycheckの判定を
+/// 現在の設定・データで再実行して、今ならどう判定されるかを返します (しきい値の調整用)。
+/// 読み取り専用で、新しい試行は保存しません。Captchaやレベルアップ制限は再評価の対象外です。
+#[post("/verifications/{id}/replay")]
+pub async fn replay_verification(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    http_client: web::Data<reqwest::Client>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    let attempt_id = path.into_inner();
+
+    let attempt = sqlx::query!(
+        r#"
+        SELECT id, attempt_type, is_success, ip_address, fingerprint_json, rejection_reason
+        FROM level_up_attempts WHERE id = $1
+        "#,
+        attempt_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された認証試行が見つかりません。".to_string()))?;
+
+    let verification_type = VerificationType::from_attempt_type(&attempt.attempt_type)
+        .ok_or_else(|| {
+            ServiceError::BadRequest(format!(
+                "未知の認証種別です: {}",
+                attempt.attempt_type
+            ))
+        })?;
+
+    // フィンガープリントの再評価 (この試行自身との一致は除外する)
+    let fingerprint_rejection_reason = match &attempt.fingerprint_json {
+        Some(fingerprint) => {
+            let hashes = verification::calculate_fingerprint_hashes(fingerprint);
+            let mut conn = pool.acquire().await?;
+            verification::verify_fingerprint_hashes_excluding(&mut conn, &hashes, Some(attempt.id))
+                .await?
+        }
+        None => None,
+    };
+
+    // proxycheckの再評価 (保存されているのは切り詰め済みのIPアドレス)
+    let proxycheck_enabled = verification::is_proxycheck_enabled(verification_type);
+    let mut proxycheck_rejection_reason = None;
+    let mut proxycheck_timed_out = false;
+    let mut proxycheck_data = None;
+    if let (true, Some(ip_address)) = (proxycheck_enabled, attempt.ip_address.as_deref()) {
+        match verification::get_proxycheck_data(http_client.get_ref(), ip_address).await? {
+            verification::ProxyCheckOutcome::Data(data) => {
+                proxycheck_rejection_reason = verification::verify_proxycheck(&data);
+                proxycheck_data = Some(data);
+            }
+            verification::ProxyCheckOutcome::TimedOut => proxycheck_timed_out = true,
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(models::VerificationReplayResponse {
+        attempt_id: attempt.id,
+        attempt_type: attempt.attempt_type,
+        original_is_success: attempt.is_success,
+        original_rejection_reason: attempt.rejection_reason,
+        would_succeed: fingerprint_rejection_reason.is_none()
+            && proxycheck_rejection_reason.is_none()
+            && (!proxycheck_timed_out || verification::is_proxycheck_timeout_allowed()),
+        fingerprint_rejection_reason,
+        proxycheck_enabled,
+        proxycheck_rejection_reason,
+        proxycheck_timed_out,
+        proxycheck_data,
+    }))
+}
+
+/// [管理者用] 失敗した認証試行を、種類・拒否理由・期間で絞り込んで取得します。
+/// 特定の不正パターン (例: 直近1日のプロキシ判定による拒否) の分析に使用します。
+#[get("/failed-verifications/search")]
+pub async fn search_failed_verifications(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<models::FailedVerificationSearchQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query.limit.unwrap_or(50).clamp(1, 200);
+    let offset = models::page_offset(page, limit);
+
+    let mut count_query: QueryBuilder<Postgres> =
+        QueryBuilder::new("SELECT COUNT(*) FROM level_up_attempts");
+    push_failed_verification_filters(&mut count_query, &query);
+    let total_count: i64 = count_query
+        .build_query_scalar()
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let mut items_query: QueryBuilder<Postgres> = QueryBuilder::new(
+        "SELECT id, user_id, attempt_type, created_at, ip_address, country_code, rejection_reason FROM level_up_attempts",
+    );
+    push_failed_verification_filters(&mut items_query, &query);
+    items_query
+        .push(" ORDER BY created_at DESC LIMIT ")
+        .push_bind(limit)
+        .push(" OFFSET ")
+        .push_bind(offset);
+    let items = items_query
+        .build_query_as::<models::FailedVerificationItem>()
+        .fetch_all(pool.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(models::PagedResponse::new(
+        items,
+        total_count,
+        page,
+        limit,
+    )))
+}
+
 /// ステップ1: レベルアップの事前検証を行い、成功すればトークンを発行する
 #[post("/preflight")]
 pub async fn level_up_preflight(
@@ -152,6 +342,10 @@ pub async fn level_up_preflight(
         let reason = result.rejection_reason.unwrap_or_else(|| "検証に失敗しました。".to_string());
         return Err(ServiceError::BadRequest(reason));
     }
+    // レベルアップの試行は常に記録されるため、IDが存在する
+    let attempt_id = attempt_id.ok_or_else(|| {
+        ServiceError::InternalServerError("Verification attempt was not recorded.".to_string())
+    })?;
 
     // 検証成功、JWTを生成
     let expiration = Utc::now()
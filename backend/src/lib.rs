@@ -1,7 +1,7 @@
 // c:\Users\sahasahu\Desktop\p\niwatori\backend\src\lib.rs
 use actix_web::{
     cookie::{time::OffsetDateTime, Cookie},
-    delete, get, post, web, HttpRequest, HttpResponse, Responder,
+    delete, get, post, web, HttpRequest, HttpResponse, Responder, ResponseError,
 };
 use ammonia::clean;
 use chrono::{Duration, TimeZone, Utc};
@@ -49,47 +49,272 @@ pub fn linkify_body(sanitized_body: &str) -> String {
 }
 // --- END: Response Anchor Helpers ---
 
+// --- START: Board Description Helpers ---
+// サニタイズ済みテキスト内のURL (`&` は `&amp;` にエスケープされている)
+static RE_DESCRIPTION_URL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"https?://(?:[A-Za-z0-9\-._~:/?#\[\]@!$%+,;=*()]|&amp;)+").unwrap()
+});
+
+/// 板の説明欄のURLをリンクとして表示するかどうか。環境変数 `BOARD_DESCRIPTION_LINKS` が `true` の場合のみ有効。
+fn is_board_description_links_enabled() -> bool {
+    env::var("BOARD_DESCRIPTION_LINKS")
+        .map(|v| v.trim().eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// 保存済み (サニタイズ済み) の板の説明欄を、リンクのみを含むHTMLに変換します。
+/// 保存されている説明欄に含まれうるタグはすべて取り除いてから、URLだけを `<a>` に変換します。
+pub fn render_board_description(sanitized_description: &str) -> String {
+    let text_only = ammonia::Builder::empty()
+        .clean(sanitized_description)
+        .to_string();
+    RE_DESCRIPTION_URL
+        .replace_all(&text_only, |caps: &regex::Captures| {
+            format!(
+                "<a href=\"{}\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">{}</a>",
+                &caps[0], &caps[0]
+            )
+        })
+        .to_string()
+}
+// --- END: Board Description Helpers ---
+
+// --- START: Diagnostic Logging Helper ---
+/// 詳細な診断ログ (`[DIAG]`, `[IP DIAG]`, `[DEVICE DIAG]` など) を出力するかどうかを返します。
+///
+/// 環境変数 `VERBOSE_DIAG_LOGS` が `true` の場合のみ有効になります (デフォルトは無効)。
+/// これらのログにはIPアドレスなどの個人情報が含まれるため、本番環境では明示的に有効にしない限り出力しません。
+pub fn is_verbose_diag_logging_enabled() -> bool {
+    parse_verbose_diag_flag(env::var("VERBOSE_DIAG_LOGS").ok().as_deref())
+}
+
+/// `VERBOSE_DIAG_LOGS` の値を解釈します。未設定や不正な値は無効として扱います。
+fn parse_verbose_diag_flag(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+// --- END: Diagnostic Logging Helper ---
+
+// --- START: Default Poster Name Helper ---
+const FALLBACK_DEFAULT_POST_NAME: &str = "名無しさん";
+
+/// 名前欄が空の場合に使用するデフォルトの投稿者名を返します。
+/// 環境変数 `DEFAULT_POST_NAME` で変更可能です (デフォルトは「名無しさん」)。
+pub fn default_post_name() -> String {
+    default_post_name_from(env::var("DEFAULT_POST_NAME").ok())
+}
+
+/// `default_post_name` の本体。`DEFAULT_POST_NAME` の値を引数で受け取ります。
+fn default_post_name_from(value: Option<String>) -> String {
+    value
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+        .unwrap_or_else(|| FALLBACK_DEFAULT_POST_NAME.to_string())
+}
+
+/// 板に設定されたデフォルト名を返します。未設定 (空) の場合は `default_post_name` を使用します。
+fn board_default_name(board: &Board) -> String {
+    if board.default_name.trim().is_empty() {
+        default_post_name()
+    } else {
+        board.default_name.clone()
+    }
+}
+// --- END: Default Poster Name Helper ---
+
+// --- START: Reserved Author Name Helper ---
+/// トリップ表示用の区切り文字。サーバーが生成するトリップ専用のため、入力された名前には使用できない。
+const TRIP_SEPARATOR: char = '◆';
+const DEFAULT_RESERVED_AUTHOR_NAMES: &str = "管理人,管理者,運営,削除人,admin,administrator";
+const DEFAULT_RESERVED_NAME_MARKERS: &str = "☕";
+
+/// 運営スタッフの識別用に予約されたマーカー (文字列) の一覧を返します。
+/// これらは管理者が名前欄に付けて身元を示すためのもので、管理者以外は名前・デフォルト名に含めることができません。
+/// 環境変数 `RESERVED_NAME_MARKERS` (カンマ区切り) で変更可能です (デフォルトは「☕」)。
+fn reserved_name_markers() -> Vec<String> {
+    env::var("RESERVED_NAME_MARKERS")
+        .unwrap_or_else(|_| DEFAULT_RESERVED_NAME_MARKERS.to_string())
+        .split(',')
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty())
+        .collect()
+}
+
+/// 管理者などになりすますために使われうる、予約済みの名前一覧を返します。
+/// 環境変数 `RESERVED_AUTHOR_NAMES` (カンマ区切り) で変更可能です。
+fn reserved_author_names() -> Vec<String> {
+    env::var("RESERVED_AUTHOR_NAMES")
+        .unwrap_or_else(|_| DEFAULT_RESERVED_AUTHOR_NAMES.to_string())
+        .split(',')
+        .map(|n| n.trim().to_lowercase())
+        .filter(|n| !n.is_empty())
+        .collect()
+}
+
+/// 管理者以外が入力した名前 (投稿者名・デフォルト名) を、保存時と同じくサニタイズしてからチェックします。
+/// ammonia は `&#9670;` のような文字参照をデコードするため、入力のままチェックすると
+/// 保存後に予約文字や予約名になる名前を見逃してしまいます。
+fn check_submitted_author_name(raw_name: &str) -> Result<(), ServiceError> {
+    check_reserved_author_name(&clean(raw_name))
+}
+
+/// サニタイズ済みの名前 (投稿者名・デフォルト名) に、予約文字や予約名が含まれていないかチェックします。
+fn check_reserved_author_name(name: &str) -> Result<(), ServiceError> {
+    // スタッフ識別用の予約マーカーは管理者専用
+    if reserved_name_markers()
+        .iter()
+        .any(|marker| name.contains(marker.as_str()))
+    {
+        return Err(ServiceError::posting_restricted());
+    }
+    // ◆ はトリップ専用のため、なりすまし防止のために禁止する
+    if name.contains(TRIP_SEPARATOR) {
+        return Err(ServiceError::BadRequest(format!(
+            "名前に「{}」は使用できません。",
+            TRIP_SEPARATOR
+        )));
+    }
+    let normalized = name.trim().to_lowercase();
+    if reserved_author_names().iter().any(|reserved| *reserved == normalized) {
+        return Err(ServiceError::BadRequest(
+            "この名前は使用できません。".to_string(),
+        ));
+    }
+    Ok(())
+}
+// --- END: Reserved Author Name Helper ---
+
 // --- START: IP Address Helper ---
+/// プロキシが付与するヘッダー (`X-Real-IP`, `X-Forwarded-For`) を信頼するか。
+/// アプリを直接公開している場合はヘッダーを偽装できるため、`TRUST_PROXY_HEADERS=false` で無効化する (デフォルト: 有効)。
+fn trust_proxy_headers() -> bool {
+    env::var("TRUST_PROXY_HEADERS")
+        .map(|v| v != "false")
+        .unwrap_or(true)
+}
+
+/// アプリの手前にある信頼済みプロキシの段数 (環境変数 `TRUSTED_PROXY_HOPS`)。
+/// 0 (デフォルト) の場合は従来どおり `X-Forwarded-For` の左端を使用する。
+fn trusted_proxy_hops() -> usize {
+    env::var("TRUSTED_PROXY_HOPS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
+
+/// `X-Forwarded-For` の値からクライアントのIPアドレスを選びます。
+/// `hops` が1以上の場合、信頼済みプロキシが追記した右端から数えて `hops` 番目のエントリを使用します
+/// (それより左はクライアントが自由に書き換えられるため)。
+/// エントリが `hops` 個に満たない場合は信頼済みプロキシを経由していないため、`None` を返します。
+fn select_forwarded_ip(xff: &str, hops: usize) -> Option<String> {
+    let entries: Vec<&str> = xff
+        .split(',')
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let index = if hops == 0 {
+        0
+    } else {
+        entries.len().checked_sub(hops)?
+    };
+    entries.get(index).map(|s| s.to_string())
+}
+
 /// HTTPリクエストからクライアントのIPアドレスを取得し、必要に応じて正規化します。
 ///
-/// 1. `X-Real-IP` ヘッダーを最優先で使用します。
-/// 2. `X-Forwarded-For` ヘッダーがあれば、その左端のIPアドレスを使用します。
+/// 1. `X-Real-IP` ヘッダーを最優先で使用します (`TRUSTED_PROXY_HOPS` 設定時は使用しません)。
+/// 2. `X-Forwarded-For` ヘッダーがあれば、その左端 (`TRUSTED_PROXY_HOPS` 設定時は右から数えたエントリ) のIPアドレスを使用します。
 /// 3. 上記ヘッダーがない場合は、直接の接続元IPアドレスを使用します。
 /// 4. 取得したIPアドレスがIPv6の場合、プライバシー保護のために `/64` プレフィックスに切り詰めます。
 ///
+/// `TRUST_PROXY_HEADERS=false` の場合は 1, 2 を行わず、TCP接続の接続元アドレスのみを使用します。
+/// (`realip_remote_addr` も転送ヘッダーを参照するため、この場合は `peer_addr` を使用します)
+///
 /// # 戻り値
 /// `(切り詰め済みIP, 生のIP)` のタプルを返します。
 pub fn get_ip_address(req: &HttpRequest) -> (String, String) {
-    log::info!("[IP DIAG] --- Start IP Address Acquisition ---");
-    let raw_ip_string = req
-        .headers()
+    let raw_ip_string = resolve_client_ip(req, trust_proxy_headers(), trusted_proxy_hops());
+    // IPv6アドレスを/64プレフィックスに切り詰める
+    let truncated_ip = truncate_ipv6_prefix(&raw_ip_string);
+    (truncated_ip, raw_ip_string)
+}
+
+/// `get_ip_address` の本体。環境変数の設定を引数で受け取り、切り詰め前のIPアドレスを返します。
+fn resolve_client_ip(req: &HttpRequest, trust_headers: bool, hops: usize) -> String {
+    // 生のIPアドレスを含むため、診断ログは明示的に有効化されている場合のみ出力する
+    let diag = is_verbose_diag_logging_enabled();
+    if diag {
+        log::info!("[IP DIAG] --- Start IP Address Acquisition ---");
+    }
+    let peer_ip = || {
+        req.connection_info()
+            .peer_addr()
+            .unwrap_or("0.0.0.0")
+            .to_string()
+    };
+
+    if !trust_headers {
+        let peer_ip = peer_ip();
+        if diag {
+            log::info!("[IP DIAG] Proxy headers are not trusted. Using peer_addr: '{}'", peer_ip);
+        }
+        return peer_ip;
+    }
+
+    let xff_header = req.headers().get("x-forwarded-for").and_then(|v| v.to_str().ok());
+
+    if hops > 0 {
+        // 信頼済みプロキシの段数が設定されている場合、X-Real-IP はクライアントが偽装できるため使用しない。
+        // XFF のエントリが足りない場合も、左端はクライアントが書き換えられるため接続元アドレスを使う
+        if diag {
+            log::info!("[IP DIAG] Raw 'x-forwarded-for' header: {:?}", xff_header);
+        }
+        return match xff_header.and_then(|s| select_forwarded_ip(s, hops)) {
+            Some(ip) => {
+                if diag {
+                    log::info!("[IP DIAG] Selected IP from XFF (trusted hops: {}): '{}'.", hops, ip);
+                }
+                ip
+            }
+            None => {
+                let peer_ip = peer_ip();
+                if diag {
+                    log::info!("[IP DIAG] XFF is shorter than trusted hops ({}). Falling back to peer_addr: '{}'", hops, peer_ip);
+                }
+                peer_ip
+            }
+        };
+    }
+
+    req.headers()
         .get("X-Real-IP")
         .and_then(|v| v.to_str().ok())
         .map(|ip| {
-            log::info!("[IP DIAG] Found 'X-Real-IP': '{}'.", ip);
+            if diag {
+                log::info!("[IP DIAG] Found 'X-Real-IP': '{}'.", ip);
+            }
             ip.to_string()
         })
         .unwrap_or_else(|| {
-            log::info!("[IP DIAG] 'X-Real-IP' not found. Checking 'X-Forwarded-For'.");
-            let xff_header = req.headers().get("x-forwarded-for").and_then(|v| v.to_str().ok());
-            log::info!("[IP DIAG] Raw 'x-forwarded-for' header: {:?}", xff_header);
+            if diag {
+                log::info!("[IP DIAG] 'X-Real-IP' not found. Checking 'X-Forwarded-For'.");
+                log::info!("[IP DIAG] Raw 'x-forwarded-for' header: {:?}", xff_header);
+            }
             xff_header
-                .and_then(|s| s.split(',').next()) // Get the leftmost IP
-                .map(|s| s.trim().to_string())
+                .and_then(|s| select_forwarded_ip(s, 0))
                 .map(|ip| {
-                    log::info!("[IP DIAG] Found leftmost IP from XFF: '{}'.", ip);
+                    if diag {
+                        log::info!("[IP DIAG] Selected leftmost IP from XFF: '{}'.", ip);
+                    }
                     ip
                 })
                 .unwrap_or_else(|| {
                     let fallback_ip = req.connection_info().realip_remote_addr().unwrap_or("0.0.0.0").to_string();
-                    log::info!("[IP DIAG] XFF is empty or invalid. Falling back to realip_remote_addr: '{}'", fallback_ip);
+                    if diag {
+                        log::info!("[IP DIAG] XFF is empty or invalid. Falling back to realip_remote_addr: '{}'", fallback_ip);
+                    }
                     fallback_ip
                 })
-        });
-
-    // IPv6アドレスを/64プレフィックスに切り詰める
-    let truncated_ip = truncate_ipv6_prefix(&raw_ip_string);
-    (truncated_ip, raw_ip_string)
+        })
 }
 // --- END: IP Address Helper ---
 
@@ -114,6 +339,7 @@ pub struct ArchivedPostsQueryParams {
     pub board_id: Option<String>,     // 板IDでフィルタリング (スペース区切りで複数指定可)
     pub created_year: Option<i32>,    // 作成年でフィルタリング
     pub created_month: Option<i32>,   // 作成月でフィルタリング
+    pub tz_offset_minutes: Option<i32>, // 年月の境界を計算するタイムゾーン (UTCからの分単位オフセット, 例: JST=540)
     pub min_responses: Option<i64>,   // 最小レス数でフィルタリング
     pub limit: Option<i64>,           // ページネーション: 取得件数
     pub offset: Option<i64>,          // ページネーション: 開始位置
@@ -125,6 +351,11 @@ pub struct ArchivedPostsQueryParams {
 #[derive(serde::Deserialize)]
 pub struct BoardListQueryParams {
     page: Option<i64>,
+    // キーセットページネーション用のカーソル (前のページのレスポンスの `next_cursor`)。
+    // 指定された場合は `page` より優先される
+    cursor: Option<String>,
+    // NSFWの板を一覧に含めるか (閲覧者の同意が必要なため、デフォルトは含めない)
+    include_nsfw: Option<bool>,
 }
 
 // タイムスタンプ検索用のパスパラメータ
@@ -139,10 +370,34 @@ pub struct TimestampQueryInfo {
     board_id: i32,
 }
 
+// 板内のスレッドを指定するパスパラメータ
+#[derive(serde::Deserialize)]
+pub struct BoardPostPathInfo {
+    id: i32,
+    post_id: i32,
+}
+
+// 板内のスレッド番号検索用のパスパラメータ
+#[derive(serde::Deserialize)]
+pub struct BoardSequencePathInfo {
+    board_id: i32,
+    seq: i32,
+}
+
 // スレッド一覧のソート用クエリパラメータ構造体
 #[derive(serde::Deserialize)]
 pub struct PostsQueryParams {
     sort: Option<String>,
+    // trueの場合、各スレッドについて同じIDのスレ主が板内に立てている他のスレッド数を含める
+    with_op_stats: Option<bool>,
+}
+
+// 全板横断の勢いランキング用のクエリパラメータ
+#[derive(serde::Deserialize)]
+pub struct HotPostsQueryParams {
+    limit: Option<i64>,
+    // NSFWの板のスレッドを含めるか (デフォルトは含めない)
+    include_nsfw: Option<bool>,
 }
 
 // パスからIDを抽出するための汎用的な構造体
@@ -178,57 +433,296 @@ pub async fn ping() -> impl Responder {
     HttpResponse::Ok().body("pong")
 }
 
+/// クライアント向けに、サーバーの制限値と機能フラグを返します。
+/// 各ハンドラが実際に参照している定数・環境変数から組み立てるため、実際の挙動と食い違うことはありません。
+#[get("/config")]
+pub async fn get_server_config() -> impl Responder {
+    use verification::{is_proxycheck_enabled, VerificationType};
+
+    HttpResponse::Ok().json(models::ServerConfigResponse {
+        post_title_max_length: models::POST_TITLE_MAX_LENGTH,
+        post_body_max_length: models::POST_BODY_MAX_LENGTH,
+        comment_body_max_length: models::COMMENT_BODY_MAX_LENGTH,
+        author_name_max_length: models::AUTHOR_NAME_MAX_LENGTH,
+        board_description_max_length: models::board_description_max_length(),
+        max_responses_per_thread: MAX_RESPONSES_PER_THREAD,
+        post_limit_archive_delay_seconds: post_limit_archive_delay_seconds(),
+        anonymous_posting_allowed: is_anonymous_posting_allowed(),
+        min_body_length: min_body_length(),
+        max_quotes_per_post: max_quotes_per_post(),
+        min_account_age_seconds: min_account_age_seconds().max(0),
+        max_fingerprint_bytes: verification::max_fingerprint_bytes(),
+        ipv6_prefix_length: IPV6_PREFIX_LENGTH,
+        captcha: models::ServerCaptchaConfig {
+            registration: "hcaptcha",
+            level_up: "turnstile",
+        },
+        ip_check: models::ServerIpCheckConfig {
+            registration: is_proxycheck_enabled(VerificationType::Registration),
+            level_up: is_proxycheck_enabled(VerificationType::LevelUp),
+            create_board: is_proxycheck_enabled(VerificationType::CreateBoard),
+            create_post: is_proxycheck_enabled(VerificationType::CreatePost),
+            create_comment: is_proxycheck_enabled(VerificationType::CreateComment),
+        },
+    })
+}
+
+/// 板一覧のキーセットページネーション用カーソル。
+/// 並び順のキー (活動量, 最終活動日時, ID) に加えて、活動量の集計期間 (開始日時と、
+/// 1ページ目を取得した時点のスナップショット日時) を保持し、ページをまたいでも
+/// 同じ集計期間で並べ替えられるようにする。
+#[derive(Debug, PartialEq)]
+struct BoardListCursor {
+    activity_since: chrono::DateTime<Utc>,
+    snapshot_at: chrono::DateTime<Utc>,
+    activity_count: i64,
+    last_activity_at: chrono::DateTime<Utc>,
+    id: i32,
+}
+
+impl BoardListCursor {
+    fn encode(&self) -> String {
+        format!(
+            "{}_{}_{}_{}_{}",
+            self.activity_since.timestamp_micros(),
+            self.snapshot_at.timestamp_micros(),
+            self.activity_count,
+            self.last_activity_at.timestamp_micros(),
+            self.id
+        )
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        // PostgreSQLのタイムスタンプはマイクロ秒精度のため、マイクロ秒で往復させれば値が一致する
+        fn from_micros(micros: i64) -> Option<chrono::DateTime<Utc>> {
+            Utc.timestamp_opt(
+                micros.div_euclid(1_000_000),
+                (micros.rem_euclid(1_000_000) * 1000) as u32,
+            )
+            .single()
+        }
+        let mut parts = cursor.split('_');
+        let activity_since = from_micros(parts.next()?.parse().ok()?)?;
+        let snapshot_at = from_micros(parts.next()?.parse().ok()?)?;
+        let activity_count = parts.next()?.parse().ok()?;
+        let last_activity_at = from_micros(parts.next()?.parse().ok()?)?;
+        let id = parts.next()?.parse().ok()?;
+        if parts.next().is_some() {
+            return None;
+        }
+        Some(Self {
+            activity_since,
+            snapshot_at,
+            activity_count,
+            last_activity_at,
+            id,
+        })
+    }
+}
+
 #[get("")]
 pub async fn get_boards(
     pool: web::Data<PgPool>,
     query: web::Query<BoardListQueryParams>,
 ) -> Result<HttpResponse, ServiceError> {
     const BOARDS_PER_PAGE: i64 = 100;
-    let page = query.page.unwrap_or(1).max(1);
-    let offset = (page - 1) * BOARDS_PER_PAGE;
+    let include_nsfw = query.include_nsfw.unwrap_or(false);
+    let cursor = match query.cursor.as_deref() {
+        Some(c) => Some(BoardListCursor::decode(c).ok_or_else(|| {
+            ServiceError::BadRequest("無効なカーソルが指定されました。".to_string())
+        })?),
+        None => None,
+    };
+    // カーソル指定時はOFFSETを使わず、カーソルの位置から続けて取得する
+    let offset = if cursor.is_some() {
+        0
+    } else {
+        (query.page.unwrap_or(1).max(1) - 1) * BOARDS_PER_PAGE
+    };
 
     // 過去24時間の活動量を計算
     let total_count: i64 = sqlx::query_scalar!(
-        r#"SELECT COUNT(*) as "total!: i64" FROM boards WHERE deleted_at IS NULL"#
+        r#"SELECT COUNT(*) as "total!: i64" FROM boards WHERE deleted_at IS NULL AND ($1 OR NOT is_nsfw)"#,
+        include_nsfw
     )
     .fetch_one(pool.get_ref())
     .await?;
 
-    let activity_since = Utc::now() - Duration::hours(24);
-    let boards = sqlx::query_as!(Board,
+    // 1ページ目では現在時刻を集計期間の終わりとして固定し、以降のページでもカーソルに
+    // 保持した同じ期間で集計することで、ページ取得の間の新しい投稿による並び替わりを防ぐ
+    let (activity_since, snapshot_at) = match cursor.as_ref() {
+        Some(c) => (c.activity_since, c.snapshot_at),
+        None => {
+            let now = Utc::now();
+            (now - Duration::hours(24), now)
+        }
+    };
+    // スレッド数・レス数は現行スレッド (削除済み・過去ログ化済みを除く) のみを集計する
+    let board_rows = sqlx::query!(
         r#"
         SELECT
             b.id, b.name, b.description, b.default_name, b.created_at, b.updated_at, b.deleted_at,
-            b.created_by, b.last_activity_at, b.archived_at, b.max_posts, b.auto_archive_enabled,
-            b.moderation_type as "moderation_type: _"
+            b.created_by, b.last_activity_at, b.archived_at, b.max_posts, b.auto_archive_enabled, b.is_nsfw, b.posting_frozen, b.members_only, b.member_min_level, b.require_captcha, b.per_board_ids, b.default_sort, b.unique_titles,
+            b.moderation_type as "moderation_type: models::BoardModerationType",
+            COALESCE(a.activity_count, 0) as "activity_count!",
+            COALESCE(pc.thread_count, 0) as "thread_count!",
+            COALESCE(cc.comment_count, 0) as "total_comment_count!"
         FROM boards b
         LEFT JOIN (
             SELECT board_id, COUNT(*) as activity_count
             FROM (
-                SELECT board_id, created_at FROM posts WHERE created_at > $1
+                SELECT board_id, created_at FROM posts WHERE created_at > $1 AND created_at <= $8
                 UNION ALL
-                SELECT p.board_id, c.created_at FROM comments c JOIN posts p ON c.post_id = p.id WHERE c.created_at > $1
+                SELECT p.board_id, c.created_at FROM comments c JOIN posts p ON c.post_id = p.id WHERE c.created_at > $1 AND c.created_at <= $8
             ) as activity
             GROUP BY board_id
         ) a ON b.id = a.board_id
-        WHERE b.deleted_at IS NULL
+        LEFT JOIN (
+            SELECT board_id, COUNT(*) as thread_count
+            FROM posts
+            WHERE deleted_at IS NULL AND archived_at IS NULL
+            GROUP BY board_id
+        ) pc ON b.id = pc.board_id
+        LEFT JOIN (
+            SELECT p.board_id, COUNT(*) as comment_count
+            FROM comments c
+            JOIN posts p ON c.post_id = p.id
+            WHERE p.deleted_at IS NULL AND p.archived_at IS NULL
+            GROUP BY p.board_id
+        ) cc ON b.id = cc.board_id
+        WHERE b.deleted_at IS NULL AND ($4 OR NOT b.is_nsfw)
+          AND ($5::bigint IS NULL OR (COALESCE(a.activity_count, 0), b.last_activity_at, b.id) < ($5, $6, $7))
         ORDER BY COALESCE(a.activity_count, 0) DESC, b.last_activity_at DESC, b.id DESC
         LIMIT $2 OFFSET $3
         "#,
         activity_since,
         BOARDS_PER_PAGE,
-        offset
+        offset,
+        include_nsfw,
+        cursor.as_ref().map(|c| c.activity_count),
+        cursor.as_ref().map(|c| c.last_activity_at),
+        cursor.as_ref().map(|c| c.id),
+        snapshot_at
     )
     .fetch_all(pool.get_ref())
     .await?;
 
-    let response = models::PaginatedResponse {
+    // 1ページ分取得できた場合のみ、次のページのカーソルを返す
+    let next_cursor = if board_rows.len() as i64 == BOARDS_PER_PAGE {
+        board_rows.last().map(|row| {
+            BoardListCursor {
+                activity_since,
+                snapshot_at,
+                activity_count: row.activity_count,
+                last_activity_at: row.last_activity_at,
+                id: row.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+
+    let boards: Vec<models::BoardListItem> = board_rows
+        .into_iter()
+        .map(|row| models::BoardListItem {
+            board: Board {
+                id: row.id,
+                name: row.name,
+                description: row.description,
+                default_name: row.default_name,
+                created_at: row.created_at,
+                updated_at: row.updated_at,
+                deleted_at: row.deleted_at,
+                created_by: row.created_by,
+                max_posts: row.max_posts,
+                archived_at: row.archived_at,
+                moderation_type: row.moderation_type,
+                last_activity_at: row.last_activity_at,
+                auto_archive_enabled: row.auto_archive_enabled,
+                is_nsfw: row.is_nsfw,
+                posting_frozen: row.posting_frozen,
+                members_only: row.members_only,
+                member_min_level: row.member_min_level,
+                require_captcha: row.require_captcha,
+                per_board_ids: row.per_board_ids,
+                default_sort: row.default_sort,
+                unique_titles: row.unique_titles,
+            },
+            thread_count: row.thread_count,
+            total_comment_count: row.total_comment_count,
+        })
+        .collect();
+
+    let response = models::BoardListResponse {
         items: boards,
         total_count,
+        next_cursor,
     };
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// 最新スレッドのプレビューに含める本文の文字数
+const LATEST_POST_SNIPPET_LENGTH: usize = 100;
+
+/// 削除されていない各板について、最も最近書き込みのあった現行スレッドを1件ずつ取得します。
+/// トップページで板ごとの最新スレッドを一覧表示するためのもので、1回のクエリで全板分を取得します。
+#[get("/latest-posts")]
+pub async fn get_latest_posts_per_board(
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServiceError> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT DISTINCT ON (p.board_id)
+            p.board_id as "board_id!", b.name as board_name, b.is_nsfw as board_is_nsfw,
+            p.id as post_id, p.title, p.body, p.last_activity_at
+        FROM posts p
+        JOIN boards b ON p.board_id = b.id
+        WHERE p.deleted_at IS NULL AND p.archived_at IS NULL
+          AND b.deleted_at IS NULL
+        ORDER BY p.board_id, p.last_activity_at DESC, p.id DESC
+        "#
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let items: Vec<models::BoardLatestPost> = rows
+        .into_iter()
+        .map(|row| models::BoardLatestPost {
+            snippet: user_history::truncate_body_snippet(&row.body, LATEST_POST_SNIPPET_LENGTH)
+                .unwrap_or(row.body),
+            board_id: row.board_id,
+            board_name: row.board_name,
+            board_is_nsfw: row.board_is_nsfw,
+            post_id: row.post_id,
+            title: row.title,
+            last_activity_at: row.last_activity_at,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+/// 板名 (完全一致) から板を取得します。専ブラなど板名で板を参照する場合に使います。
+/// 板名は保存時にサニタイズされるため、入力も同じようにサニタイズしてから比較します。
+#[get("/by-name/{name}")]
+pub async fn get_board_by_name(
+    pool: web::Data<PgPool>,
+    path: web::Path<String>,
+) -> Result<HttpResponse, ServiceError> {
+    let name = clean(&path.into_inner());
+    let board = sqlx::query_as!(
+        Board,
+        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _" FROM boards WHERE name = $1 AND deleted_at IS NULL"#,
+        name
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Board not found".to_string()))?;
+
+    Ok(HttpResponse::Ok().json(board))
+}
+
 #[get("/{id}")]
 pub async fn get_board_by_id(
     pool: web::Data<PgPool>,
@@ -238,15 +732,15 @@ pub async fn get_board_by_id(
     let board_id = path.into_inner();
     let board = sqlx::query_as!(
         Board,
-        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
+        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
         board_id
     )
     .fetch_optional(pool.get_ref())
     .await?
     .ok_or_else(|| ServiceError::NotFound("Board not found".to_string()))?;
 
-    #[cfg(debug_assertions)]
-    {
+    let diag = is_verbose_diag_logging_enabled();
+    if diag {
         log::info!(
             "[DIAG] --- Analyzing get_board_by_id for board ID: {} ---",
             board_id
@@ -264,44 +758,41 @@ pub async fn get_board_by_id(
     let can_moderate = user
         .as_ref()
         .is_some_and(|u| is_admin || board.created_by == Some(u.user_id));
-    #[cfg(debug_assertions)]
-    log::info!("[DIAG] 'can_moderate' check result: {}", can_moderate);
+    if diag {
+        log::info!("[DIAG] 'can_moderate' check result: {}", can_moderate);
+    }
 
     let mut creator_info_response = None;
 
     // モデレーション権限がある場合、
     // フロントエンドに作成者情報を表示するために、その詳細を取得します。
-    #[cfg(debug_assertions)]
-    log::info!("[DIAG] Checking condition to fetch creator_info (if can_moderate)...");
+    if diag {
+        log::info!("[DIAG] Checking condition to fetch creator_info (if can_moderate)...");
+    }
     if can_moderate {
-        #[cfg(debug_assertions)]
-        log::info!("[DIAG] Condition MET (can_moderate=true). Attempting to fetch creator_info for creator_id: {:?}", board.created_by);
+        if diag {
+            log::info!("[DIAG] Condition MET (can_moderate=true). Attempting to fetch creator_info for creator_id: {:?}", board.created_by);
+        }
         if let Some(creator_id) = board.created_by {
             if let Some(creator) =
                 sqlx::query!("SELECT email, level FROM users WHERE id = $1", creator_id)
                     .fetch_optional(pool.get_ref())
                     .await?
             {
-                let identity_hashes = identity::generate_identity_hashes(
-                    &creator.email,
-                    "board_creator_ip", // IPの代わりに固定のプレースホルダーを使用
-                    &board.id.to_string(), // Device Infoの代わりに板IDを文字列化して使用
-                );
-
                 creator_info_response = Some(CreatorInfoResponse {
-                    display_user_id: identity_hashes.display_user_id,
+                    display_user_id: board_creator_display_user_id(&creator.email, board.id),
                     level: creator.level,
                     level_at_creation: creator.level,
                 });
             }
-            #[cfg(debug_assertions)]
-            log::info!(
-                "[DIAG] Fetched creator_info_response: {:?}",
-                creator_info_response
-            );
+            if diag {
+                log::info!(
+                    "[DIAG] Fetched creator_info_response: {:?}",
+                    creator_info_response
+                );
+            }
         }
-    } else {
-        #[cfg(debug_assertions)]
+    } else if diag {
         log::info!("[DIAG] Condition NOT MET (can_moderate=false). Skipping creator_info fetch.");
     }
 
@@ -310,13 +801,16 @@ pub async fn get_board_by_id(
         can_moderate,
     };
 
+    let description_html = is_board_description_links_enabled()
+        .then(|| render_board_description(&board_with_moderation_flag.board.description));
+
     let response = BoardDetailResponse {
         board: board_with_moderation_flag.clone(),
         creator_info: creator_info_response,
+        description_html,
     };
 
-    #[cfg(debug_assertions)]
-    {
+    if diag {
         log::info!(
             "[DIAG] Constructed board_with_moderation_flag: {:?}",
             &board_with_moderation_flag
@@ -331,6 +825,159 @@ pub async fn get_board_by_id(
     Ok(HttpResponse::Ok().json(response))
 }
 
+/// 板作成者の表示用IDを算出します。
+/// 板作成時のIP・端末情報は表示用IDに使わず、固定のプレースホルダーと板IDから算出します。
+fn board_creator_display_user_id(creator_email: &str, board_id: i32) -> String {
+    identity::generate_identity_hashes(
+        creator_email,
+        "board_creator_ip", // IPの代わりに固定のプレースホルダーを使用
+        &board_id.to_string(), // Device Infoの代わりに板IDを文字列化して使用
+    )
+    .display_user_id
+}
+
+/// [管理者用] 最近作成された板を新しい順に取得します (板の乱立などの監視用)。
+/// 削除済みの板も含め、作成者の表示用IDと作成時の国コードを付与します。
+#[get("/boards/recent")]
+pub async fn get_recent_boards(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    query: web::Query<models::PaginationParams>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let page = query.page.max(1);
+    let limit = query.limit.clamp(1, 100);
+    let offset = models::page_offset(page, limit);
+
+    let total_count: i64 = sqlx::query_scalar!(r#"SELECT COUNT(*) as "count!" FROM boards"#)
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            b.id, b.name, b.created_at, b.deleted_at, b.created_by,
+            u.email as "creator_email?",
+            a.country_code as "country_code?"
+        FROM boards b
+        LEFT JOIN users u ON b.created_by = u.id
+        LEFT JOIN level_up_attempts a ON b.verification_attempt_id = a.id
+        ORDER BY b.created_at DESC, b.id DESC
+        LIMIT $1 OFFSET $2
+        "#,
+        limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let items: Vec<models::RecentBoardItem> = rows
+        .into_iter()
+        .map(|row| models::RecentBoardItem {
+            creator_display_user_id: row
+                .creator_email
+                .as_deref()
+                .map(|email| board_creator_display_user_id(email, row.id)),
+            id: row.id,
+            name: row.name,
+            created_at: row.created_at,
+            deleted_at: row.deleted_at,
+            created_by: row.created_by,
+            country_code: row.country_code,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(models::PagedResponse::new(
+        items,
+        total_count,
+        page,
+        limit,
+    )))
+}
+
+const DEFAULT_SITE_STATS_CACHE_TTL_SECONDS: u64 = 60;
+
+// サイト統計のインメモリキャッシュ (取得時刻, 集計結果)
+static SITE_STATS_CACHE: Lazy<
+    std::sync::Mutex<Option<(std::time::Instant, models::SiteStatsOverview)>>,
+> = Lazy::new(|| std::sync::Mutex::new(None));
+
+/// サイト統計のキャッシュ有効期間。環境変数 `SITE_STATS_CACHE_TTL_SECONDS` で変更可能 (0でキャッシュ無効)。
+fn site_stats_cache_ttl() -> std::time::Duration {
+    let secs = env::var("SITE_STATS_CACHE_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_SITE_STATS_CACHE_TTL_SECONDS);
+    std::time::Duration::from_secs(secs)
+}
+
+/// [管理者用] サイト全体の集計統計 (板・スレッド・コメント・ユーザー・BAN件数など) を取得します。
+/// 集計は重いため、結果を短時間キャッシュします。
+#[get("/stats/overview")]
+pub async fn get_site_stats_overview(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let ttl = site_stats_cache_ttl();
+    if let Ok(cache) = SITE_STATS_CACHE.lock() {
+        if let Some((fetched_at, stats)) = cache.as_ref() {
+            if fetched_at.elapsed() < ttl {
+                return Ok(HttpResponse::Ok().json(stats));
+            }
+        }
+    }
+
+    let row = sqlx::query!(
+        r#"
+        SELECT
+            (SELECT COUNT(*) FROM boards WHERE deleted_at IS NULL) as "total_boards!",
+            (SELECT COUNT(*) FROM posts WHERE deleted_at IS NULL) as "total_threads!",
+            (SELECT COUNT(*) FROM comments) as "total_comments!",
+            (SELECT COUNT(*) FROM users) as "total_users!",
+            (SELECT COUNT(*) FROM posts
+                WHERE deleted_at IS NULL AND created_at > NOW() - INTERVAL '24 hours') as "threads_24h!",
+            (SELECT COUNT(*) FROM comments
+                WHERE created_at > NOW() - INTERVAL '24 hours') as "comments_24h!",
+            (SELECT COUNT(DISTINCT user_id) FROM (
+                SELECT user_id FROM posts WHERE created_at > NOW() - INTERVAL '24 hours'
+                UNION ALL
+                SELECT user_id FROM comments WHERE created_at > NOW() - INTERVAL '24 hours'
+            ) recent WHERE user_id IS NOT NULL) as "active_users_24h!",
+            (SELECT COUNT(*) FROM bans
+                WHERE expires_at IS NULL OR expires_at > NOW()) as "active_bans!"
+        "#
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    let stats = models::SiteStatsOverview {
+        total_boards: row.total_boards,
+        total_threads: row.total_threads,
+        total_comments: row.total_comments,
+        total_users: row.total_users,
+        threads_24h: row.threads_24h,
+        comments_24h: row.comments_24h,
+        active_users_24h: row.active_users_24h,
+        active_bans: row.active_bans,
+        generated_at: Utc::now(),
+    };
+
+    if !ttl.is_zero() {
+        if let Ok(mut cache) = SITE_STATS_CACHE.lock() {
+            *cache = Some((std::time::Instant::now(), stats.clone()));
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(stats))
+}
+
 #[post("")]
 pub async fn create_board(
     pool: web::Data<PgPool>,
@@ -341,6 +988,10 @@ pub async fn create_board(
 ) -> Result<HttpResponse, ServiceError> {
     // 最初にバリデーションを実行
     board_data.validate()?;
+    // フィンガープリントは端末情報としてハッシュ化・暗号化して保存されるため、サイズを制限する
+    if let Some(fingerprint) = &board_data.fingerprint {
+        verification::check_fingerprint_size(fingerprint.len())?;
+    }
 
     let (truncated_ip, raw_ip) = get_ip_address(&req);
     let is_admin = matches!(user.role, middleware::Role::Admin);
@@ -348,11 +999,7 @@ pub async fn create_board(
     // 管理者でない場合、予約文字が含まれていないかチェック
     if !is_admin {
         if let Some(name) = &board_data.default_name {
-            if name.contains('☕') {
-                return Err(ServiceError::Forbidden(
-                    "".to_string(),
-                ));
-            }
+            check_submitted_author_name(name)?;
         }
     }
 
@@ -360,24 +1007,25 @@ pub async fn create_board(
     validated_board_data.name = clean(&validated_board_data.name);
     validated_board_data.description = clean(&validated_board_data.description);
 
-    // デフォルト名が指定されていればサニタイズし、なければ「野球民」を設定
+    // デフォルト名が指定されていればサニタイズし、なければ設定されたデフォルト名を使用
     let default_name = validated_board_data
         .default_name
         .filter(|s| !s.trim().is_empty())
         .map(|s| clean(&s).to_owned()) // Sanitize and own
-        .unwrap_or_else(|| "野球民".to_string());
+        .unwrap_or_else(default_post_name);
 
     let device_info: &str = {
-        log::info!("[DEVICE DIAG] --- Start Device Info Acquisition ---");
         let fingerprint = validated_board_data.fingerprint.as_deref();
-        log::info!("[DEVICE DIAG] Fingerprint from payload: {:?}", fingerprint);
         let user_agent = req.headers().get("User-Agent").and_then(|ua| ua.to_str().ok());
-        log::info!("[DEVICE DIAG] User-Agent from headers: {:?}", user_agent);
         let final_device_info = fingerprint.or(user_agent).unwrap_or("unknown");
-        log::info!(
-            "[DEVICE DIAG] Final device_info chosen: '{}'",
-            final_device_info
-        );
+        if is_verbose_diag_logging_enabled() {
+            log::info!("[DEVICE DIAG] Fingerprint from payload: {:?}", fingerprint);
+            log::info!("[DEVICE DIAG] User-Agent from headers: {:?}", user_agent);
+            log::info!(
+                "[DEVICE DIAG] Final device_info chosen: '{}'",
+                final_device_info
+            );
+        }
         final_device_info
     };
 
@@ -410,13 +1058,9 @@ pub async fn create_board(
         let (result, new_attempt_id) =
             verification::perform_verification(&mut tx, http_client.get_ref(), verification_input)
                 .await?;
-        attempt_id = Some(new_attempt_id);
+        attempt_id = new_attempt_id;
         if !result.is_success {
-            return Err(ServiceError::Forbidden(
-                result
-                    .rejection_reason
-                    .unwrap_or_else(|| "不正なリクエストとしてブロックされました。".to_string()),
-            ));
+            return Err(reject_failed_verification(tx, result.rejection_reason).await);
         }
     }
     // --- END: IP評価 ---
@@ -446,7 +1090,7 @@ pub async fn create_board(
         Board,
         r#"
         INSERT INTO boards (name, description, default_name, created_by, last_activity_at, verification_attempt_id) VALUES ($1, $2, $3, $4, NOW(), $5)
-        RETURNING id, name, description, default_name, created_at, updated_at, NULL as "deleted_at: _", created_by, last_activity_at, NULL as "archived_at: _", max_posts, auto_archive_enabled, moderation_type as "moderation_type: _"
+        RETURNING id, name, description, default_name, created_at, updated_at, NULL as "deleted_at: _", created_by, last_activity_at, NULL as "archived_at: _", max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _"
         "#,
         validated_board_data.name,
         validated_board_data.description,
@@ -534,7 +1178,7 @@ pub async fn restore_board_by_id(
         Board,
         r#"
         UPDATE boards SET deleted_at = NULL, last_activity_at = NOW() WHERE id = $1 AND deleted_at IS NOT NULL
-        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _", created_by, last_activity_at, archived_at as "archived_at: _", max_posts, auto_archive_enabled, moderation_type as "moderation_type: _"
+        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _", created_by, last_activity_at, archived_at as "archived_at: _", max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _"
         "#,
         board_id
     )
@@ -557,6 +1201,9 @@ struct PostWithCount {
     post: Post,
     response_count: i64,
     momentum: f64,
+    // `with_op_stats=true` の場合のみ: 同じIDのスレ主が板内に立てている他の (現行) スレッド数
+    #[serde(skip_serializing_if = "Option::is_none")]
+    op_other_thread_count: Option<i64>,
 }
 
 // get_posts_by_board_id で動的クエリの結果をマッピングするための構造体
@@ -581,59 +1228,378 @@ struct PostDetails {
     level: Option<i32>,
     response_count: i64,
     momentum: f64,
+    // 集計を要求された場合のみSELECTされる列
+    #[sqlx(default)]
+    op_other_thread_count: Option<i64>,
 }
 
-#[get("/{id}/posts")]
-pub async fn get_posts_by_board_id(
-    pool: web::Data<PgPool>,
-    path: web::Path<PathInfo>,
-    query: web::Query<PostsQueryParams>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // 認証状態を確認するために追加
-) -> Result<HttpResponse, ServiceError> {
-    let board_id = path.id;
-    log::info!(
-        "[API /boards/{{id}}/posts] Request for board_id: {}",
-        board_id
-    );
+// get_hot_posts のレスポンス用。板をまたいだ一覧のため板名を含める
+#[derive(serde::Serialize)]
+struct HotPostWithBoard {
+    #[serde(flatten)]
+    post: PostWithCount,
+    board_name: String,
+}
 
-    // First, check if the board exists and is not deleted.
-    let board_exists = sqlx::query!(
-        "SELECT id FROM boards WHERE id = $1 AND deleted_at IS NULL",
-        board_id
-    )
-    .fetch_optional(pool.get_ref())
-    .await
+#[derive(sqlx::FromRow)]
+struct HotPostDetails {
+    #[sqlx(flatten)]
+    details: PostDetails,
+    board_name: String,
+}
+
+/// 環境変数から勢いの上限値を取得。なければデフォルト値を使用。
+fn momentum_cap() -> f64 {
+    env::var("MOMENTUM_CAP")
+        .unwrap_or_else(|_| "9999999.99".to_string()) // デフォルト値を元のコードの値に設定
+        .parse()
+        .unwrap_or(9999999.99)
+}
+
+/// スレッド一覧のSELECT句で使う、レス数と勢いを計算するSQL断片。
+/// スレッドのエイリアスは `p` であることを前提とする。
+fn post_momentum_select_sql() -> String {
+    format!(
+        r#"
+            (1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) as response_count,
+            -- Momentum calculation (responses per day)
+            -- To avoid division by zero, if duration is less than a second, treat it as a small number.
+            LEAST(
+                CAST((1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) AS DOUBLE PRECISION) / GREATEST(EXTRACT(EPOCH FROM (NOW() - p.created_at)) / 86400.0, 0.00001),
+                {}
+            ) as momentum"#,
+        momentum_cap()
+    )
+}
+
+/// スレッド一覧の行を、レベル表示の制御を適用したレスポンス用の構造体に変換する。
+fn post_details_to_post_with_count(p: PostDetails, threshold: i32, is_admin: bool) -> PostWithCount {
+    let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+        process_level_visibility(p.level_at_creation, p.level, threshold, is_admin);
+
+    let post = Post {
+        id: p.id,
+        title: p.title,
+        // スレッド一覧ページでは、レスアンカーがスレッド詳細ページへの絶対パスを指すように、
+        // linkify_body が生成した相対リンク (`href="#res-..."`) を置換します。
+        body: linkify_body(&p.body).replace(
+            "href=\"#res-",
+            // p.id は現在処理中のスレッドのIDです。
+            &format!("href=\"/posts/{}#res-", p.id),
+        ),
+        author_name: p.author_name,
+        created_at: p.created_at,
+        updated_at: p.updated_at,
+        board_id: p.board_id,
+        deleted_at: p.deleted_at,
+        user_id: p.user_id,
+        archived_at: p.archived_at,
+        last_activity_at: p.last_activity_at,
+        display_user_id: p.display_user_id,
+        permanent_user_hash: p.permanent_user_hash,
+        permanent_ip_hash: p.permanent_ip_hash,
+        permanent_device_hash: p.permanent_device_hash,
+        level_at_creation: display_level_at_creation,
+        level: display_current_level,
+        is_current_level_hidden,
+    };
+
+    PostWithCount {
+        post,
+        response_count: p.response_count,
+        momentum: p.momentum,
+        op_other_thread_count: p.op_other_thread_count,
+    }
+}
+
+/// 1スレッドあたりの最大レス数 (スレッド本文を含む)
+const MAX_RESPONSES_PER_THREAD: i64 = 1000;
+const DEFAULT_FILLING_THREAD_RATIO: f64 = 0.9;
+
+/// 板内で、レス数が上限に近づいているアクティブなスレッドを取得します。
+/// しきい値は `ratio` クエリパラメータ、または環境変数 `FILLING_THREAD_RATIO` で指定します (デフォルトは0.9)。
+#[get("/{id}/filling")]
+pub async fn get_filling_posts_by_board_id(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    query: web::Query<models::FillingPostsQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.id;
+
+    let board_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM boards WHERE id = $1 AND deleted_at IS NULL) as "exists!""#,
+        board_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+    if !board_exists {
+        return Err(ServiceError::NotFound("Board not found".to_string()));
+    }
+
+    let ratio = query
+        .ratio
+        .or_else(|| {
+            env::var("FILLING_THREAD_RATIO")
+                .ok()
+                .and_then(|v| v.parse().ok())
+        })
+        .unwrap_or(DEFAULT_FILLING_THREAD_RATIO);
+    if !(0.0..=1.0).contains(&ratio) {
+        return Err(ServiceError::BadRequest(
+            "ratio は0から1の範囲で指定してください。".to_string(),
+        ));
+    }
+    let min_response_count = (MAX_RESPONSES_PER_THREAD as f64 * ratio).ceil() as i64;
+
+    let items = sqlx::query_as!(
+        models::FillingPostItem,
+        r#"
+        SELECT id, title, created_at, last_activity_at, response_count as "response_count!"
+        FROM (
+            SELECT
+                p.id, p.title, p.created_at, p.last_activity_at,
+                (1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) as response_count
+            FROM posts p
+            WHERE p.board_id = $1 AND p.deleted_at IS NULL AND p.archived_at IS NULL
+        ) AS counted
+        WHERE response_count >= $2
+        ORDER BY response_count DESC, last_activity_at DESC
+        "#,
+        board_id,
+        min_response_count
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(items))
+}
+
+const DEFAULT_RECENT_COMMENTS_LIMIT: i64 = 50;
+const MAX_RECENT_COMMENTS_LIMIT: i64 = 100;
+
+/// 板内の現行スレッドに付いたレスを、新しい順に取得します (ライブフィード表示用)。
+/// 各レスにはスレッドタイトルとレス番号を付与します。
+#[get("/{id}/recent-comments")]
+pub async fn get_recent_comments_by_board_id(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    query: web::Query<models::RecentCommentsQuery>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.id;
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_RECENT_COMMENTS_LIMIT)
+        .clamp(1, MAX_RECENT_COMMENTS_LIMIT);
+    let offset = models::page_offset(page, limit);
+
+    let board_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM boards WHERE id = $1 AND deleted_at IS NULL) as "exists!""#,
+        board_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+    if !board_exists {
+        return Err(ServiceError::NotFound("Board not found".to_string()));
+    }
+
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    let total_count: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!"
+        FROM comments c
+        JOIN posts p ON c.post_id = p.id
+        WHERE p.board_id = $1 AND p.deleted_at IS NULL AND p.archived_at IS NULL
+        "#,
+        board_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    // レス番号は書き込み時に保存した番号を使う (スレッド本文が1のため、レスは2から始まる)
+    let mut comments: Vec<Comment> = sqlx::query_as(
+        r#"
+        SELECT
+            c.id, c.body, c.post_id, c.user_id, c.author_name, c.created_at, c.updated_at,
+            c.display_user_id, c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash,
+            c.level_at_creation, u.level, p.title as post_title,
+            c.response_number::BIGINT as response_number
+        FROM comments c
+        JOIN posts p ON c.post_id = p.id
+        LEFT JOIN users u ON c.user_id = u.id
+        WHERE p.board_id = $1 AND p.deleted_at IS NULL AND p.archived_at IS NULL
+        ORDER BY c.created_at DESC, c.id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+    )
+    .bind(board_id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    for comment in &mut comments {
+        let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+            process_level_visibility(comment.level_at_creation, comment.level, threshold, is_admin);
+        comment.body = linkify_body(&comment.body);
+        comment.level_at_creation = display_level_at_creation;
+        comment.level = display_current_level;
+        comment.is_current_level_hidden = is_current_level_hidden;
+    }
+
+    Ok(HttpResponse::Ok().json(models::PagedResponse::new(
+        comments,
+        total_count,
+        page,
+        limit,
+    )))
+}
+
+const DEFAULT_BOARD_ACTIVITY_DAYS: i32 = 7;
+const MAX_BOARD_ACTIVITY_DAYS: i32 = 90;
+
+/// [管理者・板作成者用] 板の日ごとのスレッド数・レス数の推移を取得します (ダッシュボード表示用)。
+/// 書き込みのない日も0件として含めます。
+#[get("/{id}/activity")]
+pub async fn get_board_activity(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    query: web::Query<models::BoardActivityQuery>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.id;
+
+    let created_by: Option<i32> = sqlx::query_scalar!(
+        "SELECT created_by FROM boards WHERE id = $1 AND deleted_at IS NULL",
+        board_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    if !matches!(user.role, middleware::Role::Admin) && created_by != Some(user.user_id) {
+        return Err(ServiceError::Forbidden(
+            "この板の統計を閲覧する権限がありません。".to_string(),
+        ));
+    }
+
+    let days = query
+        .days
+        .unwrap_or(DEFAULT_BOARD_ACTIVITY_DAYS)
+        .clamp(1, MAX_BOARD_ACTIVITY_DAYS);
+
+    // generate_series で集計期間の全日を生成し、書き込みのない日を0件で埋める
+    let activity = sqlx::query_as!(
+        models::BoardActivityDay,
+        r#"
+        WITH days AS (
+            SELECT generate_series(
+                date_trunc('day', NOW()) - ($2::int - 1) * INTERVAL '1 day',
+                date_trunc('day', NOW()),
+                INTERVAL '1 day'
+            ) AS day
+        ),
+        post_counts AS (
+            SELECT date_trunc('day', p.created_at) AS day, COUNT(*) AS count
+            FROM posts p
+            WHERE p.board_id = $1 AND p.deleted_at IS NULL
+              AND p.created_at >= date_trunc('day', NOW()) - ($2::int - 1) * INTERVAL '1 day'
+            GROUP BY 1
+        ),
+        comment_counts AS (
+            SELECT date_trunc('day', c.created_at) AS day, COUNT(*) AS count
+            FROM comments c
+            JOIN posts p ON c.post_id = p.id
+            WHERE p.board_id = $1 AND p.deleted_at IS NULL
+              AND c.created_at >= date_trunc('day', NOW()) - ($2::int - 1) * INTERVAL '1 day'
+            GROUP BY 1
+        )
+        SELECT
+            d.day as "day!",
+            COALESCE(pc.count, 0) as "post_count!",
+            COALESCE(cc.count, 0) as "comment_count!"
+        FROM days d
+        LEFT JOIN post_counts pc ON pc.day = d.day
+        LEFT JOIN comment_counts cc ON cc.day = d.day
+        ORDER BY d.day ASC
+        "#,
+        board_id,
+        days
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(activity))
+}
+
+/// スレッド一覧のソート指定に対応する ORDER BY 句を返します。未知の指定の場合は `None`。
+fn thread_list_order_clause(sort: &str) -> Option<&'static str> {
+    match sort {
+        "momentum_desc" => Some("momentum DESC"),
+        "momentum_asc" => Some("momentum ASC"),
+        "responses_desc" => Some("response_count DESC"),
+        "responses_asc" => Some("response_count ASC"),
+        "last_activity_desc" => Some("p.last_activity_at DESC"),
+        "last_activity_asc" => Some("p.last_activity_at ASC"),
+        "created_at_desc" => Some("p.created_at DESC"),
+        "created_at_asc" => Some("p.created_at ASC"),
+        _ => None,
+    }
+}
+
+#[get("/{id}/posts")]
+pub async fn get_posts_by_board_id(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    query: web::Query<PostsQueryParams>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // 認証状態を確認するために追加
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.id;
+    log::info!(
+        "[API /boards/{{id}}/posts] Request for board_id: {}",
+        board_id
+    );
+
+    // First, check if the board exists and is not deleted.
+    let board_default_sort = sqlx::query_scalar!(
+        "SELECT default_sort FROM boards WHERE id = $1 AND deleted_at IS NULL",
+        board_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
     .map_err(ServiceError::from)?;
 
-    if board_exists.is_none() {
+    let Some(default_sort) = board_default_sort else {
         log::warn!(
             "[API /boards/{{id}}/posts] Board with id: {} not found or is deleted. Returning 404.",
             board_id
         );
         return Err(ServiceError::NotFound("Board not found".to_string()));
-    }
+    };
 
     // --- START: Level System Integration ---
     let threshold = get_level_display_threshold(pool.get_ref()).await?;
     let is_admin = user.is_some_and(|u| matches!(u.role, middleware::Role::Admin));
 
-    // 環境変数から勢いの上限値を取得。なければデフォルト値を使用。
-    let momentum_cap: f64 = env::var("MOMENTUM_CAP")
-        .unwrap_or_else(|_| "9999999.99".to_string()) // デフォルト値を元のコードの値に設定
-        .parse()
-        .unwrap_or(9999999.99);
-
-    // クエリパラメータからソート順を決定
-    let sort_option = query.sort.as_deref().unwrap_or("momentum_desc");
-    let order_by_clause = match sort_option {
-        "responses_desc" => "response_count DESC",
-        "responses_asc" => "response_count ASC",
-        "momentum_asc" => "momentum ASC",
-        "last_activity_desc" => "p.last_activity_at DESC",
-        "last_activity_asc" => "p.last_activity_at ASC",
-        "created_at_desc" => "p.created_at DESC",
-        "created_at_asc" => "p.created_at ASC",
-        _ => "momentum DESC", // デフォルトは勢い順 (momentum_desc)
+    // クエリパラメータからソート順を決定 (指定がなければ板のデフォルトソート順)
+    let sort_option = query.sort.as_deref().unwrap_or(&default_sort);
+    // デフォルトは勢い順 (momentum_desc)
+    let order_by_clause = thread_list_order_clause(sort_option).unwrap_or("momentum DESC");
+
+    // 同じIDのスレ主による板の占有を見つけるため、一覧の対象 (現行スレッド) の中で
+    // display_user_id ごとのスレッド数をウィンドウ関数で数える (IDのないスレッドは対象外)
+    let op_stats_select = if query.with_op_stats.unwrap_or(false) {
+        r#",
+            CASE WHEN p.display_user_id IS NULL THEN NULL
+                 ELSE COUNT(*) OVER (PARTITION BY p.display_user_id) - 1
+            END as op_other_thread_count"#
+    } else {
+        ""
     };
 
     // SQLクエリを動的に構築
@@ -642,20 +1608,15 @@ pub async fn get_posts_by_board_id(
         SELECT
             p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at,
             p.last_activity_at, p.display_user_id, p.permanent_user_hash, p.permanent_ip_hash,
-            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level,
-            (1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) as response_count,
-            -- Momentum calculation (responses per day)
-            -- To avoid division by zero, if duration is less than a second, treat it as a small number.
-            LEAST(
-                CAST((1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) AS DOUBLE PRECISION) / GREATEST(EXTRACT(EPOCH FROM (NOW() - p.created_at)) / 86400.0, 0.00001),
-                {}
-            ) as momentum
+            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level,{}{}
         FROM posts p
         LEFT JOIN users u ON p.user_id = u.id
         WHERE p.board_id = $1 AND p.deleted_at IS NULL AND p.archived_at IS NULL
         ORDER BY {}
         "#,
-        momentum_cap, order_by_clause
+        post_momentum_select_sql(),
+        op_stats_select,
+        order_by_clause
     );
 
     let posts_with_details: Vec<PostDetails> = sqlx::query_as(&query_string)
@@ -666,109 +1627,250 @@ pub async fn get_posts_by_board_id(
     // PostWithCountに変換
     let response_posts: Vec<PostWithCount> = posts_with_details
         .into_iter()
-        .map(|p| {
-            let (display_level_at_creation, display_current_level, is_current_level_hidden) =
-                process_level_visibility(p.level_at_creation, p.level, threshold, is_admin);
-
-            let post = Post {
-                id: p.id,
-                title: p.title,
-                // スレッド一覧ページでは、レスアンカーがスレッド詳細ページへの絶対パスを指すように、
-                // linkify_body が生成した相対リンク (`href="#res-..."`) を置換します。
-                body: linkify_body(&p.body).replace(
-                    "href=\"#res-",
-                    // p.id は現在処理中のスレッドのIDです。
-                    &format!("href=\"/posts/{}#res-", p.id),
-                ),
-                author_name: p.author_name,
-                created_at: p.created_at,
-                updated_at: p.updated_at,
-                board_id: p.board_id,
-                deleted_at: p.deleted_at,
-                user_id: p.user_id,
-                archived_at: p.archived_at,
-                last_activity_at: p.last_activity_at,
-                display_user_id: p.display_user_id,
-                permanent_user_hash: p.permanent_user_hash,
-                permanent_ip_hash: p.permanent_ip_hash,
-                permanent_device_hash: p.permanent_device_hash,
-                level_at_creation: display_level_at_creation,
-                level: display_current_level,
-                is_current_level_hidden,
-            };
-
-            PostWithCount {
-                post,
-                response_count: p.response_count,
-                momentum: p.momentum,
-            }
-        })
+        .map(|p| post_details_to_post_with_count(p, threshold, is_admin))
         .collect();
 
     Ok(HttpResponse::Ok().json(response_posts))
 }
 
-#[get("")]
-pub async fn get_posts(
+/// [管理者用] 板の SUBJECT.TXT がどの順番で生成されるかをJSONで返します (専ブラ互換性の調査用)。
+/// フロントエンドの subject.txt は `GET /api/boards/{id}/posts` の既定の並び順 (勢い順) をそのまま使うため、
+/// ここでも同じクエリ・並び順を使い、SUBJECT.TXT の各行と同じ形式の文字列を添えて返します。
+#[get("/{id}/subject-debug")]
+pub async fn get_subject_debug(
     pool: web::Data<PgPool>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+    path: web::Path<PathInfo>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
 ) -> Result<HttpResponse, ServiceError> {
-    let threshold = get_level_display_threshold(pool.get_ref()).await?;
-    let is_admin = user.is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    let board_id = path.id;
 
-    let posts_with_levels = sqlx::query!(
+    let board_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM boards WHERE id = $1 AND deleted_at IS NULL) as "exists!""#,
+        board_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+    if !board_exists {
+        return Err(ServiceError::NotFound("Board not found".to_string()));
+    }
+
+    // get_posts_by_board_id の既定のソート (momentum_desc) と同じクエリ
+    let query_string = format!(
         r#"
         SELECT
             p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at,
             p.last_activity_at, p.display_user_id, p.permanent_user_hash, p.permanent_ip_hash,
-            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level as "level?"
+            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level,{}
         FROM posts p
         LEFT JOIN users u ON p.user_id = u.id
-        WHERE p.deleted_at IS NULL AND p.archived_at IS NULL
-        ORDER BY p.last_activity_at DESC
-        "#
-    )
-    .fetch_all(pool.get_ref()).await?;
+        WHERE p.board_id = $1 AND p.deleted_at IS NULL AND p.archived_at IS NULL
+        ORDER BY momentum DESC
+        "#,
+        post_momentum_select_sql()
+    );
+    let posts: Vec<PostDetails> = sqlx::query_as(&query_string)
+        .bind(board_id)
+        .fetch_all(pool.get_ref())
+        .await?;
 
-    let response_posts: Vec<Post> = posts_with_levels
+    let entries: Vec<models::SubjectDebugEntry> = posts
         .into_iter()
-        .map(|p| {
-            let (display_level_at_creation, display_current_level, is_current_level_hidden) =
-                process_level_visibility(p.level_at_creation, p.level, threshold, is_admin);
-            Post {
-                id: p.id,
+        .enumerate()
+        .map(|(index, p)| {
+            let dat_filename = format!("{}.dat", p.created_at.timestamp());
+            // フロントエンドの escapeDatField と同じエスケープ
+            let escaped_title = p
+                .title
+                .replace('&', "&amp;")
+                .replace('<', "&lt;")
+                .replace("\r\n", " ")
+                .replace('\n', " ");
+            models::SubjectDebugEntry {
+                position: index + 1,
+                post_id: p.id,
+                subject_line: format!("{}<>{} ({})", dat_filename, escaped_title, p.response_count),
+                dat_filename,
                 title: p.title,
-                body: p.body,
-                author_name: p.author_name,
+                response_count: p.response_count,
+                momentum: p.momentum,
                 created_at: p.created_at,
-                updated_at: p.updated_at,
-                board_id: p.board_id,
-                deleted_at: p.deleted_at,
-                user_id: p.user_id,
-                archived_at: p.archived_at,
                 last_activity_at: p.last_activity_at,
-                display_user_id: p.display_user_id,
-                permanent_user_hash: p.permanent_user_hash,
-                permanent_ip_hash: p.permanent_ip_hash,
-                permanent_device_hash: p.permanent_device_hash,
-                level_at_creation: display_level_at_creation,
-                level: display_current_level,
-                is_current_level_hidden,
             }
         })
         .collect();
 
-    Ok(HttpResponse::Ok().json(response_posts))
+    Ok(HttpResponse::Ok().json(entries))
 }
 
-/// タイムスタンプと板IDから特定のスレッドを検索します。
-/// 専ブラが `bbs.cgi` や `.dat` ファイルにアクセスする際のパフォーマンスを向上させるために使用されます。
-#[get("/by-timestamp/{timestamp}")]
-pub async fn get_post_by_timestamp(
+const DEFAULT_HOT_POSTS_LIMIT: i64 = 20;
+const MAX_HOT_POSTS_LIMIT: i64 = 100;
+
+const MAX_COMMENT_COUNT_QUERY_ITEMS: usize = 200;
+
+/// クライアントが把握しているレス数と比較し、レス数が変化したスレッドのみを返します。
+/// カタログ表示のポーリングで、変化のないスレッドの情報を転送しないために使用します。
+/// 削除されたスレッドは結果に含めません。
+#[post("/comment-counts")]
+pub async fn get_comment_count_deltas(
     pool: web::Data<PgPool>,
-    path: web::Path<TimestampPathInfo>,
-    query: web::Query<TimestampQueryInfo>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // レベル表示のために必要
+    payload: web::Json<Vec<models::KnownCommentCount>>,
+) -> Result<HttpResponse, ServiceError> {
+    if payload.len() > MAX_COMMENT_COUNT_QUERY_ITEMS {
+        return Err(ServiceError::BadRequest(format!(
+            "一度に問い合わせできるスレッドは{}件までです。",
+            MAX_COMMENT_COUNT_QUERY_ITEMS
+        )));
+    }
+    if payload.is_empty() {
+        return Ok(HttpResponse::Ok().json(Vec::<models::CommentCountDelta>::new()));
+    }
+
+    let post_ids: Vec<i32> = payload.iter().map(|item| item.id).collect();
+    let current_counts = sqlx::query!(
+        r#"
+        SELECT p.id, COUNT(c.id) as "comment_count!"
+        FROM posts p
+        LEFT JOIN comments c ON c.post_id = p.id
+        WHERE p.id = ANY($1) AND p.deleted_at IS NULL
+        GROUP BY p.id
+        "#,
+        &post_ids
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let known_counts: std::collections::HashMap<i32, i64> = payload
+        .iter()
+        .map(|item| (item.id, item.known_count))
+        .collect();
+    let deltas: Vec<models::CommentCountDelta> = current_counts
+        .into_iter()
+        .filter(|row| known_counts.get(&row.id) != Some(&row.comment_count))
+        .map(|row| models::CommentCountDelta {
+            id: row.id,
+            comment_count: row.comment_count,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(deltas))
+}
+
+/// 全板を横断して、アクティブなスレッドを勢い順に取得します。
+/// 勢いの計算は板ごとのスレッド一覧 (get_posts_by_board_id) と同じです。
+#[get("/hot")]
+pub async fn get_hot_posts(
+    pool: web::Data<PgPool>,
+    query: web::Query<HotPostsQueryParams>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user.is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_HOT_POSTS_LIMIT)
+        .clamp(1, MAX_HOT_POSTS_LIMIT);
+    let include_nsfw = query.include_nsfw.unwrap_or(false);
+
+    let query_string = format!(
+        r#"
+        SELECT
+            p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at,
+            p.last_activity_at, p.display_user_id, p.permanent_user_hash, p.permanent_ip_hash,
+            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level,{},
+            b.name as board_name
+        FROM posts p
+        JOIN boards b ON p.board_id = b.id
+        LEFT JOIN users u ON p.user_id = u.id
+        WHERE p.deleted_at IS NULL AND p.archived_at IS NULL
+          AND b.deleted_at IS NULL AND b.archived_at IS NULL
+          AND ($1 OR NOT b.is_nsfw)
+        ORDER BY momentum DESC, p.last_activity_at DESC
+        LIMIT $2
+        "#,
+        post_momentum_select_sql()
+    );
+
+    let hot_posts: Vec<HotPostDetails> = sqlx::query_as(&query_string)
+        .bind(include_nsfw)
+        .bind(limit)
+        .fetch_all(pool.get_ref())
+        .await?;
+
+    let response_posts: Vec<HotPostWithBoard> = hot_posts
+        .into_iter()
+        .map(|p| HotPostWithBoard {
+            post: post_details_to_post_with_count(p.details, threshold, is_admin),
+            board_name: p.board_name,
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response_posts))
+}
+
+#[get("")]
+pub async fn get_posts(
+    pool: web::Data<PgPool>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user.is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    let posts_with_levels = sqlx::query!(
+        r#"
+        SELECT
+            p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at,
+            p.last_activity_at, p.display_user_id, p.permanent_user_hash, p.permanent_ip_hash,
+            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level as "level?"
+        FROM posts p
+        LEFT JOIN users u ON p.user_id = u.id
+        WHERE p.deleted_at IS NULL AND p.archived_at IS NULL
+        ORDER BY p.last_activity_at DESC
+        "#
+    )
+    .fetch_all(pool.get_ref()).await?;
+
+    let response_posts: Vec<Post> = posts_with_levels
+        .into_iter()
+        .map(|p| {
+            let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+                process_level_visibility(p.level_at_creation, p.level, threshold, is_admin);
+            Post {
+                id: p.id,
+                title: p.title,
+                body: p.body,
+                author_name: p.author_name,
+                created_at: p.created_at,
+                updated_at: p.updated_at,
+                board_id: p.board_id,
+                deleted_at: p.deleted_at,
+                user_id: p.user_id,
+                archived_at: p.archived_at,
+                last_activity_at: p.last_activity_at,
+                display_user_id: p.display_user_id,
+                permanent_user_hash: p.permanent_user_hash,
+                permanent_ip_hash: p.permanent_ip_hash,
+                permanent_device_hash: p.permanent_device_hash,
+                level_at_creation: display_level_at_creation,
+                level: display_current_level,
+                is_current_level_hidden,
+            }
+        })
+        .collect();
+
+    Ok(HttpResponse::Ok().json(response_posts))
+}
+
+/// タイムスタンプと板IDから特定のスレッドを検索します。
+/// 専ブラが `bbs.cgi` や `.dat` ファイルにアクセスする際のパフォーマンスを向上させるために使用されます。
+#[get("/by-timestamp/{timestamp}")]
+pub async fn get_post_by_timestamp(
+    pool: web::Data<PgPool>,
+    path: web::Path<TimestampPathInfo>,
+    query: web::Query<TimestampQueryInfo>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // レベル表示のために必要
 ) -> Result<HttpResponse, ServiceError> {
     let timestamp_sec = path.timestamp;
     let board_id = query.board_id;
@@ -846,173 +1948,441 @@ pub async fn get_post_by_timestamp(
     Ok(HttpResponse::Ok().json(post))
 }
 
-#[get("/{id}")]
-pub async fn get_post_by_id(
+// get_post_by_timestamp_any_board のレスポンス
+#[derive(serde::Serialize)]
+struct TimestampResolvedPost {
+    board_id: Option<i32>,
+    post: Post,
+    // 同じ秒に作成されたスレッドの数 (全板合計)
+    match_count: i64,
+    // 複数のスレッドが該当した場合の注記
+    #[serde(skip_serializing_if = "Option::is_none")]
+    note: Option<String>,
+}
+
+/// タイムスタンプのみから、全板を対象にスレッドを検索します (板IDが分からない専ブラからの .dat 解決用)。
+/// 同じ秒に作成されたスレッドが複数の板にある場合は、最も早く作成されたものを返し、注記を添えます。
+#[get("/by-timestamp/{timestamp}/any")]
+pub async fn get_post_by_timestamp_any_board(
     pool: web::Data<PgPool>,
-    path: web::Path<PathInfo>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+    path: web::Path<TimestampPathInfo>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // レベル表示のために必要
 ) -> Result<HttpResponse, ServiceError> {
-    let post_id = path.id;
+    let start_time_utc = Utc
+        .timestamp_opt(path.timestamp, 0)
+        .single()
+        .ok_or_else(|| ServiceError::BadRequest("Invalid timestamp format".to_string()))?;
+    let end_time_utc = start_time_utc + chrono::Duration::seconds(1);
+
     let threshold = get_level_display_threshold(pool.get_ref()).await?;
-    let is_admin = user
-        .as_ref()
-        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+    let is_admin = user.is_some_and(|u| matches!(u.role, middleware::Role::Admin));
 
-    // 投稿情報と、それが属する板の作成者IDを一度に取得する
-    let post_details = sqlx::query!(
+    let post_with_level = sqlx::query!(
         r#"
         SELECT
-            p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id,
-            p.deleted_at, p.archived_at, p.last_activity_at, p.display_user_id,
-            p.permanent_user_hash, p.level_at_creation, p.permanent_ip_hash, p.permanent_device_hash,
-            p.user_id,
-            u.level as "level?",
-            b.created_by as "board_creator_id",
-            b.name as "board_name",
-            b.moderation_type as "moderation_type: models::BoardModerationType"
+            p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at,
+            p.last_activity_at, p.display_user_id, p.permanent_user_hash, p.permanent_ip_hash,
+            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level as "level?",
+            COUNT(*) OVER () as "match_count!"
         FROM posts p
-        LEFT JOIN users u ON p.user_id = u.id
         JOIN boards b ON p.board_id = b.id
-        WHERE p.id = $1 AND p.deleted_at IS NULL
+        LEFT JOIN users u ON p.user_id = u.id
+        WHERE p.created_at >= $1
+          AND p.created_at < $2
+          AND p.deleted_at IS NULL
+          AND b.deleted_at IS NULL
+        ORDER BY p.created_at ASC, p.id ASC
+        LIMIT 1
         "#,
-        post_id
+        start_time_utc,
+        end_time_utc
     )
     .fetch_optional(pool.get_ref())
     .await?
-    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
-
-    // モデレーション権限を計算
-    let can_moderate = user.as_ref().is_some_and(|u| {
-        let is_board_creator = post_details.board_creator_id == Some(u.user_id);
-        // βタイプの板では、スレ主もモデレーション権限を持つ
-        let is_thread_creator_on_beta_board = post_details.moderation_type
-            == models::BoardModerationType::Beta
-            && post_details.user_id == Some(u.user_id);
-
-        is_admin || is_board_creator || is_thread_creator_on_beta_board
-    });
+    .ok_or_else(|| ServiceError::NotFound("Thread not found for the given timestamp.".to_string()))?;
 
-    // 表示レベルを計算
     let (display_level_at_creation, display_current_level, is_current_level_hidden) =
         process_level_visibility(
-            post_details.level_at_creation,
-            post_details.level,
+            post_with_level.level_at_creation,
+            post_with_level.level,
             threshold,
             is_admin,
         );
 
     let post = Post {
-        id: post_details.id,
-        title: post_details.title,              // タイトルはサニタイズ済み
-        body: linkify_body(&post_details.body), // 本文をリンク化
-        author_name: post_details.author_name,
-        created_at: post_details.created_at,
-        updated_at: post_details.updated_at,
-        board_id: post_details.board_id,
-        deleted_at: post_details.deleted_at,
-        user_id: post_details.user_id,
-        archived_at: post_details.archived_at,
-        last_activity_at: post_details.last_activity_at,
-        display_user_id: post_details.display_user_id,
-        permanent_user_hash: post_details.permanent_user_hash,
-        permanent_ip_hash: post_details.permanent_ip_hash,
-        permanent_device_hash: post_details.permanent_device_hash,
+        id: post_with_level.id,
+        title: post_with_level.title,
+        body: linkify_body(&post_with_level.body),
+        author_name: post_with_level.author_name,
+        created_at: post_with_level.created_at,
+        updated_at: post_with_level.updated_at,
+        board_id: post_with_level.board_id,
+        deleted_at: post_with_level.deleted_at,
+        user_id: post_with_level.user_id,
+        archived_at: post_with_level.archived_at,
+        last_activity_at: post_with_level.last_activity_at,
+        display_user_id: post_with_level.display_user_id,
+        permanent_user_hash: post_with_level.permanent_user_hash,
+        permanent_ip_hash: post_with_level.permanent_ip_hash,
+        permanent_device_hash: post_with_level.permanent_device_hash,
         level_at_creation: display_level_at_creation,
         level: display_current_level,
         is_current_level_hidden,
     };
 
-    let response_post = PostDetailResponse {
-        post,
-        can_moderate,
-        // SQLのJOINにより、これらの値は常に存在するため、unwrap()で安全に値を取り出せます。
-        board_id: post_details.board_id.unwrap(),
-        board_name: post_details.board_name,
-    };
+    let match_count = post_with_level.match_count;
+    let note = (match_count > 1).then(|| {
+        format!(
+            "同じ時刻に作成されたスレッドが{}件あります。最も早く作成されたスレッドを返しています。",
+            match_count
+        )
+    });
 
-    Ok(HttpResponse::Ok().json(response_post))
+    Ok(HttpResponse::Ok().json(TimestampResolvedPost {
+        board_id: post.board_id,
+        post,
+        match_count,
+        note,
+    }))
 }
 
-#[post("")]
-pub async fn create_post(
+/// [管理者/板作成者用] スレッド投稿者の識別情報の要約を取得します。
+/// 板作成者がモデレーションの判断に使えるよう、暗号化された個人情報は復号せずに
+/// 表示用IDの各部分と、板内で同じ端末・IPを使っている別ユーザーの数だけを返します。
+#[get("/{id}/posts/{post_id}/identity-summary")]
+pub async fn get_post_identity_summary(
     pool: web::Data<PgPool>,
-    http_client: web::Data<reqwest::Client>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // Require authentication
-    post_data: web::Json<CreatePostRequest>,
-    req: HttpRequest,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<BoardPostPathInfo>,
 ) -> Result<HttpResponse, ServiceError> {
-    // 最初にバリデーションを実行
-    post_data.validate()?;
-
-    // 管理者でない場合、予約文字が含まれていないかチェック
-    if !user
-        .as_ref()
-        .is_some_and(|u| matches!(u.role, middleware::Role::Admin))
-    {
-        if let Some(name) = &post_data.author_name {
-            if name.contains('☕') {
-                return Err(ServiceError::Forbidden(
-                    "".to_string(),
-                ));
-            }
-        }
-    }
-    // --- START: Refactored Authentication & Token Logic ---
-    let user_role_opt = user.as_ref().map(|u| u.role);
-    let is_admin = user_role_opt == Some(middleware::Role::Admin);
-    let threshold = get_level_display_threshold(pool.get_ref()).await?;
-    let (user_id, new_session_cookie, final_body) =
-        authenticate_poster(pool.get_ref(), user, &post_data.body).await?;
-    // --- END: Refactored Authentication & Token Logic ---
-
-    // 認証ヘルパーの後に `into_inner` を呼び出し、所有権を取得します
-    let mut validated_post_data = post_data.into_inner();
-    // 認証ヘルパーが処理した後の本文で上書きします
-    validated_post_data.body = final_body;
-
-    let (truncated_ip, raw_ip) = get_ip_address(&req);
+    let board_id = path.id;
+    let post_id = path.post_id;
 
-    let board = sqlx::query_as!(
-        Board,
-        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
-        validated_post_data.board_id
+    let board_creator_id: Option<i32> = sqlx::query_scalar!(
+        "SELECT created_by FROM boards WHERE id = $1 AND deleted_at IS NULL",
+        board_id
     )
     .fetch_optional(pool.get_ref())
     .await?
     .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
 
-    // アーカイブされた板には新規スレッドを作成できない
-    if board.archived_at.is_some() {
+    if !matches!(user.role, middleware::Role::Admin) && board_creator_id != Some(user.user_id) {
         return Err(ServiceError::Forbidden(
-            "この板はアーカイブされているため、新しいスレッドを作成できません。".to_string(),
+            "この板を管理する権限がありません。".to_string(),
         ));
     }
 
-    // --- START: ID生成ロジック ---
-    // ユーザーIDから永続的な識別子と現在のレベルを取得
-    let user_info = sqlx::query!("SELECT email, level FROM users WHERE id = $1", user_id)
-        .fetch_one(pool.get_ref())
-        .await?;
-    let user_email = user_info.email;
-    let level_at_creation = Some(user_info.level);
+    let post = sqlx::query!(
+        r#"
+        SELECT display_user_id, display_id_user, display_id_ip, display_id_device,
+               permanent_user_hash, permanent_ip_hash, permanent_device_hash
+        FROM posts
+        WHERE id = $1 AND board_id = $2
+        "#,
+        post_id,
+        board_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたスレッドが見つかりません。".to_string()))?;
+
+    // 板内のスレッド本文とレスを対象に、同じユーザー・端末・IPの書き込みを集計する
+    let counts = sqlx::query!(
+        r#"
+        WITH board_entries AS (
+            SELECT p.permanent_user_hash, p.permanent_ip_hash, p.permanent_device_hash
+            FROM posts p
+            WHERE p.board_id = $1
+            UNION ALL
+            SELECT c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash
+            FROM comments c
+            JOIN posts p ON c.post_id = p.id
+            WHERE p.board_id = $1
+        )
+        SELECT
+            COUNT(*) FILTER (WHERE permanent_user_hash = $2) as "same_user_post_count!",
+            COUNT(DISTINCT permanent_user_hash) FILTER (
+                WHERE permanent_device_hash = $3 AND permanent_user_hash IS DISTINCT FROM $2
+            ) as "other_users_same_device!",
+            COUNT(DISTINCT permanent_user_hash) FILTER (
+                WHERE permanent_ip_hash = $4 AND permanent_user_hash IS DISTINCT FROM $2
+            ) as "other_users_same_ip!"
+        FROM board_entries
+        "#,
+        board_id,
+        post.permanent_user_hash,
+        post.permanent_device_hash,
+        post.permanent_ip_hash
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(models::PostIdentitySummary {
+        post_id,
+        board_id,
+        display_user_id: post.display_user_id,
+        display_id_user: post.display_id_user,
+        display_id_ip: post.display_id_ip,
+        display_id_device: post.display_id_device,
+        same_user_post_count: counts.same_user_post_count,
+        other_users_same_device: counts.other_users_same_device,
+        other_users_same_ip: counts.other_users_same_ip,
+    }))
+}
+
+/// 板IDと板内のスレッド番号 (`board_sequence`) からスレッドを取得します。
+#[get("/{board_id}/threads/{seq}")]
+pub async fn get_post_by_board_sequence(
+    pool: web::Data<PgPool>,
+    path: web::Path<BoardSequencePathInfo>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // レベル表示のために必要
+) -> Result<HttpResponse, ServiceError> {
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user.is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    let post_with_level = sqlx::query!(
+        r#"
+        SELECT
+            p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at,
+            p.last_activity_at, p.display_user_id, p.permanent_user_hash, p.permanent_ip_hash,
+            p.permanent_device_hash, p.user_id, p.level_at_creation, u.level as "level?"
+        FROM posts p
+        LEFT JOIN users u ON p.user_id = u.id
+        WHERE p.board_id = $1
+          AND p.board_sequence = $2
+          AND p.deleted_at IS NULL
+        "#,
+        path.board_id,
+        path.seq
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された番号のスレッドが見つかりません。".to_string()))?;
+
+    let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+        process_level_visibility(
+            post_with_level.level_at_creation,
+            post_with_level.level,
+            threshold,
+            is_admin,
+        );
+
+    let post = Post {
+        id: post_with_level.id,
+        title: post_with_level.title,
+        body: linkify_body(&post_with_level.body),
+        author_name: post_with_level.author_name,
+        created_at: post_with_level.created_at,
+        updated_at: post_with_level.updated_at,
+        board_id: post_with_level.board_id,
+        deleted_at: post_with_level.deleted_at,
+        user_id: post_with_level.user_id,
+        archived_at: post_with_level.archived_at,
+        last_activity_at: post_with_level.last_activity_at,
+        display_user_id: post_with_level.display_user_id,
+        permanent_user_hash: post_with_level.permanent_user_hash,
+        permanent_ip_hash: post_with_level.permanent_ip_hash,
+        permanent_device_hash: post_with_level.permanent_device_hash,
+        level_at_creation: display_level_at_creation,
+        level: display_current_level,
+        is_current_level_hidden,
+    };
+
+    Ok(HttpResponse::Ok().json(post))
+}
+
+#[get("/{id}")]
+pub async fn get_post_by_id(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let post_id = path.id;
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    // 投稿情報と、それが属する板の作成者IDを一度に取得する
+    let post_details = sqlx::query!(
+        r#"
+        SELECT
+            p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id,
+            p.deleted_at, p.archived_at, p.last_activity_at, p.display_user_id,
+            p.permanent_user_hash, p.level_at_creation, p.permanent_ip_hash, p.permanent_device_hash,
+            p.user_id,
+            u.level as "level?",
+            b.created_by as "board_creator_id",
+            b.name as "board_name",
+            b.moderation_type as "moderation_type: models::BoardModerationType"
+        FROM posts p
+        LEFT JOIN users u ON p.user_id = u.id
+        JOIN boards b ON p.board_id = b.id
+        WHERE p.id = $1 AND p.deleted_at IS NULL
+        "#,
+        post_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+
+    // モデレーション権限を計算
+    let can_moderate = compute_thread_permissions(
+        user.as_deref(),
+        post_details.board_creator_id,
+        post_details.user_id,
+        post_details.moderation_type,
+    )
+    .can_moderate;
+
+    // 表示レベルを計算
+    let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+        process_level_visibility(
+            post_details.level_at_creation,
+            post_details.level,
+            threshold,
+            is_admin,
+        );
+
+    let post = Post {
+        id: post_details.id,
+        title: post_details.title,              // タイトルはサニタイズ済み
+        body: linkify_body(&post_details.body), // 本文をリンク化
+        author_name: post_details.author_name,
+        created_at: post_details.created_at,
+        updated_at: post_details.updated_at,
+        board_id: post_details.board_id,
+        deleted_at: post_details.deleted_at,
+        user_id: post_details.user_id,
+        archived_at: post_details.archived_at,
+        last_activity_at: post_details.last_activity_at,
+        display_user_id: post_details.display_user_id,
+        permanent_user_hash: post_details.permanent_user_hash,
+        permanent_ip_hash: post_details.permanent_ip_hash,
+        permanent_device_hash: post_details.permanent_device_hash,
+        level_at_creation: display_level_at_creation,
+        level: display_current_level,
+        is_current_level_hidden,
+    };
+
+    let response_post = PostDetailResponse {
+        post,
+        can_moderate,
+        // SQLのJOINにより、これらの値は常に存在するため、unwrap()で安全に値を取り出せます。
+        board_id: post_details.board_id.unwrap(),
+        board_name: post_details.board_name,
+    };
+
+    Ok(HttpResponse::Ok().json(response_post))
+}
+
+#[post("")]
+pub async fn create_post(
+    pool: web::Data<PgPool>,
+    http_client: web::Data<reqwest::Client>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // Require authentication
+    post_data: web::Json<CreatePostRequest>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    // 最初にバリデーションを実行
+    post_data.validate()?;
+    // フィンガープリントは端末情報としてハッシュ化・暗号化して保存されるため、サイズを制限する
+    if let Some(fingerprint) = &post_data.fingerprint {
+        verification::check_fingerprint_size(fingerprint.len())?;
+    }
+
+    // 管理者でない場合、予約文字が含まれていないかチェック
+    if !user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin))
+    {
+        if let Some(name) = &post_data.author_name {
+            check_submitted_author_name(name)?;
+        }
+    }
+
+    // 外部API (proxycheck) の呼び出しや認証試行の記録などの重い処理の前に、
+    // 安価なチェック (板の存在・アーカイブ状態) を済ませておく
+    let board = sqlx::query_as!(
+        Board,
+        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
+        post_data.board_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    // アーカイブされた板には新規スレッドを作成できない
+    if board.archived_at.is_some() {
+        return Err(ServiceError::Forbidden(
+            "この板はアーカイブされているため、新しいスレッドを作成できません。".to_string(),
+        ));
+    }
+    check_board_posting_not_frozen(&board)?;
+
+    // --- START: Refactored Authentication & Token Logic ---
+    let user_role_opt = user.as_ref().map(|u| u.role);
+    let is_admin = user_role_opt == Some(middleware::Role::Admin);
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    // 拒否される書き込みで連携トークンを消費したりセッションを作成したりしないよう、
+    // 認証の前にトークンを取り除いた本文をチェックする
+    let precheck_body = sanitize_body_without_linking_token(&post_data.body)?;
+    if !is_admin {
+        // 作成されたばかりのアカウントからの書き込みを制限する (管理者は除外)
+        let mut conn = pool.acquire().await?;
+        let session_user_id = user.as_ref().map(|u| u.user_id);
+        if let Some(created_at) =
+            poster_account_created_at(&mut conn, session_user_id, &post_data.body).await?
+        {
+            check_min_account_age(created_at)?;
+        }
+        check_quote_count(&precheck_body)?;
+    }
+    let (user_id, new_session_cookie, final_body) =
+        authenticate_poster(pool.get_ref(), user, &post_data.body).await?;
+    // --- END: Refactored Authentication & Token Logic ---
+
+    // 認証ヘルパーの後に `into_inner` を呼び出し、所有権を取得します
+    let mut validated_post_data = post_data.into_inner();
+    // 認証ヘルパーが処理した後の本文で上書きします
+    // 本文をサニタイズし、投稿できる内容かチェック
+    validated_post_data.title = clean(&validated_post_data.title);
+    validated_post_data.body = sanitize_submitted_body(&final_body)?;
+    let body_hash = body_content_hash(&validated_post_data.body);
+
+    let (truncated_ip, raw_ip) = get_ip_address(&req);
+
+    // --- START: ID生成ロジック ---
+    // ユーザーIDから永続的な識別子と現在のレベルを取得
+    let user_info = sqlx::query!("SELECT email, level FROM users WHERE id = $1", user_id)
+        .fetch_one(pool.get_ref())
+        .await?;
+    let user_email = user_info.email;
+    let level_at_creation = Some(user_info.level);
 
-    let user_identifier = &user_email;
     let device_info: &str = {
-        log::info!("[DEVICE DIAG] --- Start Device Info Acquisition ---");
         let fingerprint = validated_post_data.fingerprint.as_deref();
-        log::info!("[DEVICE DIAG] Fingerprint from payload: {:?}", fingerprint);
         let user_agent = req.headers().get("User-Agent").and_then(|ua| ua.to_str().ok());
-        log::info!("[DEVICE DIAG] User-Agent from headers: {:?}", user_agent);
         let final_device_info = fingerprint.or(user_agent).unwrap_or("unknown");
-        log::info!(
-            "[DEVICE DIAG] Final device_info chosen: '{}'",
-            final_device_info
-        );
+        if is_verbose_diag_logging_enabled() {
+            log::info!("[DEVICE DIAG] Fingerprint from payload: {:?}", fingerprint);
+            log::info!("[DEVICE DIAG] User-Agent from headers: {:?}", user_agent);
+            log::info!(
+                "[DEVICE DIAG] Final device_info chosen: '{}'",
+                final_device_info
+            );
+        }
         final_device_info
     };
+    let user_identifier = &poster_identifier(&user_email, &truncated_ip, device_info);
 
     let identity_hashes =
-        identity::generate_identity_hashes(user_identifier, &truncated_ip, device_info);
+        identity::generate_identity_hashes_for_board(
+            user_identifier,
+            &truncated_ip,
+            device_info,
+            board.per_board_ids.then_some(board.id),
+        );
     // --- END: ID生成ロジック ---
 
     // トランザクションを開始し、すべてのチェックと作成をアトミックに行う
@@ -1031,19 +2401,15 @@ pub async fn create_post(
             role: user_role_opt,
             ip_address: truncated_ip.clone(),
             raw_ip_address: Some(raw_ip.clone()),
-            captcha_token: None,
+            captcha_token: board_captcha_token(&board, &validated_post_data.captcha_token)?,
             fingerprint_data: fingerprint_value,
         };
         let (result, new_attempt_id) =
             verification::perform_verification(&mut tx, http_client.get_ref(), verification_input)
                 .await?;
-        attempt_id = Some(new_attempt_id);
+        attempt_id = new_attempt_id;
         if !result.is_success {
-            return Err(ServiceError::Forbidden(
-                result
-                    .rejection_reason
-                    .unwrap_or_else(|| "不正なリクエストとしてブロックされました。".to_string()),
-            ));
+            return Err(reject_failed_verification(tx, result.rejection_reason).await);
         }
     }
     // --- END: IP評価 ---
@@ -1059,6 +2425,20 @@ pub async fn create_post(
     )
     .await?;
 
+    // --- START: メンバー限定の板の参加チェック ---
+    check_board_membership(&mut tx, &board, user_id, is_admin).await?;
+
+    // --- START: 同一内容の連続投稿チェック (管理者は除外) ---
+    if !is_admin {
+        check_duplicate_content(
+            &mut tx,
+            &body_hash,
+            &identity_hashes.permanent_user_hash,
+            &identity_hashes.permanent_device_hash,
+        )
+        .await?;
+    }
+
     // --- START: レート制限チェック ---
     rate_limiter::check_and_track_rate_limits(
         &mut tx,
@@ -1069,34 +2449,54 @@ pub async fn create_post(
     )
     .await?;
 
-    // Sanitize body, title, and author_name
-    validated_post_data.title = clean(&validated_post_data.title);
-    validated_post_data.body = clean(&validated_post_data.body);
-
-    // Prevent users from accidentally posting a raw token
-    if is_potentially_exposed_token(&validated_post_data.body) {
-        return Err(ServiceError::BadRequest(
-            "連携トークンを本文に貼り付ける際は、!token(...) の形式で貼り付けてください。"
-                .to_string(),
-        ));
-    }
-
     let author_name = validated_post_data
         .author_name
         .filter(|s| !s.trim().is_empty())
         .map(|s| clean(&s).to_owned())
-        .unwrap_or_else(|| board.default_name.clone());
+        .unwrap_or_else(|| board_default_name(&board));
 
     // --- START: Transaction and Identity Encryption ---
     // Encrypt sensitive information before storing
-    let encrypted_email = encryption::encrypt(user_identifier)?; // emailは変わらない
+    let encrypted_email = encryption::encrypt(&user_email)?; // emailは変わらない
     let encrypted_ip = encryption::encrypt(&truncated_ip)?; // 切り詰め済みのIPを暗号化
     let encrypted_device_info = encryption::encrypt(device_info)?;
 
+    // 板ごとのスレッド番号 (1から始まる連番) を採番する。
+    // 板の行を更新することで行ロックがかかり、同じ板への同時作成はコミットまで直列化される。
+    // トランザクションがロールバックされた場合はカウンターも元に戻るため、番号に欠番は生じない。
+    let board_sequence = sqlx::query_scalar!(
+        "UPDATE boards SET next_seq = next_seq + 1 WHERE id = $1 RETURNING next_seq",
+        validated_post_data.board_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // --- START: タイトルの重複チェック ---
+    // 上の採番で板の行がロックされているため、同じ板への同時作成と競合せずに判定できる
+    if board.unique_titles {
+        let title_exists: bool = sqlx::query_scalar!(
+            r#"
+            SELECT EXISTS (
+                SELECT 1 FROM posts
+                WHERE board_id = $1 AND title = $2 AND deleted_at IS NULL AND archived_at IS NULL
+            ) as "exists!"
+            "#,
+            board.id,
+            validated_post_data.title
+        )
+        .fetch_one(&mut *tx)
+        .await?;
+        if title_exists {
+            return Err(ServiceError::BadRequest(
+                "同じタイトルのスレッドが既にこの板にあります。".to_string(),
+            ));
+        }
+    }
+
     let mut new_post = sqlx::query_as!(Post,
         r#"
-        INSERT INTO posts (title, body, board_id, author_name, user_id, level_at_creation, last_activity_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, display_id_user, display_id_ip, display_id_device, verification_attempt_id)
-        VALUES ($1, $2, $3, $4, $5, $6, NOW(), $7, $8, $9, $10, $11, $12, $13, $14)
+        INSERT INTO posts (title, body, board_id, author_name, user_id, level_at_creation, last_activity_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, display_id_user, display_id_ip, display_id_device, verification_attempt_id, body_hash, board_sequence)
+        VALUES ($1, $2, $3, $4, $5, $6, NOW(), $7, $8, $9, $10, $11, $12, $13, $14, $15, $16)
         RETURNING id, title, body, author_name, created_at, updated_at, board_id as "board_id: _",
             NULL as "deleted_at: _", user_id, NULL as "archived_at: _", last_activity_at,
             display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, level_at_creation,
@@ -1115,7 +2515,9 @@ pub async fn create_post(
         identity_hashes.display_id_user_part,
         identity_hashes.display_id_ip_part,
         identity_hashes.display_id_device_part, // 13
-        attempt_id // 14
+        attempt_id, // 14
+        body_hash, // 15
+        board_sequence // 16
     )
     .fetch_one(&mut *tx)
     .await?;
@@ -1167,1147 +2569,3791 @@ pub async fn create_post(
     Ok(response_builder.json(new_post))
 }
 
-#[post("/comments")]
-pub async fn create_comment(
-    pool: web::Data<PgPool>,
-    http_client: web::Data<reqwest::Client>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
-    req: HttpRequest,
-    comment_data: web::Json<CreateCommentRequest>,
+/// 本文を投稿せずに、実際の表示結果をプレビューします。
+/// 連携トークンの除去、サニタイズ、アンカーのリンク化を投稿時と同じ順序で行います。DBには一切アクセスしません。
+#[post("/render-preview")]
+pub async fn render_preview(
+    payload: web::Json<models::RenderPreviewRequest>,
 ) -> Result<HttpResponse, ServiceError> {
-    // 最初にバリデーションを実行
-    comment_data.validate()?;
+    let payload = payload.into_inner();
 
-    // 管理者でない場合、予約文字が含まれていないかチェック
-    if !user
-        .as_ref()
-        .is_some_and(|u| matches!(u.role, middleware::Role::Admin))
-    {
-        if let Some(name) = &comment_data.author_name {
-            if name.contains('☕') {
-                return Err(ServiceError::Forbidden(
-                    "".to_string(),
-                ));
-            }
-        }
-    }
-    // --- START: Refactored Authentication & Token Logic ---
-    let user_role_opt = user.as_ref().map(|u| u.role);
-    let is_admin = user_role_opt == Some(middleware::Role::Admin);
-    let threshold = get_level_display_threshold(pool.get_ref()).await?;
-    let (user_id, new_session_cookie, final_body) =
-        authenticate_poster(pool.get_ref(), user, &comment_data.body).await?;
-    // --- END: Refactored Authentication & Token Logic ---
-
-    // 認証ヘルパーの後に `into_inner` を呼び出し、所有権を取得します
-    let mut validated_comment_data = comment_data.into_inner();
-    // 認証ヘルパーが処理した後の本文で上書きします
-    validated_comment_data.body = final_body;
-
-    let (truncated_ip, raw_ip) = get_ip_address(&req);
+    // 投稿時と同じチェックを事前検証APIと共通の処理で行う。
+    // プレビューにはログイン情報を含めないため、一般ユーザーとしてチェックする
+    let validation_errors = collect_submission_issues(
+        validate_preview_body(payload.kind, &payload.body),
+        None,
+        None,
+        &payload.body,
+        None,
+        false,
+    );
 
-    // スレッドの存在と所属する板のID、アーカイブ状態を確認
-    let post_info = sqlx::query!(
-        "SELECT board_id, archived_at FROM posts WHERE id = $1 AND deleted_at IS NULL",
-        validated_comment_data.post_id
-    )
-    .fetch_optional(pool.get_ref())
-    .await?
-    .ok_or_else(|| ServiceError::NotFound("指定されたスレッドが見つかりません。".to_string()))?;
+    let (linking_token, cleaned_body) = extract_and_remove_linking_token(&payload.body);
+    let contains_linking_token = linking_token.is_some();
+    let body = if contains_linking_token && cleaned_body.is_empty() {
+        token_only_post_body().unwrap_or_default()
+    } else if contains_linking_token {
+        cleaned_body
+    } else {
+        payload.body
+    };
+    // チェックに失敗する本文でも表示結果を確認できるよう、正規化とサニタイズのみ行った本文を表示する
+    let sanitized_body = clean_submitted_body(&body);
+    let is_potentially_exposed_token = is_potentially_exposed_token(&sanitized_body);
+
+    Ok(HttpResponse::Ok().json(models::RenderPreviewResponse {
+        rendered_html: linkify_body(&sanitized_body),
+        contains_linking_token,
+        is_potentially_exposed_token,
+        validation_errors,
+    }))
+}
 
-    // 既に過去ログ化されている場合は書き込みを拒否
-    if post_info.archived_at.is_some() {
-        return Err(ServiceError::BadRequest(
-            "このスレッドは過去ログ化されており、新規の書き込みはできません。".to_string(),
-        ));
+/// プレビュー対象の本文を、実際の投稿リクエストと同じバリデーションにかけます。
+/// プレビューには本文以外の入力が含まれないため、本文に対するエラーのみを返します。
+fn validate_preview_body(
+    kind: models::PreviewBodyKind,
+    body: &str,
+) -> Result<(), validator::ValidationErrors> {
+    let result = match kind {
+        models::PreviewBodyKind::Post => CreatePostRequest {
+            title: String::new(),
+            body: body.to_string(),
+            author_name: None,
+            board_id: 0,
+            fingerprint: None,
+            captcha_token: None,
+        }
+        .validate(),
+        models::PreviewBodyKind::Comment => CreateCommentRequest {
+            body: body.to_string(),
+            author_name: None,
+            post_id: 0,
+            fingerprint: None,
+            captcha_token: None,
+            quote_response_number: None,
+        }
+        .validate(),
+    };
+    let Err(errors) = result else {
+        return Ok(());
+    };
+    let Some(body_errors) = errors.field_errors().get("body").copied() else {
+        return Ok(());
+    };
+    let mut only_body_errors = validator::ValidationErrors::new();
+    for error in body_errors {
+        only_body_errors.add("body", error.clone());
     }
+    Err(only_body_errors)
+}
 
-    // 板の情報を取得
-    let board = sqlx::query_as!(
-        Board,
-        // moderation_type を追加
-        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
-        post_info.board_id,
-    )
-    .fetch_optional(pool.get_ref())
-    .await?
-    .ok_or_else(|| ServiceError::NotFound("スレッドが属する板が見つかりません。".to_string()))?;
+/// 投稿時と同じく、本文の空白を正規化してからサニタイズします (投稿できる内容かのチェックは行わない)。
+fn clean_submitted_body(body: &str) -> String {
+    if is_body_whitespace_normalization_enabled() {
+        clean(&normalize_body_whitespace(body))
+    } else {
+        clean(body)
+    }
+}
 
-    // 本文をサニタイズ
-    validated_comment_data.body = clean(&validated_comment_data.body);
+/// 連携トークンを取り除いた後の本文に対して、空白の正規化とサニタイズを行い、投稿できる内容かチェックします。
+/// `create_post` / `create_comment` と事前検証API (`validate_post` / `validate_comment`) で共通の処理です。
+fn sanitize_submitted_body(body: &str) -> Result<String, ServiceError> {
+    let sanitized_body = clean_submitted_body(body);
 
     // Prevent users from accidentally posting a raw token
-    if is_potentially_exposed_token(&validated_comment_data.body) {
+    if is_potentially_exposed_token(&sanitized_body) {
         return Err(ServiceError::BadRequest(
             "連携トークンを本文に貼り付ける際は、!token(...) の形式で貼り付けてください。"
                 .to_string(),
         ));
     }
+    check_body_min_length(&sanitized_body)?;
+    Ok(sanitized_body)
+}
 
-    // 投稿者名が指定されていなければ、板のデフォルト名を使用
-    let author_name = validated_comment_data
-        .author_name
-        .filter(|s| !s.trim().is_empty())
-        .map(|s| clean(&s).to_owned())
-        .unwrap_or_else(|| board.default_name.clone());
-
-    // --- START: ID生成ロジック ---
-    // ユーザーIDから永続的な識別子（メールアドレス）と現在のレベルを取得
-    let user_info = sqlx::query!("SELECT email, level FROM users WHERE id = $1", user_id)
-        .fetch_one(pool.get_ref())
-        .await?;
-    let user_email = user_info.email;
-    let level_at_creation = Some(user_info.level);
-
-    let user_identifier = &user_email;
-    let device_info: &str = {
-        log::info!("[DEVICE DIAG] --- Start Device Info Acquisition ---");
-        let fingerprint = validated_comment_data.fingerprint.as_deref();
-        log::info!("[DEVICE DIAG] Fingerprint from payload: {:?}", fingerprint);
-        let user_agent = req.headers().get("User-Agent").and_then(|ua| ua.to_str().ok());
-        log::info!("[DEVICE DIAG] User-Agent from headers: {:?}", user_agent);
-        let final_device_info = fingerprint.or(user_agent).unwrap_or("unknown");
-        log::info!(
-            "[DEVICE DIAG] Final device_info chosen: '{}'",
-            final_device_info
-        );
-        final_device_info
+/// 連携トークンを取り除いた本文に対して `sanitize_submitted_body` を行います。
+/// 書き込み時は連携トークンを消費する前に、事前検証APIではDBを使わずに、投稿時と同じ本文のチェックを行うために使います。
+fn sanitize_body_without_linking_token(body: &str) -> Result<String, ServiceError> {
+    let (linking_token, cleaned_body) = extract_and_remove_linking_token(body);
+    let body_after_token = if linking_token.is_some() && cleaned_body.is_empty() {
+        token_only_post_body()?
+    } else if linking_token.is_some() {
+        cleaned_body
+    } else {
+        body.to_string()
     };
+    sanitize_submitted_body(&body_after_token)
+}
 
-    let identity_hashes =
-        identity::generate_identity_hashes(user_identifier, &truncated_ip, device_info);
-    // --- END: ID生成ロジック ---
-
-    // トランザクションを開始
-    let mut tx = pool.begin().await?;
+/// 投稿時に `ServiceError` として返されるエラーを、事前検証APIのレスポンス用に変換します。
+fn submission_issue_from_error(error: ServiceError) -> models::SubmissionValidationIssue {
+    let status = error.status_code().as_u16();
+    let details = match &error {
+        ServiceError::ValidationFailed(val_errors) => {
+            let details: serde_json::Map<String, serde_json::Value> = val_errors
+                .field_errors()
+                .into_iter()
+                .map(|(field, field_errors)| {
+                    let messages: Vec<String> = field_errors
+                        .iter()
+                        .filter_map(|e| e.message.as_ref().map(|m| m.to_string()))
+                        .collect();
+                    (field.to_string(), serde_json::Value::from(messages))
+                })
+                .collect();
+            Some(serde_json::Value::Object(details))
+        }
+        _ => None,
+    };
+    models::SubmissionValidationIssue {
+        status,
+        error: error.to_string(),
+        details,
+    }
+}
 
-    // --- START: IP評価 (トランザクション内) ---
-    let mut attempt_id: Option<i32> = None;
+/// 投稿前の事前検証で、DBや外部APIを使わずに行えるチェックをまとめて実行し、発生したエラーをすべて返します。
+fn collect_submission_issues(
+    validation: Result<(), validator::ValidationErrors>,
+    fingerprint: Option<&str>,
+    author_name: Option<&str>,
+    body: &str,
+    quote_response_number: Option<i64>,
+    is_admin: bool,
+) -> Vec<models::SubmissionValidationIssue> {
+    let mut errors: Vec<ServiceError> = Vec::new();
+    if let Err(e) = validation {
+        errors.push(e.into());
+    }
+    if let Some(fingerprint) = fingerprint {
+        if let Err(e) = verification::check_fingerprint_size(fingerprint.len()) {
+            errors.push(e);
+        }
+    }
     if !is_admin {
-        let fingerprint_value: Option<serde_json::Value> = validated_comment_data
-            .fingerprint
-            .as_ref()
-            .and_then(|s| serde_json::from_str(s).ok());
-        let verification_input = verification::VerificationInput {
-            verification_type: verification::VerificationType::CreateComment,
-            user_id: Some(user_id),
-            role: user_role_opt,
-            ip_address: truncated_ip.clone(),
-            raw_ip_address: Some(raw_ip.clone()),
-            captcha_token: None,
-            fingerprint_data: fingerprint_value,
-        };
-        let (result, new_attempt_id) =
-            verification::perform_verification(&mut tx, http_client.get_ref(), verification_input)
-                .await?;
-        attempt_id = Some(new_attempt_id);
-        if !result.is_success {
-            return Err(ServiceError::Forbidden(
-                result
-                    .rejection_reason
-                    .unwrap_or_else(|| "不正なリクエストとしてブロックされました。".to_string()),
-            ));
+        if let Some(name) = author_name {
+            if let Err(e) = check_submitted_author_name(name) {
+                errors.push(e);
+            }
         }
     }
-    // --- END: IP評価 ---
+    // 連携トークンの検証はDBを使うため行わず、投稿時と同じくトークンを取り除いた本文をチェックする
+    match sanitize_body_without_linking_token(body) {
+        Ok(sanitized_body) if !is_admin => {
+            let final_body = with_reply_anchor(&sanitized_body, quote_response_number);
+            if let Err(e) = check_quote_count(&final_body) {
+                errors.push(e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => errors.push(e),
+    }
+    errors.into_iter().map(submission_issue_from_error).collect()
+}
 
-    // --- START: BANチェック ---
-    bans::check_if_banned(
-        &mut tx,
-        Some(board.id),
-        Some(validated_comment_data.post_id), // スレッドBANをチェックするためにpost_idを渡す
-        Some(&identity_hashes.permanent_user_hash),
-        Some(&identity_hashes.permanent_ip_hash),
-        Some(&identity_hashes.permanent_device_hash),
+/// スレッドを作成せずに、投稿時と同じ入力チェックだけを行います。
+/// DBや外部APIには一切アクセスしないため、板の状態やBAN・レート制限は確認しません。
+#[post("/validate")]
+pub async fn validate_post(
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+    post_data: web::Json<CreatePostRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+    let errors = collect_submission_issues(
+        post_data.validate(),
+        post_data.fingerprint.as_deref(),
+        post_data.author_name.as_deref(),
+        &post_data.body,
+        None,
+        is_admin,
+    );
+    Ok(HttpResponse::Ok().json(models::SubmissionValidationResponse {
+        valid: errors.is_empty(),
+        errors,
+    }))
+}
+
+/// レスを書き込まずに、書き込み時と同じ入力チェックだけを行います。
+/// DBや外部APIには一切アクセスしないため、スレッドの状態やBAN・レート制限は確認しません。
+#[post("/comments/validate")]
+pub async fn validate_comment(
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+    comment_data: web::Json<CreateCommentRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+    let errors = collect_submission_issues(
+        comment_data.validate(),
+        comment_data.fingerprint.as_deref(),
+        comment_data.author_name.as_deref(),
+        &comment_data.body,
+        comment_data.quote_response_number,
+        is_admin,
+    );
+    Ok(HttpResponse::Ok().json(models::SubmissionValidationResponse {
+        valid: errors.is_empty(),
+        errors,
+    }))
+}
+
+/// レスの編集履歴を閲覧できるかどうかを返します。
+/// レスの投稿者本人と、そのスレッドのモデレーション権限を持つユーザー (管理者・板作成者など) が閲覧できます。
+fn can_view_comment_edits(
+    user: &middleware::AuthenticatedUser,
+    comment_author_id: Option<i32>,
+    permissions: &models::ThreadPermissions,
+) -> bool {
+    comment_author_id == Some(user.user_id) || permissions.can_moderate
+}
+
+/// レスの本文を更新し、編集前の本文を編集履歴 (`comment_edits`) に保存します。
+/// 呼び出し側で、対象のレスを行ロックしてから呼び出すことを想定しています。
+async fn record_comment_edit(
+    conn: &mut sqlx::PgConnection,
+    comment_id: i32,
+    new_body: &str,
+    edited_by: i32,
+) -> Result<(), ServiceError> {
+    sqlx::query!(
+        r#"
+        INSERT INTO comment_edits (comment_id, body, edited_by)
+        SELECT id, body, $2 FROM comments WHERE id = $1
+        "#,
+        comment_id,
+        edited_by
     )
+    .execute(&mut *conn)
     .await?;
 
-    // --- START: レート制限チェック ---
-    rate_limiter::check_and_track_rate_limits(
-        &mut tx,
-        user_id,
-        &identity_hashes.permanent_ip_hash,
-        &identity_hashes.permanent_device_hash,
-        models::RateLimitActionType::CreateComment,
+    sqlx::query!(
+        "UPDATE comments SET body = $1, body_hash = $2, updated_at = NOW() WHERE id = $3",
+        new_body,
+        body_content_hash(new_body),
+        comment_id
     )
+    .execute(&mut *conn)
     .await?;
 
-    // --- START: Identity Encryption ---
-    // Encrypt sensitive information before storing
-    let encrypted_email = encryption::encrypt(user_identifier)?; // emailは変わらない
-    let encrypted_ip = encryption::encrypt(&truncated_ip)?; // 切り詰め済みのIPを暗号化
-    let encrypted_device_info = encryption::encrypt(device_info)?;
-    // --- END: Identity Encryption ---
+    Ok(())
+}
 
-    // 2. 現在のコメント数を取得 (スレッド本体は含まない)
-    let current_comment_count: i64 = sqlx::query_scalar!(
-        "SELECT COUNT(*) FROM comments WHERE post_id = $1",
-        validated_comment_data.post_id
+/// レスの編集履歴を、古い編集から順に取得します。
+async fn fetch_comment_edits(
+    conn: &mut sqlx::PgConnection,
+    comment_id: i32,
+) -> Result<Vec<models::CommentEdit>, ServiceError> {
+    let edits = sqlx::query_as!(
+        models::CommentEdit,
+        r#"
+        SELECT id, comment_id, body, edited_by, edited_at
+        FROM comment_edits
+        WHERE comment_id = $1
+        ORDER BY edited_at ASC, id ASC
+        "#,
+        comment_id
     )
-    .fetch_one(&mut *tx)
-    .await?
-    .unwrap_or(0);
+    .fetch_all(conn)
+    .await?;
+    Ok(edits)
+}
 
-    // 3. 新しいコメントを追加した場合に、合計書き込み数が1000に達するかチェック
-    // スレッド本体が1書き込み、コメントが999書き込みで合計1000
-    if current_comment_count >= 999 {
+/// [認証必須] 自分のレスの本文を編集します。
+/// 編集前の本文は編集履歴として保存され、`GET /api/comments/{id}/edits` で確認できます。
+/// 過去ログ化・削除されたスレッドのレスは編集できません。
+#[actix_web::patch("/comments/{id}")]
+pub async fn update_comment(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<PathInfo>,
+    payload: web::Json<models::UpdateCommentRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    payload.validate()?;
+    let comment_id = path.id;
+    let is_admin = matches!(user.role, middleware::Role::Admin);
+
+    // 編集では連携トークンを使えないため、トークンを含む本文はそのまま保存せずに拒否する
+    if extract_and_remove_linking_token(&payload.body).0.is_some() {
         return Err(ServiceError::BadRequest(
-            "このスレッドは1000レスに達しており、新規の書き込みはできません。".to_string(),
+            "編集では連携トークンを使用できません。".to_string(),
         ));
     }
+    let new_body = sanitize_submitted_body(&payload.body)?;
+    if !is_admin {
+        check_quote_count(&new_body)?;
+    }
 
-    // コメントを挿入
-    let mut new_comment = sqlx::query_as!(
-        Comment,
+    let mut tx = pool.begin().await?;
+
+    let comment = sqlx::query!(
         r#"
-        INSERT INTO comments (body, post_id, author_name, user_id, level_at_creation, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, display_id_user, display_id_ip, display_id_device, verification_attempt_id)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13)
-        RETURNING id, body, post_id, user_id, author_name, created_at, updated_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, level_at_creation, level_at_creation as "level: _", NULL as "is_current_level_hidden: _", NULL as "post_title?", NULL as "response_number: _"
+        SELECT c.user_id, p.archived_at
+        FROM comments c
+        JOIN posts p ON c.post_id = p.id
+        WHERE c.id = $1 AND p.deleted_at IS NULL
+        FOR UPDATE OF c
         "#,
-        validated_comment_data.body,
-        validated_comment_data.post_id,
-        author_name,
-        user_id,
-        level_at_creation,
-        identity_hashes.display_user_id,
-        identity_hashes.permanent_user_hash,
-        identity_hashes.permanent_ip_hash,
-        identity_hashes.permanent_device_hash,
-        identity_hashes.display_id_user_part,
-        identity_hashes.display_id_ip_part,
-        identity_hashes.display_id_device_part, // 12
-        attempt_id // 13
-    )
-    .fetch_one(&mut *tx) // トランザクションを使用
-    .await?;
-
-    // Insert encrypted identities into the new table
-    sqlx::query!(
-        "INSERT INTO comment_identities (comment_id, encrypted_email, encrypted_ip, encrypted_device_info) VALUES ($1, $2, $3, $4)",
-        new_comment.id,
-        encrypted_email,
-        encrypted_ip,
-        encrypted_device_info
+        comment_id
     )
-    .execute(&mut *tx)
-    .await?;
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたレスが見つかりません。".to_string()))?;
 
-    // スレッドの最終活動日時を更新
-    // アーカイブ処理はバッチジョブに一任するため、ここでの archived_at 更新ロジックは削除
-    sqlx::query!(
-        "UPDATE posts SET last_activity_at = NOW() WHERE id = $1",
-        validated_comment_data.post_id
-    )
-    .execute(&mut *tx)
-    .await?;
+    if comment.user_id != Some(user.user_id) {
+        return Err(ServiceError::Forbidden(
+            "自分の書き込み以外は編集できません。".to_string(),
+        ));
+    }
+    if comment.archived_at.is_some() {
+        return Err(ServiceError::ThreadArchived(
+            "このスレッドは過去ログ化されているため、レスを編集できません。".to_string(),
+        ));
+    }
 
-    // コメントが投稿された板の最終活動日時も更新
-    sqlx::query!(
-        "UPDATE boards SET last_activity_at = NOW() WHERE id = $1",
-        board.id
-    )
-    .execute(&mut *tx)
-    .await?;
+    record_comment_edit(&mut tx, comment_id, &new_body, user.user_id).await?;
 
-    // トランザクションをコミット
     tx.commit().await?;
 
-    // コメント数による3分後アーカイブチェック
-    // `current_comment_count` は挿入前のコメント数。
-    // これが998だった場合、今追加されたのが999番目のコメントであり、
-    // スレッド本体(1) + コメント(999) = 1000レスに達したことになる。
-    if current_comment_count == 998 {
-        let pool_clone = pool.clone(); // `pool` is a web::Data<PgPool>
-        let post_id_to_archive = validated_comment_data.post_id;
-        tokio::spawn(async move {
-            log::info!(
-                "Post {} reached comment limit. Scheduling for archival in 3 minutes.",
-                post_id_to_archive
-            );
-            tokio::time::sleep(std::time::Duration::from_secs(180)).await;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "id": comment_id,
+        "body": linkify_body(&new_body),
+    })))
+}
 
-            // 3分後に再度スレッドの状態を確認し、まだアーカイブされていなければアーカイブする
-            // (バッチジョブなど他の要因で既にアーカイブされている可能性を考慮)
-            let is_not_archived: Option<bool> = sqlx::query_scalar!(
-                "SELECT archived_at IS NULL FROM posts WHERE id = $1",
-                post_id_to_archive
-            )
-            .fetch_one(pool_clone.get_ref())
-            .await
-            .ok()
-            .flatten();
-
-            if is_not_archived.unwrap_or(false) {
-                match sqlx::query!(
-                    "UPDATE posts SET archived_at = NOW() WHERE id = $1",
-                    post_id_to_archive
-                )
-                .execute(pool_clone.get_ref())
-                .await
-                {
-                    Ok(_) => log::info!(
-                        "Post {} successfully archived after 3 minutes due to comment limit.",
-                        post_id_to_archive
-                    ),
-                    Err(e) => log::error!(
-                        "Failed to archive post {} after 3 minutes: {}",
-                        post_id_to_archive,
-                        e
-                    ),
-                }
-            }
-        });
-    }
-
-    // --- START: レスポンス用のレベル情報フィルタリング ---
-    let (display_level_at_creation, display_current_level, is_current_level_hidden) =
-        process_level_visibility(
-            new_comment.level_at_creation,
-            new_comment.level,
-            threshold,
-            is_admin,
-        );
-    new_comment.level_at_creation = display_level_at_creation;
-    new_comment.level = display_current_level;
-    new_comment.is_current_level_hidden = is_current_level_hidden;
-    // --- END: レスポンス用のレベル情報フィルタリング ---
-
-    // レスポンス用に本文を変換
-    new_comment.body = linkify_body(&new_comment.body);
-
-    // 専ブラの互換性を考慮し、成功時のステータスコードを 201 Created から 200 OK に変更します。
-    // これにより、より多くのクライアントが成功応答を正しく解釈できるようになります。
-    let mut response_builder = HttpResponse::Ok();
-    if let Some(cookie) = new_session_cookie {
-        response_builder.cookie(cookie);
-    }
-    Ok(response_builder.json(new_comment))
-}
-
-#[get("/{id}/comments")]
-pub async fn get_comments_by_post_id(
+/// [認証必須] レスの編集履歴 (編集前の本文と編集日時) を古い順に返します。
+/// レスの投稿者本人と、そのスレッドのモデレーション権限を持つユーザーのみが閲覧できます。
+#[get("/comments/{id}/edits")]
+pub async fn get_comment_edits(
     pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
     path: web::Path<PathInfo>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
 ) -> Result<HttpResponse, ServiceError> {
-    let post_id = path.id;
-    let threshold = get_level_display_threshold(pool.get_ref()).await?;
-    let is_admin = user
-        .as_ref()
-        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+    let comment_id = path.id;
 
-    // --- START: 権限判定のために、まず投稿が属する板の作成者IDを取得 ---
-    let thread_mod_info = sqlx::query!(
+    let mut conn = pool.acquire().await?;
+
+    let comment = sqlx::query!(
         r#"
         SELECT
+            c.user_id as "comment_author_id",
             p.user_id as "thread_creator_id",
             b.created_by as "board_creator_id",
             b.moderation_type as "moderation_type: models::BoardModerationType"
-        FROM posts p
+        FROM comments c
+        JOIN posts p ON c.post_id = p.id
         JOIN boards b ON p.board_id = b.id
-        WHERE p.id = $1 AND p.deleted_at IS NULL
+        WHERE c.id = $1 AND p.deleted_at IS NULL
         "#,
-        post_id
+        comment_id
     )
-    .fetch_optional(pool.get_ref())
+    .fetch_optional(&mut *conn)
     .await?
-    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+    .ok_or_else(|| ServiceError::NotFound("指定されたレスが見つかりません。".to_string()))?;
 
-    let can_moderate = user.as_ref().is_some_and(|u| {
-        let is_board_creator = thread_mod_info.board_creator_id == Some(u.user_id);
-        let is_thread_creator_on_beta_board = thread_mod_info.moderation_type
-            == models::BoardModerationType::Beta
-            && thread_mod_info.thread_creator_id == Some(u.user_id);
-        is_admin || is_board_creator || is_thread_creator_on_beta_board
-    });
+    let permissions = compute_thread_permissions(
+        Some(&*user),
+        comment.board_creator_id,
+        comment.thread_creator_id,
+        comment.moderation_type,
+    );
+    if !can_view_comment_edits(&user, comment.comment_author_id, &permissions) {
+        return Err(ServiceError::Forbidden(
+            "このレスの編集履歴を閲覧する権限がありません。".to_string(),
+        ));
+    }
 
-    let comments_with_levels = sqlx::query!(
-        r#"
-        SELECT
-            c.id, c.body, c.post_id, c.user_id, c.author_name, c.created_at, c.updated_at,
-            c.display_user_id, c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash, c.level_at_creation,
-            u.level as "level?"
-        FROM comments c
-        LEFT JOIN users u ON c.user_id = u.id
-        WHERE c.post_id = $1
-        ORDER BY c.created_at ASC
-        "#,
-        post_id
+    let mut edits = fetch_comment_edits(&mut conn, comment_id).await?;
+    for edit in &mut edits {
+        edit.body = linkify_body(&edit.body);
+    }
+
+    Ok(HttpResponse::Ok().json(edits))
+}
+
+/// 書き込み時の認証 (IP評価など) に失敗した場合の拒否エラーを返します。
+/// 失敗した試行の記録と、それによる自動BANを残すため、拒否する前にトランザクションをコミットします。
+/// 認証はトランザクションの最初に行うため、この時点でコミットされるのは試行の記録と自動BANのみです。
+async fn reject_failed_verification(
+    tx: sqlx::Transaction<'_, Postgres>,
+    rejection_reason: Option<String>,
+) -> ServiceError {
+    if let Err(e) = tx.commit().await {
+        return e.into();
+    }
+    ServiceError::Forbidden(
+        rejection_reason.unwrap_or_else(|| "不正なリクエストとしてブロックされました。".to_string()),
     )
-    .fetch_all(pool.get_ref())
-    .await?;
+}
 
-    let response_comments: Vec<CommentResponse> = comments_with_levels
-        .into_iter()
-        .map(|c| {
-            let (display_level_at_creation, display_current_level, is_current_level_hidden) =
-                process_level_visibility(c.level_at_creation, c.level, threshold, is_admin);
-            let comment = Comment {
-                id: c.id,
-                body: linkify_body(&c.body),
-                post_id: c.post_id,
-                user_id: c.user_id,
-                author_name: c.author_name,
-                created_at: c.created_at,
-                updated_at: c.updated_at,
-                display_user_id: c.display_user_id,
-                permanent_user_hash: c.permanent_user_hash,
-                permanent_ip_hash: c.permanent_ip_hash,
-                permanent_device_hash: c.permanent_device_hash,
-                level_at_creation: display_level_at_creation,
-                post_title: None, // このフィールドはここでは不要なためNoneを設定
-                response_number: None, // このフィールドはここでは不要なためNoneを設定
-                level: display_current_level,
-                is_current_level_hidden,
-            };
+/// 板が書き込み停止 (凍結) 中でないかを確認します。
+/// 凍結中の板は閲覧のみ可能で、新規スレッド作成とレスの書き込みを拒否します (管理者も含む)。
+fn check_board_posting_not_frozen(board: &Board) -> Result<(), ServiceError> {
+    if board.posting_frozen {
+        return Err(ServiceError::Forbidden(
+            "この板は現在、書き込みが一時停止されています。".to_string(),
+        ));
+    }
+    Ok(())
+}
 
-            CommentResponse {
-                comment,
-                can_moderate,
-            }
-        })
-        .collect();
+/// 書き込みに必要なアカウントの経過時間 (秒)。環境変数 `MIN_ACCOUNT_AGE_SECONDS` で設定する (デフォルト: 0 = 制限なし)。
+/// 匿名投稿用の共有ユーザーには適用しない (`poster_account_created_at` を参照)。
+fn min_account_age_seconds() -> i64 {
+    env::var("MIN_ACCOUNT_AGE_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0)
+}
 
-    Ok(HttpResponse::Ok().json(response_comments))
+/// アカウント作成から `MIN_ACCOUNT_AGE_SECONDS` が経過しているかを確認します。
+/// 使い捨てアカウントによる荒らし対策で、経過していない場合は残り時間を含めて拒否します。
+fn check_min_account_age(account_created_at: chrono::DateTime<Utc>) -> Result<(), ServiceError> {
+    check_min_account_age_with(account_created_at, min_account_age_seconds(), Utc::now())
 }
 
-#[get("/archive")]
-pub async fn get_archived_posts(
-    pool: web::Data<PgPool>,
-    query_params: web::Query<ArchivedPostsQueryParams>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // 権限チェックのために追加
-) -> Result<HttpResponse, ServiceError> {
-    // データを取得するためのクエリビルダー
-    let mut data_builder: QueryBuilder<Postgres> = QueryBuilder::new("SELECT p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at, p.last_activity_at, (1 + COALESCE(cc.count, 0)) as total_responses, b.name as board_name FROM posts p LEFT JOIN boards b ON p.board_id = b.id LEFT JOIN (SELECT post_id, COUNT(*) as count FROM comments GROUP BY post_id) cc ON p.id = cc.post_id");
-    // 総件数を取得するためのクエリビルダー
-    let mut count_builder: QueryBuilder<Postgres> =
-        QueryBuilder::new("SELECT COUNT(*) FROM posts p");
+/// `check_min_account_age` の本体。必要な経過時間 (秒) と現在時刻を引数で受け取ります。
+fn check_min_account_age_with(
+    account_created_at: chrono::DateTime<Utc>,
+    min_age: i64,
+    now: chrono::DateTime<Utc>,
+) -> Result<(), ServiceError> {
+    if min_age <= 0 {
+        return Ok(());
+    }
+    let age = (now - account_created_at).num_seconds();
+    let remaining = min_age - age;
+    if remaining > 0 {
+        return Err(ServiceError::Forbidden(format!(
+            "作成されたばかりのアカウントでは書き込めません。あと{}秒お待ちください。",
+            remaining
+        )));
+    }
+    Ok(())
+}
 
-    let is_admin = user
+/// 書き込みに使われるアカウントの作成日時を、連携トークンを消費せずに取得します。
+/// `authenticate_poster` と同じ優先順位 (連携トークン → セッション) でアカウントを決めます。
+/// 匿名投稿用の共有ユーザーは全員で共有されており、アカウントの経過時間では投稿者を区別できないため
+/// 対象外として `None` を返します。無効な連携トークンの場合も `None` を返し、エラーは `authenticate_poster` に任せます。
+async fn poster_account_created_at(
+    conn: &mut sqlx::PgConnection,
+    session_user_id: Option<i32>,
+    body: &str,
+) -> Result<Option<chrono::DateTime<Utc>>, ServiceError> {
+    let (linking_token_opt, _) = extract_and_remove_linking_token(body);
+    if let Some(linking_token) = linking_token_opt {
+        let mut hasher = Sha256::new();
+        hasher.update(linking_token.as_bytes());
+        let token_hash = hex::encode(hasher.finalize());
+        let created_at = sqlx::query_scalar!(
+            r#"
+            SELECT u.created_at FROM device_linking_tokens t
+            JOIN users u ON u.id = t.user_id
+            WHERE t.token_hash = $1 AND t.expires_at > NOW() AND t.used_at IS NULL
+            "#,
+            token_hash
+        )
+        .fetch_optional(&mut *conn)
+        .await?;
+        return Ok(created_at);
+    }
+    let Some(user_id) = session_user_id else {
+        // 匿名投稿 (共有ユーザー) は対象外
+        return Ok(None);
+    };
+    let created_at = sqlx::query_scalar!(
+        "SELECT created_at FROM users WHERE id = $1 AND email <> $2",
+        user_id,
+        ANONYMOUS_USER_ACCOUNT_ID
+    )
+    .fetch_optional(&mut *conn)
+    .await?;
+    Ok(created_at)
+}
+
+/// Captchaが必須の板 (`require_captcha`) では、リクエストのCaptchaトークンを返します (ない場合はエラー)。
+/// 必須でない板ではトークンを無視し、認証処理でCaptcha検証を行わないよう `None` を返します。
+fn board_captcha_token(
+    board: &Board,
+    captcha_token: &Option<String>,
+) -> Result<Option<String>, ServiceError> {
+    if !board.require_captcha {
+        return Ok(None);
+    }
+    captcha_token
         .as_ref()
-        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+        .filter(|t| !t.trim().is_empty())
+        .cloned()
+        .map(Some)
+        .ok_or_else(|| {
+            ServiceError::BadRequest("この板に書き込むにはCaptcha認証が必要です。".to_string())
+        })
+}
 
-    // --- WHERE句の動的構築 ---
-    let where_clause = if query_params.show_deleted.unwrap_or(false) && is_admin {
-        // 削除済みスレッドを検索する場合
-        " WHERE p.deleted_at IS NOT NULL".to_string()
-    } else {
-        // 通常の検索（削除されていないスレッド）
-        let mut base_where = " WHERE p.deleted_at IS NULL".to_string();
-        if query_params.include_active_threads == Some(false) {
-            base_where.push_str(" AND p.archived_at IS NOT NULL");
-        }
-        base_where
-    };
-    data_builder.push(where_clause.clone());
-    count_builder.push(where_clause);
+/// メンバー限定の板で、投稿者が板に参加しているかを確認します。
+/// 管理者と板の作成者は参加していなくても書き込めます。
+async fn check_board_membership(
+    conn: &mut sqlx::PgConnection,
+    board: &Board,
+    user_id: i32,
+    is_admin: bool,
+) -> Result<(), ServiceError> {
+    if !board.members_only || is_admin || board.created_by == Some(user_id) {
+        return Ok(());
+    }
 
-    // --- 追加の検索条件 ---
-    // キーワード検索
-    if let Some(q) = &query_params.q {
-        if !q.is_empty() {
-            // キーワードを空白で分割し、空の文字列を除去
-            let keywords: Vec<_> = q.split_whitespace().filter(|s| !s.is_empty()).collect();
-            if !keywords.is_empty() {
-                let search_type_is_or = query_params.search_type.as_deref() == Some("or");
-                let operator = if search_type_is_or { " OR " } else { " AND " };
+    let is_member = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM board_members WHERE board_id = $1 AND user_id = $2) as "exists!""#,
+        board.id,
+        user_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
 
-                // 各ビルダーに条件句の開始を追加
-                data_builder.push(" AND (");
-                count_builder.push(" AND (");
+    if !is_member {
+        return Err(ServiceError::Forbidden(
+            "この板はメンバー限定です。書き込むには板に参加してください。".to_string(),
+        ));
+    }
+    Ok(())
+}
 
-                for (i, keyword) in keywords.iter().enumerate() {
-                    if i > 0 {
-                        data_builder.push(operator);
-                        count_builder.push(operator);
-                    }
+// 3行以上連続する改行 (空白のみの行を含む)
+static RE_EXCESSIVE_NEWLINES: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\n(?:[ \t]*\n){2,}").unwrap());
 
-                    let search_term = format!("%{}%", keyword.to_lowercase());
+/// 本文の空白の正規化が有効か。環境変数 `NORMALIZE_BODY_WHITESPACE=false` で無効化できる (デフォルト: 有効)。
+fn is_body_whitespace_normalization_enabled() -> bool {
+    env::var("NORMALIZE_BODY_WHITESPACE")
+        .map(|v| v != "false")
+        .unwrap_or(true)
+}
 
-                    match query_params.search_field.as_deref().unwrap_or("title") {
-                        "title" => {
-                            data_builder
-                                .push("LOWER(p.title) LIKE ")
-                                .push_bind(search_term.clone());
-                            count_builder
-                                .push("LOWER(p.title) LIKE ")
-                                .push_bind(search_term.clone());
-                        }
-                        "body" => {
-                            data_builder.push("(");
-                            count_builder.push("(");
+/// 本文の余分な空行を正規化します (サニタイズ前に呼び出す)。
+/// - 3行以上連続する改行を2行 (空行1つ) にまとめる
+/// - 先頭の空行と末尾の空白・改行を取り除く
+///
+/// 行内の空白や1行目の字下げ (AAなど) はそのまま残します。
+fn normalize_body_whitespace(body: &str) -> String {
+    let unified = body.replace("\r\n", "\n");
+    let collapsed = RE_EXCESSIVE_NEWLINES.replace_all(&unified, "\n\n");
+    // 先頭の空白のみの行を取り除く
+    let mut rest: &str = &collapsed;
+    while let Some(pos) = rest.find('\n') {
+        if !rest[..pos].trim().is_empty() {
+            break;
+        }
+        rest = &rest[pos + 1..];
+    }
+    rest.trim_end().to_string()
+}
 
-                            data_builder
-                                .push("LOWER(p.body) LIKE ")
-                                .push_bind(search_term.clone());
-                            count_builder
-                                .push("LOWER(p.body) LIKE ")
-                                .push_bind(search_term.clone());
+/// 本文の最小文字数 (前後の空白を除いた文字数)。
+/// 環境変数 `MIN_BODY_LENGTH` で変更可能 (デフォルト: 1)。
+fn min_body_length() -> usize {
+    env::var("MIN_BODY_LENGTH")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(1)
+}
 
-                            data_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.body) LIKE ").push_bind(search_term.clone()).push(")");
-                            count_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.body) LIKE ").push_bind(search_term.clone()).push(")");
+/// 本文が空白のみ、または最小文字数に満たない場合に拒否します。
+/// サニタイズ後の本文に対して呼び出すことを想定しています。
+fn check_body_min_length(body: &str) -> Result<(), ServiceError> {
+    check_body_min_length_with(body, min_body_length())
+}
 
-                            if query_params.include_author_names.unwrap_or(false) {
-                                data_builder
-                                    .push(" OR LOWER(p.author_name) LIKE ")
-                                    .push_bind(search_term.clone());
-                                count_builder
-                                    .push(" OR LOWER(p.author_name) LIKE ")
-                                    .push_bind(search_term.clone());
+/// `check_body_min_length` の本体。最小文字数を引数で受け取ります。
+fn check_body_min_length_with(body: &str, min_length: usize) -> Result<(), ServiceError> {
+    if body.trim().chars().count() < min_length {
+        return Err(ServiceError::BadRequest(if min_length <= 1 {
+            "本文を入力してください。空白のみの書き込みはできません。".to_string()
+        } else {
+            format!(
+                "本文は空白を除いて{}文字以上で入力してください。",
+                min_length
+            )
+        }));
+    }
+    Ok(())
+}
 
-                                data_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.author_name) LIKE ").push_bind(search_term.clone()).push(")");
-                                count_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.author_name) LIKE ").push_bind(search_term.clone()).push(")");
-                            }
+/// 1つの書き込みに含められるレスアンカー (`>>N`) の最大数。
+/// 環境変数 `MAX_QUOTES_PER_POST` で設定します (未設定または0の場合は無制限)。
+fn max_quotes_per_post() -> Option<usize> {
+    env::var("MAX_QUOTES_PER_POST")
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|v| *v > 0)
+}
 
-                            data_builder.push(")");
-                            count_builder.push(")");
-                        }
-                        _ => {} // 無効な値の場合は何もしない
-                    }
-                }
-                // 条件句の終了
-                data_builder.push(")");
-                count_builder.push(")");
-            }
-        }
+/// 大量のレスアンカーで多数の書き込みに通知を送るスパムを防ぐため、
+/// 本文中のレスアンカーの数が上限を超えている場合に拒否します。
+/// `linkify_body` と同じ正規表現を使うため、サニタイズ後の本文に対して呼び出すことを想定しています。
+fn check_quote_count(sanitized_body: &str) -> Result<(), ServiceError> {
+    check_quote_count_with(sanitized_body, max_quotes_per_post())
+}
+
+/// `check_quote_count` の本体。上限を引数で受け取ります (`None` なら無制限)。
+fn check_quote_count_with(
+    sanitized_body: &str,
+    max_quotes: Option<usize>,
+) -> Result<(), ServiceError> {
+    let Some(max_quotes) = max_quotes else {
+        return Ok(());
+    };
+    let quote_count = RE_RES_ANCHOR_ESCAPED.find_iter(sanitized_body).count();
+    if quote_count > max_quotes {
+        return Err(ServiceError::BadRequest(format!(
+            "レスアンカー (>>番号) は1つの書き込みにつき{}個までです。",
+            max_quotes
+        )));
     }
+    Ok(())
+}
 
-    // 板IDでのフィルタリング
-    if let Some(board_id_str) = &query_params.board_id {
-        // スペースで区切られたID文字列をパースしてi32のベクターに変換
-        let board_ids: Vec<i32> = board_id_str
-            .split_whitespace()
-            .filter_map(|s| s.parse::<i32>().ok())
-            .collect();
+/// 同一内容の連続投稿を拒否する期間 (秒)。
+/// 環境変数 `DUPLICATE_POST_WINDOW_SECONDS` が設定されている場合のみ有効 (未設定・0以下なら無効)。
+fn duplicate_post_window_seconds() -> Option<i64> {
+    env::var("DUPLICATE_POST_WINDOW_SECONDS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+}
 
-        if !board_ids.is_empty() {
-            // PostgreSQLのARRAY型にバインドするために `= ANY()` を使用
-            // これによりSQLインジェクションを防ぎつつ、複数のIDでフィルタリングできる
-            data_builder
-                .push(" AND p.board_id = ANY(")
-                .push_bind(board_ids.clone())
-                .push(")");
-            count_builder
-                .push(" AND p.board_id = ANY(")
-                .push_bind(board_ids)
-                .push(")");
+/// サニタイズ済みの本文の先頭に返信先のレスアンカーを付与します。
+/// 本文は既にサニタイズ済みのため、linkify_body が認識できるようエスケープ済みの形式で付与します。
+fn with_reply_anchor(sanitized_body: &str, quote_response_number: Option<i64>) -> String {
+    match quote_response_number {
+        Some(quote_number) => format!("&gt;&gt;{}\n{}", quote_number, sanitized_body),
+        None => sanitized_body.to_string(),
+    }
+}
+
+/// 重複判定用に本文を正規化してハッシュ化します。
+/// 空白・改行の違いや大文字・小文字の違いだけの書き込みは同一内容とみなします。
+fn body_content_hash(body: &str) -> String {
+    let normalized = body
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    hex::encode(Sha256::digest(normalized.as_bytes()))
+}
+
+/// 同じユーザーまたは同じ端末が、期間内に同一内容のスレッド本文・レスを書き込んでいないか確認します。
+/// `DUPLICATE_POST_WINDOW_SECONDS` が未設定の場合は何もしません。
+async fn check_duplicate_content(
+    conn: &mut sqlx::PgConnection,
+    body_hash: &str,
+    permanent_user_hash: &str,
+    permanent_device_hash: &str,
+) -> Result<(), ServiceError> {
+    let Some(window_seconds) = duplicate_post_window_seconds() else {
+        return Ok(());
+    };
+    let since = Utc::now() - Duration::seconds(window_seconds);
+
+    let is_duplicate = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM posts
+            WHERE body_hash = $1 AND created_at > $2
+              AND (permanent_user_hash = $3 OR permanent_device_hash = $4)
+            UNION ALL
+            SELECT 1 FROM comments
+            WHERE body_hash = $1 AND created_at > $2
+              AND (permanent_user_hash = $3 OR permanent_device_hash = $4)
+        ) as "exists!"
+        "#,
+        body_hash,
+        since,
+        permanent_user_hash,
+        permanent_device_hash
+    )
+    .fetch_one(conn)
+    .await?;
+
+    if is_duplicate {
+        return Err(ServiceError::BadRequest(
+            "同じ内容の書き込みが短時間に繰り返されています。内容を変更するか、しばらく時間をおいてから書き込んでください。".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// --- START: Post Limit Archival Helper ---
+const DEFAULT_POST_LIMIT_ARCHIVE_DELAY_SECONDS: u64 = 180;
+const DEFAULT_MAX_DELAYED_ARCHIVE_TASKS: usize = 100;
+
+/// 同時に待機できる遅延アーカイブタスク数の上限。
+/// 環境変数 `MAX_DELAYED_ARCHIVE_TASKS` で変更可能です (デフォルトは100)。
+/// 上限を超えた分はタスクを起動せず、`archive_scheduled_at` を元に定期バッチがアーカイブします。
+static DELAYED_ARCHIVE_TASKS: Lazy<std::sync::Arc<tokio::sync::Semaphore>> = Lazy::new(|| {
+    let max_tasks = env::var("MAX_DELAYED_ARCHIVE_TASKS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_DELAYED_ARCHIVE_TASKS);
+    std::sync::Arc::new(tokio::sync::Semaphore::new(max_tasks))
+});
+
+/// レス数上限に達したスレッドをアーカイブするまでの猶予秒数を返します。
+/// 環境変数 `POST_LIMIT_ARCHIVE_DELAY_SECONDS` で変更可能です (デフォルトは180秒)。
+fn post_limit_archive_delay_seconds() -> u64 {
+    env::var("POST_LIMIT_ARCHIVE_DELAY_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_POST_LIMIT_ARCHIVE_DELAY_SECONDS)
+}
+
+/// アーカイブ予定日時 (`archive_scheduled_at`) を過ぎたスレッドをアーカイブします。
+/// 遅延アーカイブタスクの実行中にプロセスが停止した場合でも、定期バッチからこの関数を呼ぶことで確実にアーカイブされます。
+pub async fn archive_scheduled_posts(
+    pool: &PgPool,
+    http_client: &reqwest::Client,
+) -> Result<u64, sqlx::Error> {
+    let archived = sqlx::query_as!(
+        ArchiveNotification,
+        r#"
+        UPDATE posts SET archived_at = NOW(), archive_scheduled_at = NULL
+        WHERE archive_scheduled_at IS NOT NULL AND archive_scheduled_at <= NOW() AND archived_at IS NULL
+        RETURNING id as post_id, board_id, title, 'comment_limit' as "reason!"
+        "#
+    )
+    .fetch_all(pool)
+    .await?;
+
+    notify_archived_posts(http_client, &archived).await;
+    Ok(archived.len() as u64)
+}
+
+/// Webhookに送信するアーカイブ通知の内容
+#[derive(serde::Serialize, Debug)]
+pub struct ArchiveNotification {
+    pub post_id: i32,
+    pub board_id: Option<i32>,
+    pub title: String,
+    // アーカイブされた理由 (例: "comment_limit")
+    pub reason: String,
+}
+
+/// スレッドのアーカイブを外部のWebhook (環境変数 `ARCHIVE_WEBHOOK_URL`) に通知します。
+/// 未設定の場合は何もしません。通知の失敗はアーカイブ処理に影響させず、ログに記録するだけにします。
+/// スレッドをアーカイブする処理 (レス数上限による遅延アーカイブとその定期的な確定、管理者の一括アーカイブ) は、すべてこの関数で通知します。
+pub async fn notify_archived_posts(
+    http_client: &reqwest::Client,
+    notifications: &[ArchiveNotification],
+) {
+    let Some(webhook_url) = env::var("ARCHIVE_WEBHOOK_URL").ok().filter(|u| !u.is_empty()) else {
+        return;
+    };
+    send_archive_notifications(http_client, &webhook_url, notifications).await;
+}
+
+/// アーカイブ通知を指定したWebhookのURLに1件ずつPOSTします。失敗はログに記録して次の通知に進みます。
+async fn send_archive_notifications(
+    http_client: &reqwest::Client,
+    webhook_url: &str,
+    notifications: &[ArchiveNotification],
+) {
+
+    for notification in notifications {
+        let result = http_client
+            .post(webhook_url)
+            .timeout(std::time::Duration::from_secs(5))
+            .json(notification)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status());
+        if let Err(e) = result {
+            log::warn!(
+                "Failed to send archive notification for post {} to webhook: {}",
+                notification.post_id,
+                e
+            );
         }
     }
+}
+// --- END: Post Limit Archival Helper ---
 
-    // 最小レス数でのフィルタリング
-    if let Some(min_responses) = query_params.min_responses {
-        // data_builderは最適化されたクエリを使っているため、cc.countでフィルタリング
-        data_builder
-            .push(" AND (1 + COALESCE(cc.count, 0)) >= ")
-            .push_bind(min_responses);
-        // count_builderはJOINしていないため、元のサブクエリでフィルタリング（パフォーマンスは落ちるが、結果は正確）
-        count_builder
-            .push(" AND (1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) >= ")
-            .push_bind(min_responses);
+#[post("/comments")]
+pub async fn create_comment(
+    pool: web::Data<PgPool>,
+    http_client: web::Data<reqwest::Client>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+    req: HttpRequest,
+    comment_data: web::Json<CreateCommentRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    // 最初にバリデーションを実行
+    comment_data.validate()?;
+    // フィンガープリントは端末情報としてハッシュ化・暗号化して保存されるため、サイズを制限する
+    if let Some(fingerprint) = &comment_data.fingerprint {
+        verification::check_fingerprint_size(fingerprint.len())?;
     }
 
-    // 作成年月でのフィルタリング
-    if let Some(year) = query_params.created_year {
-        if let Some(month) = query_params.created_month {
-            // 年と月が両方指定された場合
-            let start_of_month = Utc
-                .with_ymd_and_hms(year, month as u32, 1, 0, 0, 0)
-                .single()
-                .ok_or_else(|| {
-                    ServiceError::BadRequest("無効な年月が指定されました。".to_string())
-                })?;
-            let end_of_month = if month == 12 {
-                Utc.with_ymd_and_hms(year + 1, 1, 1, 0, 0, 0).single()
-            } else {
-                Utc.with_ymd_and_hms(year, (month + 1) as u32, 1, 0, 0, 0)
-                    .single()
-            }
-            .ok_or_else(|| ServiceError::BadRequest("無効な年月が指定されました。".to_string()))?;
+    // 管理者でない場合、予約文字が含まれていないかチェック
+    if !user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin))
+    {
+        if let Some(name) = &comment_data.author_name {
+            check_submitted_author_name(name)?;
+        }
+    }
 
-            data_builder
-                .push(" AND p.created_at >= ")
-                .push_bind(start_of_month);
-            count_builder
-                .push(" AND p.created_at >= ")
-                .push_bind(start_of_month);
+    // 外部API (proxycheck) の呼び出しや認証試行の記録などの重い処理の前に、
+    // 安価なチェック (スレッド・板の存在、過去ログ化、レス数上限) を済ませておく
+    let post_info = sqlx::query!(
+        "SELECT board_id, archived_at FROM posts WHERE id = $1 AND deleted_at IS NULL",
+        comment_data.post_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたスレッドが見つかりません。".to_string()))?;
 
-            data_builder
-                .push(" AND p.created_at < ")
-                .push_bind(end_of_month);
-            count_builder
-                .push(" AND p.created_at < ")
-                .push_bind(end_of_month);
-        } else {
-            // 年のみ指定された場合
-            let start_of_year = Utc
-                .with_ymd_and_hms(year, 1, 1, 0, 0, 0)
-                .single()
-                .ok_or_else(|| {
-                    ServiceError::BadRequest("無効な年が指定されました。".to_string())
-                })?;
-            let end_of_year = Utc
-                .with_ymd_and_hms(year + 1, 1, 1, 0, 0, 0)
-                .single()
-                .ok_or_else(|| {
-                    ServiceError::BadRequest("無効な年が指定されました。".to_string())
-                })?;
+    // 既に過去ログ化されている場合は書き込みを拒否
+    if post_info.archived_at.is_some() {
+        return Err(ServiceError::ThreadArchived(
+            "このスレッドは過去ログ化されており、新規の書き込みはできません。".to_string(),
+        ));
+    }
 
-            data_builder
-                .push(" AND p.created_at >= ")
-                .push_bind(start_of_year);
-            count_builder
-                .push(" AND p.created_at >= ")
-                .push_bind(start_of_year);
+    // 板の情報を取得
+    let board = sqlx::query_as!(
+        Board,
+        // moderation_type を追加
+        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
+        post_info.board_id,
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("スレッドが属する板が見つかりません。".to_string()))?;
 
-            data_builder
-                .push(" AND p.created_at < ")
-                .push_bind(end_of_year);
-            count_builder
-                .push(" AND p.created_at < ")
-                .push_bind(end_of_year);
+    // アーカイブされた板のスレッドには書き込めない
+    if board.archived_at.is_some() {
+        return Err(ServiceError::BadRequest(
+            "この板はアーカイブされているため、新規の書き込みはできません。".to_string(),
+        ));
+    }
+    check_board_posting_not_frozen(&board)?;
+
+    // レス数上限の事前チェック (確定的なチェックはトランザクション内で再度行う)
+    let precheck_comment_count: i64 = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM comments WHERE post_id = $1",
+        comment_data.post_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?
+    .unwrap_or(0);
+    if precheck_comment_count >= MAX_RESPONSES_PER_THREAD - 1 {
+        return Err(ServiceError::BadRequest(
+            "このスレッドは1000レスに達しており、新規の書き込みはできません。".to_string(),
+        ));
+    }
+
+    // --- START: Refactored Authentication & Token Logic ---
+    let user_role_opt = user.as_ref().map(|u| u.role);
+    let is_admin = user_role_opt == Some(middleware::Role::Admin);
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    // 拒否される書き込みで連携トークンを消費したりセッションを作成したりしないよう、
+    // 認証の前にトークンを取り除いた本文をチェックする
+    // レスアンカーの数は、返信先アンカーを付与した後の最終的な本文で数える
+    let precheck_body = sanitize_body_without_linking_token(&comment_data.body)?;
+    if !is_admin {
+        // 作成されたばかりのアカウントからの書き込みを制限する (管理者は除外)
+        let mut conn = pool.acquire().await?;
+        let session_user_id = user.as_ref().map(|u| u.user_id);
+        if let Some(created_at) =
+            poster_account_created_at(&mut conn, session_user_id, &comment_data.body).await?
+        {
+            check_min_account_age(created_at)?;
         }
+        check_quote_count(&with_reply_anchor(
+            &precheck_body,
+            comment_data.quote_response_number,
+        ))?;
     }
+    let (user_id, new_session_cookie, final_body) =
+        authenticate_poster(pool.get_ref(), user, &comment_data.body).await?;
+    // --- END: Refactored Authentication & Token Logic ---
 
-    // --- START: SQLデバッグ用ログ ---
-    // 実際に実行されるSQLクエリと、受け取ったパラメータをログに出力します。
-    // これにより、バックエンドが意図通りのクエリを生成しているかを確認できます。
-    let data_sql_debug = data_builder.sql();
-    log::info!(
-        "[ARCHIVE_SEARCH_DEBUG] Received Params: {:?}",
-        query_params.0
-    );
-    log::info!("[ARCHIVE_SEARCH_DEBUG] Data SQL: {}", data_sql_debug);
-    // --- END: SQLデバッグ用ログ ---
+    // 認証ヘルパーの後に `into_inner` を呼び出し、所有権を取得します
+    let mut validated_comment_data = comment_data.into_inner();
+    // 認証ヘルパーが処理した後の本文で上書きします
+    // 本文をサニタイズし、投稿できる内容かチェック
+    validated_comment_data.body = sanitize_submitted_body(&final_body)?;
+    // 返信先アンカーを付与する前の本文で重複判定する (アンカー番号を変えただけの連投も検出するため)
+    let body_hash = body_content_hash(&validated_comment_data.body);
 
-    // 総件数を取得するクエリ
-    let total_count: i64 = count_builder
-        .build_query_scalar()
+    // 投稿者名が指定されていなければ、板のデフォルト名を使用
+    let author_name = validated_comment_data
+        .author_name
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| clean(&s).to_owned())
+        .unwrap_or_else(|| board_default_name(&board));
+
+    let (truncated_ip, raw_ip) = get_ip_address(&req);
+
+    // --- START: ID生成ロジック ---
+    // ユーザーIDから永続的な識別子（メールアドレス）と現在のレベルを取得
+    let user_info = sqlx::query!("SELECT email, level FROM users WHERE id = $1", user_id)
         .fetch_one(pool.get_ref())
         .await?;
+    let user_email = user_info.email;
+    let level_at_creation = Some(user_info.level);
 
-    // ソート条件の追加
-    let sort_param = query_params.sort.as_deref().unwrap_or("archived_at_desc");
-    let (sort_column, sort_order) = match sort_param.rsplit_once('_') {
-        Some((col, order)) => (col, order),
-        None => (sort_param, "desc"), // "_" がない場合はデフォルトで降順
+    let device_info: &str = {
+        let fingerprint = validated_comment_data.fingerprint.as_deref();
+        let user_agent = req.headers().get("User-Agent").and_then(|ua| ua.to_str().ok());
+        let final_device_info = fingerprint.or(user_agent).unwrap_or("unknown");
+        if is_verbose_diag_logging_enabled() {
+            log::info!("[DEVICE DIAG] Fingerprint from payload: {:?}", fingerprint);
+            log::info!("[DEVICE DIAG] User-Agent from headers: {:?}", user_agent);
+            log::info!(
+                "[DEVICE DIAG] Final device_info chosen: '{}'",
+                final_device_info
+            );
+        }
+        final_device_info
     };
+    let user_identifier = &poster_identifier(&user_email, &truncated_ip, device_info);
 
-    let final_sort_order = if sort_order.eq_ignore_ascii_case("asc") {
-        "ASC"
-    } else {
-        "DESC"
-    };
+    let identity_hashes =
+        identity::generate_identity_hashes_for_board(
+            user_identifier,
+            &truncated_ip,
+            device_info,
+            board.per_board_ids.then_some(board.id),
+        );
+    // --- END: ID生成ロジック ---
 
-    // SQLインジェクションを防ぎつつ、ソート順を組み立てる
-    // 第2ソートキーとして last_activity_at を追加し、順序の一貫性を保証する
-    let order_by_clause = match sort_column {
-        "created_at" => {
-            format!("p.created_at {}, p.last_activity_at DESC", final_sort_order)
-        }
-        "archived_at" => {
-            // DESCの場合、NULLS FIRSTで現行スレッドを先頭に。ASCの場合はNULLS LASTで末尾に。
-            let nulls_order = if final_sort_order == "DESC" {
-                "NULLS FIRST"
-            } else {
-                "NULLS LAST"
-            };
-            format!(
-                "p.archived_at {} {}, p.last_activity_at DESC",
-                final_sort_order, nulls_order
-            )
-        }
-        // デフォルトは新着アーカイブ順
-        _ => "p.archived_at DESC NULLS FIRST, p.last_activity_at DESC".to_string(),
-    };
-    data_builder.push(format!(" ORDER BY {}", order_by_clause));
+    // トランザクションを開始
+    let mut tx = pool.begin().await?;
 
-    // ページネーションの追加
-    let limit = query_params.limit.unwrap_or(20); // デフォルトは20件
-    let offset = query_params.offset.unwrap_or(0); // デフォルトは0件目から
-    data_builder.push(" LIMIT ");
-    data_builder.push_bind(limit);
-    data_builder.push(" OFFSET ");
-    data_builder.push_bind(offset);
+    // --- START: IP評価 (トランザクション内) ---
+    let mut attempt_id: Option<i32> = None;
+    if !is_admin {
+        let fingerprint_value: Option<serde_json::Value> = validated_comment_data
+            .fingerprint
+            .as_ref()
+            .and_then(|s| serde_json::from_str(s).ok());
+        let verification_input = verification::VerificationInput {
+            verification_type: verification::VerificationType::CreateComment,
+            user_id: Some(user_id),
+            role: user_role_opt,
+            ip_address: truncated_ip.clone(),
+            raw_ip_address: Some(raw_ip.clone()),
+            captcha_token: board_captcha_token(&board, &validated_comment_data.captcha_token)?,
+            fingerprint_data: fingerprint_value,
+        };
+        let (result, new_attempt_id) =
+            verification::perform_verification(&mut tx, http_client.get_ref(), verification_input)
+                .await?;
+        attempt_id = new_attempt_id;
+        if !result.is_success {
+            return Err(reject_failed_verification(tx, result.rejection_reason).await);
+        }
+    }
+    // --- END: IP評価 ---
+
+    // --- START: BANチェック ---
+    bans::check_if_banned(
+        &mut tx,
+        Some(board.id),
+        Some(validated_comment_data.post_id), // スレッドBANをチェックするためにpost_idを渡す
+        Some(&identity_hashes.permanent_user_hash),
+        Some(&identity_hashes.permanent_ip_hash),
+        Some(&identity_hashes.permanent_device_hash),
+    )
+    .await?;
+
+    // --- START: メンバー限定の板の参加チェック ---
+    check_board_membership(&mut tx, &board, user_id, is_admin).await?;
+
+    // --- START: 同一内容の連続投稿チェック (管理者は除外) ---
+    if !is_admin {
+        check_duplicate_content(
+            &mut tx,
+            &body_hash,
+            &identity_hashes.permanent_user_hash,
+            &identity_hashes.permanent_device_hash,
+        )
+        .await?;
+    }
+
+    // --- START: レート制限チェック ---
+    rate_limiter::check_and_track_rate_limits(
+        &mut tx,
+        user_id,
+        &identity_hashes.permanent_ip_hash,
+        &identity_hashes.permanent_device_hash,
+        models::RateLimitActionType::CreateComment,
+    )
+    .await?;
+
+    // --- START: Identity Encryption ---
+    // Encrypt sensitive information before storing
+    let encrypted_email = encryption::encrypt(&user_email)?; // emailは変わらない
+    let encrypted_ip = encryption::encrypt(&truncated_ip)?; // 切り詰め済みのIPを暗号化
+    let encrypted_device_info = encryption::encrypt(device_info)?;
+    // --- END: Identity Encryption ---
+
+    // --- START: 低速モードのチェック (管理者は除外) ---
+    if !is_admin {
+        check_thread_slow_mode(&mut tx, validated_comment_data.post_id).await?;
+    }
+
+    // スレッドの行をロックし、同じスレッドへの同時書き込みをコミットまで直列化する
+    // (ロックせずに数えると、同時に書き込まれたレスに同じレス番号が付いてしまう)
+    sqlx::query!(
+        "SELECT id FROM posts WHERE id = $1 FOR UPDATE",
+        validated_comment_data.post_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    // 2. 現在のコメント数を取得 (スレッド本体は含まない)
+    let current_comment_count: i64 = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM comments WHERE post_id = $1",
+        validated_comment_data.post_id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .unwrap_or(0);
+
+    // 3. 新しいコメントを追加した場合に、合計書き込み数が1000に達するかチェック
+    // スレッド本体が1書き込み、コメントが999書き込みで合計1000
+    if current_comment_count >= 999 {
+        return Err(ServiceError::BadRequest(
+            "このスレッドは1000レスに達しており、新規の書き込みはできません。".to_string(),
+        ));
+    }
+
+    // 返信先のレス番号が指定されている場合、スレッド内に存在するか確認してから本文の先頭にアンカーを付与する
+    // 本文は既にサニタイズ済みのため、linkify_body が認識できるようエスケープ済みの形式で付与する
+    // (アンカーを含めた文字数はリクエストのバリデーションでチェック済み)
+    if let Some(quote_number) = validated_comment_data.quote_response_number {
+        // スレッド本体が1、既存のコメントが2から (1 + current_comment_count) まで
+        if quote_number < 1 || quote_number > 1 + current_comment_count {
+            return Err(ServiceError::BadRequest(
+                "返信先のレス番号が存在しません。".to_string(),
+            ));
+        }
+        validated_comment_data.body =
+            with_reply_anchor(&validated_comment_data.body, Some(quote_number));
+    }
+
+    // コメントを挿入
+    let mut new_comment = sqlx::query_as!(
+        Comment,
+        r#"
+        INSERT INTO comments (body, post_id, author_name, user_id, level_at_creation, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, display_id_user, display_id_ip, display_id_device, verification_attempt_id, response_number, body_hash)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+        RETURNING id, body, post_id, user_id, author_name, created_at, updated_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, level_at_creation, level_at_creation as "level: _", NULL as "is_current_level_hidden: _", NULL as "post_title?", response_number::BIGINT as "response_number: _"
+        "#,
+        validated_comment_data.body,
+        validated_comment_data.post_id,
+        author_name,
+        user_id,
+        level_at_creation,
+        identity_hashes.display_user_id,
+        identity_hashes.permanent_user_hash,
+        identity_hashes.permanent_ip_hash,
+        identity_hashes.permanent_device_hash,
+        identity_hashes.display_id_user_part,
+        identity_hashes.display_id_ip_part,
+        identity_hashes.display_id_device_part, // 12
+        attempt_id, // 13
+        (current_comment_count + 2) as i32, // 14: スレッド本文が1、既存のレスの次の番号
+        body_hash // 15
+    )
+    .fetch_one(&mut *tx) // トランザクションを使用
+    .await?;
+
+    // Insert encrypted identities into the new table
+    sqlx::query!(
+        "INSERT INTO comment_identities (comment_id, encrypted_email, encrypted_ip, encrypted_device_info) VALUES ($1, $2, $3, $4)",
+        new_comment.id,
+        encrypted_email,
+        encrypted_ip,
+        encrypted_device_info
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // スレッドの最終活動日時を更新
+    // アーカイブ処理はバッチジョブに一任するため、ここでの archived_at 更新ロジックは削除
+    sqlx::query!(
+        "UPDATE posts SET last_activity_at = NOW() WHERE id = $1",
+        validated_comment_data.post_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // コメントが投稿された板の最終活動日時も更新
+    sqlx::query!(
+        "UPDATE boards SET last_activity_at = NOW() WHERE id = $1",
+        board.id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // コメント数による遅延アーカイブチェック
+    // `current_comment_count` は挿入前のコメント数。
+    // これが998だった場合、今追加されたのが999番目のコメントであり、
+    // スレッド本体(1) + コメント(999) = 1000レスに達したことになる。
+    let reached_comment_limit = current_comment_count == 998;
+    let archive_delay_seconds = post_limit_archive_delay_seconds();
+    if reached_comment_limit {
+        // 再起動で遅延タスクが失われてもバッチジョブがアーカイブできるよう、予定日時をコミット前に記録する
+        sqlx::query!(
+            "UPDATE posts SET archive_scheduled_at = NOW() + make_interval(secs => $2) WHERE id = $1",
+            validated_comment_data.post_id,
+            archive_delay_seconds as f64
+        )
+        .execute(&mut *tx)
+        .await?;
+    }
+
+    // トランザクションをコミット
+    tx.commit().await?;
+
+    // 遅延アーカイブタスクの数には上限を設ける。枠が空いていない場合は、記録済みの予定日時を元に定期バッチに任せる
+    let delayed_archive_permit = if reached_comment_limit {
+        let permit = DELAYED_ARCHIVE_TASKS.clone().try_acquire_owned().ok();
+        if permit.is_none() {
+            log::warn!(
+                "Post {} reached comment limit, but too many delayed archive tasks are pending. Leaving it to the scheduled batch.",
+                validated_comment_data.post_id
+            );
+        }
+        permit
+    } else {
+        None
+    };
+
+    if let Some(permit) = delayed_archive_permit {
+        let pool_clone = pool.clone(); // `pool` is a web::Data<PgPool>
+        let http_client_clone = http_client.clone();
+        let post_id_to_archive = validated_comment_data.post_id;
+        tokio::spawn(async move {
+            // タスクが終了するまで枠を保持する
+            let _permit = permit;
+            log::info!(
+                "Post {} reached comment limit. Scheduling for archival in {} seconds.",
+                post_id_to_archive,
+                archive_delay_seconds
+            );
+            tokio::time::sleep(std::time::Duration::from_secs(archive_delay_seconds)).await;
+
+            // 猶予期間後、まだアーカイブされていなければアーカイブする
+            // (バッチジョブなど他の要因で既にアーカイブされている可能性を考慮し、条件付きで更新する)
+            match sqlx::query_as!(
+                ArchiveNotification,
+                r#"
+                UPDATE posts SET archived_at = NOW(), archive_scheduled_at = NULL WHERE id = $1 AND archived_at IS NULL
+                RETURNING id as post_id, board_id, title, 'comment_limit' as "reason!"
+                "#,
+                post_id_to_archive
+            )
+            .fetch_optional(pool_clone.get_ref())
+            .await
+            {
+                Ok(Some(notification)) => {
+                    log::info!(
+                        "Post {} successfully archived after {} seconds due to comment limit.",
+                        post_id_to_archive,
+                        archive_delay_seconds
+                    );
+                    notify_archived_posts(http_client_clone.get_ref(), &[notification]).await;
+                }
+                Ok(None) => {}
+                Err(e) => log::error!(
+                    "Failed to archive post {} after {} seconds: {}",
+                    post_id_to_archive,
+                    archive_delay_seconds,
+                    e
+                ),
+            }
+        });
+    }
+
+    // --- START: レスポンス用のレベル情報フィルタリング ---
+    let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+        process_level_visibility(
+            new_comment.level_at_creation,
+            new_comment.level,
+            threshold,
+            is_admin,
+        );
+    new_comment.level_at_creation = display_level_at_creation;
+    new_comment.level = display_current_level;
+    new_comment.is_current_level_hidden = is_current_level_hidden;
+    // --- END: レスポンス用のレベル情報フィルタリング ---
+
+    // レスポンス用に本文を変換
+    new_comment.body = linkify_body(&new_comment.body);
+
+    // 専ブラの互換性を考慮し、成功時のステータスコードを 201 Created から 200 OK に変更します。
+    // これにより、より多くのクライアントが成功応答を正しく解釈できるようになります。
+    let mut response_builder = HttpResponse::Ok();
+    if let Some(cookie) = new_session_cookie {
+        response_builder.cookie(cookie);
+    }
+    Ok(response_builder.json(new_comment))
+}
+
+#[get("/{id}/comments")]
+pub async fn get_comments_by_post_id(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    query: web::Query<models::CommentsQuery>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let post_id = path.id;
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    // --- START: 権限判定のために、まず投稿が属する板の作成者IDを取得 ---
+    let thread_mod_info = sqlx::query!(
+        r#"
+        SELECT
+            p.user_id as "thread_creator_id",
+            b.created_by as "board_creator_id",
+            b.moderation_type as "moderation_type: models::BoardModerationType",
+            COALESCE(tu.email = $2, false) as "thread_creator_is_anonymous!"
+        FROM posts p
+        JOIN boards b ON p.board_id = b.id
+        LEFT JOIN users tu ON p.user_id = tu.id
+        WHERE p.id = $1 AND p.deleted_at IS NULL
+        "#,
+        post_id,
+        ANONYMOUS_USER_ACCOUNT_ID
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+
+    // スレ主の書き込みを判定するためのユーザーID。
+    // 匿名投稿用の共有ユーザーは全員が同じIDになるため、スレ主判定の対象外とする
+    let op_user_id = thread_mod_info
+        .thread_creator_id
+        .filter(|_| !thread_mod_info.thread_creator_is_anonymous);
+
+    let can_moderate = compute_thread_permissions(
+        user.as_deref(),
+        thread_mod_info.board_creator_id,
+        thread_mod_info.thread_creator_id,
+        thread_mod_info.moderation_type,
+    )
+    .can_moderate;
+
+    let comments_with_levels = sqlx::query!(
+        r#"
+        SELECT
+            c.id, c.body, c.post_id, c.user_id, c.author_name, c.created_at, c.updated_at,
+            c.display_user_id, c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash, c.level_at_creation,
+            c.response_number,
+            u.level as "level?"
+        FROM comments c
+        LEFT JOIN users u ON c.user_id = u.id
+        WHERE c.post_id = $1
+        ORDER BY c.created_at ASC, c.id ASC
+        "#,
+        post_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    // 「このIDの書き込みを表示」用の絞り込み。前方一致の条件は履歴検索と同じものを使う
+    let id_part = query
+        .id_part
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty());
+    let matching_comment_ids: Option<std::collections::HashSet<i32>> = match id_part {
+        Some(part) => {
+            let mut id_query: QueryBuilder<Postgres> =
+                QueryBuilder::new("SELECT c.id FROM comments c WHERE c.post_id = ");
+            id_query.push_bind(post_id).push(" AND (");
+            for (i, column_name) in ["display_id_user", "display_id_ip", "display_id_device"]
+                .into_iter()
+                .enumerate()
+            {
+                if i > 0 {
+                    id_query.push(" OR ");
+                }
+                user_history::push_display_id_prefix_condition(&mut id_query, "c.", column_name, part);
+            }
+            id_query.push(")");
+            let ids: Vec<i32> = id_query
+                .build_query_scalar()
+                .fetch_all(pool.get_ref())
+                .await?;
+            Some(ids.into_iter().collect())
+        }
+        None => None,
+    };
+
+    let mut response_comments: Vec<CommentResponse> = comments_with_levels
+        .into_iter()
+        .filter(|c| {
+            matching_comment_ids
+                .as_ref()
+                .map_or(true, |ids| ids.contains(&c.id))
+        })
+        .map(|c| {
+            let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+                process_level_visibility(c.level_at_creation, c.level, threshold, is_admin);
+            let is_op = op_user_id.is_some() && c.user_id == op_user_id;
+            let comment = Comment {
+                id: c.id,
+                body: linkify_body(&c.body),
+                post_id: c.post_id,
+                user_id: c.user_id,
+                author_name: c.author_name,
+                created_at: c.created_at,
+                updated_at: c.updated_at,
+                display_user_id: c.display_user_id,
+                permanent_user_hash: c.permanent_user_hash,
+                permanent_ip_hash: c.permanent_ip_hash,
+                permanent_device_hash: c.permanent_device_hash,
+                level_at_creation: display_level_at_creation,
+                post_title: None, // このフィールドはここでは不要なためNoneを設定
+                // レス番号は表示順に関わらず書き込み順で付与されたもの (アンカーの参照先)
+                response_number: c.response_number.map(i64::from),
+                level: display_current_level,
+                is_current_level_hidden,
+            };
+
+            CommentResponse {
+                comment,
+                can_moderate,
+                is_op,
+            }
+        })
+        .collect();
+
+    // 新しい順の場合は、書き込み順に取得した結果を並べ替えるだけにする (レス番号は変わらない)
+    if query.sort == models::CommentSortOrder::Desc {
+        response_comments.reverse();
+    }
+
+    Ok(HttpResponse::Ok().json(response_comments))
+}
+
+/// LIKE のパターンで特別な意味を持つ文字 (`\`, `%`, `_`) をバックスラッシュでエスケープします。
+/// `ESCAPE '\'` を指定した LIKE で使います。
+fn escape_like_pattern(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// スレッド内のレスを本文のキーワードで検索します (大文字小文字を区別しない)。
+/// 各レスには、書き込み時に保存したスレッド内のレス番号を付与します。
+#[get("/{id}/comments/search")]
+pub async fn search_comments_in_post(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    query: web::Query<models::ThreadCommentSearchQuery>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let post_id = path.id;
+
+    let keyword = query.q.trim();
+    if keyword.is_empty() {
+        return Err(ServiceError::BadRequest(
+            "検索キーワードを入力してください。".to_string(),
+        ));
+    }
+    // 本文はサニタイズ済みの形式で保存されているため、キーワードも同じ形式に揃えて比較する
+    // `%` や `_` を含むキーワードも文字どおりに検索できるよう、LIKE のワイルドカードをエスケープする
+    let search_term = format!("%{}%", escape_like_pattern(&clean(keyword).to_lowercase()));
+
+    let post_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM posts WHERE id = $1 AND deleted_at IS NULL) as "exists!""#,
+        post_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+    if !post_exists {
+        return Err(ServiceError::NotFound("Post not found".to_string()));
+    }
+
+    let threshold = get_level_display_threshold(pool.get_ref()).await?;
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    // レス番号は書き込み時に保存した番号を使う (削除や移動があっても番号がずれないようにするため)
+    let mut comments: Vec<Comment> = sqlx::query_as(
+        r#"
+        SELECT
+            c.id, c.body, c.post_id, c.user_id, c.author_name, c.created_at, c.updated_at,
+            c.display_user_id, c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash,
+            c.level_at_creation, u.level, NULL::text as post_title,
+            c.response_number::BIGINT as response_number
+        FROM comments c
+        LEFT JOIN users u ON c.user_id = u.id
+        WHERE c.post_id = $1 AND LOWER(c.body) LIKE $2 ESCAPE '\'
+        ORDER BY c.response_number ASC, c.id ASC
+        "#,
+    )
+    .bind(post_id)
+    .bind(search_term)
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    for comment in &mut comments {
+        let (display_level_at_creation, display_current_level, is_current_level_hidden) =
+            process_level_visibility(comment.level_at_creation, comment.level, threshold, is_admin);
+        comment.body = linkify_body(&comment.body);
+        comment.level_at_creation = display_level_at_creation;
+        comment.level = display_current_level;
+        comment.is_current_level_hidden = is_current_level_hidden;
+    }
+
+    Ok(HttpResponse::Ok().json(comments))
+}
+
+/// スレッドの低速モード (`slow_mode_seconds`) が有効な場合、直前のレスから指定秒数が経過しているかを確認します。
+/// 同時に書き込まれた場合にすり抜けないよう、低速モード中のスレッドはトランザクション内で行ロックしてから判定します。
+async fn check_thread_slow_mode(
+    conn: &mut sqlx::PgConnection,
+    post_id: i32,
+) -> Result<(), ServiceError> {
+    let slow_mode_seconds: i32 = sqlx::query_scalar!(
+        "SELECT slow_mode_seconds FROM posts WHERE id = $1",
+        post_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if slow_mode_seconds <= 0 {
+        return Ok(());
+    }
+
+    sqlx::query!("SELECT id FROM posts WHERE id = $1 FOR UPDATE", post_id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+    let last_comment_at = sqlx::query_scalar!(
+        "SELECT MAX(created_at) FROM comments WHERE post_id = $1",
+        post_id
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if let Some(last_comment_at) = last_comment_at {
+        let elapsed = (Utc::now() - last_comment_at).num_seconds();
+        let remaining = slow_mode_seconds as i64 - elapsed;
+        if remaining > 0 {
+            return Err(ServiceError::TooManyRequests(format!(
+                "このスレッドは低速モード中です。あと{}秒待ってから書き込んでください。",
+                remaining
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// [モデレーター用] スレッドの低速モードを設定します。0秒を指定すると解除します。
+#[actix_web::patch("/{id}/slow-mode")]
+pub async fn update_thread_slow_mode(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    payload: web::Json<models::UpdateSlowModeRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    payload.validate()?;
+    let post_id = path.id;
+
+    let mut tx = pool.begin().await?;
+
+    let thread_mod_info = sqlx::query!(
+        r#"
+        SELECT
+            p.user_id as "thread_creator_id",
+            p.board_id,
+            p.slow_mode_seconds,
+            b.created_by as "board_creator_id",
+            b.moderation_type as "moderation_type: models::BoardModerationType"
+        FROM posts p
+        JOIN boards b ON p.board_id = b.id
+        WHERE p.id = $1 AND p.deleted_at IS NULL
+        FOR UPDATE OF p
+        "#,
+        post_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+
+    let permissions = compute_thread_permissions(
+        Some(&*user),
+        thread_mod_info.board_creator_id,
+        thread_mod_info.thread_creator_id,
+        thread_mod_info.moderation_type,
+    );
+    if !permissions.can_moderate {
+        return Err(ServiceError::Forbidden(
+            "このスレッドの低速モードを変更する権限がありません。".to_string(),
+        ));
+    }
+
+    sqlx::query!(
+        "UPDATE posts SET slow_mode_seconds = $1 WHERE id = $2",
+        payload.slow_mode_seconds,
+        post_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query!(
+        "INSERT INTO moderation_logs (actor_user_id, action, board_id, details) VALUES ($1, 'update_thread_slow_mode', $2, $3)",
+        user.user_id,
+        thread_mod_info.board_id,
+        serde_json::json!({
+            "post_id": post_id,
+            "old_value": thread_mod_info.slow_mode_seconds,
+            "new_value": payload.slow_mode_seconds,
+        })
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "post_id": post_id,
+        "slow_mode_seconds": payload.slow_mode_seconds,
+    })))
+}
+
+/// スレッドに対する各種操作の権限を計算します。
+///
+/// - モデレーション: 管理者、板の作成者、βタイプの板ではスレ主
+/// - スレッドの削除: 管理者のみ (`delete_post_by_id`)
+/// - スレッド内BAN: モデレーション権限と同じ (`bans::create_ban` のスレッドスコープ)
+/// - スレッドのロック: 管理者のみ
+pub fn compute_thread_permissions(
+    user: Option<&middleware::AuthenticatedUser>,
+    board_creator_id: Option<i32>,
+    thread_creator_id: Option<i32>,
+    moderation_type: models::BoardModerationType,
+) -> models::ThreadPermissions {
+    let Some(user) = user else {
+        return models::ThreadPermissions::default();
+    };
+    let is_admin = matches!(user.role, middleware::Role::Admin);
+    let is_board_creator = board_creator_id == Some(user.user_id);
+    // βタイプの板では、スレ主もモデレーション権限を持つ
+    let is_thread_creator_on_beta_board = moderation_type == models::BoardModerationType::Beta
+        && thread_creator_id == Some(user.user_id);
+    let can_moderate = is_admin || is_board_creator || is_thread_creator_on_beta_board;
+
+    models::ThreadPermissions {
+        can_moderate,
+        can_delete: is_admin,
+        can_ban: can_moderate,
+        can_lock: is_admin,
+    }
+}
+
+/// ログイン中のユーザーが、指定したスレッドに対して持つ権限を取得します。
+/// クライアントはこの結果を元にモデレーションUIの表示を切り替えます。
+#[get("/{id}/permissions")]
+pub async fn get_thread_permissions(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let post_id = path.id;
+
+    let thread_mod_info = sqlx::query!(
+        r#"
+        SELECT
+            p.user_id as "thread_creator_id",
+            b.created_by as "board_creator_id",
+            b.moderation_type as "moderation_type: models::BoardModerationType"
+        FROM posts p
+        JOIN boards b ON p.board_id = b.id
+        WHERE p.id = $1 AND p.deleted_at IS NULL
+        "#,
+        post_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+
+    let permissions = compute_thread_permissions(
+        user.as_deref(),
+        thread_mod_info.board_creator_id,
+        thread_mod_info.thread_creator_id,
+        thread_mod_info.moderation_type,
+    );
+
+    Ok(HttpResponse::Ok().json(permissions))
+}
+
+// get_reply_graph のレスポンス
+#[derive(serde::Serialize)]
+struct ReplyGraphResponse {
+    post_id: i32,
+    // スレッド本文を含む書き込み数
+    response_count: i64,
+    // レス番号 -> そのレスが本文中で参照 (`>>N`) しているレス番号の一覧
+    graph: std::collections::BTreeMap<i64, Vec<i64>>,
+}
+
+/// 本文中のレスアンカー (`>>N`) から、指定したレスが参照しているレス番号を抽出します。
+/// 存在しない番号や、自分自身・自分より後のレスへの参照は無視し、重複を除いて昇順で返します。
+fn extract_quoted_response_numbers(sanitized_body: &str, own_number: i64) -> Vec<i64> {
+    let quoted: std::collections::BTreeSet<i64> = RE_RES_ANCHOR_ESCAPED
+        .captures_iter(sanitized_body)
+        .filter_map(|caps| caps[1].parse::<i64>().ok())
+        .filter(|n| *n >= 1 && *n < own_number)
+        .collect();
+    quoted.into_iter().collect()
+}
+
+/// スレッド内のレスの参照関係 (返信ツリー) を返します。
+/// 各レス番号から、そのレスが引用しているレス番号への隣接リストをサーバー側で計算します。
+#[get("/{id}/reply-graph")]
+pub async fn get_reply_graph(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+) -> Result<HttpResponse, ServiceError> {
+    let post_id = path.id;
+
+    let post_body = sqlx::query_scalar!(
+        "SELECT body FROM posts WHERE id = $1 AND deleted_at IS NULL",
+        post_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+
+    // レス番号が未設定の古いレスは、書き込み順から番号を求める
+    let comments = sqlx::query!(
+        r#"
+        SELECT
+            COALESCE(
+                response_number::BIGINT,
+                ROW_NUMBER() OVER (ORDER BY created_at ASC, id ASC) + 1
+            ) as "response_number!",
+            body
+        FROM comments
+        WHERE post_id = $1
+        ORDER BY created_at ASC, id ASC
+        "#,
+        post_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let mut graph = std::collections::BTreeMap::new();
+    // スレッド本文 (>>1) は他のレスを参照できない
+    graph.insert(1, extract_quoted_response_numbers(&post_body, 1));
+    for comment in &comments {
+        graph.insert(
+            comment.response_number,
+            extract_quoted_response_numbers(&comment.body, comment.response_number),
+        );
+    }
+
+    Ok(HttpResponse::Ok().json(ReplyGraphResponse {
+        post_id,
+        response_count: 1 + comments.len() as i64,
+        graph,
+    }))
+}
+
+/// スレッドの参加者数（異なるIDの数）を取得します。
+/// モデレーション権限がある場合は、ID別の書き込み数一覧も返します。
+#[get("/{id}/participants")]
+pub async fn get_thread_participants(
+    pool: web::Data<PgPool>,
+    path: web::Path<PathInfo>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+) -> Result<HttpResponse, ServiceError> {
+    let post_id = path.id;
+
+    let thread_mod_info = sqlx::query!(
+        r#"
+        SELECT
+            p.user_id as "thread_creator_id",
+            b.created_by as "board_creator_id",
+            b.moderation_type as "moderation_type: models::BoardModerationType"
+        FROM posts p
+        JOIN boards b ON p.board_id = b.id
+        WHERE p.id = $1 AND p.deleted_at IS NULL
+        "#,
+        post_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Post not found".to_string()))?;
+
+    let can_moderate = compute_thread_permissions(
+        user.as_deref(),
+        thread_mod_info.board_creator_id,
+        thread_mod_info.thread_creator_id,
+        thread_mod_info.moderation_type,
+    )
+    .can_moderate;
+
+    // スレッド本文(>>1)とレスを合わせて、ID別に書き込み数を集計する
+    let rows = sqlx::query!(
+        r#"
+        SELECT display_user_id as "display_user_id!", COUNT(*) as "post_count!"
+        FROM (
+            SELECT display_user_id FROM posts WHERE id = $1
+            UNION ALL
+            SELECT display_user_id FROM comments WHERE post_id = $1
+        ) AS entries
+        WHERE display_user_id IS NOT NULL
+        GROUP BY display_user_id
+        ORDER BY COUNT(*) DESC, display_user_id ASC
+        "#,
+        post_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let response = models::ThreadParticipantsResponse {
+        participant_count: rows.len() as i64,
+        participants: can_moderate.then(|| {
+            rows.into_iter()
+                .map(|r| models::ThreadParticipant {
+                    display_user_id: r.display_user_id,
+                    post_count: r.post_count,
+                })
+                .collect()
+        }),
+    };
+
+    Ok(HttpResponse::Ok().json(response))
+}
+
+// タイムゾーンのオフセットとして許容する範囲 (UTC-14:00 〜 UTC+14:00)
+const MAX_TZ_OFFSET_MINUTES: i32 = 14 * 60;
+
+/// 指定オフセットにおける年月日の0時0分を、UTCの日時に変換します。
+/// オフセットは呼び出し側で範囲チェック済みであることを前提とします。
+fn local_midnight_to_utc(
+    offset: chrono::FixedOffset,
+    year: i32,
+    month: u32,
+    day: u32,
+) -> Option<chrono::DateTime<Utc>> {
+    offset
+        .with_ymd_and_hms(year, month, day, 0, 0, 0)
+        .single()
+        .map(|dt| dt.with_timezone(&Utc))
+}
+
+#[get("/archive")]
+pub async fn get_archived_posts(
+    pool: web::Data<PgPool>,
+    query_params: web::Query<ArchivedPostsQueryParams>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>, // 権限チェックのために追加
+) -> Result<HttpResponse, ServiceError> {
+    // データを取得するためのクエリビルダー
+    let mut data_builder: QueryBuilder<Postgres> = QueryBuilder::new("SELECT p.id, p.title, p.body, p.author_name, p.created_at, p.updated_at, p.board_id, p.deleted_at, p.archived_at, p.last_activity_at, (1 + COALESCE(cc.count, 0)) as total_responses, b.name as board_name FROM posts p LEFT JOIN boards b ON p.board_id = b.id LEFT JOIN (SELECT post_id, COUNT(*) as count FROM comments GROUP BY post_id) cc ON p.id = cc.post_id");
+    // 総件数を取得するためのクエリビルダー
+    let mut count_builder: QueryBuilder<Postgres> =
+        QueryBuilder::new("SELECT COUNT(*) FROM posts p");
+
+    let is_admin = user
+        .as_ref()
+        .is_some_and(|u| matches!(u.role, middleware::Role::Admin));
+
+    // --- WHERE句の動的構築 ---
+    let where_clause = if query_params.show_deleted.unwrap_or(false) && is_admin {
+        // 削除済みスレッドを検索する場合
+        " WHERE p.deleted_at IS NOT NULL".to_string()
+    } else {
+        // 通常の検索（削除されていないスレッド）
+        let mut base_where = " WHERE p.deleted_at IS NULL".to_string();
+        if query_params.include_active_threads == Some(false) {
+            base_where.push_str(" AND p.archived_at IS NOT NULL");
+        }
+        base_where
+    };
+    data_builder.push(where_clause.clone());
+    count_builder.push(where_clause);
+
+    // --- 追加の検索条件 ---
+    // キーワード検索
+    if let Some(q) = &query_params.q {
+        if !q.is_empty() {
+            // キーワードを空白で分割し、空の文字列を除去
+            let keywords: Vec<_> = q.split_whitespace().filter(|s| !s.is_empty()).collect();
+            if !keywords.is_empty() {
+                let search_type_is_or = query_params.search_type.as_deref() == Some("or");
+                let operator = if search_type_is_or { " OR " } else { " AND " };
+
+                // 各ビルダーに条件句の開始を追加
+                data_builder.push(" AND (");
+                count_builder.push(" AND (");
+
+                for (i, keyword) in keywords.iter().enumerate() {
+                    if i > 0 {
+                        data_builder.push(operator);
+                        count_builder.push(operator);
+                    }
+
+                    let search_term = format!("%{}%", keyword.to_lowercase());
+
+                    match query_params.search_field.as_deref().unwrap_or("title") {
+                        "title" => {
+                            data_builder
+                                .push("LOWER(p.title) LIKE ")
+                                .push_bind(search_term.clone());
+                            count_builder
+                                .push("LOWER(p.title) LIKE ")
+                                .push_bind(search_term.clone());
+                        }
+                        "body" => {
+                            data_builder.push("(");
+                            count_builder.push("(");
+
+                            data_builder
+                                .push("LOWER(p.body) LIKE ")
+                                .push_bind(search_term.clone());
+                            count_builder
+                                .push("LOWER(p.body) LIKE ")
+                                .push_bind(search_term.clone());
+
+                            data_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.body) LIKE ").push_bind(search_term.clone()).push(")");
+                            count_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.body) LIKE ").push_bind(search_term.clone()).push(")");
+
+                            if query_params.include_author_names.unwrap_or(false) {
+                                data_builder
+                                    .push(" OR LOWER(p.author_name) LIKE ")
+                                    .push_bind(search_term.clone());
+                                count_builder
+                                    .push(" OR LOWER(p.author_name) LIKE ")
+                                    .push_bind(search_term.clone());
+
+                                data_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.author_name) LIKE ").push_bind(search_term.clone()).push(")");
+                                count_builder.push(" OR p.id IN (SELECT c.post_id FROM comments c WHERE LOWER(c.author_name) LIKE ").push_bind(search_term.clone()).push(")");
+                            }
+
+                            data_builder.push(")");
+                            count_builder.push(")");
+                        }
+                        _ => {} // 無効な値の場合は何もしない
+                    }
+                }
+                // 条件句の終了
+                data_builder.push(")");
+                count_builder.push(")");
+            }
+        }
+    }
+
+    // 板IDでのフィルタリング
+    if let Some(board_id_str) = &query_params.board_id {
+        // スペースで区切られたID文字列をパースしてi32のベクターに変換
+        let board_ids: Vec<i32> = board_id_str
+            .split_whitespace()
+            .filter_map(|s| s.parse::<i32>().ok())
+            .collect();
+
+        if !board_ids.is_empty() {
+            // PostgreSQLのARRAY型にバインドするために `= ANY()` を使用
+            // これによりSQLインジェクションを防ぎつつ、複数のIDでフィルタリングできる
+            data_builder
+                .push(" AND p.board_id = ANY(")
+                .push_bind(board_ids.clone())
+                .push(")");
+            count_builder
+                .push(" AND p.board_id = ANY(")
+                .push_bind(board_ids)
+                .push(")");
+        }
+    }
+
+    // 最小レス数でのフィルタリング
+    if let Some(min_responses) = query_params.min_responses {
+        // data_builderは最適化されたクエリを使っているため、cc.countでフィルタリング
+        data_builder
+            .push(" AND (1 + COALESCE(cc.count, 0)) >= ")
+            .push_bind(min_responses);
+        // count_builderはJOINしていないため、元のサブクエリでフィルタリング（パフォーマンスは落ちるが、結果は正確）
+        count_builder
+            .push(" AND (1 + (SELECT COUNT(*) FROM comments c WHERE c.post_id = p.id)) >= ")
+            .push_bind(min_responses);
+    }
+
+    // 作成年月でのフィルタリング
+    // 年月の境界は指定されたタイムゾーンで計算し、UTCに変換してから比較する (デフォルトはUTC)
+    let tz_offset_minutes = query_params.tz_offset_minutes.unwrap_or(0);
+    if !(-MAX_TZ_OFFSET_MINUTES..=MAX_TZ_OFFSET_MINUTES).contains(&tz_offset_minutes) {
+        return Err(ServiceError::BadRequest(
+            "タイムゾーンのオフセットは-840から840分の範囲で指定してください。".to_string(),
+        ));
+    }
+    let tz_offset = chrono::FixedOffset::east_opt(tz_offset_minutes * 60).ok_or_else(|| {
+        ServiceError::BadRequest("無効なタイムゾーンのオフセットが指定されました。".to_string())
+    })?;
+
+    if let Some(year) = query_params.created_year {
+        if let Some(month) = query_params.created_month {
+            // 年と月が両方指定された場合
+            let start_of_month = local_midnight_to_utc(tz_offset, year, month as u32, 1)
+                .ok_or_else(|| {
+                    ServiceError::BadRequest("無効な年月が指定されました。".to_string())
+                })?;
+            let end_of_month = if month == 12 {
+                local_midnight_to_utc(tz_offset, year + 1, 1, 1)
+            } else {
+                local_midnight_to_utc(tz_offset, year, (month + 1) as u32, 1)
+            }
+            .ok_or_else(|| ServiceError::BadRequest("無効な年月が指定されました。".to_string()))?;
+
+            data_builder
+                .push(" AND p.created_at >= ")
+                .push_bind(start_of_month);
+            count_builder
+                .push(" AND p.created_at >= ")
+                .push_bind(start_of_month);
+
+            data_builder
+                .push(" AND p.created_at < ")
+                .push_bind(end_of_month);
+            count_builder
+                .push(" AND p.created_at < ")
+                .push_bind(end_of_month);
+        } else {
+            // 年のみ指定された場合
+            let start_of_year = local_midnight_to_utc(tz_offset, year, 1, 1)
+                .ok_or_else(|| {
+                    ServiceError::BadRequest("無効な年が指定されました。".to_string())
+                })?;
+            let end_of_year = local_midnight_to_utc(tz_offset, year + 1, 1, 1)
+                .ok_or_else(|| {
+                    ServiceError::BadRequest("無効な年が指定されました。".to_string())
+                })?;
+
+            data_builder
+                .push(" AND p.created_at >= ")
+                .push_bind(start_of_year);
+            count_builder
+                .push(" AND p.created_at >= ")
+                .push_bind(start_of_year);
+
+            data_builder
+                .push(" AND p.created_at < ")
+                .push_bind(end_of_year);
+            count_builder
+                .push(" AND p.created_at < ")
+                .push_bind(end_of_year);
+        }
+    }
+
+    // --- START: SQLデバッグ用ログ ---
+    // 実際に実行されるSQLクエリと、受け取ったパラメータをログに出力します。
+    // これにより、バックエンドが意図通りのクエリを生成しているかを確認できます。
+    let data_sql_debug = data_builder.sql();
+    log::info!(
+        "[ARCHIVE_SEARCH_DEBUG] Received Params: {:?}",
+        query_params.0
+    );
+    log::info!("[ARCHIVE_SEARCH_DEBUG] Data SQL: {}", data_sql_debug);
+    // --- END: SQLデバッグ用ログ ---
+
+    // 総件数を取得するクエリ
+    let total_count: i64 = count_builder
+        .build_query_scalar()
+        .fetch_one(pool.get_ref())
+        .await?;
+
+    // ソート条件の追加
+    let sort_param = query_params.sort.as_deref().unwrap_or("archived_at_desc");
+    let (sort_column, sort_order) = match sort_param.rsplit_once('_') {
+        Some((col, order)) => (col, order),
+        None => (sort_param, "desc"), // "_" がない場合はデフォルトで降順
+    };
+
+    let final_sort_order = if sort_order.eq_ignore_ascii_case("asc") {
+        "ASC"
+    } else {
+        "DESC"
+    };
+
+    // SQLインジェクションを防ぎつつ、ソート順を組み立てる
+    // 第2ソートキーとして last_activity_at を追加し、順序の一貫性を保証する
+    let order_by_clause = match sort_column {
+        "created_at" => {
+            format!("p.created_at {}, p.last_activity_at DESC", final_sort_order)
+        }
+        "archived_at" => {
+            // DESCの場合、NULLS FIRSTで現行スレッドを先頭に。ASCの場合はNULLS LASTで末尾に。
+            let nulls_order = if final_sort_order == "DESC" {
+                "NULLS FIRST"
+            } else {
+                "NULLS LAST"
+            };
+            format!(
+                "p.archived_at {} {}, p.last_activity_at DESC",
+                final_sort_order, nulls_order
+            )
+        }
+        // デフォルトは新着アーカイブ順
+        _ => "p.archived_at DESC NULLS FIRST, p.last_activity_at DESC".to_string(),
+    };
+    data_builder.push(format!(" ORDER BY {}", order_by_clause));
+
+    // ページネーションの追加
+    let limit = query_params.limit.unwrap_or(20); // デフォルトは20件
+    let offset = query_params.offset.unwrap_or(0); // デフォルトは0件目から
+    data_builder.push(" LIMIT ");
+    data_builder.push_bind(limit);
+    data_builder.push(" OFFSET ");
+    data_builder.push_bind(offset);
+
+    // DBから取得後に本文を変換
+    let posts_from_db: Vec<ArchivedPostItem> = data_builder
+        .build_query_as()
+        .fetch_all(pool.get_ref())
+        .await?;
+    let posts: Vec<ArchivedPostItem> = posts_from_db
+        .into_iter()
+        .map(|mut p| {
+            p.body = linkify_body(&p.body);
+            p
+        })
+        .collect();
+
+    // このAPIはoffset指定のため、レスポンスのページ番号はoffsetから換算する
+    let page = if limit > 0 { offset / limit + 1 } else { 1 };
+    let response = models::PagedResponse::new(posts, total_count, page, limit);
+    Ok(HttpResponse::Ok().json(response))
+}
+
+#[delete("/{id}")]
+pub async fn delete_post_by_id(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<PathInfo>,
+) -> Result<HttpResponse, ServiceError> {
+    // 論理削除に変更
+    // Authorization check: Only admins can delete posts.
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let post_id = path.id;
+
+    let result = sqlx::query!(
+        "UPDATE posts SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
+        post_id
+    )
+    .execute(pool.get_ref())
+    .await
+    .map_err(ServiceError::from)?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::NotFound(
+            "Post not found or already deleted".to_string(),
+        ));
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[post("/{id}/restore")]
+pub async fn restore_post_by_id(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<PathInfo>,
+) -> Result<HttpResponse, ServiceError> {
+    // Authorization check: Only admins can restore posts.
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let post_id = path.id;
+
+    let restored_post = sqlx::query_as!(
+        Post,
+        r#"
+        UPDATE posts SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL
+        RETURNING id, title, body, author_name, created_at, updated_at, board_id as "board_id: _", user_id, deleted_at as "deleted_at: _", archived_at as "archived_at: _", last_activity_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, level_at_creation, NULL as "level: _", NULL as "is_current_level_hidden: _"
+        "#,
+        post_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .map_err(ServiceError::from)?;
+
+    match restored_post {
+        Some(post) => Ok(HttpResponse::Ok().json(post)),
+        None => Err(ServiceError::NotFound(
+            "Post not found or was not deleted".to_string(),
+        )),
+    }
+}
+
+// --- START: Admin Identity API ---
+#[get("/identity-details")]
+async fn get_identity_details(
+    pool: web::Data<PgPool>,
+    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
+    query: web::Query<models::IdentityQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    // --- 診断用ログ ---
+    // このログは、最新のコードが実行されていることを確認するためのものです。
+    log::info!(
+        "--- EXECUTING get_identity_details (v_final_check) --- Query: {:?}",
+        query
+    );
+
+    let authenticated_user = user.ok_or(ServiceError::Unauthorized)?;
+
+    // --- START: Detailed Authorization
+    // Authorization check: Only admins can access this.
+    if !matches!(authenticated_user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let (
+        encrypted_email,
+        encrypted_ip,
+        encrypted_device_info,
+        permanent_user_hash,
+        permanent_ip_hash,
+        permanent_device_hash,
+    ) = if let Some(post_id) = query.post_id {
+        // Fetch from post_identities and posts
+        let identity_data = sqlx::query!(
+                "SELECT encrypted_email, encrypted_ip, encrypted_device_info FROM post_identities WHERE post_id = $1",
+                post_id
+            )
+            .fetch_optional(pool.get_ref())
+            .await?;
+
+        let post_hashes = sqlx::query!(
+                "SELECT permanent_user_hash, permanent_ip_hash, permanent_device_hash FROM posts WHERE id = $1",
+                post_id
+            )
+            .fetch_optional(pool.get_ref())
+            .await?;
+
+        // 両方のレコードが見つからない場合のみ、投稿が存在しないと判断する
+        if identity_data.is_none() && post_hashes.is_none() {
+            return Err(ServiceError::NotFound(
+                "DIAGNOSTIC_V4: Post and its identity information not found.".to_string(),
+            ));
+        }
+
+        (
+            identity_data
+                .as_ref()
+                .and_then(|d| d.encrypted_email.clone()),
+            identity_data.as_ref().and_then(|d| d.encrypted_ip.clone()),
+            identity_data
+                .as_ref()
+                .and_then(|d| d.encrypted_device_info.clone()),
+            post_hashes
+                .as_ref()
+                .and_then(|h| h.permanent_user_hash.clone()),
+            post_hashes
+                .as_ref()
+                .and_then(|h| h.permanent_ip_hash.clone()),
+            post_hashes
+                .as_ref()
+                .and_then(|h| h.permanent_device_hash.clone()),
+        )
+    } else if let Some(comment_id) = query.comment_id {
+        // Fetch from comment_identities and comments
+        let identity_data = sqlx::query!(
+                "SELECT encrypted_email, encrypted_ip, encrypted_device_info FROM comment_identities WHERE comment_id = $1",
+                comment_id
+            )
+            .fetch_optional(pool.get_ref())
+            .await?;
+
+        let comment_hashes = sqlx::query!(
+                "SELECT permanent_user_hash, permanent_ip_hash, permanent_device_hash FROM comments WHERE id = $1",
+                comment_id
+            )
+            .fetch_optional(pool.get_ref())
+            .await?;
+
+        // 両方のレコードが見つからない場合のみ、コメントが存在しないと判断する
+        if identity_data.is_none() && comment_hashes.is_none() {
+            return Err(ServiceError::NotFound(
+                "DIAGNOSTIC_V4: Comment and its identity information not found.".to_string(),
+            ));
+        }
+
+        (
+            identity_data
+                .as_ref()
+                .and_then(|d| d.encrypted_email.clone()),
+            identity_data.as_ref().and_then(|d| d.encrypted_ip.clone()),
+            identity_data
+                .as_ref()
+                .and_then(|d| d.encrypted_device_info.clone()),
+            comment_hashes
+                .as_ref()
+                .and_then(|h| h.permanent_user_hash.clone()),
+            comment_hashes
+                .as_ref()
+                .and_then(|h| h.permanent_ip_hash.clone()),
+            comment_hashes
+                .as_ref()
+                .and_then(|h| h.permanent_device_hash.clone()),
+        )
+    } else if let Some(user_id) = query.user_id {
+        // Case 3: Fetch by user_id directly. Data from `board_identities` is hex-encoded.
+        let user_data = sqlx::query!("SELECT email FROM users WHERE id = $1", user_id)
+            .fetch_optional(pool.get_ref())
+            .await?
+            .ok_or_else(|| ServiceError::NotFound("User not found.".to_string()))?;
+
+        // For direct user queries, only email and permanent user hash are available.
+        // We will attempt to find the latest IP/Device info from board creations.
+        let board_identity_data = sqlx::query!(
+            r#"
+                SELECT bi.encrypted_ip, bi.encrypted_device_info
+                FROM board_identities bi
+                JOIN boards b ON bi.board_id = b.id
+                WHERE b.created_by = $1
+                ORDER BY b.created_at DESC
+                LIMIT 1
+                "#,
+            user_id
+        )
+        .fetch_optional(pool.get_ref())
+        .await?;
+
+        // 1. board_identities から取得した16進数文字列をバイト列にデコード
+        let encrypted_ip_bytes = board_identity_data
+            .as_ref()
+            .and_then(|d| d.encrypted_ip.as_ref())
+            .and_then(|hex| hex::decode(hex).ok());
+        let encrypted_device_info_bytes = board_identity_data
+            .as_ref()
+            .and_then(|d| d.encrypted_device_info.as_ref())
+            .and_then(|hex| hex::decode(hex).ok());
+
+        // 2. 復号を試み、失敗した場合は空文字列にする
+        let ip_address = encrypted_ip_bytes
+            .as_deref()
+            .and_then(|bytes| encryption::decrypt(bytes).ok())
+            .unwrap_or_default();
+        let device_info = encrypted_device_info_bytes
+            .as_deref()
+            .and_then(|bytes| encryption::decrypt(bytes).ok())
+            .unwrap_or_default();
+
+        // 3. 復号した情報（または空文字列）を使ってハッシュを生成
+        let identity_hashes =
+            identity::generate_identity_hashes(&user_data.email, &ip_address, &device_info);
+
+        // 4. レスポンスを作成
+        (
+            // emailは常に暗号化して返す
+            Some(encryption::encrypt(&user_data.email)?),
+            // ipとdevice_infoはDBから取得した暗号化済みのバイト列を返す
+            encrypted_ip_bytes,
+            encrypted_device_info_bytes,
+            // ハッシュ値は再計算したものを返す
+            Some(identity_hashes.permanent_user_hash),
+            Some(identity_hashes.permanent_ip_hash),
+            Some(identity_hashes.permanent_device_hash),
+        )
+    } else if let Some(hash) = query.hash.as_deref().map(str::trim).filter(|h| !h.is_empty()) {
+        // Case 4: 永続ハッシュから、そのハッシュを持つ最新のスレッド本文またはレスを探す
+        let latest = sqlx::query!(
+            r#"
+            SELECT
+                encrypted_email as "encrypted_email?",
+                encrypted_ip as "encrypted_ip?",
+                encrypted_device_info as "encrypted_device_info?",
+                permanent_user_hash as "permanent_user_hash?",
+                permanent_ip_hash as "permanent_ip_hash?",
+                permanent_device_hash as "permanent_device_hash?"
+            FROM (
+                SELECT pi.encrypted_email, pi.encrypted_ip, pi.encrypted_device_info,
+                       p.permanent_user_hash, p.permanent_ip_hash, p.permanent_device_hash, p.created_at
+                FROM posts p
+                LEFT JOIN post_identities pi ON pi.post_id = p.id
+                WHERE p.permanent_user_hash = $1 OR p.permanent_ip_hash = $1 OR p.permanent_device_hash = $1
+                UNION ALL
+                SELECT ci.encrypted_email, ci.encrypted_ip, ci.encrypted_device_info,
+                       c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash, c.created_at
+                FROM comments c
+                LEFT JOIN comment_identities ci ON ci.comment_id = c.id
+                WHERE c.permanent_user_hash = $1 OR c.permanent_ip_hash = $1 OR c.permanent_device_hash = $1
+            ) AS matched
+            ORDER BY created_at DESC
+            LIMIT 1
+            "#,
+            hash
+        )
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| {
+            ServiceError::NotFound("No post or comment found for the given hash.".to_string())
+        })?;
+
+        (
+            latest.encrypted_email,
+            latest.encrypted_ip,
+            latest.encrypted_device_info,
+            latest.permanent_user_hash,
+            latest.permanent_ip_hash,
+            latest.permanent_device_hash,
+        )
+    } else {
+        return Err(ServiceError::BadRequest(
+            "Either post_id, comment_id, user_id, or hash must be provided.".to_string(),
+        ));
+    };
+
+    // Decrypt the data
+    let email = encryption::decrypt(&encrypted_email.unwrap_or_default()).unwrap_or_default();
+    let ip_address = encryption::decrypt(&encrypted_ip.unwrap_or_default()).unwrap_or_default();
+    let device_info =
+        encryption::decrypt(&encrypted_device_info.unwrap_or_default()).unwrap_or_default();
+
+    let details = models::IdentityDetails {
+        email,
+        ip_address,
+        device_info,
+        permanent_user_hash,
+        permanent_ip_hash,
+        permanent_device_hash,
+    };
+
+    Ok(HttpResponse::Ok().json(details))
+}
+
+/// [管理者用] ユーザーに紐づく全てのデータ (スレッド、コメント、作成した板、認証履歴、復号済みの個人情報) を
+/// JSONファイルとしてダウンロードさせます。開示請求や調査への対応に使用します。
+#[get("/{id}/export")]
+pub async fn export_user_data(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Forbidden(
+            "Only admins can export user data.".to_string(),
+        ));
+    }
+    let target_user_id = path.into_inner();
+
+    let target_user = sqlx::query_as!(
+        models::User,
+        r#"
+        SELECT id, email, role as "role: _", created_at, level, last_level_up_at, last_level_up_ip, level_up_failure_count, last_level_up_attempt_at, banned_from_level_up, is_rate_limit_exempt, last_linking_token_generated_at
+        FROM users WHERE id = $1
+        "#,
+        target_user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたユーザーが見つかりません。".to_string()))?;
+
+    // 削除済み・過去ログ化済みのものも含め、全て出力する
+    let posts = sqlx::query_as!(
+        Post,
+        r#"
+        SELECT id, title, body, author_name, created_at, updated_at, board_id as "board_id: _", user_id, deleted_at as "deleted_at: _", archived_at as "archived_at: _", last_activity_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, level_at_creation, NULL as "level: _", NULL as "is_current_level_hidden: _"
+        FROM posts WHERE user_id = $1
+        ORDER BY created_at ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let comments = sqlx::query_as!(
+        Comment,
+        r#"
+        SELECT c.id, c.body, c.post_id, c.user_id, c.author_name, c.created_at, c.updated_at, c.display_user_id, c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash, c.level_at_creation, NULL as "level: _", NULL as "is_current_level_hidden: _", p.title as "post_title?", NULL as "response_number: _"
+        FROM comments c
+        JOIN posts p ON c.post_id = p.id
+        WHERE c.user_id = $1
+        ORDER BY c.created_at ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let boards = sqlx::query_as!(
+        Board,
+        r#"
+        SELECT
+            id, name, description, default_name, created_at, updated_at,
+            deleted_at as "deleted_at: _",
+            created_by,
+            max_posts,
+            archived_at as "archived_at: _",
+            moderation_type as "moderation_type: _",
+            last_activity_at,
+            auto_archive_enabled,
+            is_nsfw,
+            posting_frozen,
+            members_only,
+            member_min_level,
+            require_captcha,
+            per_board_ids,
+            default_sort,
+            unique_titles
+        FROM boards WHERE created_by = $1
+        ORDER BY created_at ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let verification_history = sqlx::query_as!(
+        models::VerificationHistoryItem,
+        r#"
+        SELECT id, attempt_type, is_success, ip_address, created_at, rejection_reason, fingerprint_json, proxycheck_json, country_code
+        FROM level_up_attempts WHERE user_id = $1
+        ORDER BY created_at ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    // --- 個人情報の復号 ---
+    // 復号に失敗したものはNoneとして出力し、エクスポート全体は失敗させない
+    let decrypt = |data: Option<Vec<u8>>| data.and_then(|e| encryption::decrypt(&e).ok());
+    let mut identities = Vec::new();
+
+    let post_identities = sqlx::query!(
+        r#"
+        SELECT pi.post_id, pi.encrypted_email, pi.encrypted_ip, pi.encrypted_device_info
+        FROM post_identities pi
+        JOIN posts p ON pi.post_id = p.id
+        WHERE p.user_id = $1
+        ORDER BY pi.post_id ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    identities.extend(post_identities.into_iter().map(|row| models::ExportedIdentity {
+        source_type: "post".to_string(),
+        source_id: row.post_id,
+        email: decrypt(row.encrypted_email),
+        ip_address: decrypt(row.encrypted_ip),
+        device_info: decrypt(row.encrypted_device_info),
+    }));
+
+    let comment_identities = sqlx::query!(
+        r#"
+        SELECT ci.comment_id, ci.encrypted_email, ci.encrypted_ip, ci.encrypted_device_info
+        FROM comment_identities ci
+        JOIN comments c ON ci.comment_id = c.id
+        WHERE c.user_id = $1
+        ORDER BY ci.comment_id ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    identities.extend(comment_identities.into_iter().map(|row| models::ExportedIdentity {
+        source_type: "comment".to_string(),
+        source_id: row.comment_id,
+        email: decrypt(row.encrypted_email),
+        ip_address: decrypt(row.encrypted_ip),
+        device_info: decrypt(row.encrypted_device_info),
+    }));
+
+    // board_identities は16進数文字列で保存されているため、デコードしてから復号する
+    let board_identities = sqlx::query!(
+        r#"
+        SELECT bi.board_id, bi.encrypted_ip, bi.encrypted_device_info
+        FROM board_identities bi
+        JOIN boards b ON bi.board_id = b.id
+        WHERE b.created_by = $1
+        ORDER BY bi.board_id ASC
+        "#,
+        target_user_id
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+    identities.extend(board_identities.into_iter().map(|row| models::ExportedIdentity {
+        source_type: "board".to_string(),
+        source_id: row.board_id,
+        email: None,
+        ip_address: decrypt(row.encrypted_ip.and_then(|h| hex::decode(h).ok())),
+        device_info: decrypt(row.encrypted_device_info.and_then(|h| hex::decode(h).ok())),
+    }));
+
+    log::info!(
+        "[Admin] User data for user {} exported by admin {}",
+        target_user_id,
+        user.user_id
+    );
+
+    let export = models::UserDataExport {
+        exported_at: Utc::now(),
+        user: target_user,
+        posts,
+        comments,
+        boards,
+        verification_history,
+        identities,
+    };
+
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            actix_web::http::header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"user_{}_export.json\"",
+                target_user_id
+            ),
+        ))
+        .json(export))
+}
+// --- END: Admin Identity API ---
+
+/// [管理者用] 板のスレッド数上限を変更します。
+#[actix_web::patch("/boards/{id}/max-posts")]
+pub async fn update_board_max_posts(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+    payload: web::Json<models::UpdateBoardSettingsRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    // 権限チェック: 管理者でなければアクセス不可
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    // 入力値のバリデーション
+    payload.validate()?;
+
+    let board_id = path.into_inner();
+    let new_max_posts = payload.max_posts;
+
+    let mut tx = pool.begin().await?;
+
+    // 変更履歴のために変更前の値を取得 (行ロックで同時更新と競合しないようにする)
+    let old_max_posts: i32 = sqlx::query_scalar!(
+        "SELECT max_posts FROM boards WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
+        board_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    // データベースを更新し、更新後の板情報を取得
+    let updated_board = sqlx::query_as!(
+        Board,
+        r#"
+        UPDATE boards SET max_posts = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL
+        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _",
+                  created_by, max_posts, archived_at as "archived_at: _", last_activity_at, auto_archive_enabled,
+                  is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _"
+        "#,
+        new_max_posts,
+        board_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_board_setting_change(
+        &mut tx,
+        board_id,
+        user.user_id,
+        "max_posts",
+        serde_json::json!(old_max_posts),
+        serde_json::json!(updated_board.max_posts),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(updated_board))
+}
+
+/// [管理者/板作成者用] 板のモデレーションタイプ（α/β）を変更します。
+#[actix_web::patch("/boards/{id}/moderation-type")]
+pub async fn update_board_moderation_type(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+    payload: web::Json<models::UpdateBoardModerationTypeRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    // 入力値のバリデーション
+    payload.validate()?;
+
+    let board_id = path.into_inner();
+
+    let mut tx = pool.begin().await?;
+
+    // --- 権限チェック ---
+    // まず、対象の板が存在し、作成者IDと変更前のモデレーションタイプを取得する
+    let current = sqlx::query!(
+        r#"SELECT created_by, moderation_type as "moderation_type: models::BoardModerationType" FROM boards WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"#,
+        board_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    // 管理者か、または板の作成者でなければアクセス不可
+    if !matches!(user.role, middleware::Role::Admin) && current.created_by != Some(user.user_id) {
+        return Err(ServiceError::Forbidden(
+            "この板の設定を変更する権限がありません。".to_string(),
+        ));
+    }
+
+    let new_moderation_type = &payload.moderation_type;
+
+    // データベースを更新し、更新後の板情報を取得
+    let updated_board = sqlx::query_as!(
+        Board,
+        r#"
+        UPDATE boards SET moderation_type = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL
+        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _", created_by, max_posts, archived_at as "archived_at: _", last_activity_at, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _"
+        "#,
+        new_moderation_type as _,
+        board_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_board_setting_change(
+        &mut tx,
+        board_id,
+        user.user_id,
+        "moderation_type",
+        serde_json::json!(current.moderation_type),
+        serde_json::json!(updated_board.moderation_type),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(updated_board))
+}
+
+/// [管理者/板作成者用] 板の名前、説明、デフォルト名を変更します。
+#[actix_web::patch("/{id}/details")]
+pub async fn update_board_details(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+    payload: web::Json<UpdateBoardDetailsRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    // 1. バリデーション
+    payload.validate()?;
+    // 管理者でない場合、デフォルト名に予約文字が含まれていないかチェック (DBにアクセスする前に行う)
+    if !matches!(user.role, middleware::Role::Admin) {
+        if let Some(name) = &payload.default_name {
+            check_submitted_author_name(name)?;
+        }
+    }
+
+    let board_id = path.into_inner();
+
+    let mut tx = pool.begin().await?;
+
+    // 2. 権限チェックと変更履歴のために板の情報を取得 (行ロックで同時更新と競合しないようにする)
+    let board = sqlx::query_as!(
+        Board,
+        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"#,
+        board_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    // 3. 権限判定
+    let is_admin = matches!(user.role, middleware::Role::Admin);
+    if !is_admin && board.created_by != Some(user.user_id) {
+        return Err(ServiceError::Forbidden(
+            "この板の設定を変更する権限がありません。".to_string(),
+        ));
+    }
+
+    // 4. 動的なUPDATEクエリの構築
+    let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new("UPDATE boards SET ");
+    let mut separated = false;
+
+    if let Some(name) = &payload.name {
+        query_builder.push("name = ").push_bind(clean(name));
+        separated = true;
+    }
+
+    if let Some(description) = &payload.description {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("description = ")
+            .push_bind(clean(description));
+        separated = true;
+    }
+
+    if let Some(default_name) = &payload.default_name {
+        if separated {
+            query_builder.push(", ");
+        }
+        // 空欄が指定された場合は、設定されたデフォルト名に戻す
+        let default_name = if default_name.trim().is_empty() {
+            default_post_name()
+        } else {
+            clean(default_name)
+        };
+        query_builder
+            .push("default_name = ")
+            .push_bind(default_name);
+        separated = true;
+    }
+
+    if let Some(is_nsfw) = payload.is_nsfw {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder.push("is_nsfw = ").push_bind(is_nsfw);
+        separated = true;
+    }
+
+    if let Some(posting_frozen) = payload.posting_frozen {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("posting_frozen = ")
+            .push_bind(posting_frozen);
+        separated = true;
+    }
+
+    if let Some(members_only) = payload.members_only {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder.push("members_only = ").push_bind(members_only);
+        separated = true;
+    }
+
+    if let Some(member_min_level) = payload.member_min_level {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("member_min_level = ")
+            .push_bind(member_min_level);
+        separated = true;
+    }
+
+    if let Some(require_captcha) = payload.require_captcha {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("require_captcha = ")
+            .push_bind(require_captcha);
+        separated = true;
+    }
+
+    if let Some(per_board_ids) = payload.per_board_ids {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("per_board_ids = ")
+            .push_bind(per_board_ids);
+        separated = true;
+    }
+
+    if let Some(unique_titles) = payload.unique_titles {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("unique_titles = ")
+            .push_bind(unique_titles);
+        separated = true;
+    }
+
+    if !separated {
+        // 更新するフィールドがない場合は、取得済みの板情報をそのまま返す
+        return Ok(HttpResponse::Ok().json(board));
+    }
+
+    query_builder
+        .push(", updated_at = NOW() WHERE id = ")
+        .push_bind(board_id);
+    query_builder.push(" RETURNING *");
+
+    let updated_board = query_builder
+        .build_query_as::<Board>()
+        .fetch_one(&mut *tx)
+        .await?;
+
+    // 5. 変更された項目を設定変更履歴に記録
+    let changes = [
+        ("name", serde_json::json!(board.name), serde_json::json!(updated_board.name)),
+        (
+            "description",
+            serde_json::json!(board.description),
+            serde_json::json!(updated_board.description),
+        ),
+        (
+            "default_name",
+            serde_json::json!(board.default_name),
+            serde_json::json!(updated_board.default_name),
+        ),
+        ("is_nsfw", serde_json::json!(board.is_nsfw), serde_json::json!(updated_board.is_nsfw)),
+        (
+            "posting_frozen",
+            serde_json::json!(board.posting_frozen),
+            serde_json::json!(updated_board.posting_frozen),
+        ),
+        (
+            "members_only",
+            serde_json::json!(board.members_only),
+            serde_json::json!(updated_board.members_only),
+        ),
+        (
+            "member_min_level",
+            serde_json::json!(board.member_min_level),
+            serde_json::json!(updated_board.member_min_level),
+        ),
+        (
+            "require_captcha",
+            serde_json::json!(board.require_captcha),
+            serde_json::json!(updated_board.require_captcha),
+        ),
+        (
+            "per_board_ids",
+            serde_json::json!(board.per_board_ids),
+            serde_json::json!(updated_board.per_board_ids),
+        ),
+        (
+            "unique_titles",
+            serde_json::json!(board.unique_titles),
+            serde_json::json!(updated_board.unique_titles),
+        ),
+    ];
+    for (setting, old_value, new_value) in changes {
+        record_board_setting_change(&mut tx, board_id, user.user_id, setting, old_value, new_value)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(updated_board))
+}
+
+/// [管理者/板作成者用] 板の設定 (スレッド数上限、モデレーションタイプ、デフォルト名、
+/// 自動アーカイブ、デフォルトソート順) のうち、指定された項目をまとめて変更します。
+/// スレッド数上限と自動アーカイブは管理者のみ変更できます。
+#[actix_web::patch("/{id}/settings")]
+pub async fn update_board_settings(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+    payload: web::Json<models::PatchBoardSettingsRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    payload.validate()?;
+    if let Some(default_sort) = &payload.default_sort {
+        if thread_list_order_clause(default_sort).is_none() {
+            return Err(ServiceError::BadRequest(
+                "不正なソート順が指定されました。".to_string(),
+            ));
+        }
+    }
+    // 管理者でない場合、デフォルト名に予約文字が含まれていないかチェック (DBにアクセスする前に行う)
+    if !matches!(user.role, middleware::Role::Admin) {
+        if let Some(name) = &payload.default_name {
+            check_submitted_author_name(name)?;
+        }
+    }
+
+    let board_id = path.into_inner();
+
+    let mut tx = pool.begin().await?;
+
+    // 権限チェックと変更履歴のために板の情報を取得 (行ロックで同時更新と競合しないようにする)
+    let board = sqlx::query_as!(
+        Board,
+        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL FOR UPDATE"#,
+        board_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    let is_admin = matches!(user.role, middleware::Role::Admin);
+    if !is_admin && board.created_by != Some(user.user_id) {
+        return Err(ServiceError::Forbidden(
+            "この板の設定を変更する権限がありません。".to_string(),
+        ));
+    }
+    if !is_admin {
+        if payload.max_posts.is_some() || payload.auto_archive_enabled.is_some() {
+            return Err(ServiceError::Forbidden(
+                "スレッド数上限と自動アーカイブの設定は管理者のみ変更できます。".to_string(),
+            ));
+        }
+    }
+
+    let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new("UPDATE boards SET ");
+    let mut separated = false;
+
+    if let Some(max_posts) = payload.max_posts {
+        query_builder.push("max_posts = ").push_bind(max_posts);
+        separated = true;
+    }
+
+    if let Some(moderation_type) = payload.moderation_type {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("moderation_type = ")
+            .push_bind(moderation_type);
+        separated = true;
+    }
+
+    if let Some(default_name) = &payload.default_name {
+        if separated {
+            query_builder.push(", ");
+        }
+        // 空欄が指定された場合は、設定されたデフォルト名に戻す
+        let default_name = if default_name.trim().is_empty() {
+            default_post_name()
+        } else {
+            clean(default_name)
+        };
+        query_builder
+            .push("default_name = ")
+            .push_bind(default_name);
+        separated = true;
+    }
+
+    if let Some(auto_archive_enabled) = payload.auto_archive_enabled {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("auto_archive_enabled = ")
+            .push_bind(auto_archive_enabled);
+        separated = true;
+    }
+
+    if let Some(default_sort) = &payload.default_sort {
+        if separated {
+            query_builder.push(", ");
+        }
+        query_builder
+            .push("default_sort = ")
+            .push_bind(default_sort.clone());
+        separated = true;
+    }
+
+    if !separated {
+        // 更新するフィールドがない場合は、取得済みの板情報をそのまま返す
+        return Ok(HttpResponse::Ok().json(board));
+    }
+
+    query_builder
+        .push(", updated_at = NOW() WHERE id = ")
+        .push_bind(board_id);
+    query_builder.push(" RETURNING *");
+
+    let updated_board = query_builder
+        .build_query_as::<Board>()
+        .fetch_one(&mut *tx)
+        .await?;
+
+    // 変更された項目を設定変更履歴に記録
+    let changes = [
+        (
+            "max_posts",
+            serde_json::json!(board.max_posts),
+            serde_json::json!(updated_board.max_posts),
+        ),
+        (
+            "moderation_type",
+            serde_json::json!(board.moderation_type),
+            serde_json::json!(updated_board.moderation_type),
+        ),
+        (
+            "default_name",
+            serde_json::json!(board.default_name),
+            serde_json::json!(updated_board.default_name),
+        ),
+        (
+            "auto_archive_enabled",
+            serde_json::json!(board.auto_archive_enabled),
+            serde_json::json!(updated_board.auto_archive_enabled),
+        ),
+        (
+            "default_sort",
+            serde_json::json!(board.default_sort),
+            serde_json::json!(updated_board.default_sort),
+        ),
+    ];
+    for (setting, old_value, new_value) in changes {
+        record_board_setting_change(&mut tx, board_id, user.user_id, setting, old_value, new_value)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(updated_board))
+}
+
+/// 板に参加します (メンバー限定の板に書き込むために必要)。
+/// 板ごとに設定された参加に必要なレベル (`member_min_level`) を満たしている必要があります。
+#[post("/{id}/join")]
+pub async fn join_board(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.into_inner();
+
+    let board = sqlx::query!(
+        "SELECT member_min_level, archived_at FROM boards WHERE id = $1 AND deleted_at IS NULL",
+        board_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    if board.archived_at.is_some() {
+        return Err(ServiceError::BadRequest(
+            "この板はアーカイブされているため、参加できません。".to_string(),
+        ));
+    }
+
+    let is_admin = matches!(user.role, middleware::Role::Admin);
+    if !is_admin {
+        let level = sqlx::query_scalar!("SELECT level FROM users WHERE id = $1", user.user_id)
+            .fetch_one(pool.get_ref())
+            .await?;
+        if level < board.member_min_level {
+            return Err(ServiceError::Forbidden(format!(
+                "この板に参加するにはレベル{}以上が必要です。",
+                board.member_min_level
+            )));
+        }
+    }
+
+    sqlx::query!(
+        "INSERT INTO board_members (board_id, user_id) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+        board_id,
+        user.user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// 板から退出します。
+#[delete("/{id}/join")]
+pub async fn leave_board(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.into_inner();
+
+    let result = sqlx::query!(
+        "DELETE FROM board_members WHERE board_id = $1 AND user_id = $2",
+        board_id,
+        user.user_id
+    )
+    .execute(pool.get_ref())
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(ServiceError::NotFound(
+            "この板には参加していません。".to_string(),
+        ));
+    }
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// 板の設定変更を履歴 (board_settings_history) に記録します。
+/// 値が変わっていない項目は記録しません。
+async fn record_board_setting_change(
+    conn: &mut sqlx::PgConnection,
+    board_id: i32,
+    changed_by: i32,
+    setting: &str,
+    old_value: serde_json::Value,
+    new_value: serde_json::Value,
+) -> Result<(), ServiceError> {
+    if old_value == new_value {
+        return Ok(());
+    }
+    sqlx::query!(
+        "INSERT INTO board_settings_history (board_id, changed_by, setting, old_value, new_value) VALUES ($1, $2, $3, $4, $5)",
+        board_id,
+        changed_by,
+        setting,
+        old_value,
+        new_value
+    )
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+/// [管理者/板作成者用] 板の設定変更履歴を新しい順に取得します。
+/// 板作成者には変更者のユーザーIDを返しません。
+#[get("/boards/{id}/settings-history")]
+async fn get_board_settings_history(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+    query: web::Query<models::PaginationParams>,
+) -> Result<HttpResponse, ServiceError> {
+    let board_id = path.into_inner();
+    let is_admin = matches!(user.role, middleware::Role::Admin);
+
+    let board_creator_id: Option<i32> = sqlx::query_scalar!(
+        "SELECT created_by FROM boards WHERE id = $1",
+        board_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+
+    if !is_admin && board_creator_id != Some(user.user_id) {
+        return Err(ServiceError::Forbidden(
+            "この板の設定履歴を閲覧する権限がありません。".to_string(),
+        ));
+    }
+
+    let total_count: i64 = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM board_settings_history WHERE board_id = $1",
+        board_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?
+    .unwrap_or(0);
+
+    let offset = models::page_offset(query.page, query.limit);
+    let mut entries = sqlx::query_as!(
+        models::BoardSettingsHistoryEntry,
+        r#"
+        SELECT id, board_id, changed_by, setting, old_value, new_value, created_at
+        FROM board_settings_history
+        WHERE board_id = $1
+        ORDER BY created_at DESC, id DESC
+        LIMIT $2 OFFSET $3
+        "#,
+        board_id,
+        query.limit,
+        offset
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
 
-    // DBから取得後に本文を変換
-    let posts_from_db: Vec<ArchivedPostItem> = data_builder
-        .build_query_as()
-        .fetch_all(pool.get_ref())
-        .await?;
-    let posts: Vec<ArchivedPostItem> = posts_from_db
-        .into_iter()
-        .map(|mut p| {
-            p.body = linkify_body(&p.body);
-            p
-        })
-        .collect();
+    if !is_admin {
+        for entry in &mut entries {
+            entry.changed_by = None;
+        }
+    }
 
-    let response = models::PaginatedResponse {
-        items: posts,
+    Ok(HttpResponse::Ok().json(models::PagedResponse::new(
+        entries,
         total_count,
-    };
-    Ok(HttpResponse::Ok().json(response))
+        query.page,
+        query.limit,
+    )))
 }
 
-#[delete("/{id}")]
-pub async fn delete_post_by_id(
+/// [管理者用] 板をアーカイブします。
+#[post("/boards/{id}/archive")]
+async fn archive_board(
     pool: web::Data<PgPool>,
     user: web::ReqData<middleware::AuthenticatedUser>,
-    path: web::Path<PathInfo>,
+    path: web::Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
-    // 論理削除に変更
-    // Authorization check: Only admins can delete posts.
     if !matches!(user.role, middleware::Role::Admin) {
         return Err(ServiceError::Unauthorized);
     }
-
-    let post_id = path.id;
-
+    let board_id = path.into_inner();
     let result = sqlx::query!(
-        "UPDATE posts SET deleted_at = NOW() WHERE id = $1 AND deleted_at IS NULL",
-        post_id
+        "UPDATE boards SET archived_at = NOW() WHERE id = $1 AND archived_at IS NULL",
+        board_id
     )
     .execute(pool.get_ref())
-    .await
-    .map_err(ServiceError::from)?;
-
+    .await?;
     if result.rows_affected() == 0 {
         return Err(ServiceError::NotFound(
-            "Post not found or already deleted".to_string(),
+            "板が見つからないか、既にアーカイブされています。".to_string(),
         ));
     }
-    Ok(HttpResponse::NoContent().finish())
+    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "板をアーカイブしました。"})))
 }
 
-#[post("/{id}/restore")]
-pub async fn restore_post_by_id(
+/// [管理者用] 板内のアクティブなスレッドをすべてアーカイブします。
+/// 板自体はアーカイブしないため、新しいスレッドは引き続き作成できます (archive_board とは別の操作)。
+#[post("/boards/{id}/archive-all-threads")]
+async fn archive_all_threads_in_board(
     pool: web::Data<PgPool>,
+    http_client: web::Data<reqwest::Client>,
     user: web::ReqData<middleware::AuthenticatedUser>,
-    path: web::Path<PathInfo>,
+    path: web::Path<i32>,
 ) -> Result<HttpResponse, ServiceError> {
-    // Authorization check: Only admins can restore posts.
     if !matches!(user.role, middleware::Role::Admin) {
         return Err(ServiceError::Unauthorized);
     }
+    let board_id = path.into_inner();
 
-    let post_id = path.id;
+    let mut tx = pool.begin().await?;
 
-    let restored_post = sqlx::query_as!(
-        Post,
-        r#"
-        UPDATE posts SET deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL
-        RETURNING id, title, body, author_name, created_at, updated_at, board_id as "board_id: _", user_id, deleted_at as "deleted_at: _", archived_at as "archived_at: _", last_activity_at, display_user_id, permanent_user_hash, permanent_ip_hash, permanent_device_hash, level_at_creation, NULL as "level: _", NULL as "is_current_level_hidden: _"
-        "#,
-        post_id
+    let board_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM boards WHERE id = $1 AND deleted_at IS NULL) as "exists!""#,
+        board_id
     )
-    .fetch_optional(pool.get_ref())
-    .await
-    .map_err(ServiceError::from)?;
-
-    match restored_post {
-        Some(post) => Ok(HttpResponse::Ok().json(post)),
-        None => Err(ServiceError::NotFound(
-            "Post not found or was not deleted".to_string(),
-        )),
-    }
-}
-
-// --- START: Admin Identity API ---
-#[get("/identity-details")]
-async fn get_identity_details(
-    pool: web::Data<PgPool>,
-    user: Option<web::ReqData<middleware::AuthenticatedUser>>,
-    query: web::Query<models::IdentityQuery>,
-) -> Result<HttpResponse, ServiceError> {
-    // --- 診断用ログ ---
-    // このログは、最新のコードが実行されていることを確認するためのものです。
-    log::info!(
-        "--- EXECUTING get_identity_details (v_final_check) --- Query: {:?}",
-        query
-    );
-
-    let authenticated_user = user.ok_or(ServiceError::Unauthorized)?;
-
-    // --- START: Detailed Authorization
-    // Authorization check: Only admins can access this.
-    if !matches!(authenticated_user.role, middleware::Role::Admin) {
-        return Err(ServiceError::Unauthorized);
+    .fetch_one(&mut *tx)
+    .await?;
+    if !board_exists {
+        return Err(ServiceError::NotFound(
+            "指定された板が見つかりません。".to_string(),
+        ));
     }
 
-    let (
-        encrypted_email,
-        encrypted_ip,
-        encrypted_device_info,
-        permanent_user_hash,
-        permanent_ip_hash,
-        permanent_device_hash,
-    ) = if let Some(post_id) = query.post_id {
-        // Fetch from post_identities and posts
-        let identity_data = sqlx::query!(
-                "SELECT encrypted_email, encrypted_ip, encrypted_device_info FROM post_identities WHERE post_id = $1",
-                post_id
-            )
-            .fetch_optional(pool.get_ref())
-            .await?;
-
-        let post_hashes = sqlx::query!(
-                "SELECT permanent_user_hash, permanent_ip_hash, permanent_device_hash FROM posts WHERE id = $1",
-                post_id
-            )
-            .fetch_optional(pool.get_ref())
-            .await?;
-
-        // 両方のレコードが見つからない場合のみ、投稿が存在しないと判断する
-        if identity_data.is_none() && post_hashes.is_none() {
-            return Err(ServiceError::NotFound(
-                "DIAGNOSTIC_V4: Post and its identity information not found.".to_string(),
-            ));
-        }
-
-        (
-            identity_data
-                .as_ref()
-                .and_then(|d| d.encrypted_email.clone()),
-            identity_data.as_ref().and_then(|d| d.encrypted_ip.clone()),
-            identity_data
-                .as_ref()
-                .and_then(|d| d.encrypted_device_info.clone()),
-            post_hashes
-                .as_ref()
-                .and_then(|h| h.permanent_user_hash.clone()),
-            post_hashes
-                .as_ref()
-                .and_then(|h| h.permanent_ip_hash.clone()),
-            post_hashes
-                .as_ref()
-                .and_then(|h| h.permanent_device_hash.clone()),
-        )
-    } else if let Some(comment_id) = query.comment_id {
-        // Fetch from comment_identities and comments
-        let identity_data = sqlx::query!(
-                "SELECT encrypted_email, encrypted_ip, encrypted_device_info FROM comment_identities WHERE comment_id = $1",
-                comment_id
-            )
-            .fetch_optional(pool.get_ref())
-            .await?;
-
-        let comment_hashes = sqlx::query!(
-                "SELECT permanent_user_hash, permanent_ip_hash, permanent_device_hash FROM comments WHERE id = $1",
-                comment_id
-            )
-            .fetch_optional(pool.get_ref())
-            .await?;
-
-        // 両方のレコードが見つからない場合のみ、コメントが存在しないと判断する
-        if identity_data.is_none() && comment_hashes.is_none() {
-            return Err(ServiceError::NotFound(
-                "DIAGNOSTIC_V4: Comment and its identity information not found.".to_string(),
-            ));
-        }
-
-        (
-            identity_data
-                .as_ref()
-                .and_then(|d| d.encrypted_email.clone()),
-            identity_data.as_ref().and_then(|d| d.encrypted_ip.clone()),
-            identity_data
-                .as_ref()
-                .and_then(|d| d.encrypted_device_info.clone()),
-            comment_hashes
-                .as_ref()
-                .and_then(|h| h.permanent_user_hash.clone()),
-            comment_hashes
-                .as_ref()
-                .and_then(|h| h.permanent_ip_hash.clone()),
-            comment_hashes
-                .as_ref()
-                .and_then(|h| h.permanent_device_hash.clone()),
-        )
-    } else if let Some(user_id) = query.user_id {
-        // Case 3: Fetch by user_id directly. Data from `board_identities` is hex-encoded.
-        let user_data = sqlx::query!("SELECT email FROM users WHERE id = $1", user_id)
-            .fetch_optional(pool.get_ref())
-            .await?
-            .ok_or_else(|| ServiceError::NotFound("User not found.".to_string()))?;
-
-        // For direct user queries, only email and permanent user hash are available.
-        // We will attempt to find the latest IP/Device info from board creations.
-        let board_identity_data = sqlx::query!(
-            r#"
-                SELECT bi.encrypted_ip, bi.encrypted_device_info
-                FROM board_identities bi
-                JOIN boards b ON bi.board_id = b.id
-                WHERE b.created_by = $1
-                ORDER BY b.created_at DESC
-                LIMIT 1
-                "#,
-            user_id
-        )
-        .fetch_optional(pool.get_ref())
-        .await?;
-
-        // 1. board_identities から取得した16進数文字列をバイト列にデコード
-        let encrypted_ip_bytes = board_identity_data
-            .as_ref()
-            .and_then(|d| d.encrypted_ip.as_ref())
-            .and_then(|hex| hex::decode(hex).ok());
-        let encrypted_device_info_bytes = board_identity_data
-            .as_ref()
-            .and_then(|d| d.encrypted_device_info.as_ref())
-            .and_then(|hex| hex::decode(hex).ok());
-
-        // 2. 復号を試み、失敗した場合は空文字列にする
-        let ip_address = encrypted_ip_bytes
-            .as_deref()
-            .and_then(|bytes| encryption::decrypt(bytes).ok())
-            .unwrap_or_default();
-        let device_info = encrypted_device_info_bytes
-            .as_deref()
-            .and_then(|bytes| encryption::decrypt(bytes).ok())
-            .unwrap_or_default();
-
-        // 3. 復号した情報（または空文字列）を使ってハッシュを生成
-        let identity_hashes =
-            identity::generate_identity_hashes(&user_data.email, &ip_address, &device_info);
+    // 既にアーカイブ済み・削除済みのスレッドはそのまま残す
+    let archived = sqlx::query_as!(
+        ArchiveNotification,
+        r#"
+        UPDATE posts SET archived_at = NOW(), archive_scheduled_at = NULL
+        WHERE board_id = $1 AND archived_at IS NULL AND deleted_at IS NULL
+        RETURNING id as post_id, board_id, title, 'admin_archive_all' as "reason!"
+        "#,
+        board_id
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    let archived_count = archived.len() as u64;
 
-        // 4. レスポンスを作成
-        (
-            // emailは常に暗号化して返す
-            Some(encryption::encrypt(&user_data.email)?),
-            // ipとdevice_infoはDBから取得した暗号化済みのバイト列を返す
-            encrypted_ip_bytes,
-            encrypted_device_info_bytes,
-            // ハッシュ値は再計算したものを返す
-            Some(identity_hashes.permanent_user_hash),
-            Some(identity_hashes.permanent_ip_hash),
-            Some(identity_hashes.permanent_device_hash),
-        )
-    } else {
-        return Err(ServiceError::BadRequest(
-            "Either post_id, comment_id, or user_id must be provided.".to_string(),
-        ));
-    };
+    sqlx::query!(
+        "INSERT INTO moderation_logs (actor_user_id, action, board_id, details) VALUES ($1, 'archive_all_threads', $2, $3)",
+        user.user_id,
+        board_id,
+        serde_json::json!({ "archived_count": archived_count })
+    )
+    .execute(&mut *tx)
+    .await?;
 
-    // Decrypt the data
-    let email = encryption::decrypt(&encrypted_email.unwrap_or_default()).unwrap_or_default();
-    let ip_address = encryption::decrypt(&encrypted_ip.unwrap_or_default()).unwrap_or_default();
-    let device_info =
-        encryption::decrypt(&encrypted_device_info.unwrap_or_default()).unwrap_or_default();
+    tx.commit().await?;
+    notify_archived_posts(http_client.get_ref(), &archived).await;
 
-    let details = models::IdentityDetails {
-        email,
-        ip_address,
-        device_info,
-        permanent_user_hash,
-        permanent_ip_hash,
-        permanent_device_hash,
-    };
+    log::info!(
+        "[Admin] Archived {} active threads in board {} by admin {}",
+        archived_count,
+        board_id,
+        user.user_id
+    );
 
-    Ok(HttpResponse::Ok().json(details))
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "板内のスレッドをアーカイブしました。",
+        "archived_count": archived_count,
+    })))
 }
-// --- END: Admin Identity API ---
 
-/// [管理者用] 板のスレッド数上限を変更します。
-#[actix_web::patch("/boards/{id}/max-posts")]
-pub async fn update_board_max_posts(
+/// 板の最終活動日時を、板内のスレッド・レスの実際の最新の作成日時から求めるSQL式 (`boards b` を参照)。
+/// 削除済みのスレッドとそのレスは対象外で、スレッドもレスもない場合は板の作成日時を使用します。
+const RECOMPUTED_BOARD_ACTIVITY_SQL: &str = r#"
+    COALESCE(
+        GREATEST(
+            (SELECT MAX(p.created_at) FROM posts p WHERE p.board_id = b.id AND p.deleted_at IS NULL),
+            (SELECT MAX(c.created_at) FROM comments c JOIN posts p ON c.post_id = p.id
+             WHERE p.board_id = b.id AND p.deleted_at IS NULL)
+        ),
+        b.created_at
+    )
+"#;
+
+#[derive(sqlx::FromRow, serde::Serialize)]
+struct RecomputedBoardActivity {
+    id: i32,
+    last_activity_at: chrono::DateTime<Utc>,
+}
+
+/// [管理者用] 板の最終活動日時 (`last_activity_at`) を、実際の最新のスレッド・レスの日時に合わせて再計算します。
+/// バッチ処理やスレッドの統合・削除によってずれた板一覧の並び順を修正するために使用します。
+#[post("/boards/{id}/recompute-activity")]
+async fn recompute_board_activity(
     pool: web::Data<PgPool>,
     user: web::ReqData<middleware::AuthenticatedUser>,
     path: web::Path<i32>,
-    payload: web::Json<models::UpdateBoardSettingsRequest>,
 ) -> Result<HttpResponse, ServiceError> {
-    // 権限チェック: 管理者でなければアクセス不可
     if !matches!(user.role, middleware::Role::Admin) {
         return Err(ServiceError::Unauthorized);
     }
+    let board_id = path.into_inner();
 
-    // 入力値のバリデーション
-    payload.validate()?;
+    let sql = format!(
+        "UPDATE boards b SET last_activity_at = {} WHERE b.id = $1 AND b.deleted_at IS NULL RETURNING b.id, b.last_activity_at",
+        RECOMPUTED_BOARD_ACTIVITY_SQL
+    );
+    let recomputed = sqlx::query_as::<_, RecomputedBoardActivity>(&sql)
+        .bind(board_id)
+        .fetch_optional(pool.get_ref())
+        .await?
+        .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
 
-    let board_id = path.into_inner();
-    let new_max_posts = payload.max_posts;
+    log::info!(
+        "[Admin] Recomputed last_activity_at of board {} to {} by admin {}",
+        recomputed.id,
+        recomputed.last_activity_at,
+        user.user_id
+    );
 
-    // データベースを更新し、更新後の板情報を取得
-    let updated_board = sqlx::query_as!(
-        Board,
+    Ok(HttpResponse::Ok().json(recomputed))
+}
+
+/// [管理者用] 削除されていないすべての板の最終活動日時を再計算します。
+#[post("/boards/recompute-activity")]
+async fn recompute_all_boards_activity(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    // 値が変わる板だけを更新する
+    let sql = format!(
         r#"
-        UPDATE boards SET max_posts = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL
-        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _",
-                  created_by, max_posts, archived_at as "archived_at: _", last_activity_at, auto_archive_enabled,
-                  moderation_type as "moderation_type: _"
+        UPDATE boards b SET last_activity_at = {activity}
+        WHERE b.deleted_at IS NULL AND b.last_activity_at IS DISTINCT FROM {activity}
+        RETURNING b.id, b.last_activity_at
         "#,
-        new_max_posts,
-        board_id
-    )
-    .fetch_optional(pool.get_ref())
-    .await?;
+        activity = RECOMPUTED_BOARD_ACTIVITY_SQL
+    );
+    let updated = sqlx::query_as::<_, RecomputedBoardActivity>(&sql)
+        .fetch_all(pool.get_ref())
+        .await?;
 
-    // `fetch_optional` の結果を元に、成功レスポンスまたはNot Foundエラーを返す
-    updated_board.map_or_else(
-        || {
-            Err(ServiceError::NotFound(
-                "指定された板が見つかりません。".to_string(),
-            ))
-        },
-        |board| Ok(HttpResponse::Ok().json(board)),
-    )
+    log::info!(
+        "[Admin] Recomputed last_activity_at of {} boards by admin {}",
+        updated.len(),
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "updated_count": updated.len(),
+        "boards": updated,
+    })))
 }
 
-/// [管理者/板作成者用] 板のモデレーションタイプ（α/β）を変更します。
-#[actix_web::patch("/boards/{id}/moderation-type")]
-pub async fn update_board_moderation_type(
+/// [管理者用] 板の所有者（作成者）を別のユーザーに移譲します。
+/// `created_by` を更新するため、板のモデレーション権限も新しい所有者に移ります。
+#[post("/boards/{id}/transfer")]
+async fn transfer_board_ownership(
     pool: web::Data<PgPool>,
     user: web::ReqData<middleware::AuthenticatedUser>,
     path: web::Path<i32>,
-    payload: web::Json<models::UpdateBoardModerationTypeRequest>,
+    payload: web::Json<models::TransferBoardOwnershipRequest>,
 ) -> Result<HttpResponse, ServiceError> {
-    // 入力値のバリデーション
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
     payload.validate()?;
 
     let board_id = path.into_inner();
+    let new_owner_user_id = payload.new_owner_user_id;
 
-    // --- 権限チェック ---
-    // まず、対象の板が存在し、作成者IDを取得する
-    let board_creator_id: Option<i32> = sqlx::query_scalar!(
-        "SELECT created_by FROM boards WHERE id = $1 AND deleted_at IS NULL",
+    let mut tx = pool.begin().await?;
+
+    // 移譲先のユーザーが存在するか確認 (削除済みのアカウントには移譲しない)
+    // 移譲が完了するまでの間にアカウントが削除されないよう、行を共有ロックする
+    let new_owner = sqlx::query_scalar!(
+        "SELECT id FROM users WHERE id = $1 AND deleted_at IS NULL FOR SHARE",
+        new_owner_user_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+    if new_owner.is_none() {
+        return Err(ServiceError::NotFound(
+            "移譲先のユーザーが見つかりません。".to_string(),
+        ));
+    }
+
+    // 変更前の所有者を取得（行ロックをかけて同時更新を防ぐ）
+    let previous_owner_id: Option<i32> = sqlx::query_scalar!(
+        "SELECT created_by FROM boards WHERE id = $1 AND deleted_at IS NULL FOR UPDATE",
         board_id
     )
-    .fetch_optional(pool.get_ref())
+    .fetch_optional(&mut *tx)
     .await?
     .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
 
-    // 管理者か、または板の作成者でなければアクセス不可
-    if !matches!(user.role, middleware::Role::Admin) && board_creator_id != Some(user.user_id) {
-        return Err(ServiceError::Forbidden(
-            "この板の設定を変更する権限がありません。".to_string(),
+    if previous_owner_id == Some(new_owner_user_id) {
+        return Err(ServiceError::BadRequest(
+            "指定されたユーザーは既にこの板の所有者です。".to_string(),
         ));
     }
 
-    let new_moderation_type = &payload.moderation_type;
-
-    // データベースを更新し、更新後の板情報を取得
     let updated_board = sqlx::query_as!(
         Board,
         r#"
-        UPDATE boards SET moderation_type = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL
-        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _", created_by, max_posts, archived_at as "archived_at: _", last_activity_at, auto_archive_enabled, moderation_type as "moderation_type: _"
+        UPDATE boards SET created_by = $1, updated_at = NOW() WHERE id = $2 AND deleted_at IS NULL
+        RETURNING id, name, description, default_name, created_at, updated_at, deleted_at as "deleted_at: _", created_by, max_posts, archived_at as "archived_at: _", last_activity_at, auto_archive_enabled, is_nsfw, posting_frozen, members_only, member_min_level, require_captcha, per_board_ids, default_sort, unique_titles, moderation_type as "moderation_type: _"
         "#,
-        new_moderation_type as _,
+        new_owner_user_id,
         board_id
     )
-    .fetch_optional(pool.get_ref())
+    .fetch_one(&mut *tx)
     .await?;
 
-    // `fetch_optional` の結果を元に、成功レスポンスまたはNot Foundエラーを返す
-    updated_board.map_or_else(
-        || {
-            Err(ServiceError::NotFound(
-                "指定された板が見つかりません。".to_string(),
-            ))
-        },
-        |board| Ok(HttpResponse::Ok().json(board)),
+    // モデレーションログに記録
+    sqlx::query!(
+        "INSERT INTO moderation_logs (actor_user_id, action, board_id, details) VALUES ($1, 'transfer_board_ownership', $2, $3)",
+        user.user_id,
+        board_id,
+        serde_json::json!({
+            "previous_owner_user_id": previous_owner_id,
+            "new_owner_user_id": new_owner_user_id,
+        })
     )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "[Admin] Board {} ownership transferred from {:?} to {} by admin {}",
+        board_id,
+        previous_owner_id,
+        new_owner_user_id,
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(updated_board))
 }
 
-/// [管理者/板作成者用] 板の名前、説明、デフォルト名を変更します。
-#[actix_web::patch("/{id}/details")]
-pub async fn update_board_details(
+/// スレッド内の全レスのレス番号を投稿順に振り直し、`comments.response_number` に保存する。
+/// スレッド本文が1のため、レスは2から始まる。番号が変わった行数を返す。
+async fn recompute_response_numbers(
+    conn: &mut sqlx::PgConnection,
+    post_id: i32,
+) -> Result<u64, ServiceError> {
+    let updated = sqlx::query!(
+        r#"
+        WITH numbered AS (
+            SELECT id, (1 + ROW_NUMBER() OVER (ORDER BY created_at ASC, id ASC))::INTEGER AS n
+            FROM comments
+            WHERE post_id = $1
+        )
+        UPDATE comments c SET response_number = numbered.n
+        FROM numbered
+        WHERE c.id = numbered.id AND c.response_number IS DISTINCT FROM numbered.n
+        "#,
+        post_id
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+
+    Ok(updated)
+}
+
+/// [管理者用] スレッド内の自演 (同一IP・同一端末で複数の表示IDを使い分けている書き込み) の候補を取得します。
+/// スレッド本文とレスを `permanent_ip_hash` / `permanent_device_hash` ごとにまとめ、
+/// 異なる表示IDが2つ以上含まれるものを返します。
+#[get("/posts/{id}/sockpuppets")]
+pub async fn get_thread_sockpuppets(
     pool: web::Data<PgPool>,
     user: web::ReqData<middleware::AuthenticatedUser>,
     path: web::Path<i32>,
-    payload: web::Json<UpdateBoardDetailsRequest>,
 ) -> Result<HttpResponse, ServiceError> {
-    // 1. バリデーション
-    payload.validate()?;
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    let post_id = path.into_inner();
 
-    let board_id = path.into_inner();
+    let post_exists = sqlx::query_scalar!("SELECT id FROM posts WHERE id = $1", post_id)
+        .fetch_optional(pool.get_ref())
+        .await?;
+    if post_exists.is_none() {
+        return Err(ServiceError::NotFound(
+            "指定されたスレッドが見つかりません。".to_string(),
+        ));
+    }
 
-    // 2. 権限チェックのために板の情報を取得
-    let board = sqlx::query_as!(
-        Board,
-        r#"SELECT id, name, description, default_name, created_at, updated_at, deleted_at, created_by, last_activity_at, archived_at, max_posts, auto_archive_enabled, moderation_type as "moderation_type: _" FROM boards WHERE id = $1 AND deleted_at IS NULL"#,
-        board_id
+    let clusters = sqlx::query_as!(
+        models::SockpuppetCluster,
+        r#"
+        WITH thread_entries AS (
+            SELECT 1::BIGINT as response_number, display_user_id, permanent_ip_hash, permanent_device_hash
+            FROM posts
+            WHERE id = $1
+            UNION ALL
+            SELECT response_number::BIGINT, display_user_id, permanent_ip_hash, permanent_device_hash
+            FROM comments
+            WHERE post_id = $1
+        ),
+        shared AS (
+            SELECT 'ip' as kind, permanent_ip_hash as hash, display_user_id, response_number
+            FROM thread_entries
+            WHERE permanent_ip_hash IS NOT NULL
+            UNION ALL
+            SELECT 'device' as kind, permanent_device_hash as hash, display_user_id, response_number
+            FROM thread_entries
+            WHERE permanent_device_hash IS NOT NULL
+        )
+        SELECT
+            kind as "kind!",
+            hash as "hash!",
+            ARRAY_AGG(DISTINCT display_user_id) FILTER (WHERE display_user_id IS NOT NULL) as "display_user_ids!",
+            COALESCE(
+                ARRAY_AGG(response_number ORDER BY response_number) FILTER (WHERE response_number IS NOT NULL),
+                '{}'
+            ) as "response_numbers!"
+        FROM shared
+        GROUP BY kind, hash
+        HAVING COUNT(DISTINCT display_user_id) > 1
+        ORDER BY COUNT(*) DESC, kind, hash
+        "#,
+        post_id
     )
-    .fetch_optional(pool.get_ref())
-    .await?
-    .ok_or_else(|| ServiceError::NotFound("指定された板が見つかりません。".to_string()))?;
+    .fetch_all(pool.get_ref())
+    .await?;
 
-    // 3. 権限判定
-    if !matches!(user.role, middleware::Role::Admin) && board.created_by != Some(user.user_id) {
-        return Err(ServiceError::Forbidden(
-            "この板の設定を変更する権限がありません。".to_string(),
-        ));
+    Ok(HttpResponse::Ok().json(clusters))
+}
+
+/// [管理者用] スレッドのレス番号を再計算し、保存済みのレス番号を修復します。
+/// 古いレスでレス番号が未設定のものや、並び順とずれているものを補正します。
+#[post("/posts/{id}/recompute-responses")]
+async fn recompute_post_responses(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<middleware::AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, middleware::Role::Admin) {
+        return Err(ServiceError::Unauthorized);
     }
+    let post_id = path.into_inner();
 
-    // 4. 動的なUPDATEクエリの構築
-    let mut query_builder: QueryBuilder<Postgres> = QueryBuilder::new("UPDATE boards SET ");
-    let mut separated = false;
+    let mut tx = pool.begin().await?;
 
-    if let Some(name) = &payload.name {
-        query_builder.push("name = ").push_bind(clean(name));
-        separated = true;
-    }
+    // 再計算中に統合などでレスが移動しないよう、スレッドに行ロックをかける
+    sqlx::query!("SELECT id FROM posts WHERE id = $1 FOR UPDATE", post_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or_else(|| ServiceError::NotFound("スレッドが見つかりません。".to_string()))?;
 
-    if let Some(description) = &payload.description {
-        if separated {
-            query_builder.push(", ");
-        }
-        query_builder
-            .push("description = ")
-            .push_bind(clean(description));
-        separated = true;
-    }
+    let comment_count: i64 = sqlx::query_scalar!(
+        "SELECT COUNT(*) FROM comments WHERE post_id = $1",
+        post_id
+    )
+    .fetch_one(&mut *tx)
+    .await?
+    .unwrap_or(0);
 
-    if let Some(default_name) = &payload.default_name {
-        if separated {
-            query_builder.push(", ");
-        }
-        query_builder
-            .push("default_name = ")
-            .push_bind(clean(default_name));
-        separated = true;
-    }
+    let updated = recompute_response_numbers(&mut tx, post_id).await?;
 
-    if !separated {
-        // 更新するフィールドがない場合は、取得済みの板情報をそのまま返す
-        return Ok(HttpResponse::Ok().json(board));
-    }
+    tx.commit().await?;
 
-    query_builder
-        .push(", updated_at = NOW() WHERE id = ")
-        .push_bind(board_id);
-    query_builder.push(" RETURNING *");
+    log::info!(
+        "[Admin] Recomputed response numbers for post {} ({} of {} comments updated) by admin {}",
+        post_id,
+        updated,
+        comment_count,
+        user.user_id
+    );
 
-    let updated_board = query_builder
-        .build_query_as::<Board>()
-        .fetch_one(pool.get_ref())
-        .await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "post_id": post_id,
+        "comment_count": comment_count,
+        "updated_count": updated,
+    })))
+}
 
-    Ok(HttpResponse::Ok().json(updated_board))
+/// 統合元スレッドのレスを統合先スレッドへ移動します。
+/// 統合先の既存のレス番号は変えず、移動したレスには統合先の最大のレス番号に続く番号を投稿順に割り当てます。
+async fn append_moved_comments(
+    conn: &mut sqlx::PgConnection,
+    target_post_id: i32,
+    source_post_id: i32,
+) -> Result<u64, ServiceError> {
+    let moved = sqlx::query!(
+        r#"
+        WITH target_last AS (
+            SELECT COALESCE(MAX(response_number), 1) AS n FROM comments WHERE post_id = $1
+        ),
+        moved AS (
+            SELECT id, (ROW_NUMBER() OVER (ORDER BY created_at ASC, id ASC))::INTEGER AS offset_n
+            FROM comments
+            WHERE post_id = $2
+        )
+        UPDATE comments c SET post_id = $1, response_number = target_last.n + moved.offset_n
+        FROM moved, target_last
+        WHERE c.id = moved.id
+        "#,
+        target_post_id,
+        source_post_id
+    )
+    .execute(conn)
+    .await?
+    .rows_affected();
+
+    Ok(moved)
 }
 
-/// [管理者用] 板をアーカイブします。
-#[post("/boards/{id}/archive")]
-async fn archive_board(
+/// [管理者用] 重複したスレッドを統合します。
+/// 統合元スレッドのレスをすべて統合先スレッドへ移動し、統合元スレッドを削除（論理削除）します。
+/// 統合先の既存のレス番号を変えないよう、移動したレスは統合先の最後のレスの後ろに投稿順で追加します。
+/// 板ごとの権限やBANの範囲が変わらないよう、別の板のスレッド同士は統合できません。
+#[post("/posts/{id}/merge")]
+async fn merge_posts(
     pool: web::Data<PgPool>,
     user: web::ReqData<middleware::AuthenticatedUser>,
     path: web::Path<i32>,
+    payload: web::Json<models::MergePostsRequest>,
 ) -> Result<HttpResponse, ServiceError> {
     if !matches!(user.role, middleware::Role::Admin) {
         return Err(ServiceError::Unauthorized);
     }
-    let board_id = path.into_inner();
-    let result = sqlx::query!(
-        "UPDATE boards SET archived_at = NOW() WHERE id = $1 AND archived_at IS NULL",
-        board_id
+    payload.validate()?;
+
+    let target_post_id = path.into_inner();
+    let source_post_id = payload.source_post_id;
+    if target_post_id == source_post_id {
+        return Err(ServiceError::BadRequest(
+            "同じスレッド同士は統合できません。".to_string(),
+        ));
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // 両スレッドに行ロックをかけ、統合中の書き込みや削除と競合しないようにする
+    let locked_posts = sqlx::query!(
+        "SELECT id, board_id FROM posts WHERE id = ANY($1) AND deleted_at IS NULL ORDER BY id FOR UPDATE",
+        &[target_post_id, source_post_id][..]
+    )
+    .fetch_all(&mut *tx)
+    .await?;
+    if !locked_posts.iter().any(|p| p.id == target_post_id) {
+        return Err(ServiceError::NotFound(
+            "統合先のスレッドが見つかりません。".to_string(),
+        ));
+    }
+    if !locked_posts.iter().any(|p| p.id == source_post_id) {
+        return Err(ServiceError::NotFound(
+            "統合元のスレッドが見つかりません。".to_string(),
+        ));
+    }
+    let board_ids: std::collections::HashSet<Option<i32>> =
+        locked_posts.iter().map(|p| p.board_id).collect();
+    if board_ids.len() > 1 {
+        return Err(ServiceError::BadRequest(
+            "別の板のスレッド同士は統合できません。".to_string(),
+        ));
+    }
+
+    // 統合後の最後のレス番号がスレッドの上限(1000)を超えないか確認
+    let last_response_number: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT
+            COALESCE((SELECT MAX(response_number) FROM comments WHERE post_id = $1), 1)::BIGINT
+            + (SELECT COUNT(*) FROM comments WHERE post_id = $2) as "n!"
+        "#,
+        target_post_id,
+        source_post_id
     )
-    .execute(pool.get_ref())
+    .fetch_one(&mut *tx)
     .await?;
-    if result.rows_affected() == 0 {
-        return Err(ServiceError::NotFound(
-            "板が見つからないか、既にアーカイブされています。".to_string(),
+    if last_response_number > MAX_RESPONSES_PER_THREAD {
+        return Err(ServiceError::BadRequest(
+            "統合後のレス数が上限を超えるため、統合できません。".to_string(),
         ));
     }
-    Ok(HttpResponse::Ok().json(serde_json::json!({"message": "板をアーカイブしました。"})))
+
+    // レスを統合先の最後のレスの後ろへ移動
+    let moved = append_moved_comments(&mut tx, target_post_id, source_post_id).await?;
+
+    // 統合先の最終活動日時を、移動したレスも含めた最新の日時に合わせる
+    sqlx::query!(
+        r#"
+        UPDATE posts SET last_activity_at = GREATEST(
+            last_activity_at,
+            (SELECT MAX(created_at) FROM comments WHERE post_id = $1)
+        )
+        WHERE id = $1
+        "#,
+        target_post_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    // 統合元スレッドを論理削除
+    sqlx::query!(
+        "UPDATE posts SET deleted_at = NOW() WHERE id = $1",
+        source_post_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    let target_board_id = locked_posts
+        .iter()
+        .find(|p| p.id == target_post_id)
+        .and_then(|p| p.board_id);
+    sqlx::query!(
+        "INSERT INTO moderation_logs (actor_user_id, action, board_id, details) VALUES ($1, 'merge_posts', $2, $3)",
+        user.user_id,
+        target_board_id,
+        serde_json::json!({
+            "target_post_id": target_post_id,
+            "source_post_id": source_post_id,
+            "moved_comment_count": moved,
+        })
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    log::info!(
+        "[Admin] Post {} merged into post {} ({} comments moved) by admin {}",
+        source_post_id,
+        target_post_id,
+        moved,
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "message": "スレッドを統合しました。",
+        "target_post_id": target_post_id,
+        "source_post_id": source_post_id,
+        "moved_comment_count": moved,
+        "total_comment_count": combined_comment_count,
+    })))
 }
 
 /// [管理者用] 板のアーカイブを解除します。
@@ -2357,7 +6403,15 @@ async fn toggle_auto_archive(
             archived_at as "archived_at: _",
             moderation_type as "moderation_type: _",
             last_activity_at,
-            auto_archive_enabled
+            auto_archive_enabled,
+            is_nsfw,
+            posting_frozen,
+            members_only,
+            member_min_level,
+            require_captcha,
+            per_board_ids,
+            default_sort,
+            unique_titles
         "#,
         board_id
     )
@@ -2414,7 +6468,10 @@ fn process_level_visibility(
     )
 }
 
-/// IPv6アドレス文字列を/64プレフィックスに切り詰めます。
+/// ID生成時にIPv6アドレスを切り詰めるプレフィックス長
+const IPV6_PREFIX_LENGTH: u8 = 64;
+
+/// IPv6アドレス文字列を/64プレフィックス (`IPV6_PREFIX_LENGTH`) に切り詰めます。
 /// IPv4アドレスやパースできない文字列はそのまま返します。
 fn truncate_ipv6_prefix(ip_str: &str) -> String {
     match ip_str.parse::<IpAddr>() {
@@ -2427,7 +6484,9 @@ fn truncate_ipv6_prefix(ip_str: &str) -> String {
                 segments[3],
                 0, 0, 0, 0, // ホスト部を0に
             );
-            log::info!("[IP DIAG] Truncated IPv6 '{}' to '{}'", ip_str, truncated_ipv6);
+            if is_verbose_diag_logging_enabled() {
+                log::info!("[IP DIAG] Truncated IPv6 '{}' to '{}'", ip_str, truncated_ipv6);
+            }
             truncated_ipv6.to_string()
         }
         _ => ip_str.to_string(), // IPv4 or invalid, return as is
@@ -2438,32 +6497,57 @@ fn truncate_ipv6_prefix(ip_str: &str) -> String {
 pub fn configure_app(cfg: &mut web::ServiceConfig) {
     cfg.service(hello) // GET /hello
         .service(ping)  // GET /api/ping (dev)
+        .service(get_server_config) // GET /api/config
         // auth
         .service(web::scope("/auth")
             // .service(auth::request_otp) // メール認証フローは現在未使用
             .service(auth::preflight_check) // アカウント作成前の事前チェックを追加
             // .service(auth::verify_otp) // メール認証フローは現在未使用
             .service(auth::get_me)
+            .service(auth::set_contact_email) // POST /api/auth/me/contact-email
+            .service(auth::delete_my_account) // DELETE /api/auth/me
             .service(auth::toggle_rate_limit_exemption)
             .service(auth::create_account) // 新規アカウント作成 (アカウントID)
             .service(auth::login_with_account_id) // アカウントIDでログイン (アカウントID)
             .service(auth::regenerate_linking_token)
+            .service(auth::get_linking_tokens) // GET /api/auth/linking-tokens
         )
         // admin
         // 管理者用APIは /api/admin スコープに配置し、認証ミドルウェアを適用
         .service(web::scope("/admin") // 認証はmain.rsでグローバルに適用済み
             .service(update_board_max_posts) // PATCH /api/admin/boards/{id}/max-posts
             .service(update_board_moderation_type) // PATCH /api/admin/boards/{id}/moderation-type
+            .service(get_board_settings_history) // GET /api/admin/boards/{id}/settings-history
             .service(archive_board)      // POST /api/admin/boards/{id}/archive
             .service(unarchive_board)    // POST /api/admin/boards/{id}/unarchive
+            .service(archive_all_threads_in_board) // POST /api/admin/boards/{id}/archive-all-threads
+            .service(transfer_board_ownership) // POST /api/admin/boards/{id}/transfer
+            .service(recompute_all_boards_activity) // POST /api/admin/boards/recompute-activity
+            .service(recompute_board_activity) // POST /api/admin/boards/{id}/recompute-activity
+            .service(get_recent_boards)  // GET /api/admin/boards/recent
+            .service(get_site_stats_overview) // GET /api/admin/stats/overview
             .service(toggle_auto_archive) // POST /api/admin/boards/{id}/toggle-auto-archive
+            .service(merge_posts)        // POST /api/admin/posts/{id}/merge
+            .service(recompute_post_responses) // POST /api/admin/posts/{id}/recompute-responses
+            .service(get_thread_sockpuppets) // GET /api/admin/posts/{id}/sockpuppets
             .service(bans::get_admin_bans) // 管理者用BAN一覧APIを追加
+            .service(bans::simulate_bans) // GET /api/admin/bans/simulate
+            .service(bans::delete_bans_by_source) // DELETE /api/admin/bans/by-source
+            .service(bans::create_ban_reason_template) // POST /api/admin/ban-reason-templates
+            .service(bans::update_ban_reason_template) // PATCH /api/admin/ban-reason-templates/{id}
+            .service(bans::delete_ban_reason_template) // DELETE /api/admin/ban-reason-templates/{id}
             .service(admin::verifications::get_failed_verification_history) // GET /api/admin/failed-verifications
+            .service(level_up::search_failed_verifications) // GET /api/admin/failed-verifications/search
+            .service(level_up::replay_verification) // POST /api/admin/verifications/{id}/replay
             .service(get_identity_details) // /admin/identity-details
             .service(web::scope("/users") // /api/admin/users
                 .service(users::get_users)
                 .service(users::get_user_by_id)
                 .service(users::set_user_level)
+                .service(auth::set_user_rate_limit_exemption) // POST /api/admin/users/{id}/rate-limit-exemption
+                .service(auth::get_user_sessions) // GET /api/admin/users/{id}/sessions
+                .service(auth::revoke_user_sessions) // DELETE /api/admin/users/{id}/sessions
+                .service(export_user_data) // GET /api/admin/users/{id}/export
                 .service(web::scope("/{id}/history") // /api/admin/users/{id}/history
                     .service(admin::history::get_comment_history)
                     .service(admin::history::get_verification_history)
@@ -2487,7 +6571,11 @@ pub fn configure_app(cfg: &mut web::ServiceConfig) {
                 .service(rate_limiter::delete_rate_limit_rule)
                 .service(rate_limiter::toggle_rate_limit_rule)
                 .service(rate_limiter::get_active_rate_limit_locks)
+                .service(rate_limiter::get_rate_limit_tracker_hits) // GET /api/admin/rate-limits/tracker
+                .service(rate_limiter::check_rate_limit_locks) // GET /api/admin/rate-limits/check
                 .service(rate_limiter::delete_rate_limit_lock)
+                .service(rate_limiter::update_rate_limit_lock) // PATCH /api/admin/rate-limits/locks/{target_key}
+                .service(rate_limiter::delete_rate_limit_locks_by_rule) // DELETE /api/admin/rate-limits/{rule_id}/locks
             )
         )
         // bans
@@ -2495,33 +6583,64 @@ pub fn configure_app(cfg: &mut web::ServiceConfig) {
         .service(web::scope("/bans")
             // .wrap(middleware::Auth) // create_banとdelete_banは内部で認証を処理するため、ここでは不要
             .service(bans::create_ban) // POST /api/bans
+            .service(bans::get_bans_by_source) // GET /api/bans/by-source
+            .service(bans::get_ban_reason_templates) // GET /api/bans/reason-templates
             .service(bans::delete_ban) // DELETE /api/bans/{id}
         )
         // 自分のBAN一覧を取得するAPI (GET /api/me/bans)
         .service(bans::get_bans)
+        // 自分のレート制限の状況を取得するAPI (GET /api/me/rate-limit-status)
+        .service(rate_limiter::get_my_rate_limit_status)
+        // 自分の認証・レベルアップ履歴を取得するAPI (GET /api/me/verification-history)
+        .service(level_up::get_my_verification_history)
         // boards
         .service(web::scope("/boards")
             .service(get_boards)            // GET /api/boards
             .service(create_board)          // POST   /api/boards
+            .service(get_latest_posts_per_board) // GET /api/boards/latest-posts
+            .service(get_board_by_name)     // GET /api/boards/by-name/{name}
             .service(get_board_by_id)       // GET /api/boards/{id}
             .service(get_posts_by_board_id) // GET /api/boards/{id}/posts
+            .service(get_filling_posts_by_board_id) // GET /api/boards/{id}/filling
+            .service(get_recent_comments_by_board_id) // GET /api/boards/{id}/recent-comments
+            .service(get_board_activity) // GET /api/boards/{id}/activity
+            .service(get_post_by_board_sequence) // GET /api/boards/{board_id}/threads/{seq}
+            .service(get_subject_debug) // GET /api/boards/{id}/subject-debug
+            .service(get_post_identity_summary) // GET /api/boards/{id}/posts/{post_id}/identity-summary
+            .service(bans::get_board_bans) // GET /api/boards/{id}/bans
             .service(delete_board_by_id) // DELETE /api/boards/{id}
             .service(restore_board_by_id)// POST   /api/boards/{id}/restore
             .service(update_board_details) // PATCH  /api/boards/{id}/details
+            .service(update_board_settings) // PATCH  /api/boards/{id}/settings
+            .service(join_board)           // POST   /api/boards/{id}/join
+            .service(leave_board)          // DELETE /api/boards/{id}/join
         )
         // posts & comments
         .service(web::scope("/posts") // `/posts` スコープでグループ化
             // --- 認証不要なGETリクエスト ---
             .service(get_posts)                 // GET /api/posts
             .service(create_post)               // POST /api/posts
+            .service(validate_post)             // POST /api/posts/validate
+            .service(get_hot_posts)             // GET /api/posts/hot ({id} より先に登録する)
+            .service(get_comment_count_deltas)  // POST /api/posts/comment-counts
             .service(get_post_by_id)            // GET /api/posts/{id}
             .service(get_post_by_timestamp)     // GET /api/posts/by-timestamp/{timestamp}
+            .service(get_post_by_timestamp_any_board) // GET /api/posts/by-timestamp/{timestamp}/any
             .service(get_comments_by_post_id)   // GET /api/posts/{id}/comments
+            .service(search_comments_in_post)   // GET /api/posts/{id}/comments/search
+            .service(get_thread_participants)   // GET /api/posts/{id}/participants
+            .service(get_reply_graph)           // GET /api/posts/{id}/reply-graph
+            .service(get_thread_permissions)    // GET /api/posts/{id}/permissions
+            .service(update_thread_slow_mode)   // PATCH /api/posts/{id}/slow-mode
             .service(delete_post_by_id)         // DELETE /api/posts/{id}
             .service(restore_post_by_id)        // POST /api/posts/{id}/restore
         )
         // comments (POST) - create_postは/postsスコープに移動済み
         .service(create_comment) // POST /api/comments
+        .service(validate_comment) // POST /api/comments/validate
+        .service(update_comment) // PATCH /api/comments/{id}
+        .service(get_comment_edits) // GET /api/comments/{id}/edits
+        .service(render_preview) // POST /api/render-preview
         // level-up system (認証が必要)
         .service(web::scope("/level-up")
             .service(level_up::get_status)         // GET  /api/level-up/status
@@ -2566,7 +6685,76 @@ fn is_potentially_exposed_token(body: &str) -> bool {
 
 // --- START: New Authentication Helper Function ---
 
+/// 匿名投稿用の共有ユーザーのアカウントID (usersテーブルのemailカラムに保存される値)。
+/// 通常のアカウントIDは32文字の英数字のため、この値と衝突することはない。
+pub const ANONYMOUS_USER_ACCOUNT_ID: &str = "__anonymous__";
+
+/// アカウントなしでの匿名投稿を許可するかどうかを返します。
+/// 環境変数 `ALLOW_ANONYMOUS_POSTING` が `true` の場合のみ有効です (デフォルトは無効)。
+///
+/// 匿名投稿者は全員が1つの共有ユーザーで書き込むため、アカウント単位のルールは共有ユーザーに適用しません。
+/// - `MIN_ACCOUNT_AGE_SECONDS`: 共有ユーザーは最初の匿名投稿で作成されたまま古くなっていくため、対象外とする
+/// - レート制限: ユーザー単位のキーとルールは使わず、IP・デバイスを含むキーのみで制限する
+/// - BAN・表示ID: IPとデバイス情報から作る投稿者ごとの識別子 (`poster_identifier`) を使う
+fn is_anonymous_posting_allowed() -> bool {
+    env::var("ALLOW_ANONYMOUS_POSTING")
+        .map(|v| v.trim().eq_ignore_ascii_case("true"))
+        .unwrap_or(false)
+}
+
+/// 表示IDと永続ハッシュの生成に使う、投稿者を識別する文字列を返します。
+/// 通常はアカウントID (email) ですが、匿名投稿では全員が同じ共有ユーザーになるため、
+/// IPとデバイス情報から投稿者ごとの識別子を作ります。これにより、ユーザーハッシュによるBANや
+/// 同一内容の連続投稿チェックが、他の匿名投稿者を巻き込まないようになります。
+fn poster_identifier(user_email: &str, truncated_ip: &str, device_info: &str) -> String {
+    if user_email == ANONYMOUS_USER_ACCOUNT_ID {
+        format!("{}:{}:{}", ANONYMOUS_USER_ACCOUNT_ID, truncated_ip, device_info)
+    } else {
+        user_email.to_string()
+    }
+}
+
+/// 匿名投稿用の共有ユーザーのIDを取得します。存在しない場合は作成します。
+/// 匿名投稿者の識別（BAN・レート制限）は、IPとデバイスのハッシュによって行われます。
+/// 共有ユーザーの作成日時は投稿者ごとの経過時間を表さないため、アカウントの経過時間の判定には使いません。
+async fn get_or_create_anonymous_user_id(pool: &PgPool) -> Result<i32, ServiceError> {
+    let user_id = sqlx::query_scalar!(
+        r#"
+        INSERT INTO users (email) VALUES ($1)
+        ON CONFLICT (email) DO UPDATE SET email = EXCLUDED.email
+        RETURNING id
+        "#,
+        ANONYMOUS_USER_ACCOUNT_ID
+    )
+    .fetch_one(pool)
+    .await?;
+    Ok(user_id)
+}
+
+/// 連携トークンのみの書き込み (トークンを取り除くと本文が空になる) を拒否する際のメッセージ
+const TOKEN_ONLY_POST_REJECTED_MESSAGE: &str =
+    "本文を入力してください。連携トークンのみの書き込みはできません。";
+
+/// 連携トークンのみの書き込みの本文を決定します。
+/// 環境変数 `TOKEN_ONLY_POST_BEHAVIOR` で動作を切り替えます。
+/// - `placeholder` (デフォルト): 本文を「認証成功」に置き換えて書き込む
+/// - `reject`: 空の本文として書き込みを拒否する (トークンは消費しない)
+fn token_only_post_body() -> Result<String, ServiceError> {
+    token_only_post_body_for(env::var("TOKEN_ONLY_POST_BEHAVIOR").ok().as_deref())
+}
+
+/// `token_only_post_body` の本体。`TOKEN_ONLY_POST_BEHAVIOR` の値を引数で受け取ります。
+fn token_only_post_body_for(behavior: Option<&str>) -> Result<String, ServiceError> {
+    match behavior {
+        Some("reject") => Err(ServiceError::BadRequest(
+            TOKEN_ONLY_POST_REJECTED_MESSAGE.to_string(),
+        )),
+        _ => Ok("認証成功".to_string()),
+    }
+}
+
 /// Authenticates a poster using either a device linking token or an existing session cookie.
+/// If neither is present and `ALLOW_ANONYMOUS_POSTING` is enabled, the shared anonymous user is used.
 ///
 /// # Arguments
 /// * `pool` - The database connection pool.
@@ -2584,6 +6772,15 @@ async fn authenticate_poster(
     let (linking_token_opt, cleaned_body) = extract_and_remove_linking_token(body);
 
     if let Some(linking_token) = linking_token_opt {
+        // If the body is empty after removing the token, replace it with a success message
+        // (or reject it, depending on TOKEN_ONLY_POST_BEHAVIOR). This is decided before the
+        // token is consumed so that a rejected post does not use up the token.
+        let final_body = if cleaned_body.is_empty() {
+            token_only_post_body()?
+        } else {
+            cleaned_body
+        };
+
         // Case 1: A linking token was provided. Try to authenticate with it.
         let mut hasher = Sha256::new();
         hasher.update(linking_token.as_bytes());
@@ -2607,14 +6804,19 @@ async fn authenticate_poster(
             // Cookieの有効期限を1000年に設定し、実質的に半永久的なセッションとします。
             let session_expires_at = Utc::now() + chrono::Duration::days(365 * 1000);
 
+            let mut tx = pool.begin().await?;
+            // シングルセッションモードの場合、ログイン時と同様に既存のセッションを無効化する
+            auth::revoke_sessions_for_new_login(&mut tx, user_id, auth::is_single_session_mode())
+                .await?;
             sqlx::query!(
                 "INSERT INTO sessions (user_id, session_token, expires_at) VALUES ($1, $2, $3)",
                 user_id,
                 &session_token,
                 session_expires_at
             )
-            .execute(pool)
+            .execute(&mut *tx)
             .await?;
+            tx.commit().await?;
 
             // --- START: 環境に応じたCookie設定 ---
             let new_session_cookie = Cookie::build("session_token", session_token)
@@ -2636,13 +6838,6 @@ async fn authenticate_poster(
 
             // --- END: 環境に応じたCookie設定 ---
 
-            // If the body is empty after removing the token, replace it with a success message.
-            let final_body = if cleaned_body.is_empty() {
-                "認証成功".to_string()
-            } else {
-                cleaned_body
-            };
-
             Ok((user_id, Some(new_session_cookie), final_body))
         } else {
             Err(ServiceError::BadRequest(
@@ -2652,8 +6847,12 @@ async fn authenticate_poster(
     } else if let Some(authenticated_user) = user {
         // Case 2: No token, but an existing session cookie was found.
         Ok((authenticated_user.user_id, None, body.to_string()))
+    } else if is_anonymous_posting_allowed() {
+        // Case 3: No token and no session, but anonymous posting is enabled.
+        let anonymous_user_id = get_or_create_anonymous_user_id(pool).await?;
+        Ok((anonymous_user_id, None, body.to_string()))
     } else {
-        // Case 3: No token and no session. Unauthorized.
+        // Case 4: No token and no session. Unauthorized.
         Err(ServiceError::Unauthorized)
     }
 }
@@ -2701,3 +6900,870 @@ where
     }
 }
 // --- END: Post From Row Conversion ---
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn poster_identifier_uses_account_id_for_regular_users() {
+        assert_eq!(poster_identifier("abc123", "192.0.2.1", "device"), "abc123");
+    }
+
+    #[test]
+    fn poster_identifier_separates_anonymous_posters_by_ip_and_device() {
+        let a = poster_identifier(ANONYMOUS_USER_ACCOUNT_ID, "192.0.2.1", "device-a");
+        let b = poster_identifier(ANONYMOUS_USER_ACCOUNT_ID, "192.0.2.2", "device-a");
+        let c = poster_identifier(ANONYMOUS_USER_ACCOUNT_ID, "192.0.2.1", "device-b");
+        assert_ne!(a, ANONYMOUS_USER_ACCOUNT_ID);
+        assert_ne!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(
+            a,
+            poster_identifier(ANONYMOUS_USER_ACCOUNT_ID, "192.0.2.1", "device-a")
+        );
+    }
+
+    fn micros(v: i64) -> chrono::DateTime<Utc> {
+        Utc.timestamp_micros(v).single().unwrap()
+    }
+
+    #[test]
+    fn board_list_cursor_round_trips_through_encoding() {
+        let cursor = BoardListCursor {
+            activity_since: micros(1_700_000_000_123_456),
+            snapshot_at: micros(1_700_086_400_654_321),
+            activity_count: 42,
+            last_activity_at: micros(1_700_050_000_000_001),
+            id: 7,
+        };
+        assert_eq!(BoardListCursor::decode(&cursor.encode()), Some(cursor));
+    }
+
+    #[test]
+    fn board_list_cursor_rejects_malformed_input() {
+        assert_eq!(BoardListCursor::decode(""), None);
+        assert_eq!(BoardListCursor::decode("1_2_3_4"), None);
+        assert_eq!(BoardListCursor::decode("1_2_3_4_5_6"), None);
+        assert_eq!(BoardListCursor::decode("1_2_x_4_5"), None);
+    }
+
+    #[test]
+    fn board_list_cursor_preserves_sort_key_ordering() {
+        // 並び順 (活動量 DESC, 最終活動日時 DESC, ID DESC) の隣接する行が、
+        // エンコードとデコードを経てもマイクロ秒単位で同じ順序を保つことを確認する
+        let since = micros(1_700_000_000_000_000);
+        let snapshot = micros(1_700_086_400_000_000);
+        let rows = [
+            (10, micros(1_700_050_000_000_002), 3),
+            (10, micros(1_700_050_000_000_001), 9),
+            (10, micros(1_700_050_000_000_001), 2),
+            (0, micros(-1), 5),
+        ];
+        let keys: Vec<_> = rows
+            .iter()
+            .map(|&(activity_count, last_activity_at, id)| {
+                let decoded = BoardListCursor::decode(
+                    &BoardListCursor {
+                        activity_since: since,
+                        snapshot_at: snapshot,
+                        activity_count,
+                        last_activity_at,
+                        id,
+                    }
+                    .encode(),
+                )
+                .unwrap();
+                assert_eq!(decoded.activity_since, since);
+                assert_eq!(decoded.snapshot_at, snapshot);
+                (decoded.activity_count, decoded.last_activity_at, decoded.id)
+            })
+            .collect();
+        assert!(keys.windows(2).all(|w| w[0] > w[1]));
+    }
+
+    #[test]
+    fn validate_preview_body_reports_only_body_errors() {
+        assert!(validate_preview_body(models::PreviewBodyKind::Post, "本文").is_ok());
+        assert!(validate_preview_body(models::PreviewBodyKind::Comment, "本文").is_ok());
+
+        let too_long_comment = "あ".repeat(models::COMMENT_BODY_MAX_LENGTH as usize + 1);
+        let errors =
+            validate_preview_body(models::PreviewBodyKind::Comment, &too_long_comment).unwrap_err();
+        let field_errors = errors.field_errors();
+        assert_eq!(field_errors.len(), 1);
+        assert!(field_errors.contains_key("body"));
+        assert!(validate_preview_body(models::PreviewBodyKind::Post, &too_long_comment).is_ok());
+
+        // スレッド作成時のタイトルはプレビューに含まれないため、エラーにならない
+        let errors = validate_preview_body(models::PreviewBodyKind::Post, "").unwrap_err();
+        assert_eq!(errors.field_errors().len(), 1);
+        assert!(errors.field_errors().contains_key("body"));
+    }
+
+    #[test]
+    fn select_forwarded_ip_uses_leftmost_entry_without_trusted_hops() {
+        assert_eq!(
+            select_forwarded_ip("198.51.100.1, 203.0.113.5", 0).as_deref(),
+            Some("198.51.100.1")
+        );
+        assert_eq!(select_forwarded_ip(" , ", 0), None);
+    }
+
+    #[test]
+    fn select_forwarded_ip_counts_trusted_hops_from_the_right() {
+        // クライアントが先頭に偽のエントリを付けても、信頼済みプロキシが追記した位置を使う
+        let xff = "1.1.1.1, 198.51.100.1, 203.0.113.5";
+        assert_eq!(select_forwarded_ip(xff, 1).as_deref(), Some("203.0.113.5"));
+        assert_eq!(select_forwarded_ip(xff, 2).as_deref(), Some("198.51.100.1"));
+        assert_eq!(select_forwarded_ip(xff, 3).as_deref(), Some("1.1.1.1"));
+    }
+
+    #[test]
+    fn select_forwarded_ip_rejects_chains_shorter_than_trusted_hops() {
+        assert_eq!(select_forwarded_ip("1.1.1.1", 2), None);
+        assert_eq!(select_forwarded_ip("", 1), None);
+    }
+
+    fn request_from_peer(headers: &[(&str, &str)]) -> HttpRequest {
+        let mut req =
+            actix_web::test::TestRequest::default().peer_addr("192.0.2.10:40000".parse().unwrap());
+        for &(name, value) in headers {
+            req = req.insert_header((name, value));
+        }
+        req.to_http_request()
+    }
+
+    #[test]
+    fn resolve_client_ip_ignores_headers_when_untrusted() {
+        let req = request_from_peer(&[("X-Real-IP", "1.1.1.1"), ("X-Forwarded-For", "1.1.1.1")]);
+        assert_eq!(resolve_client_ip(&req, false, 0), "192.0.2.10");
+        assert_eq!(resolve_client_ip(&req, false, 1), "192.0.2.10");
+    }
+
+    #[test]
+    fn resolve_client_ip_ignores_real_ip_header_with_trusted_hops() {
+        let req = request_from_peer(&[
+            ("X-Real-IP", "1.1.1.1"),
+            ("X-Forwarded-For", "1.1.1.1, 198.51.100.1"),
+        ]);
+        assert_eq!(resolve_client_ip(&req, true, 1), "198.51.100.1");
+        // ホップ数を設定していない場合は従来どおり X-Real-IP を優先する
+        assert_eq!(resolve_client_ip(&req, true, 0), "1.1.1.1");
+    }
+
+    #[test]
+    fn resolve_client_ip_falls_back_to_peer_on_short_chain() {
+        let req = request_from_peer(&[("X-Forwarded-For", "1.1.1.1")]);
+        assert_eq!(resolve_client_ip(&req, true, 2), "192.0.2.10");
+        let req = request_from_peer(&[("X-Real-IP", "1.1.1.1")]);
+        assert_eq!(resolve_client_ip(&req, true, 1), "192.0.2.10");
+    }
+
+    #[test]
+    fn verbose_diag_flag_is_enabled_only_for_true() {
+        assert!(parse_verbose_diag_flag(Some("true")));
+        assert!(parse_verbose_diag_flag(Some(" TRUE ")));
+        assert!(!parse_verbose_diag_flag(None));
+        assert!(!parse_verbose_diag_flag(Some("")));
+        assert!(!parse_verbose_diag_flag(Some("1")));
+        assert!(!parse_verbose_diag_flag(Some("false")));
+    }
+
+    #[test]
+    fn normalize_body_whitespace_collapses_excessive_blank_lines() {
+        assert_eq!(normalize_body_whitespace("a\n\n\n\nb"), "a\n\nb");
+        assert_eq!(normalize_body_whitespace("a\r\n \r\n\t\r\nb"), "a\n\nb");
+        // 空行1つまでは保持する
+        assert_eq!(normalize_body_whitespace("a\n\nb"), "a\n\nb");
+    }
+
+    #[test]
+    fn normalize_body_whitespace_trims_leading_blank_lines_and_trailing_space() {
+        assert_eq!(normalize_body_whitespace("\n  \n本文\n\n  "), "本文");
+        // 1行目の字下げ (AAなど) と行内の空白は保持する
+        assert_eq!(
+            normalize_body_whitespace("\n   (´・ω・`)  x\n"),
+            "   (´・ω・`)  x"
+        );
+        assert_eq!(normalize_body_whitespace(" \n \n"), "");
+    }
+
+    #[test]
+    fn extract_quoted_response_numbers_keeps_earlier_responses_only() {
+        let body = "&gt;&gt;3\n&gt;&gt;1 &gt;&gt;3 &gt;&gt;5 &gt;&gt;0 &gt;&gt;9";
+        assert_eq!(extract_quoted_response_numbers(body, 5), vec![1, 3]);
+    }
+
+    #[test]
+    fn extract_quoted_response_numbers_ignores_unescaped_and_oversized_anchors() {
+        // サニタイズ前の `>>N` は対象外
+        assert!(extract_quoted_response_numbers(">>1", 5).is_empty());
+        assert!(extract_quoted_response_numbers("&gt;&gt;99999999999999999999", 5).is_empty());
+        assert!(extract_quoted_response_numbers("本文のみ", 5).is_empty());
+    }
+
+    #[test]
+    fn render_board_description_links_urls() {
+        assert_eq!(
+            render_board_description("詳細は https://example.com/a?x=1&amp;y=2 まで"),
+            "詳細は <a href=\"https://example.com/a?x=1&amp;y=2\" rel=\"nofollow noopener noreferrer\" target=\"_blank\">https://example.com/a?x=1&amp;y=2</a> まで"
+        );
+    }
+
+    #[test]
+    fn render_board_description_strips_tags_and_ignores_other_schemes() {
+        assert_eq!(render_board_description("<b>太字</b>の説明"), "太字の説明");
+        assert_eq!(
+            render_board_description("<a href=\"javascript:alert(1)\">リンク</a>"),
+            "リンク"
+        );
+        assert_eq!(
+            render_board_description("javascript:alert(1) ftp://example.com"),
+            "javascript:alert(1) ftp://example.com"
+        );
+    }
+
+    #[test]
+    fn submitted_author_name_rejects_reserved_names_and_markers() {
+        for name in ["管理人", " ADMIN ", "名前◆abc", "☕名無し"] {
+            assert!(check_submitted_author_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn submitted_author_name_rejects_entity_encoded_impersonation() {
+        for name in [
+            "&#9670;",
+            "&#x25C6;abc",
+            "&#9749;",
+            "&#31649;理人",
+            "&#97;dmin",
+        ] {
+            assert!(check_submitted_author_name(name).is_err(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn submitted_author_name_accepts_ordinary_names() {
+        for name in ["名無しさん", "管理人見習い", "a&b", "&lt;tag&gt;"] {
+            assert!(check_submitted_author_name(name).is_ok(), "{}", name);
+        }
+    }
+
+    // 名前の入力箇所ごとのテスト用に、DBに接続しないプールと認証済みユーザーを注入してハンドラーを呼び出す。
+    // 予約名のチェックはDBにアクセスする前に行われるため、DBなしで結果を確認できる。
+    async fn call_name_input_site(
+        register: impl FnOnce(&mut web::ServiceConfig),
+        req: actix_web::test::TestRequest,
+    ) -> (actix_web::http::StatusCode, serde_json::Value) {
+        use actix_web::dev::Service;
+
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .connect_lazy("postgres://localhost/unused")
+            .unwrap();
+        let app = actix_web::test::init_service(
+            actix_web::App::new()
+                .app_data(web::Data::new(pool))
+                .app_data(web::Data::new(reqwest::Client::new()))
+                .wrap_fn(|req, srv| {
+                    req.extensions_mut().insert(middleware::AuthenticatedUser {
+                        user_id: 1,
+                        role: middleware::Role::User,
+                        level: 1,
+                    });
+                    srv.call(req)
+                })
+                .configure(register),
+        )
+        .await;
+        let res = actix_web::test::call_service(&app, req.to_request()).await;
+        let status = res.status();
+        let body = actix_web::test::read_body(res).await;
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    const ENCODED_RESERVED_NAMES: [(&str, u16); 3] =
+        [("&#9670;", 400), ("&#9749;", 403), ("&#31649;理人", 400)];
+
+    #[actix_web::test]
+    async fn reserved_names_are_rejected_when_creating_boards() {
+        for (name, expected) in ENCODED_RESERVED_NAMES {
+            let (status, _) = call_name_input_site(
+                |cfg| {
+                    cfg.service(web::scope("/boards").service(create_board));
+                },
+                actix_web::test::TestRequest::post().uri("/boards").set_json(
+                    serde_json::json!({ "name": "板", "description": "説明", "default_name": name }),
+                ),
+            )
+            .await;
+            assert_eq!(status.as_u16(), expected, "{}", name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn reserved_names_are_rejected_when_updating_board_details() {
+        for (name, expected) in ENCODED_RESERVED_NAMES {
+            let (status, _) = call_name_input_site(
+                |cfg| {
+                    cfg.service(web::scope("/boards").service(update_board_details));
+                },
+                actix_web::test::TestRequest::patch()
+                    .uri("/boards/1/details")
+                    .set_json(serde_json::json!({ "default_name": name })),
+            )
+            .await;
+            assert_eq!(status.as_u16(), expected, "{}", name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn reserved_names_are_rejected_when_updating_board_settings() {
+        for (name, expected) in ENCODED_RESERVED_NAMES {
+            let (status, _) = call_name_input_site(
+                |cfg| {
+                    cfg.service(web::scope("/boards").service(update_board_settings));
+                },
+                actix_web::test::TestRequest::patch()
+                    .uri("/boards/1/settings")
+                    .set_json(serde_json::json!({ "default_name": name })),
+            )
+            .await;
+            assert_eq!(status.as_u16(), expected, "{}", name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn reserved_names_are_rejected_when_creating_posts() {
+        for (name, expected) in ENCODED_RESERVED_NAMES {
+            let (status, _) = call_name_input_site(
+                |cfg| {
+                    cfg.service(web::scope("/posts").service(create_post));
+                },
+                actix_web::test::TestRequest::post()
+                    .uri("/posts")
+                    .set_json(serde_json::json!({
+                        "title": "タイトル", "body": "本文", "author_name": name, "board_id": 1
+                    })),
+            )
+            .await;
+            assert_eq!(status.as_u16(), expected, "{}", name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn reserved_names_are_rejected_when_creating_comments() {
+        for (name, expected) in ENCODED_RESERVED_NAMES {
+            let (status, _) = call_name_input_site(
+                |cfg| {
+                    cfg.service(create_comment);
+                },
+                actix_web::test::TestRequest::post()
+                    .uri("/comments")
+                    .set_json(
+                        serde_json::json!({ "body": "本文", "author_name": name, "post_id": 1 }),
+                    ),
+            )
+            .await;
+            assert_eq!(status.as_u16(), expected, "{}", name);
+        }
+    }
+
+    #[actix_web::test]
+    async fn reserved_names_are_reported_by_submission_validation() {
+        for (name, expected) in ENCODED_RESERVED_NAMES {
+            let (status, body) = call_name_input_site(
+                |cfg| {
+                    cfg.service(web::scope("/posts").service(validate_post));
+                },
+                actix_web::test::TestRequest::post()
+                    .uri("/posts/validate")
+                    .set_json(serde_json::json!({
+                        "title": "タイトル", "body": "本文", "author_name": name, "board_id": 1
+                    })),
+            )
+            .await;
+            assert!(status.is_success());
+            assert_eq!(body["valid"], false, "{}", name);
+            assert_eq!(body["errors"][0]["status"], expected, "{}", name);
+
+            let (status, body) = call_name_input_site(
+                |cfg| {
+                    cfg.service(validate_comment);
+                },
+                actix_web::test::TestRequest::post()
+                    .uri("/comments/validate")
+                    .set_json(
+                        serde_json::json!({ "body": "本文", "author_name": name, "post_id": 1 }),
+                    ),
+            )
+            .await;
+            assert!(status.is_success());
+            assert_eq!(body["valid"], false, "{}", name);
+            assert_eq!(body["errors"][0]["status"], expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn body_min_length_ignores_surrounding_whitespace() {
+        assert!(check_body_min_length_with("あ", 1).is_ok());
+        assert!(check_body_min_length_with(" \n\t", 1).is_err());
+        assert!(check_body_min_length_with("  あいう  ", 3).is_ok());
+        assert!(check_body_min_length_with("  あい  ", 3).is_err());
+    }
+
+    #[test]
+    fn body_is_checked_after_removing_linking_token() {
+        let token = "a".repeat(32);
+        assert!(sanitize_body_without_linking_token("本文").is_ok());
+        assert!(sanitize_body_without_linking_token(" \n ").is_err());
+        assert_eq!(
+            sanitize_body_without_linking_token(&format!("!token({}) 本文", token)).unwrap(),
+            "本文"
+        );
+        // トークンを取り除いた結果、生のトークンのような文字列だけが残る本文は拒否する
+        assert!(sanitize_body_without_linking_token(&format!(
+            "!token({}) {}",
+            token,
+            "B".repeat(32)
+        ))
+        .is_err());
+    }
+
+    #[test]
+    fn quote_count_limit_counts_escaped_anchors() {
+        let body = sanitize_submitted_body(">>1 >>2 >>3").unwrap();
+        assert!(check_quote_count_with(&body, None).is_ok());
+        assert!(check_quote_count_with(&body, Some(3)).is_ok());
+        assert!(check_quote_count_with(&body, Some(2)).is_err());
+    }
+
+    #[test]
+    fn quote_count_includes_reply_anchor() {
+        let body = sanitize_submitted_body(">>1 >>2").unwrap();
+        assert!(check_quote_count_with(&with_reply_anchor(&body, None), Some(2)).is_ok());
+        assert!(check_quote_count_with(&with_reply_anchor(&body, Some(5)), Some(2)).is_err());
+        assert_eq!(with_reply_anchor("本文", Some(5)), "&gt;&gt;5\n本文");
+    }
+
+    #[test]
+    fn quote_count_ignores_linking_token() {
+        let token = "a".repeat(32);
+        let body = sanitize_body_without_linking_token(&format!("!token({}) >>1", token)).unwrap();
+        assert!(check_quote_count_with(&body, Some(1)).is_ok());
+    }
+
+    #[test]
+    fn min_account_age_blocks_new_accounts() {
+        let now = Utc::now();
+        let created_at = now - Duration::seconds(30);
+        assert!(check_min_account_age_with(created_at, 0, now).is_ok());
+        assert!(check_min_account_age_with(created_at, 30, now).is_ok());
+        match check_min_account_age_with(created_at, 60, now) {
+            Err(ServiceError::Forbidden(message)) => assert!(message.contains("あと30秒")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    #[tokio::test]
+    #[ignore]
+    async fn poster_account_age_is_looked_up_without_consuming_token() {
+        use sqlx::{Connection, PgConnection};
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let created_at = Utc::now() - Duration::days(3);
+        let user_id: i32 = sqlx::query_scalar(
+            "INSERT INTO users (email, created_at) VALUES ($1, $2) RETURNING id",
+        )
+        .bind(format!(
+            "account-age-test-{}@example.invalid",
+            Utc::now().timestamp_micros()
+        ))
+        .bind(created_at)
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        let token = "t".repeat(32);
+        let token_hash = hex::encode(Sha256::digest(token.as_bytes()));
+        sqlx::query(
+            "INSERT INTO device_linking_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, NOW() + INTERVAL '1 hour')",
+        )
+        .bind(user_id)
+        .bind(&token_hash)
+        .execute(&mut *tx)
+        .await
+        .unwrap();
+
+        let body = format!("!token({}) 本文", token);
+        let found = poster_account_created_at(&mut tx, None, &body)
+            .await
+            .unwrap();
+        assert_eq!(found.map(|t| t.timestamp()), Some(created_at.timestamp()));
+        let used_at: Option<chrono::DateTime<Utc>> =
+            sqlx::query_scalar("SELECT used_at FROM device_linking_tokens WHERE token_hash = $1")
+                .bind(&token_hash)
+                .fetch_one(&mut *tx)
+                .await
+                .unwrap();
+        assert!(used_at.is_none());
+
+        let found = poster_account_created_at(&mut tx, Some(user_id), "本文")
+            .await
+            .unwrap();
+        assert_eq!(found.map(|t| t.timestamp()), Some(created_at.timestamp()));
+        // 匿名投稿の共有ユーザーは対象外
+        assert!(poster_account_created_at(&mut tx, None, "本文")
+            .await
+            .unwrap()
+            .is_none());
+
+        tx.rollback().await.unwrap();
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    #[tokio::test]
+    #[ignore]
+    async fn shared_anonymous_account_is_excluded_from_account_age() {
+        use sqlx::{Connection, PgConnection};
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        // 共有ユーザーが十分に古くなっていても、その作成日時は使わない
+        let anonymous_user_id: i32 = sqlx::query_scalar(
+            r#"
+            INSERT INTO users (email, created_at) VALUES ($1, NOW() - INTERVAL '365 days')
+            ON CONFLICT (email) DO UPDATE SET created_at = EXCLUDED.created_at
+            RETURNING id
+            "#,
+        )
+        .bind(ANONYMOUS_USER_ACCOUNT_ID)
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        let found = poster_account_created_at(&mut tx, Some(anonymous_user_id), "本文")
+            .await
+            .unwrap();
+        assert!(found.is_none());
+
+        tx.rollback().await.unwrap();
+    }
+
+    #[test]
+    fn escape_like_pattern_escapes_wildcards_and_backslash() {
+        assert_eq!(escape_like_pattern("100%"), "100\\%");
+        assert_eq!(escape_like_pattern("a_b"), "a\\_b");
+        assert_eq!(escape_like_pattern("C:\\path"), "C:\\\\path");
+        assert_eq!(escape_like_pattern("普通の検索"), "普通の検索");
+    }
+
+    /// HTTPリクエストを受け取って200を返し、受け取ったリクエストボディを返す簡易的なWebhookサーバー。
+    /// 指定した件数のリクエストを受け取ると終了します。
+    fn spawn_mock_webhook(
+        expected_requests: usize,
+    ) -> (String, std::thread::JoinHandle<Vec<serde_json::Value>>) {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/archive", listener.local_addr().unwrap());
+        let handle = std::thread::spawn(move || {
+            let mut payloads = Vec::new();
+            for _ in 0..expected_requests {
+                let (mut socket, _) = listener.accept().unwrap();
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                // ヘッダーの終端と Content-Length 分のボディを読み込む
+                let body = loop {
+                    let n = socket.read(&mut chunk).unwrap();
+                    buf.extend_from_slice(&chunk[..n]);
+                    let text = String::from_utf8_lossy(&buf).to_string();
+                    if let Some(header_end) = text.find("\r\n\r\n") {
+                        let content_length = text[..header_end]
+                            .lines()
+                            .find_map(|l| {
+                                l.to_ascii_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+                        if buf.len() >= header_end + 4 + content_length {
+                            break buf[header_end + 4..header_end + 4 + content_length].to_vec();
+                        }
+                    }
+                    assert!(
+                        n > 0,
+                        "connection closed before the request body was received"
+                    );
+                };
+                payloads.push(serde_json::from_slice(&body).unwrap());
+                socket
+                    .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 0\r\nconnection: close\r\n\r\n")
+                    .unwrap();
+            }
+            payloads
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn archive_notifications_post_payload_to_webhook() {
+        let (url, server) = spawn_mock_webhook(2);
+        let notifications = vec![
+            ArchiveNotification {
+                post_id: 10,
+                board_id: Some(2),
+                title: "スレッド".to_string(),
+                reason: "comment_limit".to_string(),
+            },
+            ArchiveNotification {
+                post_id: 11,
+                board_id: None,
+                title: "別のスレッド".to_string(),
+                reason: "admin_archive_all".to_string(),
+            },
+        ];
+        send_archive_notifications(&reqwest::Client::new(), &url, &notifications).await;
+
+        let payloads = server.join().unwrap();
+        assert_eq!(
+            payloads,
+            vec![
+                serde_json::json!({ "post_id": 10, "board_id": 2, "title": "スレッド", "reason": "comment_limit" }),
+                serde_json::json!({ "post_id": 11, "board_id": null, "title": "別のスレッド", "reason": "admin_archive_all" }),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn archive_notification_failure_is_not_fatal() {
+        // 接続できないURLでもパニックやエラーにならず、ログに記録するだけで終わる
+        let notifications = vec![ArchiveNotification {
+            post_id: 1,
+            board_id: Some(1),
+            title: "t".to_string(),
+            reason: "comment_limit".to_string(),
+        }];
+        send_archive_notifications(
+            &reqwest::Client::new(),
+            "http://127.0.0.1:9/archive",
+            &notifications,
+        )
+        .await;
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    #[tokio::test]
+    #[ignore]
+    async fn merged_comments_are_appended_after_target_comments() {
+        use sqlx::{Connection, PgConnection};
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let user_id: i32 = sqlx::query_scalar("INSERT INTO users (email) VALUES ($1) RETURNING id")
+            .bind(format!(
+                "merge-test-{}@example.invalid",
+                Utc::now().timestamp_micros()
+            ))
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        let mut post_ids = Vec::new();
+        for title in ["統合先", "統合元"] {
+            let post_id: i32 = sqlx::query_scalar(
+                "INSERT INTO posts (title, body, user_id) VALUES ($1, '本文', $2) RETURNING id",
+            )
+            .bind(title)
+            .bind(user_id)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+            post_ids.push(post_id);
+        }
+        let (target_post_id, source_post_id) = (post_ids[0], post_ids[1]);
+        // 統合元のレスの方が古いが、統合先の既存のレス番号は変わらない
+        for (post_id, response_number, minutes_ago) in [
+            (target_post_id, 2, 10),
+            (target_post_id, 3, 5),
+            (source_post_id, 2, 30),
+            (source_post_id, 3, 20),
+        ] {
+            sqlx::query(
+                "INSERT INTO comments (body, post_id, user_id, response_number, created_at) VALUES ($1, $2, $3, $4, NOW() - make_interval(mins => $5))",
+            )
+            .bind(format!("{}-{}", post_id, response_number))
+            .bind(post_id)
+            .bind(user_id)
+            .bind(response_number)
+            .bind(minutes_ago)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+        }
+
+        let moved = append_moved_comments(&mut tx, target_post_id, source_post_id)
+            .await
+            .unwrap();
+        assert_eq!(moved, 2);
+
+        let rows: Vec<(String, i32)> = sqlx::query_as(
+            "SELECT body, response_number FROM comments WHERE post_id = $1 ORDER BY response_number",
+        )
+        .bind(target_post_id)
+        .fetch_all(&mut *tx)
+        .await
+        .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (format!("{}-2", target_post_id), 2),
+                (format!("{}-3", target_post_id), 3),
+                (format!("{}-2", source_post_id), 4),
+                (format!("{}-3", source_post_id), 5),
+            ]
+        );
+
+        tx.rollback().await.unwrap();
+    }
+
+    #[test]
+    fn comment_edits_are_visible_to_author_and_moderators() {
+        let user = middleware::AuthenticatedUser {
+            user_id: 7,
+            role: middleware::Role::User,
+            level: 1,
+        };
+        let no_permissions = models::ThreadPermissions::default();
+        let moderator = models::ThreadPermissions {
+            can_moderate: true,
+            ..Default::default()
+        };
+        assert!(can_view_comment_edits(&user, Some(7), &no_permissions));
+        assert!(!can_view_comment_edits(&user, Some(8), &no_permissions));
+        assert!(!can_view_comment_edits(&user, None, &no_permissions));
+        assert!(can_view_comment_edits(&user, Some(8), &moderator));
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    #[tokio::test]
+    #[ignore]
+    async fn editing_a_comment_records_previous_bodies_in_order() {
+        use sqlx::{Connection, PgConnection};
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let user_id: i32 = sqlx::query_scalar("INSERT INTO users (email) VALUES ($1) RETURNING id")
+            .bind(format!(
+                "comment-edit-test-{}@example.invalid",
+                Utc::now().timestamp_micros()
+            ))
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        let post_id: i32 = sqlx::query_scalar(
+            "INSERT INTO posts (title, body, user_id) VALUES ('スレッド', '本文', $1) RETURNING id",
+        )
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+        let comment_id: i32 = sqlx::query_scalar(
+            "INSERT INTO comments (body, post_id, user_id, response_number) VALUES ('最初の本文', $1, $2, 2) RETURNING id",
+        )
+        .bind(post_id)
+        .bind(user_id)
+        .fetch_one(&mut *tx)
+        .await
+        .unwrap();
+
+        record_comment_edit(&mut tx, comment_id, "2回目の本文", user_id)
+            .await
+            .unwrap();
+        record_comment_edit(&mut tx, comment_id, "3回目の本文", user_id)
+            .await
+            .unwrap();
+
+        let edits = fetch_comment_edits(&mut tx, comment_id).await.unwrap();
+        let bodies: Vec<&str> = edits.iter().map(|e| e.body.as_str()).collect();
+        assert_eq!(bodies, vec!["最初の本文", "2回目の本文"]);
+        assert!(edits.iter().all(|e| e.edited_by == Some(user_id)));
+        let current_body: String = sqlx::query_scalar("SELECT body FROM comments WHERE id = $1")
+            .bind(comment_id)
+            .fetch_one(&mut *tx)
+            .await
+            .unwrap();
+        assert_eq!(current_body, "3回目の本文");
+
+        tx.rollback().await.unwrap();
+    }
+
+    #[test]
+    fn token_only_post_body_defaults_to_placeholder() {
+        assert_eq!(token_only_post_body_for(None).unwrap(), "認証成功");
+        assert_eq!(
+            token_only_post_body_for(Some("placeholder")).unwrap(),
+            "認証成功"
+        );
+        assert_eq!(
+            token_only_post_body_for(Some("unknown")).unwrap(),
+            "認証成功"
+        );
+    }
+
+    #[test]
+    fn token_only_post_body_can_reject() {
+        match token_only_post_body_for(Some("reject")) {
+            Err(ServiceError::BadRequest(message)) => {
+                assert_eq!(message, TOKEN_ONLY_POST_REJECTED_MESSAGE)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn local_midnight_to_utc_converts_jst_midnight() {
+        let jst = chrono::FixedOffset::east_opt(9 * 3600).unwrap();
+        assert_eq!(
+            local_midnight_to_utc(jst, 2024, 1, 1),
+            Some(Utc.with_ymd_and_hms(2023, 12, 31, 15, 0, 0).unwrap())
+        );
+        let utc = chrono::FixedOffset::east_opt(0).unwrap();
+        assert_eq!(
+            local_midnight_to_utc(utc, 2024, 2, 29),
+            Some(Utc.with_ymd_and_hms(2024, 2, 29, 0, 0, 0).unwrap())
+        );
+        let west = chrono::FixedOffset::west_opt(5 * 3600).unwrap();
+        assert_eq!(
+            local_midnight_to_utc(west, 2024, 3, 1),
+            Some(Utc.with_ymd_and_hms(2024, 3, 1, 5, 0, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn local_midnight_to_utc_rejects_invalid_dates() {
+        let jst = chrono::FixedOffset::east_opt(9 * 3600).unwrap();
+        assert_eq!(local_midnight_to_utc(jst, 2023, 2, 29), None);
+        assert_eq!(local_midnight_to_utc(jst, 2024, 13, 1), None);
+    }
+
+    #[test]
+    fn default_post_name_falls_back_when_unset_or_blank() {
+        assert_eq!(default_post_name_from(None), FALLBACK_DEFAULT_POST_NAME);
+        assert_eq!(
+            default_post_name_from(Some("   ".to_string())),
+            FALLBACK_DEFAULT_POST_NAME
+        );
+        assert_eq!(
+            default_post_name_from(Some(" 風吹けば名無し ".to_string())),
+            "風吹けば名無し"
+        );
+    }
+}
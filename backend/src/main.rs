@@ -1,8 +1,12 @@
 use actix_cors::Cors;
-use actix_web::{error, http, middleware::Logger, web, App, HttpResponse, HttpServer};
+use actix_web::{
+    error, http,
+    middleware::{Compress, Condition, Logger},
+    web, App, HttpResponse, HttpServer,
+};
 use log;
 use niwatori::archive_posts::archive_posts_batch;
-use niwatori::{configure_app, middleware::Auth};
+use niwatori::{archive_scheduled_posts, configure_app, middleware::Auth};
 use serde_json::json;
 use sqlx::postgres::PgPoolOptions;
 use std::{env, path::Path}; // Path をインポート
@@ -49,6 +53,8 @@ async fn main() -> std::io::Result<()> {
 
     // アーカイブバッチジョブをバックグラウンドで実行
     let pool_for_scheduler = pool.clone();
+    // アーカイブ通知 (Webhook) の送信に使うHTTPクライアント
+    let http_client_for_scheduler = reqwest::Client::new();
     tokio::spawn(async move {
         let interval_minutes_str =
             env::var("ARCHIVE_INTERVAL_MINUTES").unwrap_or_else(|_| "60".to_string());
@@ -72,6 +78,12 @@ async fn main() -> std::io::Result<()> {
             if let Err(e) = archive_posts_batch(&pool_for_scheduler).await {
                 log::error!("Failed to run archive batch job: {}", e);
             }
+            // レス数上限による遅延アーカイブのうち、再起動などで取りこぼしたものを確定させる
+            match archive_scheduled_posts(&pool_for_scheduler, &http_client_for_scheduler).await {
+                Ok(0) => {}
+                Ok(count) => log::info!("Archived {} posts whose scheduled archival time had passed.", count),
+                Err(e) => log::error!("Failed to archive scheduled posts: {}", e),
+            }
         }
     });
 
@@ -82,6 +94,14 @@ async fn main() -> std::io::Result<()> {
     // これにより、コンパイラの型推論が正しく機能し、`trusted_proxies`メソッドが見つかるようになります。
     let pool_for_app = pool.clone();
 
+    // レスポンス圧縮 (gzip/br など、クライアントの Accept-Encoding に応じて選択)。
+    // .dat や SUBJECT.TXT などの Shift_JIS のバイト列もそのまま圧縮されるだけなので内容は変わらない。
+    // 環境変数 `ENABLE_RESPONSE_COMPRESSION=false` で無効化できる (デフォルト: 有効)。
+    let enable_compression = env::var("ENABLE_RESPONSE_COMPRESSION")
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    log::info!("Response compression enabled: {}", enable_compression);
+
     HttpServer::new(move || { // `move`クロージャを避けるため、クロージャ内で使用する変数を事前にクローンします。
         // JSONペイロードのパースエラー時に、構造化されたJSONエラーレスポンスを返すための設定
         let json_config = web::JsonConfig::default().error_handler(|err, _req| {
@@ -120,6 +140,7 @@ async fn main() -> std::io::Result<()> {
             .app_data(json_config) // カスタムJSONエラーハンドラを登録
             .app_data(web::Data::new(pool_for_app.clone()))
             .app_data(web::Data::new(http_client.clone())) // HTTPクライアントをアプリケーションデータとして登録
+            .wrap(Condition::new(enable_compression, Compress::default())) // レスポンス圧縮
             .wrap(Logger::default()) // リクエストロガーを最初に追加
             .wrap(cors)
             .wrap(Auth) // 認証ミドルウェアを登録
@@ -98,7 +98,7 @@ where
                     SELECT s.user_id, u.role as "role: Role", u.level
                     FROM sessions s
                     JOIN users u ON s.user_id = u.id
-                    WHERE s.session_token = $1 AND s.expires_at > NOW()
+                    WHERE s.session_token = $1 AND s.expires_at > NOW() AND u.deleted_at IS NULL
                     "#,
                     session_token
                 )
@@ -8,6 +8,23 @@ use sqlx::FromRow;
 use std::collections::HashMap;
 use validator::{Validate, ValidationError};
 
+// --- 投稿関連の文字数制限 ---
+// バリデーションと `/api/config` の両方で参照し、クライアントに公開する値と実際の制限が食い違わないようにする
+pub const POST_TITLE_MAX_LENGTH: u64 = 100;
+pub const POST_BODY_MAX_LENGTH: u64 = 750;
+pub const COMMENT_BODY_MAX_LENGTH: u64 = 300;
+pub const AUTHOR_NAME_MAX_LENGTH: u64 = 10;
+const DEFAULT_BOARD_DESCRIPTION_MAX_LENGTH: u64 = 100;
+
+/// 板の説明欄の最大文字数。環境変数 `BOARD_DESCRIPTION_MAX_LENGTH` で変更可能 (デフォルト100文字)。
+pub fn board_description_max_length() -> u64 {
+    std::env::var("BOARD_DESCRIPTION_MAX_LENGTH")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_BOARD_DESCRIPTION_MAX_LENGTH)
+}
+
 #[derive(Debug, FromRow, Serialize, Clone)]
 pub struct Post {
     pub id: i32,
@@ -58,6 +75,54 @@ fn validate_no_suspicious_sequences(text: &str) -> Result<(), ValidationError> {
     Ok(())
 }
 
+// タイトル専用のカスタムバリデーション関数:
+// - "!token(...)" 形式の文字列は常に禁止する
+// - URLらしき部分 (http:// または https:// で始まる部分) は、連続した英数字のチェックの対象外とする
+// - 環境変数 `TITLE_LONG_SEQUENCE_CHECK=false` で15文字以上の連続した英数字のチェックを緩和できる。
+//   緩和すると英語のフレーズなどを含む長いタイトルが使えるようになる一方、
+//   タイトルを使った意味のない文字列の連投を防ぎにくくなる。
+//   緩和時も、連携トークンの生貼り付けを防ぐため32文字以上の連続したASCII英数字は禁止する。
+fn validate_title_sequences(text: &str) -> Result<(), ValidationError> {
+    let strict = std::env::var("TITLE_LONG_SEQUENCE_CHECK")
+        .map(|v| v != "false")
+        .unwrap_or(true);
+    validate_title_sequences_with(text, strict)
+}
+
+/// `validate_title_sequences` の本体。15文字以上の連続した英数字をチェックするかどうかを引数で受け取ります。
+fn validate_title_sequences_with(text: &str, strict: bool) -> Result<(), ValidationError> {
+    static RE_URL: Lazy<Regex> = Lazy::new(|| Regex::new(r"https?://\S+").unwrap());
+    let without_urls = RE_URL.replace_all(text, " ");
+
+    if strict {
+        validate_no_suspicious_sequences(&without_urls)?;
+    } else {
+        static RE_TOKEN_LIKE: Lazy<Regex> = Lazy::new(|| Regex::new(r"[a-zA-Z0-9]{32,}").unwrap());
+        if RE_TOKEN_LIKE.is_match(&without_urls) {
+            let mut error = ValidationError::new("no_long_alphanumeric_sequences");
+            error.message = Some("32文字以上の連続した英数字は使用できません。".into());
+            return Err(error);
+        }
+    }
+
+    // URL内も含め、"!token(...)" 形式は常に禁止する
+    validate_body_sequences(text)
+}
+
+// 板の説明欄専用のカスタムバリデーション関数:
+// - 文字数は1文字以上、`board_description_max_length` 以下
+// - URLらしき部分を除いて、タイトルと同じ連続した英数字のチェックを行う (説明欄のリンク表示に対応するため)
+fn validate_board_description(text: &str) -> Result<(), ValidationError> {
+    let max_length = board_description_max_length();
+    let length = text.chars().count() as u64;
+    if length < 1 || length > max_length {
+        let mut error = ValidationError::new("length");
+        error.message = Some(format!("文字数エラー!説明欄は1~{}字まで", max_length).into());
+        return Err(error);
+    }
+    validate_title_sequences(text)
+}
+
 // 本文（body）専用のカスタムバリデーション関数:
 // - 15文字以上の連続した英数字のチェックを *行わない*
 // - "!token(...)" 形式の文字列のみを禁止する
@@ -76,22 +141,24 @@ fn validate_body_sequences(text: &str) -> Result<(), ValidationError> {
 #[derive(Debug, Deserialize, Validate)]
 pub struct CreatePostRequest {
     #[validate(
-        length(min = 1, max = 100, message = "文字数エラー!タイトルは1~100字まで"),
-        custom(function = "validate_no_suspicious_sequences")
+        length(min = 1, max = POST_TITLE_MAX_LENGTH, message = "文字数エラー!タイトルは1~100字まで"),
+        custom(function = "validate_title_sequences")
     )]
     pub title: String,
     #[validate(
-        length(min = 1, max = 750, message = "文字数エラー!本文は1~750字まで"),
+        length(min = 1, max = POST_BODY_MAX_LENGTH, message = "文字数エラー!本文は1~750字まで"),
     )]
     pub body: String,
     #[validate(
-        length(max = 10, message = "文字数エラー!名前は10字まで"),
+        length(max = AUTHOR_NAME_MAX_LENGTH, message = "文字数エラー!名前は10字まで"),
         custom(function = "validate_no_suspicious_sequences")
     )]
     pub author_name: Option<String>,
     pub board_id: i32,
     // ブラウザからの投稿時に付与されるフィンガープリント
     pub fingerprint: Option<String>,
+    // Captchaが必須の板に書き込む場合のTurnstileトークン
+    pub captcha_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
@@ -117,6 +184,33 @@ pub struct Board {
     pub moderation_type: BoardModerationType,
     pub last_activity_at: DateTime<Utc>,
     pub auto_archive_enabled: bool,
+    // NSFW (年齢制限あり) の板かどうか
+    pub is_nsfw: bool,
+    // 書き込み停止 (凍結) 中かどうか。閲覧は可能
+    pub posting_frozen: bool,
+    // メンバー限定の板かどうか。有効な場合は参加したユーザーのみ書き込み可能 (管理者・作成者を除く)
+    pub members_only: bool,
+    // 板に参加するために必要なユーザーレベル
+    pub member_min_level: i32,
+    // 書き込み時にCaptcha (Turnstile) 認証を必須にするかどうか (管理者を除く)
+    pub require_captcha: bool,
+    // 有効な場合、表示IDを板ごとに異なるものにする (日替わりIDに板IDを含める)
+    pub per_board_ids: bool,
+    // スレッド一覧でソート指定がない場合に使用するソート順
+    pub default_sort: String,
+    // 有効な場合、現行スレッドと同じタイトルのスレッドを作成できない
+    pub unique_titles: bool,
+}
+
+/// 板一覧 (カタログ表示) 用のレスポンスモデル
+#[derive(Serialize, Debug)]
+pub struct BoardListItem {
+    #[serde(flatten)]
+    pub board: Board,
+    // 現行スレッド数 (削除済み・過去ログ化済みを除く)
+    pub thread_count: i64,
+    // 現行スレッドに付いたレスの総数
+    pub total_comment_count: i64,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -126,10 +220,7 @@ pub struct CreateBoardRequest {
         custom(function = "validate_no_suspicious_sequences")
     )]
     pub name: String,
-    #[validate(
-        length(min = 1, max = 100, message = "文字数エラー!説明欄は1~100字まで"),
-        custom(function = "validate_no_suspicious_sequences")
-    )]
+    #[validate(custom(function = "validate_board_description"))]
     pub description: String,
     #[validate(length(max = 10, message = "文字数エラー!デフォルト名は10文字まで"))]
     pub default_name: Option<String>,
@@ -144,13 +235,18 @@ pub struct UpdateBoardDetailsRequest {
         custom(function = "validate_no_suspicious_sequences")
     )]
     pub name: Option<String>,
-    #[validate(
-        length(min = 1, max = 100, message = "文字数エラー!説明欄は1~100字まで"),
-        custom(function = "validate_no_suspicious_sequences")
-    )]
+    #[validate(custom(function = "validate_board_description"))]
     pub description: Option<String>,
     #[validate(length(max = 10, message = "文字数エラー!デフォルト名は10文字まで"))]
     pub default_name: Option<String>,
+    pub is_nsfw: Option<bool>,
+    pub posting_frozen: Option<bool>,
+    pub members_only: Option<bool>,
+    #[validate(range(min = 0, message = "参加に必要なレベルは0以上でなければなりません。"))]
+    pub member_min_level: Option<i32>,
+    pub require_captcha: Option<bool>,
+    pub per_board_ids: Option<bool>,
+    pub unique_titles: Option<bool>,
 }
 
 #[derive(Debug, Deserialize, Validate)]
@@ -159,11 +255,37 @@ pub struct UpdateBoardSettingsRequest {
     pub max_posts: i32,
 }
 
+/// 板の設定をまとめて変更するリクエスト (指定された項目のみ更新)
+#[derive(Debug, Deserialize, Validate)]
+pub struct PatchBoardSettingsRequest {
+    // 管理者のみ変更可能
+    #[validate(range(min = 1, message = "スレッド数上限は1以上でなければなりません。"))]
+    pub max_posts: Option<i32>,
+    pub moderation_type: Option<BoardModerationType>,
+    #[validate(length(max = 10, message = "文字数エラー!デフォルト名は10文字まで"))]
+    pub default_name: Option<String>,
+    // 管理者のみ変更可能
+    pub auto_archive_enabled: Option<bool>,
+    pub default_sort: Option<String>,
+}
+
 #[derive(Debug, Deserialize, Validate)]
 pub struct UpdateBoardModerationTypeRequest {
     pub moderation_type: BoardModerationType,
 }
 
+#[derive(Debug, Deserialize, Validate)]
+pub struct MergePostsRequest {
+    #[validate(range(min = 1, message = "統合元のスレッドIDが不正です。"))]
+    pub source_post_id: i32,
+}
+
+#[derive(Debug, Deserialize, Validate)]
+pub struct TransferBoardOwnershipRequest {
+    #[validate(range(min = 1, message = "移譲先のユーザーIDが不正です。"))]
+    pub new_owner_user_id: i32,
+}
+
 // --- Response Models for Board Details ---
 
 #[derive(Serialize, Debug)]
@@ -189,6 +311,9 @@ pub struct BoardDetailResponse {
     // 管理者専用の追加フィールド
     #[serde(skip_serializing_if = "Option::is_none")]
     pub creator_info: Option<CreatorInfoResponse>,
+    // 説明欄のURLをリンクに変換したHTML (`BOARD_DESCRIPTION_LINKS` が有効な場合のみ)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub description_html: Option<String>,
 }
 
 #[derive(Debug, FromRow, Serialize, Clone)]
@@ -220,19 +345,338 @@ pub struct Comment {
 }
 
 #[derive(Debug, Deserialize, Validate)]
+#[validate(schema(function = "validate_quote_prefix_length", skip_on_field_errors = true))]
 pub struct CreateCommentRequest {
     #[validate(
-        length(min = 1, max = 300, message = "文字数エラー!本文は1~300字まで")
+        length(min = 1, max = COMMENT_BODY_MAX_LENGTH, message = "文字数エラー!本文は1~300字まで")
     )]
     pub body: String,
     #[validate(
-        length(max = 10, message = "文字数エラー!名前は10字まで"),
+        length(max = AUTHOR_NAME_MAX_LENGTH, message = "文字数エラー!名前は10字まで"),
         custom(function = "validate_no_suspicious_sequences")
     )]
     pub author_name: Option<String>,
     pub post_id: i32,
     // ブラウザからの投稿時に付与されるフィンガープリント
     pub fingerprint: Option<String>,
+    // Captchaが必須の板に書き込む場合のTurnstileトークン
+    pub captcha_token: Option<String>,
+    // 返信先のレス番号。指定された場合、本文の先頭に `>>N` を付与する
+    #[validate(range(min = 1, message = "返信先のレス番号が不正です。"))]
+    pub quote_response_number: Option<i64>,
+}
+
+/// レスの本文を編集するリクエスト
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateCommentRequest {
+    #[validate(
+        length(min = 1, max = COMMENT_BODY_MAX_LENGTH, message = "文字数エラー!本文は1~300字まで")
+    )]
+    pub body: String,
+}
+
+/// レスの編集履歴の各項目。`body` は編集前の本文
+#[derive(Debug, FromRow, Serialize)]
+pub struct CommentEdit {
+    pub id: i32,
+    pub comment_id: i32,
+    pub body: String,
+    pub edited_by: Option<i32>,
+    pub edited_at: DateTime<Utc>,
+}
+
+/// 返信先のレス番号から本文の先頭に付与されるアンカー (`>>N` と改行) の文字数
+pub fn quote_prefix_length(quote_response_number: i64) -> u64 {
+    // ">>" + レス番号 + 改行
+    2 + quote_response_number.to_string().chars().count() as u64 + 1
+}
+
+/// 返信先のアンカーを付与した後の本文が、レスの最大文字数に収まるかチェックします。
+fn validate_quote_prefix_length(request: &CreateCommentRequest) -> Result<(), ValidationError> {
+    let Some(quote_number) = request.quote_response_number else {
+        return Ok(());
+    };
+    let body_len = request.body.chars().count() as u64;
+    if body_len + quote_prefix_length(quote_number) > COMMENT_BODY_MAX_LENGTH {
+        let mut error = ValidationError::new("quote_prefix_length");
+        error.message = Some("文字数エラー!本文は返信先のアンカーを含めて300字まで".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+/// `/api/config` で公開するサーバーの制限値と機能フラグ
+#[derive(Serialize, Debug)]
+pub struct ServerConfigResponse {
+    pub post_title_max_length: u64,
+    pub post_body_max_length: u64,
+    pub comment_body_max_length: u64,
+    pub author_name_max_length: u64,
+    pub board_description_max_length: u64,
+    // 1スレッドあたりの最大レス数 (スレッド本文を含む)
+    pub max_responses_per_thread: i64,
+    // レス数上限到達からアーカイブまでの猶予秒数
+    pub post_limit_archive_delay_seconds: u64,
+    pub anonymous_posting_allowed: bool,
+    // 本文の最小文字数 (前後の空白を除く)
+    pub min_body_length: usize,
+    // 1つの書き込みに含められるレスアンカーの最大数 (null の場合は無制限)
+    pub max_quotes_per_post: Option<usize>,
+    // 書き込みに必要なアカウント作成からの経過秒数 (0 の場合は制限なし)
+    pub min_account_age_seconds: i64,
+    // フィンガープリントの最大サイズ (バイト)
+    pub max_fingerprint_bytes: usize,
+    // ID生成時にIPv6アドレスを切り詰めるプレフィックス長
+    pub ipv6_prefix_length: u8,
+    pub captcha: ServerCaptchaConfig,
+    // 各アクションでIP評価 (proxycheck) が有効かどうか
+    pub ip_check: ServerIpCheckConfig,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServerCaptchaConfig {
+    // アカウント作成時に必要なCaptcha
+    pub registration: &'static str,
+    // レベルアップ時に必要なCaptcha
+    pub level_up: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServerIpCheckConfig {
+    pub registration: bool,
+    pub level_up: bool,
+    pub create_board: bool,
+    pub create_post: bool,
+    pub create_comment: bool,
+}
+
+/// プレビュー対象の本文の種類 (文字数制限が異なる)
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum PreviewBodyKind {
+    Post,
+    #[default]
+    Comment,
+}
+
+/// 本文プレビュー用のリクエスト
+#[derive(Debug, Deserialize)]
+pub struct RenderPreviewRequest {
+    pub body: String,
+    #[serde(default)]
+    pub kind: PreviewBodyKind,
+}
+
+/// 本文プレビューのレスポンス
+#[derive(Serialize, Debug)]
+pub struct RenderPreviewResponse {
+    // 実際の投稿と同じ処理を経て表示されるHTML
+    pub rendered_html: String,
+    // `!token(...)` が含まれており、投稿時に取り除かれるかどうか
+    pub contains_linking_token: bool,
+    // 連携トークンがそのまま貼り付けられている可能性があるかどうか (投稿時はエラーになる)
+    pub is_potentially_exposed_token: bool,
+    // 投稿時に発生するエラー (事前検証APIと同じ形式)
+    pub validation_errors: Vec<SubmissionValidationIssue>,
+}
+
+/// レス一覧の表示順
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentSortOrder {
+    // 古い順 (書き込み順)
+    #[default]
+    Asc,
+    // 新しい順
+    Desc,
+}
+
+/// スレッドのレス一覧のクエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct CommentsQuery {
+    #[serde(default)]
+    pub sort: CommentSortOrder,
+    // 指定された場合、ID (ユーザー・IP・端末のいずれかの部分) がこの文字列で始まるレスのみを返す
+    pub id_part: Option<String>,
+}
+
+/// 投稿前の事前検証で見つかった問題 (実際の投稿時に返されるエラーと同じステータス・内容)
+#[derive(Serialize, Debug)]
+pub struct SubmissionValidationIssue {
+    pub status: u16,
+    pub error: String,
+    // 入力値のバリデーションエラーの場合、フィールドごとのメッセージ
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+/// 投稿前の事前検証のレスポンス
+#[derive(Serialize, Debug)]
+pub struct SubmissionValidationResponse {
+    pub valid: bool,
+    pub errors: Vec<SubmissionValidationIssue>,
+}
+
+/// 板の新着レス一覧のクエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct RecentCommentsQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// レス数の差分取得リクエストの各項目 (クライアントが把握しているレス数)
+#[derive(Debug, Deserialize)]
+pub struct KnownCommentCount {
+    pub id: i32,
+    pub known_count: i64,
+}
+
+/// レス数が変化したスレッドの現在のレス数
+#[derive(Serialize, Debug)]
+pub struct CommentCountDelta {
+    pub id: i32,
+    pub comment_count: i64,
+}
+
+/// スレッド内のレス検索のクエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct ThreadCommentSearchQuery {
+    pub q: String,
+}
+
+/// 板ごとの最新スレッド (トップページの板一覧のプレビュー用)
+#[derive(Serialize, Debug)]
+pub struct BoardLatestPost {
+    pub board_id: i32,
+    pub board_name: String,
+    pub board_is_nsfw: bool,
+    pub post_id: i32,
+    pub title: String,
+    // 本文の冒頭 (サニタイズ済み、リンク化前)
+    pub snippet: String,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+/// [管理者用] 最近作成された板一覧の各項目
+#[derive(Serialize, Debug)]
+pub struct RecentBoardItem {
+    pub id: i32,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub created_by: Option<i32>,
+    // 板作成者の表示用ID (get_board_by_id の creator_info と同じ算出方法)
+    pub creator_display_user_id: Option<String>,
+    // 板作成時の認証試行から取得した国コード
+    pub country_code: Option<String>,
+}
+
+/// [管理者用] サイト全体の集計統計
+#[derive(Serialize, Debug, Clone)]
+pub struct SiteStatsOverview {
+    pub total_boards: i64,
+    pub total_threads: i64,
+    pub total_comments: i64,
+    pub total_users: i64,
+    // 直近24時間に作成されたスレッド/コメント数
+    pub threads_24h: i64,
+    pub comments_24h: i64,
+    // 直近24時間に書き込みを行ったユーザー数 (重複除外)
+    pub active_users_24h: i64,
+    // 有効期限内のBAN件数
+    pub active_bans: i64,
+    pub generated_at: DateTime<Utc>,
+}
+
+/// [板作成者用] スレッド投稿者の識別情報の要約。
+/// メールアドレスやIPアドレスは復号せず、表示用IDの各部分と板内での重複状況のみを返す。
+#[derive(Serialize, Debug)]
+pub struct PostIdentitySummary {
+    pub post_id: i32,
+    pub board_id: i32,
+    pub display_user_id: Option<String>,
+    pub display_id_user: Option<String>,
+    pub display_id_ip: Option<String>,
+    pub display_id_device: Option<String>,
+    // この板で同じユーザーが書き込んだスレッド・レスの数 (このスレッド本文を含む)
+    pub same_user_post_count: i64,
+    // この板で同じ端末から書き込んだ、別のユーザーの数
+    pub other_users_same_device: i64,
+    // この板で同じIPから書き込んだ、別のユーザーの数
+    pub other_users_same_ip: i64,
+}
+
+/// [管理者用] スレッド内で同じIPまたは端末を共有している、複数の表示IDのまとまり (自演の疑い)
+#[derive(Serialize, Debug)]
+pub struct SockpuppetCluster {
+    // 共有されている識別情報の種類 ("ip" または "device")
+    pub kind: String,
+    pub hash: String,
+    pub display_user_ids: Vec<String>,
+    // このまとまりに含まれる書き込みのレス番号 (スレッド本文は1)
+    pub response_numbers: Vec<i64>,
+}
+
+/// [管理者用] SUBJECT.TXT の並び順を確認するための各行の情報
+#[derive(Serialize, Debug)]
+pub struct SubjectDebugEntry {
+    // SUBJECT.TXT 上の行番号 (1から)
+    pub position: usize,
+    pub post_id: i32,
+    // 専ブラが参照する dat のファイル名 (スレッド作成日時のUnixタイムスタンプ)
+    pub dat_filename: String,
+    // フロントエンドが生成する SUBJECT.TXT の行 (Shift_JIS 変換前)
+    pub subject_line: String,
+    pub title: String,
+    pub response_count: i64,
+    pub momentum: f64,
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: DateTime<Utc>,
+}
+
+/// 板の設定変更履歴の各項目
+#[derive(Serialize, Debug, FromRow)]
+pub struct BoardSettingsHistoryEntry {
+    pub id: i32,
+    pub board_id: i32,
+    // 変更を実行したユーザーID (管理者にのみ返す)
+    pub changed_by: Option<i32>,
+    pub setting: String,
+    pub old_value: Option<serde_json::Value>,
+    pub new_value: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// 板のアクティビティ推移のクエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct BoardActivityQuery {
+    // 何日分を集計するか (今日を含む)
+    pub days: Option<i32>,
+}
+
+/// 板のアクティビティ推移の1日分
+#[derive(Serialize, Debug, FromRow)]
+pub struct BoardActivityDay {
+    pub day: DateTime<Utc>,
+    pub post_count: i64,
+    pub comment_count: i64,
+}
+
+/// レス数が上限に近いスレッド一覧のクエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct FillingPostsQuery {
+    // レス数上限に対する割合 (0.0〜1.0)。未指定の場合は設定値を使用
+    pub ratio: Option<f64>,
+}
+
+/// レス数が上限に近いスレッドの各項目
+#[derive(Serialize, Debug, FromRow)]
+pub struct FillingPostItem {
+    pub id: i32,
+    pub title: String,
+    pub created_at: DateTime<Utc>,
+    pub last_activity_at: DateTime<Utc>,
+    pub response_count: i64,
 }
 
 // Post詳細ページ用の新しいレスポンスモデル
@@ -251,6 +695,42 @@ pub struct CommentResponse {
     pub comment: Comment,
     // このコメントに対するモデレーション権限があるかどうかを示します。
     pub can_moderate: bool,
+    // スレ主 (スレッド作成者) による書き込みかどうか
+    pub is_op: bool,
+}
+
+/// ログイン中のユーザーがスレッドに対して持つ権限
+#[derive(Serialize, Debug, Default, Clone, Copy)]
+pub struct ThreadPermissions {
+    pub can_moderate: bool,
+    pub can_delete: bool,
+    pub can_ban: bool,
+    pub can_lock: bool,
+}
+
+/// スレッドの低速モードを設定するリクエスト
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateSlowModeRequest {
+    // レスとレスの間の最小秒数。0で低速モードを解除する (最大1日)
+    #[validate(range(min = 0, max = 86400, message = "低速モードの秒数は0~86400の範囲で指定してください。"))]
+    pub slow_mode_seconds: i32,
+}
+
+/// スレッド参加者一覧の各項目 (ID別の書き込み数)
+#[derive(Serialize, Debug)]
+pub struct ThreadParticipant {
+    pub display_user_id: String,
+    pub post_count: i64,
+}
+
+/// スレッドの参加者情報のレスポンス
+#[derive(Serialize, Debug)]
+pub struct ThreadParticipantsResponse {
+    // スレッド本文とレスを合わせた、異なるIDの数
+    pub participant_count: i64,
+    // モデレーション権限がある場合のみ、ID別の書き込み数一覧を返します。
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub participants: Option<Vec<ThreadParticipant>>,
 }
 
 // --- User History Search Models ---
@@ -289,6 +769,52 @@ pub struct PaginatedResponse<T> {
     pub total_count: i64,
 }
 
+/// ページ番号、1ページあたりの件数、総ページ数を含むページネーション化されたレスポンス。
+/// `PaginatedResponse` を構造体リテラルで組み立てている既存の呼び出し元を壊さないよう、別の型にしています。
+#[derive(Serialize)]
+pub struct PagedResponse<T> {
+    pub items: Vec<T>,
+    pub total_count: i64,
+    pub page: i64,
+    pub limit: i64,
+    pub total_pages: i64,
+}
+
+impl<T> PagedResponse<T> {
+    pub fn new(items: Vec<T>, total_count: i64, page: i64, limit: i64) -> Self {
+        Self {
+            items,
+            total_count,
+            page: page.max(1),
+            limit,
+            total_pages: total_pages(total_count, limit),
+        }
+    }
+}
+
+/// ページ番号 (1始まり) と1ページあたりの件数から、OFFSETを計算する。
+/// 1未満のページ番号は1ページ目として扱う。
+pub fn page_offset(page: i64, limit: i64) -> i64 {
+    (page.max(1) - 1) * limit.max(0)
+}
+
+/// 総件数と1ページあたりの件数から、総ページ数を計算する。
+pub fn total_pages(total_count: i64, limit: i64) -> i64 {
+    if limit <= 0 {
+        return 0;
+    }
+    (total_count.max(0) + limit - 1) / limit
+}
+
+/// 板一覧のレスポンス
+#[derive(Serialize, Debug)]
+pub struct BoardListResponse {
+    pub items: Vec<BoardListItem>,
+    pub total_count: i64,
+    // 次のページを取得するためのカーソル。最後のページの場合はnull
+    pub next_cursor: Option<String>,
+}
+
 /// ユーザーのレス投稿履歴の各項目を表す構造体
 #[derive(Debug, FromRow, Serialize)]
 pub struct CommentHistoryItem {
@@ -313,6 +839,61 @@ pub struct VerificationHistoryItem {
     pub rejection_reason: Option<String>,
     pub fingerprint_json: Option<serde_json::Value>,
     pub proxycheck_json: Option<serde_json::Value>,
+    /// proxycheckのレスポンスから抽出した国コード (例: "JP")
+    pub country_code: Option<String>,
+}
+
+/// ユーザー本人向けの認証・レベルアップ履歴の各項目。
+/// IPアドレスやproxycheck/フィンガープリントの生データなどの機微な情報は含めない。
+#[derive(Debug, FromRow, Serialize)]
+pub struct MyVerificationHistoryItem {
+    pub id: i32,
+    pub attempt_type: Option<String>,
+    pub is_success: bool,
+    pub created_at: DateTime<Utc>,
+    pub rejection_reason: Option<String>,
+}
+
+/// [管理者用] 失敗した認証試行の検索条件
+#[derive(Debug, Deserialize)]
+pub struct FailedVerificationSearchQuery {
+    // 'level_up', 'registration', 'create_post' など
+    pub attempt_type: Option<String>,
+    // 拒否理由に含まれる文字列 (大文字小文字を区別しない)
+    pub reason: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// [管理者用] 失敗した認証試行の検索結果の各項目
+#[derive(Debug, FromRow, Serialize)]
+pub struct FailedVerificationItem {
+    pub id: i32,
+    pub user_id: Option<i32>,
+    pub attempt_type: String,
+    pub created_at: DateTime<Utc>,
+    pub ip_address: Option<String>,
+    pub country_code: Option<String>,
+    pub rejection_reason: Option<String>,
+}
+
+/// [管理者用] 保存済みの認証試行を現在の設定・データで再評価した結果
+#[derive(Debug, Serialize)]
+pub struct VerificationReplayResponse {
+    pub attempt_id: i32,
+    pub attempt_type: String,
+    // 元の試行時の判定
+    pub original_is_success: bool,
+    pub original_rejection_reason: Option<String>,
+    // 現在の設定・データで再評価した場合の判定
+    pub would_succeed: bool,
+    pub fingerprint_rejection_reason: Option<String>,
+    pub proxycheck_enabled: bool,
+    pub proxycheck_rejection_reason: Option<String>,
+    pub proxycheck_timed_out: bool,
+    pub proxycheck_data: Option<ProxyCheckResponse>,
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
@@ -471,6 +1052,8 @@ pub struct CreateBanRequest {
 
     #[validate(length(max = 255))]
     pub reason: Option<String>,
+    // BAN理由の定型文ID。指定された場合は定型文を理由とし、`reason` は追記として末尾に付け加える
+    pub template_id: Option<i32>,
 
     // ハッシュ直接指定で板BAN/スレッドBANを行う場合に使用
     pub board_id: Option<i32>,
@@ -485,6 +1068,62 @@ pub struct CreateBanRequest {
     pub source_device_info: Option<String>,
 }
 
+/// BAN理由の定型文
+#[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
+pub struct BanReasonTemplate {
+    pub id: i32,
+    pub name: String,
+    pub reason: String,
+    pub created_by: Option<i32>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// [管理者用] BAN理由の定型文の作成リクエスト
+#[derive(Debug, Deserialize, Validate)]
+pub struct CreateBanReasonTemplateRequest {
+    #[validate(length(min = 1, max = 64, message = "名前は1〜64文字で入力してください。"))]
+    pub name: String,
+    #[validate(length(min = 1, max = 255, message = "理由は1〜255文字で入力してください。"))]
+    pub reason: String,
+}
+
+/// [管理者用] BAN理由の定型文の更新リクエスト
+#[derive(Debug, Deserialize, Validate)]
+pub struct UpdateBanReasonTemplateRequest {
+    #[validate(length(min = 1, max = 64, message = "名前は1〜64文字で入力してください。"))]
+    pub name: Option<String>,
+    #[validate(length(min = 1, max = 255, message = "理由は1〜255文字で入力してください。"))]
+    pub reason: Option<String>,
+}
+
+/// [管理者用] BAN一覧の検索・ページネーション用クエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct AdminBanSearchParams {
+    pub page: i64,
+    pub limit: i64,
+    // 指定されたハッシュ値に完全一致するBANのみを返す
+    pub hash_value: Option<String>,
+    pub ban_type: Option<BanType>,
+    pub scope: Option<BanScope>,
+}
+
+/// 発生源（スレッド本文またはレス）からBANを検索するためのクエリパラメータ
+#[derive(Debug, Deserialize)]
+pub struct BanSourceQuery {
+    pub post_id: Option<i32>,
+    pub comment_id: Option<i32>,
+}
+
+/// [管理者用] 発生源を指定してBANを一括解除するためのクエリパラメータ (いずれか1つを指定)
+#[derive(Debug, Deserialize)]
+pub struct BulkUnbanBySourceQuery {
+    pub source_post_id: Option<i32>,
+    pub source_comment_id: Option<i32>,
+    // このユーザーが投稿したスレッド・レスを発生源とするBANをすべて対象にする
+    pub user_id: Option<i32>,
+}
+
 #[derive(Debug, Clone, serde::Serialize, sqlx::FromRow)]
 pub struct BanDetails {
     pub id: i32,
@@ -525,6 +1164,8 @@ pub struct IdentityQuery {
     pub post_id: Option<i32>,
     pub comment_id: Option<i32>,
     pub user_id: Option<i32>,
+    // 永続ハッシュ (ユーザー/IP/デバイスのいずれか)。BANのハッシュ値から投稿者を特定するために使用
+    pub hash: Option<String>,
 }
 
 #[derive(serde::Serialize)]
@@ -537,6 +1178,29 @@ pub struct IdentityDetails {
     pub permanent_device_hash: Option<String>,
 }
 
+/// データエクスポートに含める、投稿・コメント・板ごとの復号済み個人情報
+#[derive(Debug, Serialize)]
+pub struct ExportedIdentity {
+    // "post" | "comment" | "board"
+    pub source_type: String,
+    pub source_id: i32,
+    pub email: Option<String>,
+    pub ip_address: Option<String>,
+    pub device_info: Option<String>,
+}
+
+/// [管理者用] ユーザーに紐づく全データのエクスポート (開示請求・調査用)
+#[derive(Debug, Serialize)]
+pub struct UserDataExport {
+    pub exported_at: DateTime<Utc>,
+    pub user: User,
+    pub posts: Vec<Post>,
+    pub comments: Vec<Comment>,
+    pub boards: Vec<Board>,
+    pub verification_history: Vec<VerificationHistoryItem>,
+    pub identities: Vec<ExportedIdentity>,
+}
+
 // --- Rate Limiter Models ---
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, serde::Serialize, serde::Deserialize)]
@@ -601,9 +1265,167 @@ pub struct CreateRateLimitRuleRequest {
 
 pub type UpdateRateLimitRuleRequest = CreateRateLimitRuleRequest;
 
+/// [管理者用] ユーザーのレート制限免除設定を変更するリクエスト
+#[derive(Debug, Deserialize)]
+pub struct SetRateLimitExemptionRequest {
+    pub is_rate_limit_exempt: bool,
+}
+
+/// 連絡用メールアドレスを設定するリクエスト (nullで削除)
+#[derive(Debug, Deserialize, Validate)]
+pub struct SetContactEmailRequest {
+    #[validate(
+        email(message = "有効なメールアドレスを入力してください。"),
+        length(
+            max = 254,
+            message = "メールアドレスは254文字以下である必要があります。"
+        )
+    )]
+    pub contact_email: Option<String>,
+}
+
+/// アカウント削除時のオプション
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountQuery {
+    // trueの場合、過去の書き込みの投稿者名を匿名化する (書き込み自体は残る)
+    pub anonymize_content: Option<bool>,
+}
+
 /// ページネーション用の汎用クエリパラメータ
 #[derive(Debug, Deserialize)]
 pub struct PaginationParams {
     pub page: i64,
     pub limit: i64,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_offset_treats_pages_below_one_as_first_page() {
+        assert_eq!(page_offset(1, 20), 0);
+        assert_eq!(page_offset(0, 20), 0);
+        assert_eq!(page_offset(-3, 20), 0);
+        assert_eq!(page_offset(3, 20), 40);
+        assert_eq!(page_offset(3, -1), 0);
+    }
+
+    #[test]
+    fn total_pages_handles_empty_exact_and_remainder() {
+        assert_eq!(total_pages(0, 20), 0);
+        assert_eq!(total_pages(40, 20), 2);
+        assert_eq!(total_pages(41, 20), 3);
+        assert_eq!(total_pages(1, 20), 1);
+        assert_eq!(total_pages(10, 0), 0);
+    }
+
+    #[test]
+    fn paged_response_normalizes_page_and_computes_total_pages() {
+        let response = PagedResponse::new(vec![1, 2, 3], 43, 0, 20);
+        assert_eq!(response.page, 1);
+        assert_eq!(response.limit, 20);
+        assert_eq!(response.total_pages, 3);
+        assert_eq!(response.total_count, 43);
+        assert_eq!(response.items, vec![1, 2, 3]);
+    }
+
+    fn comment_request(body: &str, quote_response_number: Option<i64>) -> CreateCommentRequest {
+        CreateCommentRequest {
+            body: body.to_string(),
+            author_name: None,
+            post_id: 1,
+            fingerprint: None,
+            captcha_token: None,
+            quote_response_number,
+        }
+    }
+
+    #[test]
+    fn quote_prefix_counts_anchor_and_newline() {
+        assert_eq!(quote_prefix_length(1), 4);
+        assert_eq!(quote_prefix_length(999), 6);
+    }
+
+    #[test]
+    fn comment_with_quote_must_fit_within_max_length() {
+        let max = COMMENT_BODY_MAX_LENGTH as usize;
+        assert!(comment_request(&"あ".repeat(max), None).validate().is_ok());
+        assert!(comment_request(&"あ".repeat(max), Some(12))
+            .validate()
+            .is_err());
+        assert!(comment_request(&"あ".repeat(max - 5), Some(12))
+            .validate()
+            .is_ok());
+        assert!(comment_request(&"あ".repeat(max - 4), Some(12))
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn title_rule_rejects_long_sequences_when_strict() {
+        assert!(validate_title_sequences_with("普通のスレッドタイトル 2024", true).is_ok());
+        assert!(validate_title_sequences_with("abcdefghijklmnopqrstu", true).is_err());
+        // URL内の長い英数字は対象外
+        assert!(validate_title_sequences_with(
+            "まとめ https://example.com/abcdefghijklmnopqrstuvwxyz",
+            true
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn title_rule_allows_long_words_when_relaxed() {
+        assert!(validate_title_sequences_with("internationalization", true).is_err());
+        assert!(validate_title_sequences_with("internationalization", false).is_ok());
+        // 緩和時も、連携トークンのような32文字以上の英数字は拒否する
+        assert!(validate_title_sequences_with(&"a".repeat(32), false).is_err());
+        assert!(validate_title_sequences_with(&"a".repeat(31), false).is_ok());
+    }
+
+    #[test]
+    fn title_rule_always_rejects_linking_tokens() {
+        let title = format!("https://example.com/!token({})", "a".repeat(32));
+        assert!(validate_title_sequences_with(&title, true).is_err());
+        assert!(validate_title_sequences_with(&title, false).is_err());
+    }
+
+    #[test]
+    fn board_list_item_flattens_board_fields_with_counts() {
+        let now = Utc::now();
+        let item = BoardListItem {
+            board: Board {
+                id: 1,
+                name: "ニュース".to_string(),
+                description: "説明".to_string(),
+                default_name: "名無しさん".to_string(),
+                created_at: now,
+                updated_at: now,
+                deleted_at: None,
+                created_by: None,
+                max_posts: 100,
+                archived_at: None,
+                moderation_type: BoardModerationType::Alpha,
+                last_activity_at: now,
+                auto_archive_enabled: true,
+                is_nsfw: false,
+                posting_frozen: false,
+                members_only: false,
+                member_min_level: 0,
+                require_captcha: false,
+                per_board_ids: false,
+                default_sort: "momentum_desc".to_string(),
+                unique_titles: false,
+            },
+            thread_count: 3,
+            total_comment_count: 42,
+        };
+
+        let json = serde_json::to_value(&item).unwrap();
+        assert_eq!(json["id"], 1);
+        assert_eq!(json["name"], "ニュース");
+        assert_eq!(json["thread_count"], 3);
+        assert_eq!(json["total_comment_count"], 42);
+        assert!(json.get("board").is_none());
+    }
+}
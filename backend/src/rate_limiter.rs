@@ -1,4 +1,4 @@
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse};
 use chrono::{Duration, Utc};
 use sqlx::{FromRow, PgConnection, PgPool, Postgres, QueryBuilder};
 use validator::Validate;
@@ -11,7 +11,7 @@ use crate::{
         UpdateRateLimitRuleRequest,
     },
 };
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 /// [管理者用] ロック情報をフロントエンドに返すための構造体
 #[derive(FromRow, Serialize, Debug)]
@@ -22,6 +22,54 @@ pub struct RateLimitLockInfo {
     pub rule_name: Option<String>, // ルールが削除されている可能性を考慮してOption
 }
 
+/// [管理者用] レート制限トラッカーに記録されたイベントを返すための構造体
+#[derive(FromRow, Serialize, Debug)]
+pub struct RateLimitTrackerHit {
+    pub rule_id: i32,
+    pub rule_name: Option<String>,
+    pub target_key: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// [管理者用] トラッカー検索用のクエリパラメータ
+#[derive(Deserialize, Debug)]
+pub struct RateLimitTrackerQuery {
+    pub target_key: String,
+    // 何秒前までのイベントを対象にするか (デフォルト: 24時間)
+    pub window_seconds: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+/// [管理者用] ロック状態を確認するためのクエリパラメータ
+#[derive(Deserialize, Debug)]
+pub struct RateLimitLockCheckQuery {
+    pub user_id: i32,
+    pub ip_hash: String,
+    pub device_hash: String,
+}
+
+/// [管理者用] ロック状態の確認結果
+#[derive(Serialize, Debug)]
+pub struct RateLimitLockCheckResponse {
+    // 投稿時にチェックされるすべての対象キー
+    pub checked_keys: Vec<String>,
+    pub is_locked: bool,
+    pub locks: Vec<RateLimitLockInfo>,
+}
+
+/// [管理者用] ルールの有効/無効切り替え時のクエリパラメータ
+#[derive(Deserialize, Debug)]
+pub struct ToggleRateLimitRuleQuery {
+    // trueの場合、ルールを無効化する際にそのルールによる既存のロックも解除する
+    pub clear_locks: Option<bool>,
+}
+
+/// [管理者用] ロックの有効期限を変更するリクエスト
+#[derive(Deserialize, Debug)]
+pub struct UpdateRateLimitLockRequest {
+    pub expires_at: chrono::DateTime<chrono::Utc>,
+}
+
 /// [管理者用] レート制限ルールを作成します。
 #[post("")]
 pub async fn create_rate_limit_rule(
@@ -201,17 +249,22 @@ pub async fn delete_rate_limit_rule(
 }
 
 /// [管理者用] 特定のレート制限ルールの有効/無効を切り替えます。
+/// `?clear_locks=true` を指定して無効化した場合は、`delete_rate_limit_rule` と同様に
+/// このルールによって生成された既存のロックも同じトランザクションで解除します。
 #[post("/{id}/toggle")]
 pub async fn toggle_rate_limit_rule(
     pool: web::Data<PgPool>,
     user: web::ReqData<AuthenticatedUser>,
     path: web::Path<i32>,
+    query: web::Query<ToggleRateLimitRuleQuery>,
 ) -> Result<HttpResponse, ServiceError> {
     if !matches!(user.role, Role::Admin) {
         return Err(ServiceError::Unauthorized);
     }
     let rule_id = path.into_inner();
 
+    let mut tx = pool.begin().await?;
+
     let updated_rule = sqlx::query_as!(
         RateLimitRule,
         r#"
@@ -222,13 +275,26 @@ pub async fn toggle_rate_limit_rule(
         "#,
         rule_id
     )
-    .fetch_optional(pool.get_ref())
-    .await?;
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("Rule not found".to_string()))?;
 
-    updated_rule.map_or_else(
-        || Err(ServiceError::NotFound("Rule not found".to_string())),
-        |rule| Ok(HttpResponse::Ok().json(rule)),
-    )
+    if !updated_rule.is_enabled && query.clear_locks.unwrap_or(false) {
+        let cleared = sqlx::query!("DELETE FROM rate_limit_locks WHERE rule_id = $1", rule_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        log::info!(
+            "[Admin] Cleared {} rate limit locks of disabled rule {} by admin {}",
+            cleared,
+            rule_id,
+            user.user_id
+        );
+    }
+
+    tx.commit().await?;
+
+    Ok(HttpResponse::Ok().json(updated_rule))
 }
 
 /// [管理者用] 現在有効なすべてのレート制限ロックを取得します。
@@ -261,6 +327,101 @@ pub async fn get_active_rate_limit_locks(
     Ok(HttpResponse::Ok().json(locks))
 }
 
+/// [管理者用] 指定したユーザー・IP・端末の組み合わせが、現在ロックされているかを確認します。
+/// 投稿時 (`check_and_track_rate_limits`) と同じ対象キーを調べ、ロックされているキーと期限を返します。
+/// 「書き込めない」という問い合わせの調査用で、データは変更しません。
+#[get("/check")]
+pub async fn check_rate_limit_locks(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<RateLimitLockCheckQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let ip_hash = query.ip_hash.trim();
+    let device_hash = query.device_hash.trim();
+    if ip_hash.is_empty() || device_hash.is_empty() {
+        return Err(ServiceError::BadRequest(
+            "ip_hashとdevice_hashを指定してください。".to_string(),
+        ));
+    }
+
+    let checked_keys = get_all_target_keys(query.user_id, ip_hash, device_hash);
+
+    let locks = sqlx::query_as!(
+        RateLimitLockInfo,
+        r#"
+        SELECT
+            l.target_key,
+            l.expires_at,
+            l.rule_id,
+            r.name as "rule_name?"
+        FROM rate_limit_locks l
+        LEFT JOIN rate_limit_rules r ON l.rule_id = r.id
+        WHERE l.target_key = ANY($1) AND l.expires_at > NOW()
+        ORDER BY l.expires_at DESC
+        "#,
+        &checked_keys
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(RateLimitLockCheckResponse {
+        checked_keys,
+        is_locked: !locks.is_empty(),
+        locks,
+    }))
+}
+
+/// [管理者用] 指定した対象キーについて、レート制限トラッカーに記録された直近のイベントを取得します。
+/// どの操作がロックの閾値にカウントされたのかを調査するために使用します。
+#[get("/tracker")]
+pub async fn get_rate_limit_tracker_hits(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    query: web::Query<RateLimitTrackerQuery>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+
+    let target_key = query.target_key.trim();
+    if target_key.is_empty() {
+        return Err(ServiceError::BadRequest(
+            "target_keyを指定してください。".to_string(),
+        ));
+    }
+    // 最大7日間まで遡れる
+    let window_seconds = query.window_seconds.unwrap_or(86400).clamp(1, 7 * 86400);
+    let limit = query.limit.unwrap_or(200).clamp(1, 1000);
+    let since = Utc::now() - Duration::seconds(window_seconds);
+
+    let hits = sqlx::query_as!(
+        RateLimitTrackerHit,
+        r#"
+        SELECT
+            t.rule_id,
+            r.name as "rule_name?",
+            t.target_key,
+            t.created_at
+        FROM rate_limit_tracker t
+        LEFT JOIN rate_limit_rules r ON t.rule_id = r.id
+        WHERE t.target_key = $1 AND t.created_at >= $2
+        ORDER BY t.created_at DESC
+        LIMIT $3
+        "#,
+        target_key,
+        since,
+        limit
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    Ok(HttpResponse::Ok().json(hits))
+}
+
 /// [管理者用] 特定のレート制限ロックを解除します。
 #[delete("/locks/{target_key}")]
 pub async fn delete_rate_limit_lock(
@@ -289,6 +450,93 @@ pub async fn delete_rate_limit_lock(
     Ok(HttpResponse::NoContent().finish())
 }
 
+/// [管理者用] 特定のレート制限ロックの有効期限を変更します。
+/// 悪質な対象のロックを延長したり、誤検知によるロックを短縮したりするために使用します。
+#[patch("/locks/{target_key}")]
+pub async fn update_rate_limit_lock(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<String>,
+    payload: web::Json<UpdateRateLimitLockRequest>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    let target_key = path.into_inner();
+
+    if payload.expires_at <= Utc::now() {
+        return Err(ServiceError::BadRequest(
+            "有効期限には未来の日時を指定してください。ロックを解除する場合は削除してください。"
+                .to_string(),
+        ));
+    }
+
+    // 既に期限切れのロックは更新対象外とする (期限切れのロックの復活を防ぐ)
+    let lock = sqlx::query_as!(
+        RateLimitLockInfo,
+        r#"
+        WITH updated AS (
+            UPDATE rate_limit_locks SET expires_at = $2
+            WHERE target_key = $1 AND expires_at > NOW()
+            RETURNING target_key, expires_at, rule_id
+        )
+        SELECT
+            u.target_key,
+            u.expires_at,
+            u.rule_id,
+            r.name as "rule_name?"
+        FROM updated u
+        LEFT JOIN rate_limit_rules r ON u.rule_id = r.id
+        "#,
+        target_key,
+        payload.expires_at
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("指定されたロックが見つかりません。".to_string()))?;
+
+    log::info!(
+        "[Admin] Rate limit lock for {} updated to expire at {} by admin {}",
+        lock.target_key,
+        lock.expires_at,
+        user.user_id
+    );
+
+    Ok(HttpResponse::Ok().json(lock))
+}
+
+/// [管理者用] 指定したルールによって作成されたロックをすべて解除します。
+/// 設定ミスのあったルールで多数のユーザーがロックされた場合の復旧に使用します。
+#[delete("/{rule_id}/locks")]
+pub async fn delete_rate_limit_locks_by_rule(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    path: web::Path<i32>,
+) -> Result<HttpResponse, ServiceError> {
+    if !matches!(user.role, Role::Admin) {
+        return Err(ServiceError::Unauthorized);
+    }
+    let rule_id = path.into_inner();
+
+    let rule_exists: bool = sqlx::query_scalar!(
+        r#"SELECT EXISTS(SELECT 1 FROM rate_limit_rules WHERE id = $1) as "exists!""#,
+        rule_id
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+    if !rule_exists {
+        return Err(ServiceError::NotFound("Rule not found".to_string()));
+    }
+
+    let result = sqlx::query!("DELETE FROM rate_limit_locks WHERE rule_id = $1", rule_id)
+        .execute(pool.get_ref())
+        .await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "deleted_count": result.rows_affected()
+    })))
+}
+
 /// 投稿者のID情報を受け取り、レート制限に違反していないかチェックし、今回の投稿イベントを記録します。
 pub async fn check_and_track_rate_limits(
     conn: &mut PgConnection,
@@ -297,17 +545,13 @@ pub async fn check_and_track_rate_limits(
     device_hash: &str,
     action_type: models::RateLimitActionType,
 ) -> Result<(), ServiceError> {
-    // --- START: Admin Exemption Check ---
-    // First, check if the user is an admin exempt from rate limiting.
-    struct UserInfo {
-        role: Role,
-        is_rate_limit_exempt: bool,
-    }
-
-    let user_info = sqlx::query_as!(
-        UserInfo,
-        r#"SELECT role as "role: _", is_rate_limit_exempt FROM users WHERE id = $1"#,
-        user_id
+    // --- START: Exemption Check ---
+    // First, check if the user is exempt from rate limiting.
+    // 管理者に限らず、管理者が免除を設定したユーザー (信頼済みのbotなど) もスキップする。
+    let user_flags = sqlx::query!(
+        r#"SELECT is_rate_limit_exempt, email = $2 as "is_anonymous!" FROM users WHERE id = $1"#,
+        user_id,
+        crate::ANONYMOUS_USER_ACCOUNT_ID
     )
     .fetch_optional(&mut *conn)
     .await?
@@ -315,16 +559,23 @@ pub async fn check_and_track_rate_limits(
         ServiceError::InternalServerError("User not found during rate limit check.".to_string())
     })?;
 
-    if matches!(user_info.role, Role::Admin) && user_info.is_rate_limit_exempt {
+    if user_flags.is_rate_limit_exempt {
         log::info!(
-            "[Rate Limiter] Skipping check for exempt admin user_id: {}",
+            "[Rate Limiter] Skipping check for exempt user_id: {}",
             user_id
         );
         return Ok(());
     }
-    // --- END: Admin Exemption Check ---
+    // --- END: Exemption Check ---
 
-    let all_keys = get_all_target_keys(user_id, ip_hash, device_hash);
+    // 匿名投稿者は全員が同じ共有ユーザーのため、ユーザー単位のキーで制限すると
+    // 1人の違反で全ての匿名投稿者がロックされてしまう。匿名投稿ではユーザー単位のキーを使わず、
+    // IP・デバイスを含むキーのみで制限する。
+    let is_anonymous = user_flags.is_anonymous;
+    let all_keys: Vec<String> = get_all_target_keys(user_id, ip_hash, device_hash)
+        .into_iter()
+        .filter(|key| !(is_anonymous && key.starts_with("user:")))
+        .collect();
 
     // 1. まず、いずれかのキーがロックされていないかチェックする
     let now = Utc::now();
@@ -351,6 +602,11 @@ pub async fn check_and_track_rate_limits(
         .fetch_all(&mut *conn)
         .await?;
 
+    let rules: Vec<RateLimitRule> = rules
+        .into_iter()
+        .filter(|rule| !(is_anonymous && matches!(rule.target, RateLimitTarget::UserId)))
+        .collect();
+
     if rules.is_empty() {
         return Ok(()); // ルールがなければチェック不要
     }
@@ -414,6 +670,117 @@ pub async fn check_and_track_rate_limits(
     Ok(())
 }
 
+/// 自分に適用されるレート制限ルールごとの現在の状況
+#[derive(Serialize, Debug)]
+pub struct RateLimitRuleStatus {
+    pub rule_id: i32,
+    pub rule_name: String,
+    pub action_type: models::RateLimitActionType,
+    pub threshold: i32,
+    pub window_seconds: i32,
+    // 時間枠内に記録された回数
+    pub current_count: i64,
+    // ロックされるまでに残っている回数
+    pub remaining: i64,
+}
+
+/// 自分のレート制限の状況
+#[derive(Serialize, Debug)]
+pub struct RateLimitStatusResponse {
+    // 管理者、またはレート制限を免除されたユーザーの場合は true (rules は空になる)
+    pub exempt: bool,
+    // いずれかのキーがロックされている場合、その解除日時 (最も遅いもの)
+    pub locked_until: Option<chrono::DateTime<Utc>>,
+    pub rules: Vec<RateLimitRuleStatus>,
+}
+
+/// [認証必須] 自分のレート制限の状況を取得します。
+/// `check_and_track_rate_limits` と同じキーで集計しますが、記録は行いません (読み取り専用)。
+/// 端末のキーはリクエストの User-Agent から計算するため、投稿時にフィンガープリントを
+/// 送信している場合、端末単位のルールの回数は実際と一致しないことがあります。
+#[get("/me/rate-limit-status")]
+pub async fn get_my_rate_limit_status(
+    pool: web::Data<PgPool>,
+    user: web::ReqData<AuthenticatedUser>,
+    req: HttpRequest,
+) -> Result<HttpResponse, ServiceError> {
+    let user_info = sqlx::query!(
+        "SELECT email, is_rate_limit_exempt FROM users WHERE id = $1",
+        user.user_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await?
+    .ok_or_else(|| ServiceError::NotFound("ユーザーが見つかりません。".to_string()))?;
+
+    if matches!(user.role, Role::Admin) || user_info.is_rate_limit_exempt {
+        return Ok(HttpResponse::Ok().json(RateLimitStatusResponse {
+            exempt: true,
+            locked_until: None,
+            rules: Vec::new(),
+        }));
+    }
+
+    // 投稿時と同じ方法で、IP・端末のハッシュを計算する
+    let (truncated_ip, _) = crate::get_ip_address(&req);
+    let device_info = req
+        .headers()
+        .get("User-Agent")
+        .and_then(|ua| ua.to_str().ok())
+        .unwrap_or("unknown");
+    let identity_hashes =
+        crate::identity::generate_identity_hashes(&user_info.email, &truncated_ip, device_info);
+    let ip_hash = &identity_hashes.permanent_ip_hash;
+    let device_hash = &identity_hashes.permanent_device_hash;
+
+    let now = Utc::now();
+    let all_keys = get_all_target_keys(user.user_id, ip_hash, device_hash);
+    let locked_until: Option<chrono::DateTime<Utc>> = sqlx::query_scalar!(
+        "SELECT MAX(expires_at) FROM rate_limit_locks WHERE target_key = ANY($1) AND expires_at > $2",
+        &all_keys,
+        now
+    )
+    .fetch_one(pool.get_ref())
+    .await?;
+
+    let rules = sqlx::query_as!(
+        RateLimitRule,
+        r#"SELECT id, name, target as "target: _", action_type as "action_type: _", threshold, time_frame_seconds, lockout_seconds, is_enabled, created_at, updated_at, created_by FROM rate_limit_rules WHERE is_enabled = true ORDER BY action_type, id"#
+    )
+    .fetch_all(pool.get_ref())
+    .await?;
+
+    let mut statuses = Vec::with_capacity(rules.len());
+    for rule in rules {
+        let target_key = get_target_key_for_rule(&rule.target, user.user_id, ip_hash, device_hash);
+        let time_window_start = now - Duration::seconds(rule.time_frame_seconds as i64);
+        let current_count: i64 = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM rate_limit_tracker WHERE rule_id = $1 AND target_key = $2 AND created_at > $3",
+            rule.id,
+            target_key,
+            time_window_start
+        )
+        .fetch_one(pool.get_ref())
+        .await?
+        .unwrap_or(0);
+
+        statuses.push(RateLimitRuleStatus {
+            rule_id: rule.id,
+            rule_name: rule.name,
+            action_type: rule.action_type,
+            threshold: rule.threshold,
+            window_seconds: rule.time_frame_seconds,
+            current_count,
+            remaining: (rule.threshold as i64 - current_count).max(0),
+        });
+    }
+
+    Ok(HttpResponse::Ok().json(RateLimitStatusResponse {
+        exempt: false,
+        locked_until,
+        rules: statuses,
+    }))
+}
+
 /// ルールの監視対象に応じて、DBに保存する一意なキーを生成する
 fn get_target_key_for_rule(
     target: &RateLimitTarget,
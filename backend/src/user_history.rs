@@ -6,7 +6,7 @@ use sqlx::{PgPool, Postgres, QueryBuilder, Row};
 use std::collections::{HashMap, HashSet};
 
 use crate::{
-    errors::ServiceError, get_ip_address,
+    errors::ServiceError, get_ip_address, identity,
     middleware,
     models::{Comment, Post},
     rate_limiter,
@@ -20,6 +20,23 @@ pub struct HistoryQuery {
     pub device_part: Option<String>,
     pub logic: Option<String>, // "and" or "or"
     pub sort: Option<String>,  // "time_desc", "time_asc", "thread_desc", "thread_asc"
+    /// 指定された場合、本文をこの文字数までに切り詰めて返す (未指定なら全文)
+    pub snippet_length: Option<usize>,
+    /// スレッド・コメントそれぞれの取得件数の上限 (サーバー側の上限を超える値は切り詰める)
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+const DEFAULT_HISTORY_MAX_RESULTS: i64 = 500;
+
+/// 履歴検索でスレッド・コメントそれぞれについて返す件数の上限。
+/// 環境変数 `HISTORY_SEARCH_MAX_RESULTS` で変更可能です。
+fn history_max_results() -> i64 {
+    std::env::var("HISTORY_SEARCH_MAX_RESULTS")
+        .ok()
+        .and_then(|v| v.parse::<i64>().ok())
+        .filter(|v| *v > 0)
+        .unwrap_or(DEFAULT_HISTORY_MAX_RESULTS)
 }
 
 /// レスポンスでスレッドとコメントを統一的に扱うためのenum
@@ -42,6 +59,8 @@ pub struct HistorySummary {
     pub created_threads: Vec<(String, i64)>,
     /// (スレッドタイトル, そのスレッド内での総投稿数)
     pub commented_in_threads: Vec<(String, i64)>,
+    /// 件数上限により結果が切り詰められた場合は true (サマリーも切り詰め後の結果に基づく)
+    pub truncated: bool,
 }
 
 /// APIレスポンス全体
@@ -51,6 +70,43 @@ pub struct HistoryResponse {
     pub items: Vec<HistoryItem>,
 }
 
+/// 入力されたID部分の先頭から切り出した、保存されている可能性のある短いIDの候補を返します。
+/// IDは旧フォーマットの長さ (`LEGACY_DISPLAY_ID_PART_LENGTH`) 未満にはならないため、それ以上の長さのみを返します。
+fn shorter_display_id_candidates(part: &str) -> Vec<String> {
+    let chars: Vec<char> = part.chars().collect();
+    (identity::LEGACY_DISPLAY_ID_PART_LENGTH..chars.len())
+        .map(|len| chars[..len].iter().collect())
+        .collect()
+}
+
+/// ID部分 (`display_id_user` など) の前方一致条件をクエリに追加します。
+pub(crate) fn push_display_id_prefix_condition(
+    builder: &mut QueryBuilder<Postgres>,
+    alias: &str,
+    column_name: &str,
+    part: &str,
+) {
+    // IDの長さが変更されたため、完全一致(=)から前方一致(LIKE)に変更します。
+    // これにより、新旧両方のフォーマット（4文字と8文字）を検索できます。
+    // ユーザーが入力した文字列で始まるIDを検索するために、末尾に'%'を追加します。
+    // また、新しい長さのIDで検索した場合でも旧フォーマットの短いIDにヒットするよう、
+    // 入力の先頭部分と完全一致するIDも条件に含めます。
+    // (列側を式にするとインデックスが使えないため、候補はアプリ側で切り出して渡す)
+    let pattern = format!("{}%", part);
+    builder
+        .push("(")
+        .push(alias)
+        .push(column_name)
+        .push(" LIKE ")
+        .push_bind(pattern)
+        .push(" OR ")
+        .push(alias)
+        .push(column_name)
+        .push(" = ANY(")
+        .push_bind(shorter_display_id_candidates(part))
+        .push("))");
+}
+
 /// 指定されたIDの各部分文字列に一致する投稿履歴を取得します。
 #[get("/by-id-parts")]
 pub async fn get_history_by_id_parts(
@@ -105,7 +161,8 @@ pub async fn get_history_by_id_parts(
         // JOINを追加してスレッドタイトルを取得
         r#"SELECT c.id, c.body, c.post_id, c.user_id, c.author_name, c.created_at, c.updated_at, c.display_user_id,
                    c.permanent_user_hash, c.permanent_ip_hash, c.permanent_device_hash,
-                   c.level_at_creation, u.level, p.title as post_title
+                   c.level_at_creation, u.level, p.title as post_title,
+                   c.response_number::BIGINT as response_number
             FROM comments c
             JOIN posts p ON c.post_id = p.id
             LEFT JOIN users u ON c.user_id = u.id
@@ -130,20 +187,8 @@ pub async fn get_history_by_id_parts(
                 posts_query.push(logic_separator);
                 comments_query.push(logic_separator);
             }
-            // IDの長さが変更されたため、完全一致(=)から前方一致(LIKE)に変更します。
-            // これにより、新旧両方のフォーマット（4文字と8文字）を検索できます。
-            // ユーザーが入力した文字列で始まるIDを検索するために、末尾に'%'を追加します。
-            let pattern = format!("{}%", p);
-            posts_query
-                .push("p.")
-                .push(column_name)
-                .push(" LIKE ")
-                .push_bind(pattern.clone());
-            comments_query
-                .push("c.")
-                .push(column_name)
-                .push(" LIKE ")
-                .push_bind(pattern);
+            push_display_id_prefix_condition(&mut posts_query, "p.", column_name, p);
+            push_display_id_prefix_condition(&mut comments_query, "c.", column_name, p);
             condition_count += 1;
         }
     }
@@ -157,6 +202,20 @@ pub async fn get_history_by_id_parts(
     posts_query.push(")");
     comments_query.push(")");
 
+    // 件数上限を適用する。切り詰めの有無を判定するため、上限より1件多く取得する
+    let limit = query
+        .limit
+        .unwrap_or(i64::MAX)
+        .clamp(1, history_max_results());
+    let offset = query.offset.unwrap_or(0).max(0);
+    for (builder, alias) in [(&mut posts_query, "p"), (&mut comments_query, "c")] {
+        builder
+            .push(format!(" ORDER BY {alias}.created_at DESC, {alias}.id DESC LIMIT "))
+            .push_bind(limit + 1)
+            .push(" OFFSET ")
+            .push_bind(offset);
+    }
+
     // --- 2. データベースから投稿とコメントを並行して検索 ---
     let posts_task = posts_query
         .build_query_as::<Post>()
@@ -166,54 +225,19 @@ pub async fn get_history_by_id_parts(
         .fetch_all(pool.get_ref());
 
     let (posts_result, comments_result) = tokio::join!(posts_task, comments_task);
-    let posts = posts_result?;
+    let mut posts = posts_result?;
     let mut comments = comments_result?;
 
+    let truncated = posts.len() as i64 > limit || comments.len() as i64 > limit;
+    posts.truncate(limit as usize);
+    comments.truncate(limit as usize);
+
     if posts.is_empty() && comments.is_empty() {
         return Err(ServiceError::NotFound(
             "指定されたIDを持つ投稿は見つかりませんでした。".to_string(),
         ));
     }
 
-    // --- START: 正しいレスナンバーを計算して付与 ---
-    if !comments.is_empty() {
-        // 1. 関連するスレッドIDをすべて集める
-        let relevant_thread_ids: Vec<i32> = comments
-            .iter()
-            .map(|c| c.post_id)
-            .collect::<HashSet<_>>()
-            .into_iter()
-            .collect();
-
-        // 2. 関連スレッドの全コメントを投稿順に取得し、レスナンバーを計算するためのマップを作成
-        let all_thread_comments: Vec<(i32, i32)> = sqlx::query_as(
-            "SELECT post_id, id FROM comments WHERE post_id = ANY($1) ORDER BY post_id, created_at ASC"
-        )
-        .bind(&relevant_thread_ids)
-        .fetch_all(pool.get_ref())
-        .await?;
-
-        let mut response_number_map: HashMap<i32, i64> = HashMap::new();
-        if !all_thread_comments.is_empty() {
-            let mut current_thread_id = all_thread_comments[0].0;
-            let mut counter = 2; // 1はスレ本体なので2から開始
-            for (post_id, comment_id) in all_thread_comments {
-                if post_id != current_thread_id {
-                    current_thread_id = post_id;
-                    counter = 2; // スレッドが変わったらカウンターをリセット
-                }
-                response_number_map.insert(comment_id, counter);
-                counter += 1;
-            }
-        }
-
-        // 3. 検索結果のコメントにレス番号をセット
-        for comment in &mut comments {
-            comment.response_number = response_number_map.get(&comment.id).cloned();
-        }
-    }
-    // --- END: 正しいレスナンバーを計算して付与 ---
-
     // --- 3. 結果のマージ、サマリー計算、ソート ---
     let mut items: Vec<HistoryItem> = posts
         .iter()
@@ -222,7 +246,8 @@ pub async fn get_history_by_id_parts(
         .chain(comments.iter().cloned().map(HistoryItem::Comment))
         .collect();
 
-    let summary = calculate_summary(&posts, &comments, pool.get_ref()).await?;
+    let mut summary = calculate_summary(&posts, &comments, pool.get_ref()).await?;
+    summary.truncated = truncated;
 
     // --- START: ソート機能の修正 ---
     // スレッドごとのソートを正しく機能させるため、検索結果に含まれるすべてのスレッドの作成日時を収集します。
@@ -258,7 +283,10 @@ pub async fn get_history_by_id_parts(
     // --- END: ソート機能の修正 ---
 
     // レスポンスから機密情報を除去
-    let sanitized_items = sanitize_items(items);
+    let mut sanitized_items = sanitize_items(items);
+    if let Some(max_chars) = query.snippet_length {
+        truncate_item_bodies(&mut sanitized_items, max_chars);
+    }
 
     Ok(HttpResponse::Ok().json(HistoryResponse {
         summary,
@@ -337,6 +365,7 @@ async fn calculate_summary(
         total_contribution_count: (posts.len() + comments.len()) as i64,
         created_threads,
         commented_in_threads,
+        truncated: false,
     })
 }
 
@@ -404,6 +433,50 @@ fn get_thread_and_item_time(
     }
 }
 
+/// 各アイテムの本文を `max_chars` 文字までに切り詰めるヘルパー関数
+fn truncate_item_bodies(items: &mut [HistoryItem], max_chars: usize) {
+    for item in items.iter_mut() {
+        let body = match item {
+            HistoryItem::Post(p) => &mut p.body,
+            HistoryItem::Comment(c) => &mut c.body,
+        };
+        if let Some(snippet) = truncate_body_snippet(body, max_chars) {
+            *body = snippet;
+        }
+    }
+}
+
+/// サニタイズ済み (リンク化前) の本文を `max_chars` 文字に切り詰め、末尾に「…」を付けます。
+///
+/// `&gt;` などのHTMLエンティティは1文字として数え、途中で分断しません。
+/// 切り詰めが不要な場合は `None` を返します。
+/// リンク化 (`linkify_body`) はこの結果に対して行うことで、タグが途中で切れることを防ぎます。
+pub(crate) fn truncate_body_snippet(body: &str, max_chars: usize) -> Option<String> {
+    let mut count = 0;
+    let mut iter = body.char_indices().peekable();
+    while let Some((idx, ch)) = iter.next() {
+        if count == max_chars {
+            return Some(format!("{}…", &body[..idx]));
+        }
+        if ch == '&' {
+            // エンティティ (`&...;`) であれば末尾の `;` まで読み進める
+            let entity_len = body[idx..].find(';').filter(|&len| {
+                len > 1
+                    && body[idx + 1..idx + len]
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '#')
+            });
+            if let Some(len) = entity_len {
+                while iter.peek().is_some_and(|&(i, _)| i <= idx + len) {
+                    iter.next();
+                }
+            }
+        }
+        count += 1;
+    }
+    None
+}
+
 /// レスポンスから機密情報を除去するヘルパー関数
 fn sanitize_items(items: Vec<HistoryItem>) -> Vec<HistoryItem> {
     items
@@ -424,3 +497,93 @@ fn sanitize_items(items: Vec<HistoryItem>) -> Vec<HistoryItem> {
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shorter_display_id_candidates_start_at_legacy_length() {
+        assert!(shorter_display_id_candidates("abc").is_empty());
+        assert!(shorter_display_id_candidates("abcd").is_empty());
+        assert_eq!(
+            shorter_display_id_candidates("abcdefg"),
+            vec!["abcd", "abcde", "abcdef"]
+        );
+    }
+
+    #[test]
+    fn display_id_prefix_condition_keeps_column_on_the_left() {
+        let mut builder = QueryBuilder::<Postgres>::new("SELECT 1 FROM posts p WHERE ");
+        push_display_id_prefix_condition(&mut builder, "p.", "display_id_user", "abcdefgh");
+        assert_eq!(
+            builder.sql(),
+            "SELECT 1 FROM posts p WHERE (p.display_id_user LIKE $1 OR p.display_id_user = ANY($2))"
+        );
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    #[tokio::test]
+    #[ignore]
+    async fn display_id_prefix_condition_matches_legacy_and_current_ids() {
+        use sqlx::Connection;
+
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = sqlx::PgConnection::connect(&database_url).await.unwrap();
+        sqlx::query("CREATE TEMP TABLE history_ids (id INT, display_id_user VARCHAR(8))")
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        sqlx::query(
+            "INSERT INTO history_ids VALUES (1, 'abcd'), (2, 'abcdefgh'), (3, 'abcx'), (4, 'abcdzzzz')",
+        )
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        // 8文字で検索した場合は、8文字のIDと旧フォーマットの4文字のIDの両方に一致する
+        for (part, expected) in [
+            ("abcdefgh", vec![1, 2]),
+            ("abcd", vec![1, 2, 4]),
+            ("abc", vec![1, 2, 3, 4]),
+        ] {
+            let mut builder = QueryBuilder::<Postgres>::new("SELECT id FROM history_ids h WHERE ");
+            push_display_id_prefix_condition(&mut builder, "h.", "display_id_user", part);
+            builder.push(" ORDER BY id");
+            let ids: Vec<i32> = builder
+                .build_query_scalar()
+                .fetch_all(&mut conn)
+                .await
+                .unwrap();
+            assert_eq!(ids, expected, "part = {}", part);
+        }
+    }
+
+    #[test]
+    fn truncate_body_snippet_keeps_bodies_within_limit() {
+        assert_eq!(truncate_body_snippet("あいう", 3), None);
+        assert_eq!(truncate_body_snippet("abc", 5), None);
+        assert_eq!(truncate_body_snippet("", 0), None);
+    }
+
+    #[test]
+    fn truncate_body_snippet_cuts_on_character_boundaries() {
+        assert_eq!(
+            truncate_body_snippet("あいうえ", 3).as_deref(),
+            Some("あいう…")
+        );
+        assert_eq!(truncate_body_snippet("🐔🐔🐔", 1).as_deref(), Some("🐔…"));
+        assert_eq!(truncate_body_snippet("abcd", 0).as_deref(), Some("…"));
+    }
+
+    #[test]
+    fn truncate_body_snippet_counts_entities_as_one_character() {
+        assert_eq!(truncate_body_snippet("&gt;&gt;1", 3), None);
+        assert_eq!(
+            truncate_body_snippet("&gt;&gt;12", 2).as_deref(),
+            Some("&gt;&gt;…")
+        );
+        // `;` のない `&` は通常の1文字として数える
+        assert_eq!(truncate_body_snippet("a&b c", 2).as_deref(), Some("a&…"));
+    }
+}
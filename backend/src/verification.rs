@@ -1,14 +1,29 @@
 use chrono::{Duration, Utc};
+use once_cell::sync::Lazy;
 use serde::Deserialize;
 use serde_json::Value;
 use sha2::{Digest, Sha256};
 use sqlx::PgConnection;
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::Instant,
+};
 
 use crate::{errors::ServiceError, middleware::Role, models::ProxyCheckResponse};
 
 // --- Configuration ---
 const FINGERPRINT_3_HASH_LOCK_DURATION_HOURS: i64 = 23;
 const FINGERPRINT_2_HASH_LOCK_DURATION_HOURS: i64 = 1;
+const DEFAULT_PROXYCHECK_TIMEOUT_MS: u64 = 3000;
+const DEFAULT_PROXYCHECK_CACHE_TTL_SECONDS: u64 = 300;
+const DEFAULT_MAX_FINGERPRINT_BYTES: usize = 16 * 1024;
+// キャッシュが際限なく肥大化しないよう、期限切れエントリを掃除する目安の件数
+const PROXYCHECK_CACHE_SWEEP_THRESHOLD: usize = 10_000;
+
+// IPアドレスごとのproxycheck結果のインメモリキャッシュ (取得時刻, レスポンス)
+static PROXYCHECK_CACHE: Lazy<Mutex<HashMap<String, (Instant, ProxyCheckResponse)>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
 
 // --- Structs for external APIs ---
 
@@ -44,6 +59,32 @@ pub enum VerificationType {
     CreateComment,
 }
 
+impl VerificationType {
+    /// `level_up_attempts.attempt_type` に保存される文字列
+    pub fn as_attempt_type(self) -> &'static str {
+        match self {
+            VerificationType::LevelUp => "level_up",
+            VerificationType::Registration => "registration",
+            VerificationType::CreateBoard => "create_board",
+            VerificationType::CreatePost => "create_post",
+            VerificationType::CreateComment => "create_comment",
+        }
+    }
+
+    /// `level_up_attempts.attempt_type` の文字列から種別を復元します。
+    pub fn from_attempt_type(attempt_type: &str) -> Option<Self> {
+        [
+            VerificationType::LevelUp,
+            VerificationType::Registration,
+            VerificationType::CreateBoard,
+            VerificationType::CreatePost,
+            VerificationType::CreateComment,
+        ]
+        .into_iter()
+        .find(|t| t.as_attempt_type() == attempt_type)
+    }
+}
+
 pub struct VerificationInput {
     pub verification_type: VerificationType,
     pub user_id: Option<i32>, // Registration時はNone
@@ -71,24 +112,84 @@ pub struct FingerprintHashes {
     pub h_ca: String, // canvas + audio
 }
 
+// --- Fingerprint Size Limit ---
+
+/// クライアントから送信されるフィンガープリントの最大サイズ (バイト)。
+/// 環境変数 `MAX_FINGERPRINT_BYTES` で変更可能 (デフォルト16KB)。
+pub fn max_fingerprint_bytes() -> usize {
+    std::env::var("MAX_FINGERPRINT_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FINGERPRINT_BYTES)
+}
+
+/// フィンガープリントのサイズが上限以内かをチェックする。
+/// 巨大なデータがハッシュ計算やDBへの保存に回らないよう、できるだけ早い段階で呼び出すこと。
+pub fn check_fingerprint_size(size_in_bytes: usize) -> Result<(), ServiceError> {
+    check_fingerprint_size_with(size_in_bytes, max_fingerprint_bytes())
+}
+
+/// `check_fingerprint_size` の本体。上限を引数で受け取ります。
+fn check_fingerprint_size_with(size_in_bytes: usize, max_bytes: usize) -> Result<(), ServiceError> {
+    if size_in_bytes > max_bytes {
+        return Err(ServiceError::BadRequest(
+            "フィンガープリントのデータが大きすぎます。".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+// --- Attempt Logging Toggle ---
+
+/// 成功した認証試行を `level_up_attempts` に記録するかどうかを判定する。
+/// 書き込み系のアクションは件数が多いため、`LOG_ATTEMPTS_CREATE_BOARD` / `LOG_ATTEMPTS_CREATE_POST` /
+/// `LOG_ATTEMPTS_CREATE_COMMENT` に "false" を設定すると記録を省略できる (デフォルト: 記録する)。
+/// 登録・レベルアップは JWT に試行IDを含めるため常に記録し、拒否された試行は調査のため常に記録する。
+/// なお、記録を省略したアクションのフィンガープリントは、再利用チェックの対象にならない。
+fn should_log_successful_attempt(verification_type: VerificationType) -> bool {
+    let env_key = match verification_type {
+        VerificationType::LevelUp | VerificationType::Registration => return true,
+        VerificationType::CreateBoard => "LOG_ATTEMPTS_CREATE_BOARD",
+        VerificationType::CreatePost => "LOG_ATTEMPTS_CREATE_POST",
+        VerificationType::CreateComment => "LOG_ATTEMPTS_CREATE_COMMENT",
+    };
+    std::env::var(env_key)
+        .map(|v| v != "false")
+        .unwrap_or(true)
+}
+
 // --- Main Verification Logic ---
 
+/// 認証処理を行い、結果と記録した試行のIDを返す。
+/// 成功した試行の記録が無効化されている場合、試行IDは `None` になる。
 pub async fn perform_verification(
     // プールとトランザクションの両方を受け入れられるようにジェネリックにする
     conn: &mut PgConnection,
     http_client: &reqwest::Client,
     input: VerificationInput,
-) -> Result<(VerificationResult, i32), ServiceError> {
+) -> Result<(VerificationResult, Option<i32>), ServiceError> {
     // --- START: 診断ログ ---
+    // IPアドレスは個人情報のため、詳細な診断ログが有効な場合のみ出力する
+    let log_ip = if crate::is_verbose_diag_logging_enabled() {
+        input.ip_address.as_str()
+    } else {
+        "<redacted>"
+    };
     log::info!(
         "[Verification DIAG] === Starting verification process for type: {:?}, IP: {} ===",
         input.verification_type,
-        input.ip_address
+        log_ip
     );
     log::debug!("[Verification DIAG] Input details: user_id={:?}, role={:?}, captcha_token is_some={}, fingerprint_data is_some={}", input.user_id, input.role, input.captcha_token.is_some(), input.fingerprint_data.is_some());
     // Check if the user is an admin. If so, we can bypass rate-limiting checks.
     let is_admin = matches!(input.role, Some(Role::Admin));
 
+    // 巨大なフィンガープリントはハッシュ計算・保存の前に拒否する
+    if let Some(fp_data) = &input.fingerprint_data {
+        let size = serde_json::to_vec(fp_data).map(|v| v.len()).unwrap_or(usize::MAX);
+        check_fingerprint_size(size)?;
+    }
+
     // 1. Captcha verification (Turnstile or hCaptcha)
     // 認証系のアクションの場合のみ実行
     match input.verification_type {
@@ -104,7 +205,16 @@ pub async fn perform_verification(
             verify_hcaptcha(http_client, token, Some(&input.ip_address)).await?;
             log::info!("[Verification DIAG] hCaptcha verification successful.");
         }
-        // 投稿系のアクションではCaptcha検証をスキップ
+        // Captchaが必須の板への書き込みでは、呼び出し元がトークンを渡すためTurnstileで検証する
+        VerificationType::CreatePost | VerificationType::CreateComment
+            if input.captcha_token.is_some() =>
+        {
+            log::info!("[Verification DIAG] Performing Turnstile verification for captcha-required board...");
+            let token = input.captcha_token.as_deref().unwrap_or_default();
+            verify_turnstile(http_client, token, Some(&input.ip_address)).await?;
+            log::info!("[Verification DIAG] Turnstile verification successful.");
+        }
+        // それ以外の投稿系のアクションではCaptcha検証をスキップ
         VerificationType::CreateBoard
         | VerificationType::CreatePost
         | VerificationType::CreateComment => {
@@ -137,7 +247,7 @@ pub async fn perform_verification(
                 hashes: None,
             };
             let attempt_id = save_attempt(&mut *conn, &input, &result).await?;
-            return Ok((result, attempt_id));
+            return Ok((result, Some(attempt_id)));
         }
     }
 
@@ -176,24 +286,7 @@ pub async fn perform_verification(
     };
 
     // --- START: Verification (Conditional) ---
-    // アクション種別に応じて、使用する環境変数を切り替える
-    let proxycheck_enabled: bool = match input.verification_type {
-        VerificationType::LevelUp => {
-            std::env::var("PROXYCHECK_ENABLED_LEVEL_UP").unwrap_or_else(|_| "true".to_string()).parse().unwrap_or(true)
-        }
-        VerificationType::Registration => {
-            std::env::var("PROXYCHECK_ENABLED_REGISTRATION").unwrap_or_else(|_| "true".to_string()).parse().unwrap_or(true)
-        }
-        VerificationType::CreateBoard => {
-            std::env::var("PROXYCHECK_ENABLED_CREATE_BOARD").unwrap_or_else(|_| "true".to_string()).parse().unwrap_or(true)
-        }
-        VerificationType::CreatePost => {
-            std::env::var("PROXYCHECK_ENABLED_CREATE_POST").unwrap_or_else(|_| "true".to_string()).parse().unwrap_or(true)
-        }
-        VerificationType::CreateComment => {
-            std::env::var("PROXYCHECK_ENABLED_CREATE_COMMENT").unwrap_or_else(|_| "true".to_string()).parse().unwrap_or(true)
-        }
-    };
+    let proxycheck_enabled = is_proxycheck_enabled(input.verification_type);
 
     // フィンガープリントチェックでまだ拒否されていない場合のみ実行
     let proxycheck_data: Option<ProxyCheckResponse> = if rejection_reason.is_none() && proxycheck_enabled {
@@ -203,18 +296,34 @@ pub async fn perform_verification(
             .raw_ip_address
             .as_deref()
             .unwrap_or(&input.ip_address);
-        let data = get_proxycheck_data(http_client, ip_for_proxycheck).await?; // 外部APIコール
-        log::debug!("[Verification DIAG] [proxycheck] Received data: {:?}", &data); // ログ出力
-        // レスポンスを評価 (管理者でない場合のみ)
-        if !is_admin { // 管理者チェック
-            log::info!("[Verification DIAG] [proxycheck] Not an admin, evaluating response...");
-            if let Some(reason) = verify_proxycheck(&data) {
-                rejection_reason = Some(reason);
-                rejection_type = Some(RejectionType::RateLimit);
-                log::warn!("[Verification DIAG] [proxycheck] REJECTED. Reason: {}", rejection_reason.as_ref().unwrap());
+        match get_proxycheck_data(http_client, ip_for_proxycheck).await { // 外部APIコール
+            Ok(ProxyCheckOutcome::Data(data)) => {
+                log::debug!("[Verification DIAG] [proxycheck] Received data: {:?}", &data); // ログ出力
+                // レスポンスを評価 (管理者でない場合のみ)
+                if !is_admin { // 管理者チェック
+                    log::info!("[Verification DIAG] [proxycheck] Not an admin, evaluating response...");
+                    if let Some(reason) = verify_proxycheck(&data) {
+                        rejection_reason = Some(reason);
+                        rejection_type = Some(RejectionType::RateLimit);
+                        log::warn!("[Verification DIAG] [proxycheck] REJECTED. Reason: {}", rejection_reason.as_ref().unwrap());
+                    }
+                }
+                Some(data) // レスポンス(data)をSomeでラップして代入する
+            }
+            Ok(ProxyCheckOutcome::TimedOut) => {
+                // タイムアウト時は設定に応じて許可または拒否する (管理者は常に許可)
+                if !is_admin && !is_proxycheck_timeout_allowed() {
+                    rejection_reason = Some("IP評価サービスが応答しませんでした。しばらくしてから再度お試しください。".to_string());
+                    rejection_type = Some(RejectionType::Generic);
+                    log::warn!("[Verification DIAG] [proxycheck] Timed out. REJECTED by fallback policy.");
+                } else {
+                    log::warn!("[Verification DIAG] [proxycheck] Timed out. ALLOWED by fallback policy.");
+                }
+                None
             }
+            // APIキー/URLの未設定やAPIエラー時は、設定に応じてチェックをスキップする
+            Err(e) => skip_unavailable_proxycheck(e, is_proxycheck_fail_open())?,
         }
-        Some(data) // レスポンス(data)をSomeでラップして代入する
     } else {
         None // 無効、または既に拒否されている場合はNone
     };
@@ -227,7 +336,7 @@ pub async fn perform_verification(
         log::warn!(
             "[Verification DIAG] Rejection occurred for type: {:?}, IP: {}, Reason: {:?}",
             input.verification_type,
-            input.ip_address,
+            log_ip,
             rejection_reason.as_deref().unwrap_or("N/A")
         );
         let result = VerificationResult {
@@ -239,7 +348,7 @@ pub async fn perform_verification(
         };
         // Save the failed attempt and return immediately.
         let attempt_id = save_attempt(conn, &input, &result).await?;
-        return Ok((result, attempt_id));
+        return Ok((result, Some(attempt_id)));
     }
     // --- END: Early Return on Rejection ---
 
@@ -254,7 +363,12 @@ pub async fn perform_verification(
     };
 
     // 7. Save attempt information
-    let attempt_id = save_attempt(conn, &input, &result).await?;
+    let attempt_id = if should_log_successful_attempt(input.verification_type) {
+        Some(save_attempt(conn, &input, &result).await?)
+    } else {
+        log::debug!("[Verification DIAG] Attempt logging is disabled for {:?}. Skipping save.", input.verification_type);
+        None
+    };
 
     log::info!(
         "[Verification DIAG] === Verification process finished. Success: {}. Reason: {:?} ===",
@@ -266,6 +380,19 @@ pub async fn perform_verification(
 
 // --- Helper Functions ---
 
+/// アクション種別ごとに、proxycheckによるIP評価が有効かどうかを返す。
+/// アクション種別に応じて、使用する環境変数を切り替える (デフォルトは有効)。
+pub fn is_proxycheck_enabled(verification_type: VerificationType) -> bool {
+    let env_key = match verification_type {
+        VerificationType::LevelUp => "PROXYCHECK_ENABLED_LEVEL_UP",
+        VerificationType::Registration => "PROXYCHECK_ENABLED_REGISTRATION",
+        VerificationType::CreateBoard => "PROXYCHECK_ENABLED_CREATE_BOARD",
+        VerificationType::CreatePost => "PROXYCHECK_ENABLED_CREATE_POST",
+        VerificationType::CreateComment => "PROXYCHECK_ENABLED_CREATE_COMMENT",
+    };
+    std::env::var(env_key).unwrap_or_else(|_| "true".to_string()).parse().unwrap_or(true)
+}
+
 /// Verifies a Cloudflare Turnstile token.
 /// Returns Ok(()) on success, or an Err(ServiceError) on failure.
 pub async fn verify_turnstile(
@@ -370,23 +497,118 @@ pub async fn verify_hcaptcha(
     Ok(())
 }
 
+/// proxycheck APIの呼び出し結果
+pub enum ProxyCheckOutcome {
+    Data(ProxyCheckResponse),
+    // 設定されたタイムアウト時間内に応答がなかった
+    TimedOut,
+}
+
+/// proxycheck APIのタイムアウト (ミリ秒)。環境変数 `PROXYCHECK_TIMEOUT_MS` で変更可能 (デフォルト3000ms)。
+fn proxycheck_timeout() -> std::time::Duration {
+    let ms = std::env::var("PROXYCHECK_TIMEOUT_MS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_PROXYCHECK_TIMEOUT_MS);
+    std::time::Duration::from_millis(ms)
+}
+
+/// proxycheck結果のキャッシュ有効期間。環境変数 `PROXYCHECK_CACHE_TTL_SECONDS` で変更可能 (0でキャッシュ無効)。
+fn proxycheck_cache_ttl() -> std::time::Duration {
+    let secs = std::env::var("PROXYCHECK_CACHE_TTL_SECONDS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_PROXYCHECK_CACHE_TTL_SECONDS);
+    std::time::Duration::from_secs(secs)
+}
+
+/// proxycheckがタイムアウトした場合に、リクエストを許可するかどうか。
+/// 環境変数 `PROXYCHECK_TIMEOUT_FALLBACK` が `deny` の場合は拒否し、それ以外 (デフォルト `allow`) は許可する。
+pub fn is_proxycheck_timeout_allowed() -> bool {
+    !std::env::var("PROXYCHECK_TIMEOUT_FALLBACK")
+        .map(|v| v.trim().eq_ignore_ascii_case("deny"))
+        .unwrap_or(false)
+}
+
+/// proxycheckが利用できない場合 (APIキー/URLの未設定、APIエラー) に、チェックをスキップして処理を続行するかどうか。
+/// 環境変数 `PROXYCHECK_FAIL_OPEN` が `true` の場合のみスキップし、デフォルトは従来通りエラーとする。
+fn is_proxycheck_fail_open() -> bool {
+    parse_proxycheck_fail_open(std::env::var("PROXYCHECK_FAIL_OPEN").ok().as_deref())
+}
+
+fn parse_proxycheck_fail_open(value: Option<&str>) -> bool {
+    value.is_some_and(|v| v.trim().eq_ignore_ascii_case("true"))
+}
+
+/// proxycheckが利用できなかった場合の扱いを決めます。
+/// `fail_open` が有効ならチェックをスキップして `None` (評価結果なし) を返し、無効なら元のエラーを返します。
+fn skip_unavailable_proxycheck(
+    error: ServiceError,
+    fail_open: bool,
+) -> Result<Option<ProxyCheckResponse>, ServiceError> {
+    if !fail_open {
+        return Err(error);
+    }
+    log::warn!(
+        "[Verification DIAG] [proxycheck] Unavailable ({}). SKIPPED because PROXYCHECK_FAIL_OPEN is enabled.",
+        error
+    );
+    Ok(None)
+}
+
+fn get_cached_proxycheck(ip: &str, ttl: std::time::Duration) -> Option<ProxyCheckResponse> {
+    let cache = PROXYCHECK_CACHE.lock().ok()?;
+    cache
+        .get(ip)
+        .filter(|(fetched_at, _)| fetched_at.elapsed() < ttl)
+        .map(|(_, data)| data.clone())
+}
+
+fn store_cached_proxycheck(ip: &str, data: &ProxyCheckResponse, ttl: std::time::Duration) {
+    if let Ok(mut cache) = PROXYCHECK_CACHE.lock() {
+        if cache.len() >= PROXYCHECK_CACHE_SWEEP_THRESHOLD {
+            cache.retain(|_, (fetched_at, _)| fetched_at.elapsed() < ttl);
+        }
+        cache.insert(ip.to_string(), (Instant::now(), data.clone()));
+    }
+}
+
 pub async fn get_proxycheck_data(
     client: &reqwest::Client,
     ip: &str,
-) -> Result<ProxyCheckResponse, ServiceError> {
+) -> Result<ProxyCheckOutcome, ServiceError> {
+    // 同じIPからの連続した投稿では、キャッシュ済みの結果を再利用する
+    let cache_ttl = proxycheck_cache_ttl();
+    if !cache_ttl.is_zero() {
+        if let Some(cached) = get_cached_proxycheck(ip, cache_ttl) {
+            log::debug!("[proxycheck] Cache hit.");
+            return Ok(ProxyCheckOutcome::Data(cached));
+        }
+    }
+
     let api_key = std::env::var("PROXYCHECK_API_KEY")
         .map_err(|_| ServiceError::InternalServerError("PROXYCHECK_API_KEY not set".to_string()))?;
     let base_url = std::env::var("PROXYCHECK_API_URL")
         .map_err(|_| ServiceError::InternalServerError("PROXYCHECK_API_URL not set".to_string()))?;
     let url = format!("{}/{}?key={}", base_url, ip, api_key);
-    log::info!("[proxycheck] Requesting data for IP: {} from URL: {}", ip, url);
-    let response = client.get(&url).send().await.map_err(|e| {
-        log::error!(
-            "[proxycheck] API request failed. Full error details: {:?}",
-            e
-        );
-        ServiceError::InternalServerError(format!("Failed to contact API: {}", e))
-    })?;
+    // URLにはAPIキーが、IPは個人情報が含まれるため、詳細な診断ログが有効な場合のみ出力する
+    if crate::is_verbose_diag_logging_enabled() {
+        log::info!("[proxycheck] Requesting data for IP: {} from URL: {}", ip, url);
+    }
+    let response = match client.get(&url).timeout(proxycheck_timeout()).send().await {
+        Ok(response) => response,
+        Err(e) if e.is_timeout() => {
+            log::warn!("[proxycheck] API request timed out.");
+            return Ok(ProxyCheckOutcome::TimedOut);
+        }
+        Err(e) => {
+            log::error!(
+                "[proxycheck] API request failed. Full error details: {:?}",
+                e
+            );
+            return Err(ServiceError::InternalServerError(format!("Failed to contact API: {}", e)));
+        }
+    };
 
     if !response.status().is_success() {
         let status = response.status();
@@ -401,7 +623,30 @@ pub async fn get_proxycheck_data(
 
     // レスポンスを新しいProxyCheckResponse構造体にデシリアライズします。
     let proxy_data: ProxyCheckResponse = response.json().await.map_err(|e| ServiceError::InternalServerError(format!("Failed to parse proxycheck response: {}", e)))?;
-    Ok(proxy_data)
+    if !cache_ttl.is_zero() {
+        store_cached_proxycheck(ip, &proxy_data, cache_ttl);
+    }
+    Ok(ProxyCheckOutcome::Data(proxy_data))
+}
+
+/// proxycheckのレスポンスから国コード (ISO 3166-1 alpha-2) を抽出する。
+/// v2形式の `isocode` と、v3形式の `location.country_code` の両方に対応する。
+pub fn extract_country_code(data: &ProxyCheckResponse) -> Option<String> {
+    data.ip_details.values().find_map(|details| {
+        details
+            .other_fields
+            .get("isocode")
+            .and_then(|v| v.as_str())
+            .or_else(|| {
+                details
+                    .other_fields
+                    .get("location")
+                    .and_then(|loc| loc.get("country_code"))
+                    .and_then(|v| v.as_str())
+            })
+            .map(|code| code.trim().to_uppercase())
+            .filter(|code| !code.is_empty())
+    })
 }
 
 pub fn calculate_fingerprint_hashes(fingerprint_data: &Value) -> FingerprintHashes {
@@ -421,7 +666,7 @@ pub fn calculate_fingerprint_hashes(fingerprint_data: &Value) -> FingerprintHash
 }
 
 /// レスポンスを評価する
-fn verify_proxycheck(data: &ProxyCheckResponse) -> Option<String> {
+pub fn verify_proxycheck(data: &ProxyCheckResponse) -> Option<String> {
     // `ip_details`はHashMapなので、最初の（そして唯一の）エントリの値を取得します。
     log::debug!("[verify_proxycheck] Evaluating proxycheck response...");
     if let Some(details) = data.ip_details.values().next() {
@@ -464,6 +709,16 @@ fn verify_proxycheck(data: &ProxyCheckResponse) -> Option<String> {
 pub async fn verify_fingerprint_hashes(
     conn: &mut PgConnection,
     hashes: &FingerprintHashes,
+) -> Result<Option<String>, sqlx::Error> {
+    verify_fingerprint_hashes_excluding(conn, hashes, None).await
+}
+
+/// `verify_fingerprint_hashes` と同じ判定を、指定した認証試行を除外して行います。
+/// 保存済みの試行を再評価する際に、その試行自身との一致を除くために使います。
+pub async fn verify_fingerprint_hashes_excluding(
+    conn: &mut PgConnection,
+    hashes: &FingerprintHashes,
+    exclude_attempt_id: Option<i32>,
 ) -> Result<Option<String>, sqlx::Error> {
     // --- Development Bypass for Rate Limiting ---
     // 環境変数 `DEV_MODE_DISABLE_RATE_LIMIT` が "true" の場合、レート制限をスキップします。
@@ -489,11 +744,13 @@ pub async fn verify_fingerprint_hashes(
             EXISTS (
                 SELECT 1 FROM level_up_attempts
                 WHERE hash_webgl_canvas_audio = $1 AND created_at > $2
+                  AND id IS DISTINCT FROM $7
             ) as "h3_found!",
             EXISTS (
                 SELECT 1 FROM level_up_attempts
                 WHERE (hash_webgl_canvas = $3 OR hash_webgl_audio = $4 OR hash_canvas_audio = $5)
                   AND created_at > $6
+                  AND id IS DISTINCT FROM $7
             ) as "h2_found!"
         "#,
         &hashes.h3,
@@ -501,7 +758,8 @@ pub async fn verify_fingerprint_hashes(
         &hashes.h_wc,
         &hashes.h_wa,
         &hashes.h_ca,
-        h2_lock_time
+        h2_lock_time,
+        exclude_attempt_id
     )
     .fetch_one(conn)
     .await?;
@@ -527,17 +785,15 @@ pub async fn save_attempt(
     // Note: The `level_up_attempts` table stores verification attempts for BOTH
     // level-up and registration processes. A more accurate name might be
     // `verification_attempts`, but it's used consistently throughout the system.
-    let attempt_type_str = match input.verification_type {
-        VerificationType::LevelUp => "level_up",
-        VerificationType::Registration => "registration",
-        VerificationType::CreateBoard => "create_board",
-        VerificationType::CreatePost => "create_post",
-        VerificationType::CreateComment => "create_comment",
-    };
+    let attempt_type_str = input.verification_type.as_attempt_type();
     let proxycheck_json = result
         .proxycheck_data
         .as_ref()
         .and_then(|s| serde_json::to_value(s).ok());
+    let country_code = result
+        .proxycheck_data
+        .as_ref()
+        .and_then(extract_country_code);
 
     let fingerprint_json = input.fingerprint_data.as_ref().and_then(|fp| serde_json::to_value(fp).ok());
     let (h3, h_wc, h_wa, h_ca) = result
@@ -554,10 +810,10 @@ pub async fn save_attempt(
 
     let attempt_id = sqlx::query_scalar!(
         r#"
-        INSERT INTO level_up_attempts (user_id, attempt_type, is_success, ip_address, proxycheck_json, fingerprint_json, hash_webgl_canvas_audio, hash_webgl_canvas, hash_webgl_audio, hash_canvas_audio, rejection_reason)
-        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING id
+        INSERT INTO level_up_attempts (user_id, attempt_type, is_success, ip_address, proxycheck_json, fingerprint_json, hash_webgl_canvas_audio, hash_webgl_canvas, hash_webgl_audio, hash_canvas_audio, rejection_reason, country_code)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id
         "#,
-        input.user_id, attempt_type_str, result.is_success, &input.ip_address, proxycheck_json, fingerprint_json, h3, h_wc, h_wa, h_ca, result.rejection_reason
+        input.user_id, attempt_type_str, result.is_success, &input.ip_address, proxycheck_json, fingerprint_json, h3, h_wc, h_wa, h_ca, result.rejection_reason, country_code
     ).fetch_one(&mut *conn).await?;
 
     // --- START: Update user failure count on level-up failure ---
@@ -573,5 +829,399 @@ pub async fn save_attempt(
     }
     // --- END: Update user failure count on level-up failure ---
 
+    if let (false, Some(config)) = (result.is_success, verification_auto_ban_config()) {
+        auto_ban_after_repeated_failures(&mut *conn, &config, &input.ip_address, attempt_type_str)
+            .await?;
+    }
+
     Ok(attempt_id)
 }
+
+const DEFAULT_VERIFICATION_AUTO_BAN_WINDOW_SECONDS: i64 = 3600;
+const DEFAULT_VERIFICATION_AUTO_BAN_DURATION_SECONDS: i64 = 86400;
+
+/// 認証失敗の繰り返しによる自動BANの設定
+struct VerificationAutoBanConfig {
+    // この回数以上失敗すると自動BANする
+    threshold: i64,
+    // 失敗回数を数える期間
+    window: Duration,
+    // BANの有効期間
+    duration: Duration,
+    // BANの作成者として記録する管理者のユーザーID
+    created_by: i32,
+}
+
+impl VerificationAutoBanConfig {
+    /// 期間内の失敗回数 (今回の失敗を含む) が、自動BANの対象となる回数に達しているか
+    fn should_ban(&self, failure_count: i64) -> bool {
+        failure_count >= self.threshold
+    }
+}
+
+/// 認証失敗の繰り返しによる自動BANの設定を読み込みます。デフォルトは無効です。
+///
+/// `VERIFICATION_AUTO_BAN_THRESHOLD` (1以上) と `VERIFICATION_AUTO_BAN_CREATED_BY`
+/// (BANの作成者として記録する管理者のユーザーID) の両方が設定されている場合のみ有効になります。
+/// 期間は `VERIFICATION_AUTO_BAN_WINDOW_SECONDS` (デフォルト1時間)、
+/// BANの有効期間は `VERIFICATION_AUTO_BAN_DURATION_SECONDS` (デフォルト24時間) で変更できます。
+fn verification_auto_ban_config() -> Option<VerificationAutoBanConfig> {
+    verification_auto_ban_config_from(|key| std::env::var(key).ok())
+}
+
+/// `verification_auto_ban_config` の本体。値の取得元を差し替えられるように分けています。
+fn verification_auto_ban_config_from(
+    lookup: impl Fn(&str) -> Option<String>,
+) -> Option<VerificationAutoBanConfig> {
+    let env_i64 = |key: &str| {
+        lookup(key)
+            .and_then(|v| v.trim().parse::<i64>().ok())
+            .filter(|v| *v > 0)
+    };
+    let threshold = env_i64("VERIFICATION_AUTO_BAN_THRESHOLD")?;
+    let created_by = i32::try_from(env_i64("VERIFICATION_AUTO_BAN_CREATED_BY")?).ok()?;
+    Some(VerificationAutoBanConfig {
+        threshold,
+        window: Duration::seconds(
+            env_i64("VERIFICATION_AUTO_BAN_WINDOW_SECONDS")
+                .unwrap_or(DEFAULT_VERIFICATION_AUTO_BAN_WINDOW_SECONDS),
+        ),
+        duration: Duration::seconds(
+            env_i64("VERIFICATION_AUTO_BAN_DURATION_SECONDS")
+                .unwrap_or(DEFAULT_VERIFICATION_AUTO_BAN_DURATION_SECONDS),
+        ),
+        created_by,
+    })
+}
+
+/// 同じIPから、同じ種類の認証に一定期間内で繰り返し失敗している場合に、
+/// そのIPに対する期限付きのグローバルBANを作成します (既に有効なBANがある場合は何もしない)。
+/// BANの対象と数える対象を一致させるため、失敗回数はIP単位でのみ数えます。
+async fn auto_ban_after_repeated_failures(
+    conn: &mut PgConnection,
+    config: &VerificationAutoBanConfig,
+    ip_address: &str,
+    attempt_type: &str,
+) -> Result<(), sqlx::Error> {
+    let since = Utc::now() - config.window;
+    let failure_count: i64 = sqlx::query_scalar!(
+        r#"
+        SELECT COUNT(*) as "count!" FROM level_up_attempts
+        WHERE attempt_type = $1 AND is_success = false AND created_at > $2
+          AND ip_address = $3
+        "#,
+        attempt_type,
+        since,
+        ip_address
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+
+    if !config.should_ban(failure_count) {
+        return Ok(());
+    }
+
+    let ip_hash = crate::identity::permanent_ip_hash(ip_address);
+    let already_banned: bool = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM bans
+            WHERE ban_type = 'ip' AND hash_value = $1 AND board_id IS NULL AND post_id IS NULL
+              AND (expires_at IS NULL OR expires_at > NOW())
+        ) as "exists!"
+        "#,
+        ip_hash
+    )
+    .fetch_one(&mut *conn)
+    .await?;
+    if already_banned {
+        return Ok(());
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO bans (ban_type, hash_value, board_id, post_id, reason, created_by, expires_at)
+        VALUES ('ip', $1, NULL, NULL, $2, $3, $4)
+        "#,
+        ip_hash,
+        format!("認証の失敗が繰り返されたため自動BAN ({})", attempt_type),
+        config.created_by,
+        Utc::now() + config.duration
+    )
+    .execute(&mut *conn)
+    .await?;
+
+    log::warn!(
+        "[AUTO BAN] Created a temporary global IP ban after {} failed {} verifications.",
+        failure_count,
+        attempt_type
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::Connection;
+    use std::collections::HashMap;
+
+    fn lookup_from(pairs: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let map: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |key| map.get(key).cloned()
+    }
+
+    #[test]
+    fn auto_ban_config_is_disabled_without_threshold_and_creator() {
+        assert!(verification_auto_ban_config_from(lookup_from(&[])).is_none());
+        assert!(verification_auto_ban_config_from(lookup_from(&[(
+            "VERIFICATION_AUTO_BAN_THRESHOLD",
+            "5"
+        )]))
+        .is_none());
+        assert!(verification_auto_ban_config_from(lookup_from(&[
+            ("VERIFICATION_AUTO_BAN_THRESHOLD", "0"),
+            ("VERIFICATION_AUTO_BAN_CREATED_BY", "1"),
+        ]))
+        .is_none());
+    }
+
+    #[test]
+    fn auto_ban_config_uses_defaults_for_window_and_duration() {
+        let config = verification_auto_ban_config_from(lookup_from(&[
+            ("VERIFICATION_AUTO_BAN_THRESHOLD", " 3 "),
+            ("VERIFICATION_AUTO_BAN_CREATED_BY", "7"),
+            ("VERIFICATION_AUTO_BAN_WINDOW_SECONDS", "invalid"),
+        ]))
+        .unwrap();
+        assert_eq!(config.threshold, 3);
+        assert_eq!(config.created_by, 7);
+        assert_eq!(
+            config.window,
+            Duration::seconds(DEFAULT_VERIFICATION_AUTO_BAN_WINDOW_SECONDS)
+        );
+        assert_eq!(
+            config.duration,
+            Duration::seconds(DEFAULT_VERIFICATION_AUTO_BAN_DURATION_SECONDS)
+        );
+    }
+
+    #[test]
+    fn verification_type_round_trips_through_attempt_type() {
+        for verification_type in [
+            VerificationType::LevelUp,
+            VerificationType::Registration,
+            VerificationType::CreateBoard,
+            VerificationType::CreatePost,
+            VerificationType::CreateComment,
+        ] {
+            assert_eq!(
+                VerificationType::from_attempt_type(verification_type.as_attempt_type()),
+                Some(verification_type)
+            );
+        }
+    }
+
+    #[test]
+    fn verification_type_rejects_unknown_attempt_type() {
+        assert_eq!(VerificationType::from_attempt_type("unknown"), None);
+        assert_eq!(VerificationType::from_attempt_type("LEVEL_UP"), None);
+        assert_eq!(VerificationType::from_attempt_type(""), None);
+    }
+
+    #[test]
+    fn auto_ban_triggers_once_failures_reach_threshold() {
+        let config = VerificationAutoBanConfig {
+            threshold: 3,
+            window: Duration::seconds(3600),
+            duration: Duration::seconds(60),
+            created_by: 1,
+        };
+        assert!(!config.should_ban(0));
+        assert!(!config.should_ban(2));
+        assert!(config.should_ban(3));
+        assert!(config.should_ban(10));
+    }
+
+    fn proxycheck_response(json: serde_json::Value) -> ProxyCheckResponse {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn extract_country_code_reads_v2_isocode() {
+        let data = proxycheck_response(serde_json::json!({
+            "status": "ok",
+            "203.0.113.1": { "proxy": "no", "isocode": "jp" }
+        }));
+        assert_eq!(extract_country_code(&data).as_deref(), Some("JP"));
+    }
+
+    #[test]
+    fn extract_country_code_reads_v3_location() {
+        let data = proxycheck_response(serde_json::json!({
+            "status": "ok",
+            "203.0.113.1": { "location": { "country_code": " us " } }
+        }));
+        assert_eq!(extract_country_code(&data).as_deref(), Some("US"));
+    }
+
+    #[test]
+    fn extract_country_code_ignores_missing_or_empty_codes() {
+        let data = proxycheck_response(serde_json::json!({
+            "status": "ok",
+            "203.0.113.1": { "isocode": "  " }
+        }));
+        assert_eq!(extract_country_code(&data), None);
+        let data = proxycheck_response(serde_json::json!({
+            "status": "ok",
+            "203.0.113.1": { "location": {} }
+        }));
+        assert_eq!(extract_country_code(&data), None);
+    }
+
+    // データベースが必要なため、`DATABASE_URL` を設定して `cargo test -- --ignored` で実行します。
+    // 変更はトランザクション内で行い、最後にロールバックします。
+    async fn count_auto_bans(conn: &mut PgConnection, ip_hash: &str, created_by: i32) -> i64 {
+        sqlx::query_scalar(
+            "SELECT COUNT(*) FROM bans WHERE ban_type = 'ip' AND hash_value = $1 AND created_by = $2",
+        )
+        .bind(ip_hash)
+        .bind(created_by)
+        .fetch_one(conn)
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn exceeding_failure_threshold_creates_ip_ban() {
+        if std::env::var("PERMANENT_HASH_SALT").is_err() {
+            std::env::set_var("PERMANENT_HASH_SALT", "test-permanent-salt");
+        }
+        let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
+        let mut conn = PgConnection::connect(&database_url).await.unwrap();
+        let mut tx = conn.begin().await.unwrap();
+
+        let admin_id: i32 =
+            sqlx::query_scalar("INSERT INTO users (email) VALUES ($1) RETURNING id")
+                .bind(format!(
+                    "auto-ban-test-{}@example.invalid",
+                    Utc::now().timestamp_micros()
+                ))
+                .fetch_one(&mut *tx)
+                .await
+                .unwrap();
+        let config = VerificationAutoBanConfig {
+            threshold: 3,
+            window: Duration::seconds(3600),
+            duration: Duration::seconds(60),
+            created_by: admin_id,
+        };
+        let ip_address = "203.0.113.0";
+        let ip_hash = crate::identity::permanent_ip_hash(ip_address);
+
+        for attempt in 1..=config.threshold {
+            sqlx::query(
+                "INSERT INTO level_up_attempts (attempt_type, is_success, ip_address) VALUES ('registration', false, $1)",
+            )
+            .bind(ip_address)
+            .execute(&mut *tx)
+            .await
+            .unwrap();
+            auto_ban_after_repeated_failures(&mut *tx, &config, ip_address, "registration")
+                .await
+                .unwrap();
+
+            let expected = if attempt < config.threshold { 0 } else { 1 };
+            assert_eq!(
+                count_auto_bans(&mut *tx, &ip_hash, admin_id).await,
+                expected
+            );
+        }
+
+        // 既に有効なBANがある場合は重複して作成しない
+        auto_ban_after_repeated_failures(&mut *tx, &config, ip_address, "registration")
+            .await
+            .unwrap();
+        assert_eq!(count_auto_bans(&mut *tx, &ip_hash, admin_id).await, 1);
+
+        tx.rollback().await.unwrap();
+    }
+
+    #[test]
+    fn proxycheck_cache_returns_stored_result_within_ttl() {
+        let ttl = std::time::Duration::from_secs(60);
+        let data = proxycheck_response(serde_json::json!({
+            "status": "ok",
+            "198.51.100.10": { "proxy": "no", "isocode": "JP" }
+        }));
+        // 他のテストとキャッシュを共有するため、このテスト専用のIPを使う
+        assert!(get_cached_proxycheck("198.51.100.10", ttl).is_none());
+        store_cached_proxycheck("198.51.100.10", &data, ttl);
+        let cached = get_cached_proxycheck("198.51.100.10", ttl).unwrap();
+        assert_eq!(cached.status, "ok");
+        assert_eq!(extract_country_code(&cached), Some("JP".to_string()));
+        // 別のIPはキャッシュに当たらない
+        assert!(get_cached_proxycheck("198.51.100.11", ttl).is_none());
+    }
+
+    #[test]
+    fn proxycheck_cache_ignores_expired_entries() {
+        let data = proxycheck_response(serde_json::json!({ "status": "ok" }));
+        store_cached_proxycheck("198.51.100.20", &data, std::time::Duration::from_secs(60));
+        assert!(get_cached_proxycheck("198.51.100.20", std::time::Duration::ZERO).is_none());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(
+            get_cached_proxycheck("198.51.100.20", std::time::Duration::from_millis(10)).is_none()
+        );
+        assert!(
+            get_cached_proxycheck("198.51.100.20", std::time::Duration::from_secs(60)).is_some()
+        );
+    }
+
+    #[test]
+    fn proxycheck_fail_open_is_enabled_only_for_true() {
+        assert!(parse_proxycheck_fail_open(Some("true")));
+        assert!(parse_proxycheck_fail_open(Some(" TRUE ")));
+        assert!(!parse_proxycheck_fail_open(Some("false")));
+        assert!(!parse_proxycheck_fail_open(Some("1")));
+        assert!(!parse_proxycheck_fail_open(None));
+    }
+
+    #[test]
+    fn unavailable_proxycheck_is_skipped_only_when_fail_open() {
+        let unavailable =
+            || ServiceError::InternalServerError("PROXYCHECK_API_KEY not set".to_string());
+        assert!(matches!(
+            skip_unavailable_proxycheck(unavailable(), true),
+            Ok(None)
+        ));
+        assert!(matches!(
+            skip_unavailable_proxycheck(unavailable(), false),
+            Err(ServiceError::InternalServerError(_))
+        ));
+    }
+
+    #[test]
+    fn fingerprint_size_limit_is_inclusive() {
+        assert!(check_fingerprint_size_with(0, 16).is_ok());
+        assert!(check_fingerprint_size_with(16, 16).is_ok());
+        assert!(matches!(
+            check_fingerprint_size_with(17, 16),
+            Err(ServiceError::BadRequest(_))
+        ));
+        assert!(check_fingerprint_size_with(
+            DEFAULT_MAX_FINGERPRINT_BYTES,
+            DEFAULT_MAX_FINGERPRINT_BYTES
+        )
+        .is_ok());
+        assert!(check_fingerprint_size_with(
+            DEFAULT_MAX_FINGERPRINT_BYTES + 1,
+            DEFAULT_MAX_FINGERPRINT_BYTES
+        )
+        .is_err());
+    }
+}